@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::extensions::loader::DiscoveredExtension;
+use crate::starlark::StarlarkEngine;
+use crate::starlark::config::ExtensionSettings;
+use crate::starlark::diagnostics::located;
+use crate::starlark::lint::{LintIssue, Severity};
+use crate::starlark::manifest::ExtensionManifest;
+
+/// Result of `starlark-mcp check`
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    /// Rendered diagnostics for every file, then a summary line
+    pub output: String,
+    pub files: usize,
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+impl CheckReport {
+    pub fn has_errors(&self) -> bool {
+        self.errors > 0
+    }
+}
+
+/// Load and lint each extension the way the server would, collecting
+/// rustc-style diagnostics. Extensions are loaded into `engine`, so their
+/// top-level code and `on_load()` run.
+pub async fn check_extensions(
+    engine: &StarlarkEngine,
+    extensions: &[DiscoveredExtension],
+) -> CheckReport {
+    let mut report = CheckReport::default();
+
+    for discovered in extensions {
+        report.files += 1;
+        let display = display_path(discovered);
+        if discovered.package.is_some() {
+            engine.add_package_root(&discovered.root);
+        }
+
+        let content = match std::fs::read_to_string(&discovered.path) {
+            Ok(content) => content,
+            Err(e) => {
+                let issue = LintIssue::new(
+                    Severity::Error,
+                    "io",
+                    format!("Failed to read file: {}", e),
+                    None,
+                );
+                report.add(&issue, &display, None);
+                continue;
+            }
+        };
+
+        let issues = check_file(engine, discovered, &content).await;
+        for issue in &issues {
+            // Errors in modules the extension loads are located in that module
+            let in_this_file = issue
+                .span
+                .as_ref()
+                .is_none_or(|span| span.file == discovered.name);
+            if in_this_file {
+                report.add(issue, &display, Some(&content));
+            } else {
+                let file = issue.span.as_ref().map(|s| s.file.clone()).unwrap();
+                let source = std::fs::read_to_string(discovered.root.join(&file)).ok();
+                let file = match discovered.package {
+                    Some(ref archive) => format!("{}/{}", archive.display(), file),
+                    None => file,
+                };
+                report.add(issue, &file, source.as_deref());
+            }
+        }
+    }
+
+    report.output.push_str(&format!(
+        "Checked {} extension{}: {} error{}, {} warning{}\n",
+        report.files,
+        plural(report.files),
+        report.errors,
+        plural(report.errors),
+        report.warnings,
+        plural(report.warnings)
+    ));
+    report
+}
+
+async fn check_file(
+    engine: &StarlarkEngine,
+    discovered: &DiscoveredExtension,
+    content: &str,
+) -> Vec<LintIssue> {
+    let name = &discovered.name;
+
+    // Report every type error rather than the first one a load would stop at
+    let type_errors = engine.type_errors(name, content);
+    if !type_errors.is_empty() {
+        let mut issues = type_errors;
+        issues.extend(engine.lint(name, content, None));
+        return issues;
+    }
+
+    match engine
+        .load_extension_with_path(name, content, Some(&discovered.path))
+        .await
+    {
+        Ok(extension) => engine.lint(name, content, Some(&extension)),
+        Err(e) => {
+            let (message, span) = match located(&e) {
+                Some(located) => (located.summary().to_string(), located.span.clone()),
+                None => (format!("{:#}", e), None),
+            };
+            let mut issues = vec![LintIssue::new(Severity::Error, "load", message, span)];
+            issues.extend(engine.lint(name, content, None));
+            issues
+        }
+    }
+}
+
+/// Load each extension into `engine` and describe what it can reach under
+/// `settings`, which replace the engine's. Disabled extensions are loaded
+/// anyway so they can be reviewed before being enabled. Returns the manifests and an error for each
+/// extension that failed to load.
+pub async fn manifests(
+    engine: &StarlarkEngine,
+    extensions: &[DiscoveredExtension],
+    settings: &HashMap<String, ExtensionSettings>,
+) -> (Vec<ExtensionManifest>, Vec<String>) {
+    let mut enabled_settings = settings.clone();
+    for settings in enabled_settings.values_mut() {
+        settings.enabled = true;
+    }
+    engine.apply_settings(enabled_settings).await;
+
+    let mut manifests = Vec::new();
+    let mut errors = Vec::new();
+    for discovered in extensions {
+        if discovered.package.is_some() {
+            engine.add_package_root(&discovered.root);
+        }
+        let loaded = match std::fs::read_to_string(&discovered.path) {
+            Ok(content) => engine
+                .load_extension_with_path(&discovered.name, &content, Some(&discovered.path))
+                .await
+                .map_err(|e| format!("{:#}", e)),
+            Err(e) => Err(format!("Failed to read file: {}", e)),
+        };
+        match loaded {
+            Ok(extension) => {
+                let enabled = settings.get(&extension.name).is_none_or(|s| s.enabled);
+                manifests.push(ExtensionManifest::new(
+                    &extension,
+                    Some(PathBuf::from(display_path(discovered))),
+                    enabled,
+                ));
+            }
+            Err(e) => errors.push(format!("{}: {}", display_path(discovered), e)),
+        }
+    }
+
+    (manifests, errors)
+}
+
+impl CheckReport {
+    fn add(&mut self, issue: &LintIssue, file: &str, source: Option<&str>) {
+        match issue.severity {
+            Severity::Error => self.errors += 1,
+            Severity::Warning => self.warnings += 1,
+        }
+        self.output.push_str(&issue.render(file, source));
+        self.output.push('\n');
+    }
+}
+
+/// Where to say an extension came from: its file, or its path inside the
+/// package archive it was unpacked from
+fn display_path(discovered: &DiscoveredExtension) -> String {
+    match discovered.package {
+        Some(ref archive) => {
+            let relative = discovered
+                .path
+                .strip_prefix(&discovered.root)
+                .unwrap_or(&discovered.path);
+            format!("{}/{}", archive.display(), relative.display())
+        }
+        None => discovered.path.display().to_string(),
+    }
+}
+
+/// An extension file named on the command line, outside discovery
+pub fn file_extension(path: &Path) -> DiscoveredExtension {
+    DiscoveredExtension {
+        name: path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        path: path.to_path_buf(),
+        root: path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        package: None,
+    }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const GOOD: &str = r#"
+def forecast(params):
+    return {"content": [{"type": "text", "text": "sunny"}]}
+
+def describe_extension():
+    return Extension(
+        name = "weather",
+        version = "1.0.0",
+        description = "Weather",
+        tools = [Tool(name = "forecast", description = "Forecast", handler = forecast)],
+    )
+"#;
+
+    const LINTY: &str = r#"
+load("//lib/unused.star", "helper")
+
+def forecast(params):
+    exec.run("curl", ["https://wttr.in"])
+    return "sunny"
+
+def describe_extension():
+    return Extension(
+        name = "weather",
+        version = "1.0.0",
+        description = "Weather",
+        tools = [
+            Tool(
+                name = "forecast",
+                description = "Forecast",
+                parameters = [
+                    ToolParameter(name = "city", param_type = "string", required = True, description = ""),
+                ],
+                handler = forecast,
+            ),
+        ],
+    )
+"#;
+
+    fn write(dir: &Path, name: &str, content: &str) -> DiscoveredExtension {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        DiscoveredExtension {
+            name: path.file_stem().unwrap().to_string_lossy().into_owned(),
+            path,
+            root: dir.to_path_buf(),
+            package: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clean_extension() {
+        let dir = TempDir::new().unwrap();
+        let weather = write(dir.path(), "weather.star", GOOD);
+        let engine = StarlarkEngine::new().with_extensions_dir(dir.path());
+
+        let report = check_extensions(&engine, &[weather]).await;
+        assert!(!report.has_errors());
+        assert_eq!(report.warnings, 0, "{}", report.output);
+        assert_eq!(report.output, "Checked 1 extension: 0 errors, 0 warnings\n");
+    }
+
+    #[tokio::test]
+    async fn test_reports_lint_and_load_errors() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "lib/unused.star",
+            "def helper():\n    return 1\n",
+        );
+        let weather = write(dir.path(), "weather.star", LINTY);
+        let broken = write(
+            dir.path(),
+            "broken.star",
+            "def describe_extension():\n    return undefined_name\n",
+        );
+        let engine = StarlarkEngine::new().with_extensions_dir(dir.path());
+
+        let report = check_extensions(&engine, &[weather, broken]).await;
+        let output = &report.output;
+        assert!(report.has_errors());
+        assert_eq!((report.errors, report.warnings), (4, 2), "{}", output);
+
+        assert!(output.contains("warning[unused-load]"), "{}", output);
+        assert!(
+            output.contains("warning[missing-description]"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains(
+                "error[handler-result]: Handler 'forecast' for tool 'forecast' returns a string instead of a result dict"
+            ),
+            "{}",
+            output
+        );
+        assert!(output.contains("error[exec-not-allowed]"), "{}", output);
+        assert!(
+            output.contains(&format!(
+                " --> {}:5:5\n  |\n5 |     exec.run(\"curl\", [\"https://wttr.in\"])\n  |     ^^^^^^^^",
+                dir.path().join("weather.star").display()
+            )),
+            "{}",
+            output
+        );
+
+        assert!(output.contains("error[load]: Eval error: "), "{}", output);
+        assert!(output.contains("error[using-undefined]"), "{}", output);
+        assert!(output.ends_with("Checked 2 extensions: 4 errors, 2 warnings\n"));
+    }
+
+    #[tokio::test]
+    async fn test_manifests_include_disabled_extensions() {
+        let dir = TempDir::new().unwrap();
+        let weather = write(dir.path(), "weather.star", GOOD);
+        let broken = write(dir.path(), "broken.star", "x = 1\n");
+        let settings = [(
+            "weather".to_string(),
+            ExtensionSettings {
+                enabled: false,
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect();
+        let engine = StarlarkEngine::new().with_extensions_dir(dir.path());
+
+        let (manifests, errors) = manifests(&engine, &[weather, broken], &settings).await;
+        assert_eq!(manifests.len(), 1);
+        assert_eq!(manifests[0].name, "weather");
+        assert!(!manifests[0].enabled);
+        assert_eq!(manifests[0].tools, vec!["forecast"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("describe_extension"), "{}", errors[0]);
+    }
+
+    #[tokio::test]
+    async fn test_reports_each_type_error() {
+        let dir = TempDir::new().unwrap();
+        let source = GOOD.replace(
+            "return {",
+            "http.get(1)\n    env.get(\"A\", 2)\n    return {",
+        );
+        let weather = write(dir.path(), "weather.star", &source);
+        let engine = StarlarkEngine::new()
+            .with_extensions_dir(dir.path())
+            .with_typecheck(true)
+            .unwrap();
+
+        let report = check_extensions(&engine, &[weather]).await;
+        assert_eq!(report.errors, 2, "{}", report.output);
+        assert_eq!(report.output.matches("error[type-error]").count(), 2);
+        assert!(engine.get_extension("weather").await.is_none());
+    }
+}
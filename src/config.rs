@@ -0,0 +1,359 @@
+use anyhow::{Result, anyhow};
+use notify::{EventKind, RecursiveMode};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::info;
+
+use crate::extensions::loader::spawn_watcher;
+use crate::signing::{SignatureVerifier, UnsignedPolicy};
+use crate::starlark::backtrace::ErrorVerbosity;
+use crate::starlark::config::ExtensionSettings;
+use crate::starlark::limits::{ExecutionLimits, heap_bytes};
+
+/// Config file read from the working directory when `--config` isn't given
+pub const CONFIG_FILE_NAME: &str = "starlark-mcp.toml";
+
+/// Server configuration loaded from `starlark-mcp.toml`.
+///
+/// Command-line options take precedence over the file. Relative paths are
+/// resolved against the directory containing the file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Directories to load extensions from, in precedence order
+    pub extension_dirs: Vec<PathBuf>,
+    /// Patterns for files and directories to skip when discovering extensions
+    pub ignore: Vec<String>,
+    pub data_dir: Option<PathBuf>,
+    pub max_concurrent_tools: Option<usize>,
+    /// Expose tools as `<extension>__<tool>`
+    pub namespace_tools: bool,
+    /// List the built-in `server_status` tool
+    pub status_tool: bool,
+    /// Typecheck extensions against the builtin module stubs when they load
+    pub typecheck: bool,
+    /// Whether failed tool calls include the Starlark backtrace in their text
+    pub error_verbosity: Option<ErrorVerbosity>,
+    /// Let every extension's http requests reach loopback, private and
+    /// link-local addresses
+    pub allow_private_network: bool,
+    /// Fail `env.get()` of variables outside an extension's `allowed_env`
+    /// instead of returning the default
+    pub strict_env: bool,
+    pub limits: LimitsConfig,
+    pub transport: TransportConfig,
+    pub signatures: SignaturesConfig,
+    /// Per-extension settings, keyed by extension name
+    pub extensions: HashMap<String, ExtensionSettings>,
+}
+
+/// Server-wide defaults for tools that don't set their own limits
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub timeout_secs: Option<f64>,
+    pub max_steps: Option<u64>,
+    pub max_heap_mb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportConfig {
+    pub mode: Option<TransportMode>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+}
+
+/// Keys that extension files and packages must be signed with
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignaturesConfig {
+    /// Hex-encoded ed25519 public keys; signatures aren't checked when empty
+    pub trusted_keys: Vec<String>,
+    /// Whether unsigned extensions are rejected or loaded without `exec` and
+    /// `http`
+    pub unsigned: UnsignedPolicy,
+}
+
+impl SignaturesConfig {
+    /// A verifier for the trusted keys, or `None` if there are none
+    pub fn verifier(&self) -> Result<Option<SignatureVerifier>> {
+        if self.trusted_keys.is_empty() {
+            return Ok(None);
+        }
+        SignatureVerifier::new(&self.trusted_keys, self.unsigned).map(Some)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportMode {
+    Stdio,
+    Http,
+}
+
+impl LimitsConfig {
+    pub fn to_limits(&self) -> ExecutionLimits {
+        ExecutionLimits {
+            timeout: self.timeout_secs.map(Duration::from_secs_f64),
+            max_steps: self.max_steps,
+            max_heap_bytes: self.max_heap_mb.and_then(heap_bytes),
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        Self::parse(&content, base).map_err(|e| anyhow!("Invalid {}: {}", path.display(), e))
+    }
+
+    /// Parse a config file, resolving relative paths against `base`
+    pub fn parse(content: &str, base: &Path) -> Result<Self> {
+        let mut config: ServerConfig = toml::from_str(content)?;
+
+        if let Some(limit) = config.limits.timeout_secs
+            && (limit.is_nan() || limit <= 0.0)
+        {
+            return Err(anyhow!("limits.timeout_secs must be positive"));
+        }
+        if let Some(limit) = config.limits.max_heap_mb
+            && heap_bytes(limit).is_none()
+        {
+            return Err(anyhow!("limits.max_heap_mb is too large: {}", limit));
+        }
+        config
+            .signatures
+            .verifier()
+            .map_err(|e| anyhow!("signatures.trusted_keys: {}", e))?;
+
+        for dir in &mut config.extension_dirs {
+            *dir = base.join(&*dir);
+        }
+        if let Some(ref mut dir) = config.data_dir {
+            *dir = base.join(&*dir);
+        }
+
+        Ok(config)
+    }
+
+    /// Settings that can't be applied while the server is running
+    pub fn restart_required_changes(&self, previous: &ServerConfig) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.extension_dirs != previous.extension_dirs {
+            changed.push("extension_dirs");
+        }
+        if self.ignore != previous.ignore {
+            changed.push("ignore");
+        }
+        if self.data_dir != previous.data_dir {
+            changed.push("data_dir");
+        }
+        if self.max_concurrent_tools != previous.max_concurrent_tools {
+            changed.push("max_concurrent_tools");
+        }
+        if self.namespace_tools != previous.namespace_tools {
+            changed.push("namespace_tools");
+        }
+        if self.status_tool != previous.status_tool {
+            changed.push("status_tool");
+        }
+        if self.typecheck != previous.typecheck {
+            changed.push("typecheck");
+        }
+        if self.allow_private_network != previous.allow_private_network {
+            changed.push("allow_private_network");
+        }
+        if self.strict_env != previous.strict_env {
+            changed.push("strict_env");
+        }
+        if self.transport != previous.transport {
+            changed.push("transport");
+        }
+        if self.signatures != previous.signatures {
+            changed.push("signatures");
+        }
+        changed
+    }
+
+    /// Watch `path` and call `on_change` whenever it is written. The parent
+    /// directory is watched so editors that save by renaming are picked up.
+    pub fn start_watching<F>(path: &Path, on_change: F) -> Result<()>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let path = path
+            .canonicalize()
+            .map_err(|e| anyhow!("Failed to watch {}: {}", path.display(), e))?;
+        let dir = path
+            .parent()
+            .ok_or_else(|| anyhow!("Config file has no parent directory"))?
+            .to_path_buf();
+
+        info!("Watching server config: {}", path.display());
+
+        let mut rx = spawn_watcher(dir, RecursiveMode::NonRecursive);
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let relevant = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                    && event
+                        .paths
+                        .iter()
+                        .any(|p| p.file_name() == path.file_name());
+                if relevant {
+                    on_change();
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+extension_dirs = ["extensions", "/opt/company/extensions"]
+ignore = ["drafts", "*_wip.star"]
+data_dir = "data"
+max_concurrent_tools = 4
+namespace_tools = true
+status_tool = true
+typecheck = true
+error_verbosity = "backtrace"
+allow_private_network = true
+strict_env = true
+
+[limits]
+timeout_secs = 30
+max_heap_mb = 64
+
+[transport]
+mode = "http"
+port = 8080
+
+[signatures]
+trusted_keys = ["d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"]
+unsigned = "restrict"
+
+[extensions.plane]
+disabled_tools = ["plane_delete_issue"]
+allowed_hosts = ["plane.internal.example.com"]
+allowed_env = ["PLANE_*"]
+
+[extensions.plane.env]
+PLANE_WORKSPACE_SLUG = "acme"
+
+[extensions.github]
+allowed_exec = ["gh", "git"]
+
+[extensions.docker]
+enabled = false
+"#;
+
+    #[test]
+    fn test_parse_example() {
+        let config = ServerConfig::parse(EXAMPLE, Path::new("/etc/starlark-mcp")).unwrap();
+
+        assert_eq!(
+            config.extension_dirs,
+            vec![
+                PathBuf::from("/etc/starlark-mcp/extensions"),
+                PathBuf::from("/opt/company/extensions"),
+            ]
+        );
+        assert_eq!(config.ignore, vec!["drafts", "*_wip.star"]);
+        assert_eq!(
+            config.data_dir,
+            Some(PathBuf::from("/etc/starlark-mcp/data"))
+        );
+        assert_eq!(config.max_concurrent_tools, Some(4));
+        assert!(config.namespace_tools);
+        assert!(config.status_tool);
+        assert!(config.typecheck);
+        assert_eq!(config.error_verbosity, Some(ErrorVerbosity::Backtrace));
+        assert!(config.allow_private_network);
+        assert!(config.strict_env);
+        assert_eq!(config.transport.mode, Some(TransportMode::Http));
+        assert_eq!(config.transport.port, Some(8080));
+        assert_eq!(config.signatures.unsigned, UnsignedPolicy::Restrict);
+        assert!(config.signatures.verifier().unwrap().is_some());
+
+        let limits = config.limits.to_limits();
+        assert_eq!(limits.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(limits.max_steps, None);
+        assert_eq!(limits.max_heap_bytes, Some(64 * 1024 * 1024));
+
+        let plane = &config.extensions["plane"];
+        assert!(plane.enabled);
+        assert_eq!(plane.disabled_tools, vec!["plane_delete_issue"]);
+        assert_eq!(plane.env["PLANE_WORKSPACE_SLUG"], "acme");
+        assert_eq!(
+            plane.allowed_hosts,
+            Some(vec!["plane.internal.example.com".to_string()])
+        );
+        assert_eq!(plane.allowed_env, Some(vec!["PLANE_*".to_string()]));
+        assert_eq!(
+            config.extensions["github"].allowed_exec,
+            Some(vec!["gh".to_string(), "git".to_string()])
+        );
+        assert!(!config.extensions["docker"].enabled);
+    }
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config = ServerConfig::parse("", Path::new(".")).unwrap();
+        assert_eq!(config, ServerConfig::default());
+        assert!(config.limits.to_limits().is_unlimited());
+    }
+
+    #[test]
+    fn test_unknown_keys_rejected() {
+        let err = ServerConfig::parse("[extensions.plane]\nenable = false\n", Path::new("."))
+            .unwrap_err();
+        assert!(err.to_string().contains("enable"));
+
+        assert!(ServerConfig::parse("[transport]\nmode = \"grpc\"\n", Path::new(".")).is_err());
+        assert!(ServerConfig::parse("[limits]\ntimeout_secs = 0\n", Path::new(".")).is_err());
+        let err = ServerConfig::parse(
+            "[limits]\nmax_heap_mb = 9223372036854775807\n",
+            Path::new("."),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "limits.max_heap_mb is too large: 9223372036854775807"
+        );
+    }
+
+    #[test]
+    fn test_restart_required_changes() {
+        let before = ServerConfig::parse(EXAMPLE, Path::new(".")).unwrap();
+        let mut after = before.clone();
+        after.limits.timeout_secs = Some(5.0);
+        after.error_verbosity = None;
+        after.extensions.remove("docker");
+        assert!(after.restart_required_changes(&before).is_empty());
+
+        after.transport.port = Some(9090);
+        assert_eq!(after.restart_required_changes(&before), vec!["transport"]);
+    }
+
+    #[test]
+    fn test_invalid_trusted_key_rejected() {
+        let err = ServerConfig::parse("[signatures]\ntrusted_keys = [\"abc\"]\n", Path::new("."))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("signatures.trusted_keys: Invalid trusted key 'abc'"),
+            "{}",
+            err
+        );
+    }
+}
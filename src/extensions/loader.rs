@@ -1,57 +1,398 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::fs;
 use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
+use walkdir::WalkDir;
 
+use super::package::{PackageCache, is_package};
+use crate::lockfile::Lockfile;
+use crate::signing::{SignatureVerifier, signed_path};
 use crate::starlark::StarlarkEngine;
+use crate::starlark::engine::ExtensionDisabled;
 
+/// Patterns ignored in every root: hidden files and directories
+const DEFAULT_IGNORE: &[&str] = &[".*"];
+
+/// Default quiet period before a burst of file events is applied
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Discovers extensions under one or more root directories and keeps them
+/// loaded as files change.
+///
+/// Roots are searched recursively. Files directly in a root are always
+/// treated as extensions; files in subdirectories only if they define
+/// `describe_extension()`, so helper libraries such as `lib/` are left to
+/// `load()`. When the same extension name is found more than once, the
+/// earlier root wins, and within a root the shallowest file (then the first
+/// in path order) wins.
+///
+/// Package archives (`.zip`, `.tar.gz`) directly in a root are unpacked and
+/// their extensions discovered after the root's own files.
+#[derive(Clone)]
 pub struct ExtensionLoader {
-    extensions_dir: String,
+    /// Roots in precedence order
+    roots: Vec<PathBuf>,
+    ignore: IgnorePatterns,
+    packages: PackageCache,
+    /// Quiet period that ends a burst of file events
+    debounce: Duration,
+    /// Set in `--locked` mode: package archives must match it before they
+    /// are unpacked
+    lockfile: Option<Arc<Lockfile>>,
+    /// Set when signatures are checked: a package archive's signature covers
+    /// the files unpacked from it
+    signatures: Option<Arc<SignatureVerifier>>,
+}
+
+/// Result of applying a batch of file changes
+#[derive(Debug, Default)]
+pub struct ReloadOutcome {
+    /// Whether any extension was loaded, reloaded or removed
+    pub changed: bool,
+    /// Files that failed to load. Their last good versions, if any, are still
+    /// being served.
+    pub errors: Vec<LoadFailure>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadFailure {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+impl ReloadOutcome {
+    /// Record that `path` failed to load. Clients are sent the error, so it
+    /// keeps its context and is redacted with the secrets of the version
+    /// still serving; the same text is returned for the server's log.
+    fn fail(&mut self, engine: &StarlarkEngine, path: &Path, error: anyhow::Error) -> String {
+        let error = engine.redact_failure(extension_name(path), Some(path), &error);
+        self.errors.push(LoadFailure {
+            path: path.to_path_buf(),
+            error: error.clone(),
+        });
+        error
+    }
+}
+
+/// An extension file chosen by discovery
+#[derive(Debug, Clone)]
+pub struct DiscoveredExtension {
+    /// Extension name, taken from the file stem
+    pub name: String,
+    pub path: PathBuf,
+    /// Root the file was found in; for a package, the unpacked package
+    pub root: PathBuf,
+    /// Archive the extension was unpacked from, if it came from a package
+    pub package: Option<PathBuf>,
+}
+
+/// Gitignore-style patterns: a pattern without `/` matches any single path
+/// component, anything else matches the path relative to the root.
+#[derive(Clone)]
+struct IgnorePatterns {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl IgnorePatterns {
+    fn new(patterns: &[String]) -> Result<Self> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for pattern in DEFAULT_IGNORE
+            .iter()
+            .copied()
+            .chain(patterns.iter().map(String::as_str))
+        {
+            let trimmed = pattern.trim_start_matches('/').trim_end_matches('/');
+            let glob = GlobBuilder::new(trimmed)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| anyhow!("Invalid ignore pattern '{}': {}", pattern, e))?;
+            if trimmed.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .map_err(|e| anyhow!("Invalid ignore patterns: {}", e))
+        };
+        Ok(Self {
+            names: build(names)?,
+            paths: build(paths)?,
+        })
+    }
+
+    /// Whether `relative`, a path inside a root, is ignored
+    fn is_ignored(&self, relative: &Path) -> bool {
+        relative
+            .components()
+            .any(|c| self.names.is_match(Path::new(c.as_os_str())))
+            || self.paths.is_match(relative)
+    }
+}
+
+impl Default for IgnorePatterns {
+    fn default() -> Self {
+        Self::new(&[]).expect("default ignore patterns are valid")
+    }
 }
 
 impl ExtensionLoader {
-    pub fn new(extensions_dir: String) -> Self {
-        Self { extensions_dir }
+    pub fn new(extensions_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            roots: vec![extensions_dir.into()],
+            ignore: IgnorePatterns::default(),
+            packages: PackageCache::private(),
+            debounce: DEFAULT_DEBOUNCE,
+            lockfile: None,
+            signatures: None,
+        }
     }
 
-    pub async fn load_all(&self, engine: &StarlarkEngine, include_tests: bool) -> Result<()> {
-        let dir_path = Path::new(&self.extensions_dir);
+    /// Unpack package archives under `dir` rather than a private temporary
+    /// directory. It is created if needed and restricted to this user.
+    pub fn with_packages_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.packages = PackageCache::new(dir);
+        self
+    }
+
+    /// Add another root, with lower precedence than those already added
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.roots.push(dir.into());
+        self
+    }
+
+    /// Skip files and directories matching `patterns` in every root
+    pub fn with_ignore(mut self, patterns: &[String]) -> Result<Self> {
+        self.ignore = IgnorePatterns::new(patterns)?;
+        Ok(self)
+    }
+
+    /// How long the files must be quiet before a burst of changes is applied
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Refuse to unpack package archives that don't match `lockfile`. The
+    /// engine checks extension sources itself.
+    pub fn with_lockfile(mut self, lockfile: Arc<Lockfile>) -> Self {
+        self.lockfile = Some(lockfile);
+        self
+    }
 
-        if !dir_path.exists() {
-            warn!(
-                "Extensions directory does not exist: {}",
-                self.extensions_dir
+    /// Check package archives against trusted signatures; the engine checks
+    /// extension files itself. Writing a signature reloads the file it signs.
+    pub fn with_signatures(mut self, signatures: Arc<SignatureVerifier>) -> Self {
+        self.signatures = Some(signatures);
+        self
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Every file under the roots that isn't ignored: extensions, the modules
+    /// and data files they use, and package archives. This is what
+    /// `starlark-mcp lock` records.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for root in self.roots.iter().filter(|root| root.exists()) {
+            files.extend(
+                WalkDir::new(root)
+                    .follow_links(true)
+                    .sort_by_file_name()
+                    .into_iter()
+                    .filter_entry(|entry| {
+                        entry.depth() == 0
+                            || !self
+                                .ignore
+                                .is_ignored(entry.path().strip_prefix(root).unwrap_or(entry.path()))
+                    })
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_file())
+                    .map(|entry| entry.into_path()),
             );
-            return Ok(());
         }
+        files
+    }
 
-        info!("Loading extensions from: {}", self.extensions_dir);
+    /// Find every extension under the roots, resolving name clashes by
+    /// precedence. Shadowed files are logged and left out.
+    pub fn discover(&self, include_tests: bool) -> Vec<DiscoveredExtension> {
+        let mut discovered: Vec<DiscoveredExtension> = Vec::new();
+        let mut by_name: HashMap<String, PathBuf> = HashMap::new();
 
-        let mut entries = fs::read_dir(dir_path).await?;
+        for root in &self.roots {
+            if !root.exists() {
+                warn!("Extensions directory does not exist: {}", root.display());
+                continue;
+            }
 
-        while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
+            let mut packages: Vec<PathBuf> = Vec::new();
+            let mut candidates: Vec<(usize, PathBuf)> = WalkDir::new(root)
+                .follow_links(true)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_entry(|entry| {
+                    entry.depth() == 0
+                        || !self
+                            .ignore
+                            .is_ignored(entry.path().strip_prefix(root).unwrap_or(entry.path()))
+                })
+                .filter_map(|entry| match entry {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        warn!("Error reading {}: {}", root.display(), e);
+                        None
+                    }
+                })
+                .filter(|entry| {
+                    let is_file = entry.file_type().is_file();
+                    if is_file && entry.depth() == 1 && is_package(entry.path()) {
+                        packages.push(entry.path().to_path_buf());
+                    }
+                    is_file && is_star_file(entry.path())
+                })
+                .filter(|entry| {
+                    if !include_tests && is_test_file(entry.path()) {
+                        debug!("Skipping test file: {}", entry.path().display());
+                        return false;
+                    }
+                    entry.depth() == 1 || defines_extension(entry.path())
+                })
+                .map(|entry| (entry.depth(), entry.into_path()))
+                .collect();
+            candidates.sort();
 
-            if path.extension().and_then(|s| s.to_str()) == Some("star") {
-                if !include_tests && Self::is_test_file(&path) {
-                    info!("Skipping test file: {}", path.display());
+            let mut found: Vec<DiscoveredExtension> = candidates
+                .into_iter()
+                .map(|(_, path)| DiscoveredExtension {
+                    name: extension_name(&path).to_string(),
+                    path,
+                    root: root.clone(),
+                    package: None,
+                })
+                .collect();
+            for archive in packages {
+                match self.discover_package(&archive) {
+                    Ok(extensions) => found.extend(extensions),
+                    Err(e) => warn!("Ignoring package {}: {}", archive.display(), e),
+                }
+            }
+
+            for extension in found {
+                if let Some(winner) = by_name.get(&extension.name) {
+                    warn!(
+                        "Ignoring {}: extension '{}' is already provided by {}",
+                        extension.path.display(),
+                        extension.name,
+                        winner.display()
+                    );
                     continue;
                 }
+                by_name.insert(extension.name.clone(), extension.path.clone());
+                discovered.push(extension);
+            }
+        }
 
-                let file_name = path
-                    .file_stem()
-                    .and_then(|s| s.to_str())
-                    .unwrap_or("unknown");
+        discovered
+    }
+
+    /// Unpack the package `archive` and list the extensions it contains
+    pub fn discover_package(&self, archive: &Path) -> Result<Vec<DiscoveredExtension>> {
+        // Read once, so the bytes unpacked are the bytes verified
+        let content = std::fs::read(archive)
+            .map_err(|e| anyhow!("Failed to read package {}: {}", archive.display(), e))?;
+        if let Some(ref lockfile) = self.lockfile {
+            lockfile.verify(archive, &content)?;
+        }
+        let unsigned = match self.signatures {
+            Some(ref signatures) => signatures.check(archive, &content)?,
+            None => None,
+        };
+        let package = self.packages.unpack(archive, &content)?;
+        if let Some(ref lockfile) = self.lockfile {
+            lockfile.trust_files(&package.root, &package.files);
+        }
+        if let Some(ref signatures) = self.signatures {
+            match unsigned {
+                // Its extensions have no signatures of their own, so they
+                // load restricted
+                Some(reason) => {
+                    warn!("Package {}: {}", archive.display(), reason);
+                    signatures.distrust_dir(&package.root);
+                }
+                None => signatures.trust_files(&package.root, &package.files),
+            }
+        }
+        Ok(package
+            .extensions()?
+            .into_iter()
+            .map(|path| DiscoveredExtension {
+                name: extension_name(&path).to_string(),
+                path,
+                root: package.root.clone(),
+                package: Some(package.archive.clone()),
+            })
+            .collect())
+    }
+
+    /// The file that provides the extension called `name`, if any
+    fn resolve(&self, name: &str) -> Option<DiscoveredExtension> {
+        self.discover(false).into_iter().find(|d| d.name == name)
+    }
 
-                info!("Loading extension file: {}", path.display());
+    /// The file `path` signs, when signatures are checked and it exists, so
+    /// that writing or removing a signature reloads that file
+    fn signed_file(&self, path: &Path) -> Option<PathBuf> {
+        self.signatures.as_ref()?;
+        signed_path(path).filter(|signed| signed.is_file())
+    }
 
-                match self.load_extension_file(engine, &path, file_name).await {
-                    Ok(_) => info!("Successfully loaded extension: {}", file_name),
-                    Err(e) => warn!("Failed to load extension {}: {}", file_name, e),
+    /// Whether `path` falls under an ignore pattern of the root containing it
+    fn is_ignored(&self, path: &Path) -> bool {
+        let path = canonical(path);
+        self.roots.iter().any(|root| {
+            root.canonicalize()
+                .ok()
+                .and_then(|root| path.strip_prefix(root).ok().map(|r| r.to_path_buf()))
+                .is_some_and(|relative| self.ignore.is_ignored(&relative))
+        })
+    }
+
+    pub async fn load_all(&self, engine: &StarlarkEngine, include_tests: bool) -> Result<()> {
+        for root in &self.roots {
+            info!("Loading extensions from: {}", root.display());
+        }
+
+        for discovered in self.discover(include_tests) {
+            info!("Loading extension file: {}", discovered.path.display());
+
+            match self
+                .load_extension_file(engine, &discovered.path, &discovered.name)
+                .await
+            {
+                Ok(_) => info!("Successfully loaded extension: {}", discovered.name),
+                Err(e) if ExtensionDisabled::is(&e) => {
+                    info!("Skipping disabled extension: {}", discovered.path.display())
                 }
+                Err(e) => warn!(
+                    "Failed to load extension {}: {}",
+                    discovered.name,
+                    engine.redact_failure(&discovered.name, Some(&discovered.path), &e)
+                ),
             }
         }
 
@@ -64,138 +405,1128 @@ impl ExtensionLoader {
         path: &Path,
         name: &str,
     ) -> Result<()> {
+        if let Some(root) = self.packages.root_of(path) {
+            engine.add_package_root(&root);
+        }
         let content = fs::read_to_string(path).await?;
-        engine.load_extension(name, &content).await?;
+        engine
+            .load_extension_with_path(name, &content, Some(path))
+            .await?;
         Ok(())
     }
 
-    /// Monitors extensions directory for file changes and triggers on_change callback.
-    /// Uses OS-native file watching (inotify/FSEvents/kqueue) in a separate thread.
+    /// Monitors every root for file changes and calls `on_change` with the
+    /// outcome of each reload. Uses OS-native file watching
+    /// (inotify/FSEvents/kqueue) in a separate thread. Subdirectories such as
+    /// `lib/` are watched too, so edits to shared modules reload every
+    /// extension that loads them.
+    ///
+    /// Events are debounced: a burst of events, such as the several writes an
+    /// editor makes for one save, is applied as a single batch once the files
+    /// have been quiet for the debounce interval.
     pub fn start_watching<F>(&self, engine: Arc<StarlarkEngine>, on_change: F) -> Result<()>
     where
-        F: Fn() + Send + Sync + 'static,
+        F: Fn(ReloadOutcome) + Send + Sync + 'static,
     {
-        let extensions_dir = self.extensions_dir.clone();
-        let dir_path = PathBuf::from(&extensions_dir);
+        let on_change = Arc::new(on_change);
 
-        if !dir_path.exists() {
-            warn!("Extensions directory does not exist, skipping file watching");
-            return Ok(());
-        }
+        for root in &self.roots {
+            if !root.exists() {
+                warn!(
+                    "Extensions directory does not exist, skipping file watching: {}",
+                    root.display()
+                );
+                continue;
+            }
 
-        info!("Starting file watcher for: {}", extensions_dir);
+            info!("Starting file watcher for: {}", root.display());
 
-        let (tx, mut rx) = mpsc::channel::<Event>(100);
+            let mut rx = spawn_watcher(root.clone(), RecursiveMode::Recursive);
+            let loader = self.clone();
+            let engine = engine.clone();
+            let on_change = on_change.clone();
 
-        // Required to call async tx.send() from the blocking notify thread
-        let rt = tokio::runtime::Handle::current();
+            tokio::spawn(async move {
+                while let Some(event) = rx.recv().await {
+                    let mut batch = vec![event];
+                    while let Ok(Some(event)) =
+                        tokio::time::timeout(loader.debounce, rx.recv()).await
+                    {
+                        batch.push(event);
+                    }
 
-        // notify crate requires synchronous blocking thread, not tokio runtime
-        std::thread::spawn(move || {
-            let mut watcher =
-                notify::recommended_watcher(move |res: Result<Event, notify::Error>| match res {
-                    Ok(event) => {
-                        let _ = rt.block_on(tx.send(event));
+                    let outcome = loader.handle_file_events(batch, &engine).await;
+                    if outcome.changed || !outcome.errors.is_empty() {
+                        on_change(outcome);
                     }
-                    Err(e) => error!("Watch error: {:?}", e),
-                })
-                .expect("Failed to create file watcher");
+                }
+            });
+        }
 
-            watcher
-                .watch(&dir_path, RecursiveMode::NonRecursive)
-                .expect("Failed to watch extensions directory");
+        Ok(())
+    }
 
-            // Park the thread indefinitely - watcher stays alive
-            std::thread::park();
-        });
+    /// Apply a batch of file events. Renames and editors' atomic saves arrive
+    /// as various create, modify, rename and remove events depending on the
+    /// platform, so each path is judged by whether it still exists rather
+    /// than by the event kind. Paths are deduplicated, and removals are
+    /// applied before changes.
+    async fn handle_file_events(
+        &self,
+        events: Vec<Event>,
+        engine: &StarlarkEngine,
+    ) -> ReloadOutcome {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for event in events {
+            if !matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) {
+                continue;
+            }
+            for path in event.paths {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        let (existing, removed): (Vec<PathBuf>, Vec<PathBuf>) =
+            paths.into_iter().partition(|path| path.exists());
+
+        let mut outcome = ReloadOutcome::default();
+        for path in removed {
+            self.path_removed(&path, engine, &mut outcome).await;
+        }
+        for path in existing {
+            self.path_changed(&path, engine, &mut outcome).await;
+        }
+        outcome
+    }
 
-        tokio::spawn(async move {
-            while let Some(event) = rx.recv().await {
-                if let Err(e) = Self::handle_file_event(event, &engine, &on_change).await {
-                    error!("Error handling file event: {}", e);
+    /// Handle a file or directory that was created, written or renamed into
+    /// place
+    async fn path_changed(
+        &self,
+        path: &Path,
+        engine: &StarlarkEngine,
+        outcome: &mut ReloadOutcome,
+    ) {
+        if let Some(signed) = self.signed_file(path) {
+            return self.file_changed(&signed, engine, outcome).await;
+        }
+        if path.is_dir() {
+            // A directory moved in brings its extensions without events for
+            // the files inside
+            let dir = canonical(path);
+            for discovered in self.discover(false) {
+                let source = discovered.package.as_ref().unwrap_or(&discovered.path);
+                if canonical(source).starts_with(&dir) {
+                    self.file_changed(&discovered.path, engine, outcome).await;
                 }
             }
-        });
+            return;
+        }
+        self.file_changed(path, engine, outcome).await
+    }
 
-        Ok(())
+    async fn file_changed(
+        &self,
+        path: &Path,
+        engine: &StarlarkEngine,
+        outcome: &mut ReloadOutcome,
+    ) {
+        if is_package(path) && !self.is_ignored(path) {
+            return self.package_changed(path, engine, outcome).await;
+        }
+        if !is_star_file(path) || self.is_ignored(path) {
+            return;
+        }
+        if is_test_file(path) {
+            info!("Skipping test file in hot reload: {}", path.display());
+            return;
+        }
+
+        let file_name = extension_name(path);
+
+        info!("Extension file changed: {}", path.display());
+
+        // Reload the file itself if it is a loaded extension, plus everything
+        // that load()s it
+        match engine.reload_dependents(path).await {
+            Ok(reloaded) if !reloaded.is_empty() => {
+                for extension in &reloaded {
+                    info!("Successfully reloaded extension: {}", extension.name);
+                }
+                outcome.changed = true;
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                let error = outcome.fail(engine, path, e);
+                warn!(
+                    "Failed to reload after change to {}: {}",
+                    path.display(),
+                    error
+                );
+                return;
+            }
+        }
+
+        match self.resolve(file_name) {
+            Some(winner) if canonical(&winner.path) == canonical(path) => {}
+            Some(winner) => {
+                info!(
+                    "{} is shadowed by {}",
+                    path.display(),
+                    winner.path.display()
+                );
+                return;
+            }
+            None => {
+                info!("No extensions depend on {}", path.display());
+                return;
+            }
+        }
+
+        match self.load_extension_file(engine, path, file_name).await {
+            Ok(_) => {
+                info!("Successfully reloaded extension: {}", file_name);
+                outcome.changed = true;
+            }
+            Err(e) if ExtensionDisabled::is(&e) => {
+                info!("Ignoring change to disabled extension: {}", path.display());
+            }
+            Err(e) => {
+                let error = outcome.fail(engine, path, e);
+                warn!("Failed to reload extension {}: {}", file_name, error);
+            }
+        }
     }
 
-    async fn handle_file_event<F>(
-        event: Event,
+    /// Re-unpack a package archive and reload its extensions, unloading any it
+    /// no longer contains
+    async fn package_changed(
+        &self,
+        archive: &Path,
         engine: &StarlarkEngine,
-        on_change: &F,
-    ) -> Result<()>
-    where
-        F: Fn(),
-    {
-        match event.kind {
-            EventKind::Create(_) | EventKind::Modify(_) => {
-                for path in event.paths {
-                    if path.extension().and_then(|s| s.to_str()) == Some("star") {
-                        if Self::is_test_file(&path) {
-                            info!("Skipping test file in hot reload: {}", path.display());
-                            continue;
-                        }
-
-                        let file_name = path
-                            .file_stem()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or("unknown");
-
-                        info!("Extension file changed: {}", path.display());
-
-                        match fs::read_to_string(&path).await {
-                            Ok(content) => match engine.load_extension(file_name, &content).await {
-                                Ok(_) => {
-                                    info!("Successfully reloaded extension: {}", file_name);
-                                    on_change();
-                                }
-                                Err(e) => {
-                                    warn!("Failed to reload extension {}: {}", file_name, e)
-                                }
-                            },
-                            Err(e) => {
-                                warn!("Failed to read extension file {}: {}", path.display(), e)
-                            }
-                        }
-                    }
+        outcome: &mut ReloadOutcome,
+    ) {
+        let archive = canonical(archive);
+        if !self
+            .roots
+            .iter()
+            .any(|root| archive.parent() == Some(&canonical(root)))
+        {
+            return;
+        }
+        info!("Package changed: {}", archive.display());
+
+        let previous = match self.packages.unpacked_root(&archive) {
+            Some(root) => engine.files_under(&root).await,
+            None => Vec::new(),
+        };
+        let extensions = match self.discover_package(&archive) {
+            Ok(extensions) => extensions,
+            Err(e) => {
+                let error = outcome.fail(engine, &archive, e);
+                warn!("Failed to unpack package {}: {}", archive.display(), error);
+                return;
+            }
+        };
+
+        for file in previous {
+            if !file.exists()
+                && let Some(extension) = engine.remove_extension_at(&file).await
+            {
+                info!("Extension '{}' removed from package", extension.name);
+                outcome.changed = true;
+            }
+        }
+        if let Some(root) = extensions.first().map(|e| e.root.clone()) {
+            engine.module_cache().invalidate_under(&root);
+        }
+
+        for discovered in extensions {
+            match self.resolve(&discovered.name) {
+                Some(winner) if winner.path == discovered.path => {}
+                _ => {
+                    info!("{} in {} is shadowed", discovered.name, archive.display());
+                    continue;
+                }
+            }
+            match self
+                .load_extension_file(engine, &discovered.path, &discovered.name)
+                .await
+            {
+                Ok(_) => {
+                    info!("Successfully reloaded extension: {}", discovered.name);
+                    outcome.changed = true;
+                }
+                Err(e) if ExtensionDisabled::is(&e) => {}
+                Err(e) => {
+                    let error = outcome.fail(engine, &discovered.path, e);
+                    warn!("Failed to reload extension {}: {}", discovered.name, error);
                 }
             }
-            EventKind::Remove(_) => {
-                for path in event.paths {
-                    if path.extension().and_then(|s| s.to_str()) == Some("star") {
-                        if Self::is_test_file(&path) {
-                            info!(
-                                "Skipping test file removal in hot reload: {}",
-                                path.display()
-                            );
-                            continue;
-                        }
-
-                        let file_name = path
-                            .file_stem()
-                            .and_then(|s| s.to_str())
-                            .unwrap_or("unknown");
-
-                        info!("Extension file removed: {}", path.display());
-
-                        if let Some(_) = engine.remove_extension(file_name).await {
-                            info!("Successfully removed extension: {}", file_name);
-                            on_change();
-                        }
+        }
+    }
+
+    /// Handle a file or directory that was deleted or renamed away. Every
+    /// extension loaded from it is unloaded, whatever name it declared, and
+    /// lower-precedence copies take over where there are any.
+    async fn path_removed(
+        &self,
+        path: &Path,
+        engine: &StarlarkEngine,
+        outcome: &mut ReloadOutcome,
+    ) {
+        if let Some(signed) = self.signed_file(path) {
+            return self.file_changed(&signed, engine, outcome).await;
+        }
+        let mut path = canonical(path);
+        if is_package(&path) {
+            // The package's extensions live where it was unpacked
+            engine.clear_failures_under(&path);
+            match self.packages.remove(&path) {
+                Some(root) => path = root,
+                None => return,
+            }
+        }
+        engine.clear_failures_under(&path);
+        let files = engine.files_under(&path).await;
+
+        if files.is_empty() {
+            if is_star_file(&path) && !self.is_ignored(&path) && !is_test_file(&path) {
+                // A shared module disappeared; dependents will fail to reload
+                // and keep serving their previous versions
+                if let Err(e) = engine.reload_dependents(&path).await {
+                    let error = outcome.fail(engine, &path, e);
+                    warn!(
+                        "Failed to reload after removal of {}: {}",
+                        path.display(),
+                        error
+                    );
+                }
+            }
+            return;
+        }
+
+        for file in files {
+            info!("Extension file removed: {}", file.display());
+            let file_name = extension_name(&file);
+
+            if let Some(fallback) = self.resolve(file_name) {
+                info!(
+                    "Extension '{}' now provided by {}",
+                    file_name,
+                    fallback.path.display()
+                );
+                match self
+                    .load_extension_file(engine, &fallback.path, file_name)
+                    .await
+                {
+                    Ok(_) => outcome.changed = true,
+                    Err(e) if ExtensionDisabled::is(&e) => {}
+                    Err(e) => {
+                        let error = outcome.fail(engine, &fallback.path, e);
+                        warn!("Failed to load extension {}: {}", file_name, error);
                     }
                 }
             }
-            _ => {}
+
+            // Still registered unless the fallback replaced it under the same name
+            if let Some(extension) = engine.remove_extension_at(&file).await {
+                info!("Successfully removed extension: {}", extension.name);
+                outcome.changed = true;
+            }
         }
+    }
+}
 
-        Ok(())
+fn is_star_file(path: &Path) -> bool {
+    path.extension().and_then(|s| s.to_str()) == Some("star")
+}
+
+fn is_test_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .map(|name| name.ends_with("_test.star"))
+        .unwrap_or(false)
+}
+
+fn extension_name(path: &Path) -> &str {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown")
+}
+
+/// Whether a file in a subdirectory is an extension rather than a library
+fn defines_extension(path: &Path) -> bool {
+    std::fs::read_to_string(path)
+        .map(|content| content.contains("def describe_extension("))
+        .unwrap_or(false)
+}
+
+/// Canonical form of `path`, which may no longer exist: its parent is
+/// canonicalized instead
+fn canonical(path: &Path) -> PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    match (path.parent().map(Path::canonicalize), path.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Watch `path` on a dedicated thread and forward its events to the returned
+/// channel. Must be called from within a tokio runtime.
+pub(crate) fn spawn_watcher(path: PathBuf, mode: RecursiveMode) -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel::<Event>(100);
+
+    // Required to call async tx.send() from the blocking notify thread
+    let rt = tokio::runtime::Handle::current();
+
+    // notify crate requires synchronous blocking thread, not tokio runtime
+    std::thread::spawn(move || {
+        let mut watcher =
+            notify::recommended_watcher(move |res: Result<Event, notify::Error>| match res {
+                Ok(event) => {
+                    let _ = rt.block_on(tx.send(event));
+                }
+                Err(e) => error!("Watch error: {:?}", e),
+            })
+            .expect("Failed to create file watcher");
+
+        watcher
+            .watch(&path, mode)
+            .unwrap_or_else(|e| panic!("Failed to watch {}: {}", path.display(), e));
+
+        // Park the thread indefinitely - watcher stays alive
+        std::thread::park();
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{self, UnsignedPolicy};
+    use ed25519_dalek::SigningKey;
+    use notify::event::{CreateKind, DataChange, ModifyKind, RemoveKind, RenameMode};
+
+    fn ignored(patterns: &[&str], path: &str) -> bool {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        IgnorePatterns::new(&patterns)
+            .unwrap()
+            .is_ignored(Path::new(path))
+    }
+
+    #[test]
+    fn test_hidden_entries_ignored_by_default() {
+        assert!(ignored(&[], ".git/hooks.star"));
+        assert!(ignored(&[], "team/.draft.star"));
+        assert!(!ignored(&[], "team/weather.star"));
+    }
+
+    #[test]
+    fn test_name_patterns_match_any_component() {
+        assert!(ignored(&["drafts"], "drafts"));
+        assert!(ignored(&["drafts"], "team/drafts/wip.star"));
+        assert!(ignored(&["*_wip.star"], "team/query_wip.star"));
+        assert!(!ignored(&["drafts"], "team/drafts.star"));
+    }
+
+    #[test]
+    fn test_path_patterns_are_anchored_at_root() {
+        assert!(ignored(&["vendor/**"], "vendor/x/y.star"));
+        assert!(ignored(&["/archive/"], "archive"));
+        assert!(!ignored(&["vendor/*.star"], "team/vendor/x.star"));
+        assert!(!ignored(&["vendor/*.star"], "vendor/x/y.star"));
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let err = IgnorePatterns::new(&["[".to_string()]).err().unwrap();
+        assert!(err.to_string().contains("Invalid ignore pattern"));
+    }
+    /// An extension declaring `name`, which differs from its file name in
+    /// these tests
+    fn extension(name: &str, tool: &str) -> String {
+        format!(
+            r#"
+def handler(params):
+    return {{"content": []}}
+
+def describe_extension():
+    return Extension(
+        name = "{name}",
+        version = "1.0.0",
+        description = "Test extension",
+        tools = [Tool(name = "{tool}", description = "Tool", handler = handler)],
+    )
+"#
+        )
     }
 
-    fn is_test_file(path: &Path) -> bool {
-        path.file_name()
-            .and_then(|s| s.to_str())
-            .map(|name| name.ends_with("_test.star"))
-            .unwrap_or(false)
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        root: PathBuf,
+        loader: ExtensionLoader,
+        executor: crate::ToolExecutor,
+        engine: Arc<StarlarkEngine>,
+        changes: std::cell::Cell<usize>,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = tempfile::TempDir::new().unwrap();
+            let root = dir.path().canonicalize().unwrap();
+            let executor = crate::ToolExecutor::new()
+                .with_engine(StarlarkEngine::new().with_extensions_dir(&root));
+            Self {
+                loader: ExtensionLoader::new(&root).with_packages_dir(root.join(".packages")),
+                engine: executor.engine(),
+                executor,
+                root,
+                _dir: dir,
+                changes: std::cell::Cell::new(0),
+            }
+        }
+
+        /// Lock the files now in the root and serve them with `--locked`
+        fn locked(mut self) -> Self {
+            let path = self.root.join(crate::lockfile::LOCK_FILE_NAME);
+            Lockfile::write(&path, &self.loader.files()).unwrap();
+            let lockfile = Arc::new(Lockfile::load(&path).unwrap());
+            self.executor = crate::ToolExecutor::new().with_engine(
+                StarlarkEngine::new()
+                    .with_extensions_dir(&self.root)
+                    .with_lockfile(lockfile.clone()),
+            );
+            self.engine = self.executor.engine();
+            self.loader = self.loader.with_lockfile(lockfile);
+            self
+        }
+
+        /// Check signatures against `key` with `policy`
+        fn signed(mut self, key: &SigningKey, policy: UnsignedPolicy) -> Self {
+            let signatures =
+                Arc::new(SignatureVerifier::new(&[signing::public_key(key)], policy).unwrap());
+            self.executor = crate::ToolExecutor::new().with_engine(
+                StarlarkEngine::new()
+                    .with_extensions_dir(&self.root)
+                    .with_signatures(signatures.clone()),
+            );
+            self.engine = self.executor.engine();
+            self.loader = self.loader.with_signatures(signatures);
+            self
+        }
+
+        fn write(&self, relative: &str, content: &str) -> PathBuf {
+            let path = self.root.join(relative);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            path
+        }
+
+        async fn send(&self, kind: EventKind, paths: &[&Path]) {
+            let event = paths.iter().fold(Event::new(kind), |event, path| {
+                event.add_path(path.to_path_buf())
+            });
+            let outcome = self
+                .loader
+                .handle_file_events(vec![event], &self.engine)
+                .await;
+            if outcome.changed {
+                self.changes.set(self.changes.get() + 1);
+            }
+        }
+
+        async fn names(&self) -> Vec<String> {
+            let mut names: Vec<String> = self
+                .engine
+                .get_all_extensions()
+                .await
+                .into_iter()
+                .map(|e| e.name)
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    fn modify() -> EventKind {
+        EventKind::Modify(ModifyKind::Data(DataChange::Content))
+    }
+
+    #[tokio::test]
+    async fn test_create_registers_file_under_declared_name() {
+        let fx = Fixture::new();
+        let path = fx.root.join("weather.star");
+        std::fs::write(&path, extension("weather_tools", "forecast")).unwrap();
+
+        fx.send(EventKind::Create(CreateKind::File), &[&path]).await;
+
+        assert_eq!(fx.names().await, vec!["weather_tools"]);
+        assert_eq!(
+            fx.engine.extension_at(&path).await.as_deref(),
+            Some("weather_tools")
+        );
+        assert_eq!(fx.changes.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_modify_that_renames_extension_drops_old_name() {
+        let fx = Fixture::new();
+        let path = fx.root.join("weather.star");
+        std::fs::write(&path, extension("weather_tools", "forecast")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        std::fs::write(&path, extension("forecasts", "forecast")).unwrap();
+        fx.send(modify(), &[&path]).await;
+
+        assert_eq!(fx.names().await, vec!["forecasts"]);
+    }
+
+    #[tokio::test]
+    async fn test_remove_unregisters_extension_with_different_name() {
+        let fx = Fixture::new();
+        let path = fx.root.join("weather.star");
+        std::fs::write(&path, extension("weather_tools", "forecast")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        std::fs::remove_file(&path).unwrap();
+        fx.send(EventKind::Remove(RemoveKind::File), &[&path]).await;
+
+        assert!(fx.names().await.is_empty());
+        assert_eq!(fx.engine.extension_at(&path).await, None);
+        assert_eq!(fx.changes.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rename_moves_extension_to_new_file() {
+        let fx = Fixture::new();
+        let old = fx.root.join("weather.star");
+        let new = fx.root.join("forecast.star");
+        std::fs::write(&old, extension("weather_tools", "forecast")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        std::fs::rename(&old, &new).unwrap();
+        fx.send(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &[&old, &new],
+        )
+        .await;
+
+        assert_eq!(fx.names().await, vec!["weather_tools"]);
+        assert_eq!(fx.engine.extension_at(&old).await, None);
+        assert_eq!(
+            fx.engine.extension_at(&new).await.as_deref(),
+            Some("weather_tools")
+        );
+
+        // Some platforms report the two halves separately, destination first
+        let newer = fx.root.join("weather2.star");
+        std::fs::rename(&new, &newer).unwrap();
+        fx.send(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            &[&newer],
+        )
+        .await;
+        fx.send(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            &[&new],
+        )
+        .await;
+
+        assert_eq!(fx.names().await, vec!["weather_tools"]);
+        assert_eq!(
+            fx.engine.files_under(&fx.root).await,
+            vec![newer.canonicalize().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_atomic_save_reloads_in_place() {
+        let fx = Fixture::new();
+        let path = fx.root.join("weather.star");
+        std::fs::write(&path, extension("weather_tools", "forecast")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        // Editors write a hidden temp file, then rename it over the original
+        let temp = fx.root.join(".weather.star.swp");
+        std::fs::write(&temp, extension("weather_tools", "alerts")).unwrap();
+        fx.send(EventKind::Create(CreateKind::File), &[&temp]).await;
+        std::fs::rename(&temp, &path).unwrap();
+        fx.send(
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+            &[&temp, &path],
+        )
+        .await;
+
+        let extension = fx.engine.get_extension("weather_tools").await.unwrap();
+        assert_eq!(extension.tools[0].name, "alerts");
+        assert_eq!(fx.names().await, vec!["weather_tools"]);
+    }
+
+    #[tokio::test]
+    async fn test_directory_removal_unregisters_contents() {
+        let fx = Fixture::new();
+        let team = fx.root.join("team");
+        std::fs::create_dir(&team).unwrap();
+        std::fs::write(team.join("ops.star"), extension("ops_tools", "status")).unwrap();
+        std::fs::write(fx.root.join("keep.star"), extension("keep", "keep")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+        assert_eq!(fx.names().await, vec!["keep", "ops_tools"]);
+
+        std::fs::remove_dir_all(&team).unwrap();
+        fx.send(EventKind::Remove(RemoveKind::Folder), &[&team])
+            .await;
+
+        assert_eq!(fx.names().await, vec!["keep"]);
+    }
+
+    #[tokio::test]
+    async fn test_burst_of_events_applied_once_with_final_content() {
+        let fx = Fixture::new();
+        let path = fx.root.join("weather.star");
+        std::fs::write(&path, extension("weather_tools", "forecast")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        // The first write left a truncated file, but by the time the batch is
+        // applied the save has finished
+        std::fs::write(&path, extension("weather_tools", "alerts")).unwrap();
+        let events = (0..3)
+            .map(|_| Event::new(modify()).add_path(path.clone()))
+            .collect();
+        let outcome = fx.loader.handle_file_events(events, &fx.engine).await;
+
+        assert!(outcome.changed);
+        assert!(outcome.errors.is_empty());
+        let extension = fx.engine.get_extension("weather_tools").await.unwrap();
+        assert_eq!(extension.tools[0].name, "alerts");
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload_is_reported_redacted() {
+        let fx = Fixture::new();
+        // cargo sets CARGO_PKG_NAME when running tests
+        let secret = std::env::var("CARGO_PKG_NAME").unwrap();
+        let path = fx.write(
+            "secretive.star",
+            r#"
+def echo(params):
+    return {"content": [{"type": "text", "text": config.get("CARGO_PKG_NAME")}]}
+
+def describe_extension():
+    return Extension(
+        name = "secretive",
+        version = "1.0.0",
+        description = "Holds a secret",
+        config = [ConfigVar(name = "CARGO_PKG_NAME", required = True, secret = True)],
+        tools = [Tool(name = "echo", description = "Echo", handler = echo)],
+    )
+"#,
+        );
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        std::fs::write(&path, format!("fail(\"rejected key {}\")\n", secret)).unwrap();
+        let outcome = fx
+            .loader
+            .handle_file_events(
+                vec![Event::new(modify()).add_path(path.clone())],
+                &fx.engine,
+            )
+            .await;
+
+        let error = &outcome.errors[0].error;
+        assert!(error.contains("rejected key [REDACTED]"), "{}", error);
+        assert!(!error.contains(&secret));
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload_keeps_last_good_version() {
+        let fx = Fixture::new();
+        let path = fx.root.join("weather.star");
+        std::fs::write(&path, extension("weather_tools", "forecast")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        for broken in [
+            "def describe_extension(:\n".to_string(),
+            extension("weather_tools", "alerts").replace("handler = handler", "handler = len"),
+        ] {
+            std::fs::write(&path, broken).unwrap();
+            let outcome = fx
+                .loader
+                .handle_file_events(
+                    vec![Event::new(modify()).add_path(path.clone())],
+                    &fx.engine,
+                )
+                .await;
+
+            assert!(!outcome.changed);
+            assert_eq!(outcome.errors.len(), 1);
+            assert_eq!(outcome.errors[0].path, path);
+            let extension = fx.engine.get_extension("weather_tools").await.unwrap();
+            assert_eq!(extension.tools[0].name, "forecast");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_settings_keyed_by_declared_name() {
+        let fx = Fixture::new();
+        fx.engine
+            .apply_settings(HashMap::from([
+                (
+                    "weather".to_string(),
+                    toml::from_str("enabled = false").unwrap(),
+                ),
+                (
+                    "weather_tools".to_string(),
+                    toml::from_str("env = { REGION = \"eu\" }").unwrap(),
+                ),
+                (
+                    "news_feed".to_string(),
+                    toml::from_str("enabled = false").unwrap(),
+                ),
+            ]))
+            .await;
+        fx.write(
+            "weather.star",
+            &extension("weather_tools", "forecast").replace(
+                "{\"content\": []}",
+                "{\"content\": [{\"type\": \"text\", \"text\": env.get(\"REGION\")}]}",
+            ),
+        );
+        let news = fx.write("news.star", &extension("news_feed", "headlines"));
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        assert_eq!(fx.names().await, vec!["weather_tools"]);
+        let result = fx
+            .executor
+            .execute_tool("forecast", serde_json::json!({}))
+            .await
+            .unwrap();
+        assert!(matches!(
+            result.content.first(),
+            Some(crate::mcp::ToolContent::Text { text }) if text == "eu"
+        ));
+
+        let outcome = fx
+            .loader
+            .handle_file_events(vec![Event::new(modify()).add_path(news)], &fx.engine)
+            .await;
+        assert!(!outcome.changed);
+        assert!(outcome.errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_second_file_cannot_claim_loaded_name() {
+        let fx = Fixture::new();
+        let first = fx.root.join("weather.star");
+        let second = fx.root.join("weather_copy.star");
+        std::fs::write(&first, extension("weather_tools", "forecast")).unwrap();
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+
+        std::fs::write(&second, extension("weather_tools", "forecast")).unwrap();
+        fx.send(EventKind::Create(CreateKind::File), &[&second])
+            .await;
+
+        assert_eq!(
+            fx.engine.extension_at(&first).await.as_deref(),
+            Some("weather_tools")
+        );
+        assert_eq!(fx.engine.extension_at(&second).await, None);
+        assert_eq!(fx.changes.get(), 0);
+    }
+
+    const PACKAGED: &str = r#"
+load("//lib/items.star", "count")
+
+def items(params):
+    return {"content": [{"type": "text", "text": str(count(data.load_json("data/items.json")))}]}
+
+def describe_extension():
+    return Extension(
+        name = "pokemon",
+        version = "1.0.0",
+        description = "Pokemon items",
+        tools = [Tool(name = "items", description = "Count items", handler = items)],
+    )
+"#;
+
+    fn write_package(path: &Path, items: &str, extensions: &[(&str, &str)]) {
+        let mut files = vec![
+            ("package.toml", "name = \"pokemon\"\nversion = \"1.0.0\"\n"),
+            (
+                "lib/items.star",
+                "def count(items):\n    return len(items)\n",
+            ),
+            ("data/items.json", items),
+        ];
+        files.extend_from_slice(extensions);
+        super::super::package::write_zip(path, &files);
+    }
+
+    async fn item_count(fx: &Fixture) -> String {
+        let result = fx
+            .executor
+            .execute_tool("items", serde_json::json!({}))
+            .await
+            .unwrap();
+        match result.content.first() {
+            Some(crate::mcp::ToolContent::Text { text }) => text.clone(),
+            None => panic!("Expected text content"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_package_loads_with_scoped_lib_and_data() {
+        let fx = Fixture::new();
+        // The root's own lib/ and data/ must not be used by the package
+        std::fs::create_dir_all(fx.root.join("lib")).unwrap();
+        std::fs::write(
+            fx.root.join("lib/items.star"),
+            "def count(items):\n    return -1\n",
+        )
+        .unwrap();
+        let archive = fx.root.join("pokemon.zip");
+        write_package(&archive, "[1, 2, 3]", &[("pokemon.star", PACKAGED)]);
+
+        let discovered = fx.loader.discover(false);
+        assert_eq!(discovered.len(), 1);
+        assert_eq!(discovered[0].package.as_deref(), Some(archive.as_path()));
+
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+        assert_eq!(fx.names().await, vec!["pokemon"]);
+        assert_eq!(item_count(&fx).await, "3");
+    }
+
+    #[tokio::test]
+    async fn test_package_change_and_removal() {
+        let fx = Fixture::new();
+        let archive = fx.root.join("pokemon.zip");
+        write_package(
+            &archive,
+            "[1]",
+            &[
+                ("pokemon.star", PACKAGED),
+                ("extra.star", &extension("extra", "extra_tool")),
+            ],
+        );
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+        assert_eq!(fx.names().await, vec!["extra", "pokemon"]);
+
+        // Rewrite with new data and without extra.star; the size changes
+        write_package(&archive, "[1, 2, 3, 4]", &[("pokemon.star", PACKAGED)]);
+        fx.send(modify(), &[&archive]).await;
+        assert_eq!(fx.names().await, vec!["pokemon"]);
+        assert_eq!(item_count(&fx).await, "4");
+
+        std::fs::remove_file(&archive).unwrap();
+        fx.send(EventKind::Remove(RemoveKind::File), &[&archive])
+            .await;
+        assert!(fx.names().await.is_empty());
+        assert_eq!(fx.changes.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_locked_refuses_modified_files() {
+        let fx = Fixture::new();
+        let path = fx.root.join("pokemon.star");
+        let items = fx.root.join("data/items.json");
+        std::fs::create_dir_all(fx.root.join("lib")).unwrap();
+        std::fs::create_dir_all(fx.root.join("data")).unwrap();
+        std::fs::write(&path, PACKAGED).unwrap();
+        std::fs::write(
+            fx.root.join("lib/items.star"),
+            "def count(items):\n    return len(items)\n",
+        )
+        .unwrap();
+        std::fs::write(&items, "[1, 2, 3]").unwrap();
+        let archive = fx.root.join("extra.zip");
+        let write_extra = |tool| {
+            super::super::package::write_zip(
+                &archive,
+                &[
+                    ("package.toml", "name = \"extra\"\nversion = \"1.0.0\"\n"),
+                    ("extra.star", &extension("extra", tool)),
+                ],
+            )
+        };
+        write_extra("x");
+        let fx = fx.locked();
+
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+        assert_eq!(fx.names().await, vec!["extra", "pokemon"]);
+        assert_eq!(item_count(&fx).await, "3");
+
+        // Data files are checked when they are read
+        std::fs::write(&items, "[1, 2, 3, 4]").unwrap();
+        assert!(
+            item_count(&fx)
+                .await
+                .contains("data/items.json does not match starlark-mcp.lock"),
+        );
+        std::fs::write(&items, "[1, 2, 3]").unwrap();
+
+        // A modified extension is refused and the locked version kept
+        std::fs::write(&path, PACKAGED.replace("Count items", "Gained a tool")).unwrap();
+        fx.send(modify(), &[&path]).await;
+        assert_eq!(item_count(&fx).await, "3");
+
+        // So is a new file, and a modified package
+        let new = fx.root.join("new.star");
+        std::fs::write(&new, extension("new", "new_tool")).unwrap();
+        fx.send(EventKind::Create(CreateKind::File), &[&new]).await;
+        write_extra("y");
+        let outcome = fx
+            .loader
+            .handle_file_events(
+                vec![Event::new(modify()).add_path(archive.clone())],
+                &fx.engine,
+            )
+            .await;
+        assert!(
+            outcome.errors[0]
+                .error
+                .contains("extra.zip does not match starlark-mcp.lock"),
+            "{:?}",
+            outcome.errors
+        );
+
+        assert_eq!(fx.names().await, vec!["extra", "pokemon"]);
+        let extra = fx.engine.get_extension("extra").await.unwrap();
+        assert_eq!(extra.tools[0].name, "x");
+        assert_eq!(fx.changes.get(), 0);
+        let failures: Vec<String> = fx
+            .engine
+            .diagnostics()
+            .await
+            .failed
+            .into_iter()
+            .map(|f| f.error)
+            .collect();
+        for expected in [
+            "pokemon.star does not match starlark-mcp.lock",
+            "new.star is not in starlark-mcp.lock",
+        ] {
+            assert!(
+                failures.iter().any(|e| e.contains(expected)),
+                "{:?}",
+                failures
+            );
+        }
+    }
+
+    const FETCH: &str = r#"
+load("//lib/unsigned.star", "URL")
+
+def fetch(params):
+    return {"content": [{"type": "text", "text": str(http.get(URL))}]}
+
+def describe_extension():
+    return Extension(
+        name = "fetch",
+        version = "1.0.0",
+        description = "Fetches",
+        tools = [Tool(name = "fetch", description = "Fetch", handler = fetch)],
+    )
+"#;
+
+    async fn failures(fx: &Fixture) -> Vec<String> {
+        fx.engine
+            .diagnostics()
+            .await
+            .failed
+            .into_iter()
+            .map(|f| f.error)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_extension_rejected() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let fx = Fixture::new().signed(&key, UnsignedPolicy::Reject);
+        let weather = fx.write("weather.star", &extension("weather", "forecast"));
+        signing::sign_file(&key, &weather).unwrap();
+        fx.write("news.star", &extension("news", "headlines"));
+        let other = SigningKey::from_bytes(&[2; 32]);
+        signing::sign_file(
+            &other,
+            &fx.write("sports.star", &extension("sports", "scores")),
+        )
+        .unwrap();
+
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+        assert_eq!(fx.names().await, vec!["weather"]);
+        let failures = failures(&fx).await;
+        assert!(
+            failures
+                .iter()
+                .any(|e| e.contains("news.star is not signed: ")),
+            "{:?}",
+            failures
+        );
+        assert!(
+            failures
+                .iter()
+                .any(|e| e.contains("sports.star is not signed by a trusted key")),
+            "{:?}",
+            failures
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_extension_restricted() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let fx = Fixture::new().signed(&key, UnsignedPolicy::Restrict);
+        let fetch = fx.write("fetch.star", FETCH);
+        let module = fx.write("lib/unsigned.star", "URL = \"http://127.0.0.1:1\"\n");
+
+        fx.loader.load_all(&fx.engine, false).await.unwrap();
+        let extension = fx.engine.get_extension("fetch").await.unwrap();
+        assert!(extension.unsigned.is_some());
+        assert!(!extension.capabilities().allows_module("http"));
+        let result = fx
+            .executor
+            .execute_tool("fetch", serde_json::json!({}))
+            .await
+            .unwrap();
+        let text = serde_json::to_string(&result.content).unwrap();
+        assert!(
+            text.contains("cannot use the http module: it is not signed by a trusted key"),
+            "{}",
+            text
+        );
+
+        // Once signed, the extension may no longer load the unsigned module,
+        // even though it is cached
+        let sig = signing::sign_file(&key, &fetch).unwrap();
+        fx.send(EventKind::Create(CreateKind::File), &[&sig]).await;
+        let failures = failures(&fx).await;
+        assert!(
+            failures
+                .iter()
+                .any(|e| e.contains("lib/unsigned.star is not signed: ")),
+            "{:?}",
+            failures
+        );
+        assert!(
+            fx.engine
+                .get_extension("fetch")
+                .await
+                .unwrap()
+                .unsigned
+                .is_some()
+        );
+
+        let sig = signing::sign_file(&key, &module).unwrap();
+        fx.send(EventKind::Create(CreateKind::File), &[&sig]).await;
+        let extension = fx.engine.get_extension("fetch").await.unwrap();
+        assert_eq!(extension.unsigned, None);
+        assert!(extension.capabilities().allows_module("http"));
     }
 }
@@ -1,3 +1,4 @@
 pub mod loader;
+pub mod package;
 
 pub use loader::ExtensionLoader;
@@ -0,0 +1,611 @@
+use anyhow::{Result, anyhow};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+use crate::lockfile::sha256;
+
+/// Manifest at the top of every package
+pub const MANIFEST_FILE: &str = "package.toml";
+
+/// Limit on the unpacked size of one package, against archives that expand
+/// far beyond their own size
+const MAX_UNPACKED_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Suffixes of the archive formats packages are read from
+const ARCHIVE_SUFFIXES: &[&str] = &[".zip", ".tar.gz", ".tgz"];
+
+/// `package.toml`: what a package is and which of its files are extensions
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    /// Extension files, relative to the package root. Defaults to the `.star`
+    /// files at the top of the package.
+    #[serde(default)]
+    pub extensions: Vec<PathBuf>,
+}
+
+impl PackageManifest {
+    /// Read the manifest of the package rooted at `root`
+    pub fn read(root: &Path) -> Result<Self> {
+        toml::from_str(&std::fs::read_to_string(root.join(MANIFEST_FILE))?)
+            .map_err(|e| anyhow!("Invalid {}: {}", MANIFEST_FILE, e))
+    }
+}
+
+/// A package unpacked on disk
+#[derive(Debug, Clone)]
+pub struct Package {
+    /// The archive it was unpacked from
+    pub archive: PathBuf,
+    /// Directory holding `package.toml`; `load("//...")` and
+    /// `data.load_json()` resolve against it
+    pub root: PathBuf,
+    pub manifest: PackageManifest,
+    /// SHA-256 of every file as it was extracted, by canonical path
+    pub files: HashMap<PathBuf, String>,
+}
+
+impl Package {
+    /// The package's extension files, in manifest order
+    pub fn extensions(&self) -> Result<Vec<PathBuf>> {
+        if self.manifest.extensions.is_empty() {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(&self.root)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| {
+                    path.is_file()
+                        && path.extension().is_some_and(|e| e == "star")
+                        && !path.to_string_lossy().ends_with("_test.star")
+                })
+                .collect();
+            files.sort();
+            return Ok(files);
+        }
+
+        self.manifest
+            .extensions
+            .iter()
+            .map(|file| {
+                if !is_relative_inside(file) || file.extension().is_none_or(|e| e != "star") {
+                    return Err(anyhow!(
+                        "Package '{}' lists invalid extension '{}': expected a .star file inside the package",
+                        self.manifest.name,
+                        file.display()
+                    ));
+                }
+                let path = self.root.join(file);
+                if !path.is_file() {
+                    return Err(anyhow!(
+                        "Package '{}' lists extension '{}', which it doesn't contain",
+                        self.manifest.name,
+                        file.display()
+                    ));
+                }
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+/// Whether `path` names a package archive
+pub fn is_package(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    ARCHIVE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// Packages unpacked under one directory, each re-extracted only when its
+/// archive changes
+#[derive(Debug, Clone)]
+pub struct PackageCache {
+    /// Where packages are unpacked, or `None` for a private temporary
+    /// directory created on first use
+    dir: Option<PathBuf>,
+    temp: Arc<Mutex<Option<TempPackagesDir>>>,
+    /// Unpacked packages and the SHA-256 of the archive each came from
+    unpacked: Arc<Mutex<HashMap<PathBuf, (String, Package)>>>,
+}
+
+impl PackageCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+            ..Self::private()
+        }
+    }
+
+    /// Unpack under a temporary directory only this user can read, removed
+    /// when the last clone of the cache is dropped
+    pub fn private() -> Self {
+        Self {
+            dir: None,
+            temp: Arc::new(Mutex::new(None)),
+            unpacked: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Unpack `archive` from `content`, the bytes that were verified, or
+    /// return the copy already unpacked if they haven't changed since
+    pub fn unpack(&self, archive: &Path, content: &[u8]) -> Result<Package> {
+        let archive = archive
+            .canonicalize()
+            .map_err(|e| anyhow!("Failed to read package {}: {}", archive.display(), e))?;
+        let digest = sha256(content);
+
+        if let Some((unpacked_digest, package)) = self.unpacked.lock().unwrap().get(&archive)
+            && *unpacked_digest == digest
+            && package.root.exists()
+        {
+            return Ok(package.clone());
+        }
+
+        let dest = self.dir_for(&archive)?;
+        info!(
+            "Unpacking package {} to {}",
+            archive.display(),
+            dest.display()
+        );
+        let package = unpack_into(&archive, content, &dest)
+            .map_err(|e| anyhow!("Failed to unpack package {}: {}", archive.display(), e))?;
+        self.unpacked
+            .lock()
+            .unwrap()
+            .insert(archive, (digest, package.clone()));
+        Ok(package)
+    }
+
+    /// Root of the unpacked package containing `path`, if any
+    pub fn root_of(&self, path: &Path) -> Option<PathBuf> {
+        let path = path.canonicalize().ok()?;
+        self.unpacked
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(_, package)| &package.root)
+            .find(|root| path.starts_with(root))
+            .cloned()
+    }
+
+    /// Root of the copy of `archive` that was unpacked, if any
+    pub fn unpacked_root(&self, archive: &Path) -> Option<PathBuf> {
+        self.unpacked
+            .lock()
+            .unwrap()
+            .get(archive)
+            .map(|(_, package)| package.root.clone())
+    }
+
+    /// Forget a removed archive and delete its unpacked copy. Returns the
+    /// package root it had.
+    pub fn remove(&self, archive: &Path) -> Option<PathBuf> {
+        let (_, package) = self.unpacked.lock().unwrap().remove(archive)?;
+        if let Ok(dest) = self.dir_for(archive) {
+            let _ = std::fs::remove_dir_all(dest);
+        }
+        Some(package.root)
+    }
+
+    /// The directory packages are unpacked under, created with access for
+    /// this user only
+    fn dir(&self) -> Result<PathBuf> {
+        if let Some(ref dir) = self.dir {
+            create_private_dir(dir)?;
+            return Ok(dir.clone());
+        }
+        let mut temp = self.temp.lock().unwrap();
+        if let Some(ref temp) = *temp {
+            return Ok(temp.0.clone());
+        }
+        let created = TempPackagesDir::create()?;
+        create_private_dir(&created.0)?;
+        let dir = created.0.clone();
+        *temp = Some(created);
+        Ok(dir)
+    }
+
+    /// Where `archive` is unpacked, named after the SHA-256 of its path. The
+    /// name stays the same as the archive changes, so reloads replace
+    /// extensions in place, and across server builds, so a persistent
+    /// directory doesn't collect stale copies.
+    fn dir_for(&self, archive: &Path) -> Result<PathBuf> {
+        let digest = sha256(archive.as_os_str().as_encoded_bytes());
+        let stem = archive
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let stem = ARCHIVE_SUFFIXES
+            .iter()
+            .find_map(|suffix| stem.strip_suffix(suffix))
+            .unwrap_or(&stem)
+            .to_string();
+        Ok(self.dir()?.join(format!("{}-{}", stem, &digest[..16])))
+    }
+}
+
+/// The temporary directory of a [`PackageCache::private`] cache, deleted
+/// when dropped
+#[derive(Debug)]
+struct TempPackagesDir(PathBuf);
+
+impl TempPackagesDir {
+    /// Create a directory with a random name under the system's temporary
+    /// directory. Creation fails rather than reuse a directory that already
+    /// exists.
+    fn create() -> Result<Self> {
+        let mut suffix = [0u8; 8];
+        getrandom::getrandom(&mut suffix)
+            .map_err(|e| anyhow!("Failed to create package directory: {}", e))?;
+        let dir =
+            std::env::temp_dir().join(format!("starlark-mcp-packages-{}", hex::encode(suffix)));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder
+            .create(&dir)
+            .map_err(|e| anyhow!("Failed to create {}: {}", dir.display(), e))?;
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for TempPackagesDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Create `dir` if needed and make it accessible to this user only, so other
+/// local users can't swap the files unpacked into it
+fn create_private_dir(dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| anyhow!("Failed to create {}: {}", dir.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+            .map_err(|e| anyhow!("Failed to restrict {}: {}", dir.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Extract `content`, read from `archive`, to `dest`, replacing what was
+/// there
+fn unpack_into(archive: &Path, content: &[u8], dest: &Path) -> Result<Package> {
+    let staging = dest.with_extension("unpacking");
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+
+    let name = archive.to_string_lossy().to_lowercase();
+    let extracted = if name.ends_with(".zip") {
+        extract_zip(content, &staging)
+    } else {
+        extract_tar_gz(content, &staging)
+    };
+    let hashes = match extracted.and_then(|hashes| package_root(&staging).map(|_| hashes)) {
+        Ok(hashes) => hashes,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
+
+    if dest.exists() {
+        std::fs::remove_dir_all(dest)?;
+    }
+    std::fs::rename(&staging, dest)?;
+
+    let unpacked = dest.canonicalize()?;
+    let files = hashes
+        .into_iter()
+        .map(|(relative, hash)| (unpacked.join(relative), hash))
+        .collect();
+    let root = package_root(&unpacked)?;
+    let manifest = PackageManifest::read(&root)?;
+    Ok(Package {
+        archive: archive.to_path_buf(),
+        root,
+        manifest,
+        files,
+    })
+}
+
+/// The directory holding the manifest: the top of the archive, or its only
+/// directory for archives made from a folder
+fn package_root(dir: &Path) -> Result<PathBuf> {
+    if dir.join(MANIFEST_FILE).is_file() {
+        return Ok(dir.to_path_buf());
+    }
+    let entries: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    match entries.as_slice() {
+        [only] if only.is_dir() && only.join(MANIFEST_FILE).is_file() => Ok(only.clone()),
+        _ => Err(anyhow!("No {} at the top of the archive", MANIFEST_FILE)),
+    }
+}
+
+/// Extract a zip archive, returning the SHA-256 of each file by its path
+/// relative to `dest`
+fn extract_zip(content: &[u8], dest: &Path) -> Result<HashMap<PathBuf, String>> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(content))?;
+    let mut hashes = HashMap::new();
+    let mut total = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let Some(relative) = entry.enclosed_name() else {
+            return Err(anyhow!("Entry '{}' escapes the package", entry.name()));
+        };
+        if entry.is_symlink() {
+            return Err(anyhow!("Entry '{}' is a symlink", entry.name()));
+        }
+        let path = dest.join(&relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let (written, hash) = write_limited(&mut entry, &path, MAX_UNPACKED_BYTES - total)?;
+        total += written;
+        hashes.insert(relative, hash);
+    }
+    Ok(hashes)
+}
+
+/// Like [`extract_zip`], for a gzipped tarball
+fn extract_tar_gz(content: &[u8], dest: &Path) -> Result<HashMap<PathBuf, String>> {
+    let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(content));
+    let mut hashes = HashMap::new();
+    let mut total = 0;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let relative = entry.path()?.into_owned();
+        if !is_relative_inside(&relative) {
+            return Err(anyhow!(
+                "Entry '{}' escapes the package",
+                relative.display()
+            ));
+        }
+        let path = dest.join(&relative);
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => std::fs::create_dir_all(&path)?,
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let (written, hash) = write_limited(&mut entry, &path, MAX_UNPACKED_BYTES - total)?;
+                total += written;
+                hashes.insert(relative, hash);
+            }
+            // Metadata entries such as pax headers carry no files
+            tar::EntryType::XGlobalHeader | tar::EntryType::XHeader => {}
+            other => {
+                return Err(anyhow!(
+                    "Entry '{}' has unsupported type {:?}",
+                    relative.display(),
+                    other
+                ));
+            }
+        }
+    }
+    Ok(hashes)
+}
+
+/// Write `reader` to `path`, failing if it holds more than `limit` bytes.
+/// Returns the size and SHA-256 of what was written.
+fn write_limited(reader: &mut impl Read, path: &Path, limit: u64) -> Result<(u64, String)> {
+    let mut content = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut content)?;
+    let written = content.len() as u64;
+    if written > limit {
+        return Err(anyhow!(
+            "Package unpacks to more than {} MB",
+            MAX_UNPACKED_BYTES / (1024 * 1024)
+        ));
+    }
+    std::fs::write(path, &content)?;
+    Ok((written, sha256(&content)))
+}
+
+/// Whether `path` is relative and stays inside the directory it is joined to
+fn is_relative_inside(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Write a zip archive of `files`, for tests
+#[cfg(test)]
+pub(crate) fn write_zip(path: &Path, files: &[(&str, &str)]) {
+    use std::io::Write;
+
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    for (name, content) in files {
+        zip.start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    const MANIFEST: &str = "name = \"pokemon\"\nversion = \"1.0.0\"\n";
+
+    fn write_tar_gz(path: &Path, files: &[(&str, &str)]) {
+        let gz = flate2::write::GzEncoder::new(
+            File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_is_package() {
+        assert!(is_package(Path::new("exts/pokemon.zip")));
+        assert!(is_package(Path::new("exts/pokemon.tar.gz")));
+        assert!(is_package(Path::new("exts/pokemon.TGZ")));
+        assert!(!is_package(Path::new("exts/pokemon.star")));
+        assert!(!is_package(Path::new("exts/pokemon.tar")));
+    }
+
+    #[test]
+    fn test_unpack_zip() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let archive = dir.path().join("pokemon.zip");
+        write_zip(
+            &archive,
+            &[
+                ("package.toml", MANIFEST),
+                ("pokemon.star", "def describe_extension(): pass\n"),
+                ("pokemon_test.star", ""),
+                ("lib/util.star", ""),
+                ("data/items.json", "[]"),
+            ],
+        );
+        let cache = PackageCache::new(dir.path().join("unpacked"));
+
+        let package = cache
+            .unpack(&archive, &std::fs::read(&archive).unwrap())
+            .unwrap();
+        assert_eq!(package.manifest.name, "pokemon");
+        assert!(package.root.join("data/items.json").is_file());
+        // Named after the archive's path with a hash that doesn't change
+        // between builds
+        let path_digest = sha256(package.archive.as_os_str().as_encoded_bytes());
+        assert_eq!(
+            package.root.file_name().unwrap().to_string_lossy(),
+            format!("pokemon-{}", &path_digest[..16])
+        );
+        assert_eq!(
+            package.extensions().unwrap(),
+            vec![package.root.join("pokemon.star")]
+        );
+        assert_eq!(
+            cache.root_of(&package.root.join("lib/util.star")),
+            Some(package.root.clone())
+        );
+        assert_eq!(
+            package.files.get(&package.root.join("data/items.json")),
+            Some(&sha256(b"[]"))
+        );
+
+        // Unchanged archives aren't unpacked again
+        std::fs::remove_file(package.root.join("lib/util.star")).unwrap();
+        cache
+            .unpack(&archive, &std::fs::read(&archive).unwrap())
+            .unwrap();
+        assert!(!package.root.join("lib/util.star").exists());
+
+        assert_eq!(cache.remove(&package.archive), Some(package.root.clone()));
+        assert!(!package.root.exists());
+    }
+
+    #[test]
+    fn test_unpack_tar_gz_with_top_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let archive = dir.path().join("pokemon.tar.gz");
+        write_tar_gz(
+            &archive,
+            &[
+                (
+                    "pokemon/package.toml",
+                    "name = \"pokemon\"\nversion = \"1.0.0\"\nextensions = [\"tools/items.star\"]\n",
+                ),
+                ("pokemon/tools/items.star", ""),
+            ],
+        );
+
+        let package = PackageCache::new(dir.path().join("unpacked"))
+            .unpack(&archive, &std::fs::read(&archive).unwrap())
+            .unwrap();
+        assert!(package.root.ends_with("pokemon"));
+        assert_eq!(
+            package.extensions().unwrap(),
+            vec![package.root.join("tools/items.star")]
+        );
+    }
+
+    #[test]
+    fn test_invalid_packages() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let cache = PackageCache::new(dir.path().join("unpacked"));
+
+        let archive = dir.path().join("empty.zip");
+        write_zip(&archive, &[("pokemon.star", "")]);
+        let err = cache
+            .unpack(&archive, &std::fs::read(&archive).unwrap())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("No package.toml at the top"),
+            "{}",
+            err
+        );
+
+        let archive = dir.path().join("missing.zip");
+        write_zip(
+            &archive,
+            &[(
+                "package.toml",
+                "name = \"x\"\nversion = \"1\"\nextensions = [\"../x.star\"]\n",
+            )],
+        );
+        let err = cache
+            .unpack(&archive, &std::fs::read(&archive).unwrap())
+            .unwrap()
+            .extensions()
+            .unwrap_err();
+        assert!(err.to_string().contains("invalid extension '../x.star'"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let archive = dir.path().join("pokemon.zip");
+        write_zip(&archive, &[("package.toml", MANIFEST)]);
+
+        let cache = PackageCache::private();
+        let package = cache
+            .unpack(&archive, &std::fs::read(&archive).unwrap())
+            .unwrap();
+        let unpacked_under = package.root.parent().unwrap();
+        let mode = std::fs::metadata(unpacked_under)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+        assert!(!unpacked_under.starts_with(dir.path()));
+
+        let unpacked_under = unpacked_under.to_path_buf();
+        drop(cache);
+        assert!(!unpacked_under.exists());
+    }
+}
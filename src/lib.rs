@@ -1,5 +1,9 @@
+pub mod check;
+pub mod config;
 pub mod extensions;
+pub mod lockfile;
 pub mod mcp;
+pub mod signing;
 pub mod starlark;
 pub mod testing;
 
@@ -139,13 +143,195 @@ def describe_extension():
         let tool_result = result.unwrap();
         assert_eq!(tool_result.content.len(), 1);
 
-        if let Some(mcp::ToolContent::Text { text }) = tool_result.content.get(0) {
+        if let Some(mcp::ToolContent::Text { text }) = tool_result.content.first() {
             assert_eq!(text, "Hello, world!");
         } else {
             panic!("Expected text content in tool result");
         }
     }
 
+    #[tokio::test]
+    async fn test_tool_step_limit_returns_error_result() {
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+
+        let extension_content = r#"
+def spin(params):
+    total = 0
+    for i in range(100000000):
+        total += i
+    return {"content": [{"type": "text", "text": str(total)}]}
+
+def describe_extension():
+    return Extension(
+        name = "spinner",
+        version = "1.0.0",
+        description = "Runs forever",
+        tools = [
+            Tool(
+                name = "spin",
+                description = "Spins",
+                handler = spin,
+                max_steps = 1000,
+            ),
+        ],
+    )
+"#;
+
+        engine
+            .load_extension("spinner", extension_content)
+            .await
+            .unwrap();
+
+        let result = executor
+            .execute_tool("spin", serde_json::json!({}))
+            .await
+            .unwrap();
+
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            result.structured_content.unwrap()["limit"],
+            serde_json::json!("max_steps")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_stops_handler() {
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+
+        let extension_content = r#"
+def spin(params):
+    total = 0
+    for i in range(100000000):
+        total += i
+    return {"content": [{"type": "text", "text": str(total)}]}
+
+def describe_extension():
+    return Extension(
+        name = "spinner",
+        version = "1.0.0",
+        description = "Runs until cancelled",
+        tools = [Tool(name = "spin", description = "Spins", handler = spin)],
+    )
+"#;
+
+        engine
+            .load_extension("spinner", extension_content)
+            .await
+            .unwrap();
+
+        let token = tokio_util::sync::CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+
+        let result = executor
+            .execute_tool_for_request("spin", serde_json::json!({}), token, None)
+            .await
+            .unwrap();
+
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            result.structured_content.unwrap()["error"],
+            serde_json::json!("cancelled")
+        );
+    }
+
+    const SLEEPER_EXTENSION: &str = r#"
+def nap(params):
+    result = exec.run("sleep", ["0.5"])
+    return {"content": [{"type": "text", "text": str(result["success"])}]}
+
+def describe_extension():
+    return Extension(
+        name = "sleeper",
+        version = "1.0.0",
+        description = "Blocks for a while",
+        allowed_exec = ["sleep"],
+        tools = [Tool(name = "nap", description = "Sleeps", handler = nap)],
+    )
+"#;
+
+    #[tokio::test]
+    async fn test_tool_calls_run_concurrently() {
+        let executor = ToolExecutor::new().with_max_concurrency(4);
+        executor
+            .engine()
+            .load_extension("sleeper", SLEEPER_EXTENSION)
+            .await
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        let (a, b, c) = tokio::join!(
+            tool_text(&executor, "nap"),
+            tool_text(&executor, "nap"),
+            tool_text(&executor, "nap"),
+        );
+        assert_eq!(
+            (a.as_str(), b.as_str(), c.as_str()),
+            ("True", "True", "True")
+        );
+        assert!(
+            started.elapsed() < std::time::Duration::from_millis(1400),
+            "calls ran serially: {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dropped_call_keeps_its_slot_until_the_handler_finishes() {
+        let executor = ToolExecutor::new().with_max_concurrency(1);
+        executor
+            .engine()
+            .load_extension("sleeper", SLEEPER_EXTENSION)
+            .await
+            .unwrap();
+
+        let abandoned = tokio::spawn({
+            let executor = executor.clone();
+            async move { tool_text(&executor, "nap").await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        abandoned.abort();
+
+        // The first handler is still sleeping, so this call waits for it
+        let started = std::time::Instant::now();
+        assert_eq!(tool_text(&executor, "nap").await, "True");
+        assert!(
+            started.elapsed() > std::time::Duration::from_millis(750),
+            "calls overlapped: {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reload_is_not_blocked_by_running_tool() {
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+        engine
+            .load_extension("sleeper", SLEEPER_EXTENSION)
+            .await
+            .unwrap();
+
+        let running = tokio::spawn({
+            let executor = executor.clone();
+            async move { tool_text(&executor, "nap").await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let started = std::time::Instant::now();
+        engine
+            .load_extension("sleeper", SLEEPER_EXTENSION)
+            .await
+            .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(300));
+
+        assert_eq!(running.await.unwrap(), "True");
+    }
+
     #[tokio::test]
     async fn test_extension_loader_handles_missing_directory() {
         let engine = StarlarkEngine::new();
@@ -155,6 +341,1203 @@ def describe_extension():
         assert!(result.is_ok(), "Should handle missing directory gracefully");
     }
 
+    #[tokio::test]
+    async fn test_extension_can_load_shared_library() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("lib")).unwrap();
+        std::fs::write(
+            temp_dir.path().join("lib/common.star"),
+            "def shout(text):\n    return text.upper()\n",
+        )
+        .unwrap();
+        std::fs::write(
+            temp_dir.path().join("shouter.star"),
+            r#"
+load("//lib/common.star", "shout")
+
+def shout_handler(params):
+    return {"content": [{"type": "text", "text": shout(params.get("text", ""))}]}
+
+def describe_extension():
+    return Extension(
+        name = "shouter",
+        version = "1.0.0",
+        description = "Uses a shared helper",
+        tools = [Tool(name = "shout", description = "Shout", handler = shout_handler)],
+    )
+"#,
+        )
+        .unwrap();
+
+        let dir = temp_dir.path().to_str().unwrap().to_string();
+        let executor = ToolExecutor::new().with_extensions_dir(dir.clone());
+        let loader = ExtensionLoader::new(dir);
+        loader.load_all(&executor.engine(), false).await.unwrap();
+
+        let extensions = executor.engine().get_all_extensions().await;
+        assert_eq!(
+            extensions.len(),
+            1,
+            "lib/ files must not load as extensions"
+        );
+
+        let result = executor
+            .execute_tool("shout", serde_json::json!({"text": "hi"}))
+            .await
+            .unwrap();
+        if let Some(mcp::ToolContent::Text { text }) = result.content.first() {
+            assert_eq!(text, "HI");
+        } else {
+            panic!("Expected text content in tool result");
+        }
+    }
+
+    fn write_shared_lib_extensions(dir: &std::path::Path) {
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib/common.star"),
+            "PREFIX = \"v1\"\n\ndef tag(text):\n    return PREFIX + \":\" + text\n",
+        )
+        .unwrap();
+        for name in ["alpha", "beta"] {
+            std::fs::write(
+                dir.join(format!("{}.star", name)),
+                format!(
+                    r#"
+load("//lib/common.star", "tag")
+
+def handler(params):
+    return {{"content": [{{"type": "text", "text": tag("{name}")}}]}}
+
+def describe_extension():
+    return Extension(
+        name = "{name}",
+        version = "1.0.0",
+        description = "Shared lib user",
+        tools = [Tool(name = "{name}_tool", description = "Tag", handler = handler)],
+    )
+"#
+                ),
+            )
+            .unwrap();
+        }
+    }
+
+    /// An extension whose only tool, `<tool>`, answers with `text`, optionally
+    /// passed through `tag()` from `//lib/common.star`
+    fn tagged_extension(name: &str, tool: &str, text: &str, use_lib: bool) -> String {
+        let (load, body) = if use_lib {
+            (
+                "load(\"//lib/common.star\", \"tag\")\n",
+                format!("tag(\"{}\")", text),
+            )
+        } else {
+            ("", format!("\"{}\"", text))
+        };
+        format!(
+            r#"{load}
+def handler(params):
+    return {{"content": [{{"type": "text", "text": {body}}}]}}
+
+def describe_extension():
+    return Extension(
+        name = "{name}",
+        version = "1.0.0",
+        description = "Test extension",
+        tools = [Tool(name = "{tool}", description = "Tool", handler = handler)],
+    )
+"#
+        )
+    }
+
+    #[tokio::test]
+    async fn test_earlier_root_takes_precedence() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let project = temp_dir.path().join("project");
+        let shared = temp_dir.path().join("shared");
+        std::fs::create_dir_all(&project).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+        std::fs::write(
+            project.join("deploy.star"),
+            tagged_extension("deploy", "deploy", "project", false),
+        )
+        .unwrap();
+        std::fs::write(
+            shared.join("deploy.star"),
+            tagged_extension("deploy", "deploy", "shared", false),
+        )
+        .unwrap();
+        std::fs::write(
+            shared.join("lint.star"),
+            tagged_extension("lint", "lint", "shared", false),
+        )
+        .unwrap();
+
+        let executor = ToolExecutor::new()
+            .with_extensions_dir(&project)
+            .with_extensions_dir(&shared);
+        let loader = ExtensionLoader::new(&project).with_dir(&shared);
+
+        let discovered = loader.discover(false);
+        let names: Vec<&str> = discovered.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["deploy", "lint"]);
+        assert_eq!(discovered[0].root, project);
+
+        loader.load_all(&executor.engine(), false).await.unwrap();
+        assert_eq!(tool_text(&executor, "deploy").await, "project");
+        assert_eq!(tool_text(&executor, "lint").await, "shared");
+    }
+
+    #[tokio::test]
+    async fn test_recursive_discovery_with_ignore_patterns() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        write_shared_lib_extensions(root);
+        for dir in ["team/ops", "team/ops/old", "drafts", ".cache"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::fs::write(
+            root.join("team/ops/status.star"),
+            tagged_extension("status", "status", "ops", true),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("team/ops/old/status.star"),
+            tagged_extension("status", "status", "old", true),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("drafts/wip.star"),
+            tagged_extension("wip", "wip", "draft", false),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join(".cache/hidden.star"),
+            tagged_extension("hidden", "hidden", "hidden", false),
+        )
+        .unwrap();
+
+        let loader = ExtensionLoader::new(root)
+            .with_ignore(&["drafts".to_string()])
+            .unwrap();
+        let mut names: Vec<String> = loader.discover(false).into_iter().map(|d| d.name).collect();
+        names.sort();
+        // lib/common.star defines no extension, so it is only reachable via load()
+        assert_eq!(names, vec!["alpha", "beta", "status"]);
+
+        let executor = ToolExecutor::new().with_extensions_dir(root);
+        loader.load_all(&executor.engine(), false).await.unwrap();
+        // The shallower copy wins, and `//` resolves from the root
+        assert_eq!(tool_text(&executor, "status").await, "v1:ops");
+    }
+
+    #[tokio::test]
+    async fn test_tool_name_collision_rejected() {
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+
+        engine
+            .load_extension("one", &tagged_extension("one", "list", "one", false))
+            .await
+            .unwrap();
+        let err = engine
+            .load_extension("two", &tagged_extension("two", "list", "two", false))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("conflicts"), "got: {}", err);
+        assert!(engine.get_extension("two").await.is_none());
+        assert_eq!(tool_text(&executor, "list").await, "one");
+
+        // Reloading the owner is not a collision
+        engine
+            .load_extension("one", &tagged_extension("one", "list", "one v2", false))
+            .await
+            .unwrap();
+        assert_eq!(tool_text(&executor, "list").await, "one v2");
+
+        let duplicate = r#"
+def handler(params):
+    return {"content": []}
+
+def describe_extension():
+    return Extension(
+        name = "dup",
+        version = "1.0.0",
+        description = "Test extension",
+        tools = [
+            Tool(name = "same", description = "Tool", handler = handler),
+            Tool(name = "same", description = "Tool", handler = handler),
+        ],
+    )
+"#;
+        let err = engine.load_extension("dup", duplicate).await.unwrap_err();
+        assert!(err.to_string().contains("more than once"), "got: {}", err);
+    }
+
+    #[tokio::test]
+    async fn test_namespaced_tools() {
+        let executor =
+            ToolExecutor::new().with_engine(StarlarkEngine::new().with_namespaced_tools(true));
+        let engine = executor.engine();
+
+        for name in ["one", "two"] {
+            engine
+                .load_extension(name, &tagged_extension(name, "list", name, false))
+                .await
+                .unwrap();
+        }
+
+        let names: Vec<String> = engine
+            .get_all_tools()
+            .await
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["one__list", "two__list"]);
+        assert_eq!(tool_text(&executor, "one__list").await, "one");
+        assert_eq!(tool_text(&executor, "two__list").await, "two");
+        assert!(
+            executor
+                .execute_tool("list", serde_json::json!({}))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics_report_loaded_and_failed_extensions() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::write(
+            root.join("good.star"),
+            tagged_extension("good", "good", "ok", false),
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("broken.star"),
+            "def describe_extension():\n    return undefined_name\n",
+        )
+        .unwrap();
+
+        let engine = StarlarkEngine::new().with_extensions_dir(root);
+        assert!(engine.diagnostics().await.last_reload.is_none());
+        ExtensionLoader::new(root)
+            .load_all(&engine, false)
+            .await
+            .unwrap();
+
+        let diagnostics = engine.diagnostics().await;
+        assert_eq!(diagnostics.loaded.len(), 1);
+        assert_eq!(diagnostics.loaded[0].name, "good");
+        assert_eq!(diagnostics.loaded[0].tools, 1);
+        assert!(diagnostics.last_reload.is_some());
+
+        assert_eq!(diagnostics.failed.len(), 1);
+        let failure = &diagnostics.failed[0];
+        assert_eq!(failure.name, "broken");
+        assert!(
+            failure.error.contains("undefined_name"),
+            "{}",
+            failure.error
+        );
+        assert!(!failure.serving_previous_version);
+        let span = failure.span.as_ref().unwrap();
+        assert_eq!((span.line, span.column), (2, 12));
+        assert!(diagnostics.to_text().contains("Failed extensions (1)"));
+
+        // Fixing the file clears the failure
+        let fixed = tagged_extension("broken", "fixed", "ok", false);
+        engine
+            .load_extension_with_path("broken", &fixed, Some(&root.join("broken.star")))
+            .await
+            .unwrap();
+        let diagnostics = engine.diagnostics().await;
+        assert!(diagnostics.failed.is_empty());
+        assert_eq!(diagnostics.loaded.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_typecheck_rejects_builtin_misuse() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(
+            root.join("lib/fetch.star"),
+            "def fetch(url: str) -> dict[str, typing.Any]:\n    return http.get(url, 1)\n",
+        )
+        .unwrap();
+        let extension = r#"
+load("//lib/fetch.star", "fetch")
+
+def handler(params):
+    return {"content": [{"type": "text", "text": str(fetch(params["url"]))}]}
+
+def describe_extension():
+    return Extension(
+        name = "weather",
+        version = "1.0.0",
+        description = "Test extension",
+        tools = [Tool(name = "weather", description = "Tool", handler = handler)],
+    )
+"#;
+        let path = root.join("weather.star");
+        std::fs::write(&path, extension).unwrap();
+
+        // Without --typecheck, annotations don't parse
+        let engine = StarlarkEngine::new().with_extensions_dir(root);
+        let err = engine
+            .load_extension_with_path("weather", extension, Some(&path))
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Parse error"),
+            "got: {:#}",
+            err
+        );
+
+        let engine = StarlarkEngine::new()
+            .with_extensions_dir(root)
+            .with_typecheck(true)
+            .unwrap();
+        let err = engine
+            .load_extension_with_path("weather", extension, Some(&path))
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("Type error"), "got: {}", message);
+        assert!(message.contains("fetch.star:2:"), "got: {}", message);
+
+        std::fs::write(
+            root.join("lib/fetch.star"),
+            "def fetch(url: str) -> dict[str, typing.Any]:\n    return http.get(url, {\"units\": \"metric\"})\n",
+        )
+        .unwrap();
+        engine
+            .load_extension_with_path("weather", extension, Some(&path))
+            .await
+            .unwrap();
+    }
+
+    async fn tool_text(executor: &ToolExecutor, tool: &str) -> String {
+        let result = executor
+            .execute_tool(tool, serde_json::json!({}))
+            .await
+            .unwrap();
+        match result.content.first() {
+            Some(mcp::ToolContent::Text { text }) => text.clone(),
+            None => panic!("Expected text content in tool result"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handler_error_backtrace() {
+        use crate::starlark::backtrace::ErrorVerbosity;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(
+            root.join("lib/shell.star"),
+            "def run(command):\n    return exec.run(command, [])\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("deploy.star"),
+            r#"load("//lib/shell.star", "run")
+
+def deploy(params):
+    result = run("rm")
+    return {"content": [{"type": "text", "text": result["stdout"]}]}
+
+def describe_extension():
+    return Extension(
+        name = "deploy",
+        version = "1.0.0",
+        description = "Deploys",
+        tools = [Tool(name = "deploy", description = "Deploy", handler = deploy)],
+    )
+"#,
+        )
+        .unwrap();
+
+        let executor = ToolExecutor::new().with_extensions_dir(root);
+        ExtensionLoader::new(root)
+            .load_all(&executor.engine(), false)
+            .await
+            .unwrap();
+
+        let text = tool_text(&executor, "deploy").await;
+        assert!(
+            text.starts_with("Error: tool 'deploy' failed: "),
+            "{}",
+            text
+        );
+        assert!(text.ends_with("(lib/shell.star:2:12)"), "{}", text);
+        assert!(!text.contains("Traceback"), "{}", text);
+
+        let result = executor
+            .execute_tool("deploy", serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["error"], "handler_error");
+        let frames = structured["frames"].as_array().unwrap();
+        let locations: Vec<(&str, &str, u64, u64)> = frames
+            .iter()
+            .map(|f| {
+                (
+                    f["function"].as_str().unwrap(),
+                    f["file"].as_str().unwrap(),
+                    f["line"].as_u64().unwrap(),
+                    f["column"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                ("deploy", "deploy", 4, 14),
+                ("run", "lib/shell.star", 2, 12),
+            ]
+        );
+        assert_eq!(frames[0]["source"], "result = run(\"rm\")");
+        assert_eq!(
+            frames[1]["path"].as_str().map(std::path::PathBuf::from),
+            Some(root.join("lib/shell.star"))
+        );
+
+        executor.set_error_verbosity(ErrorVerbosity::Backtrace);
+        let text = tool_text(&executor, "deploy").await;
+        assert!(
+            text.contains(
+                "Traceback (most recent call last):\n  \
+                 deploy:4:14, in deploy\n    \
+                 result = run(\"rm\")\n  \
+                 lib/shell.star:2:12, in run\n    \
+                 return exec.run(command, [])"
+            ),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_library_change_reloads_dependent_extensions() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        write_shared_lib_extensions(temp_dir.path());
+
+        let dir = temp_dir.path().to_str().unwrap().to_string();
+        let executor = ToolExecutor::new().with_extensions_dir(dir.clone());
+        let engine = executor.engine();
+        ExtensionLoader::new(dir)
+            .load_all(&engine, false)
+            .await
+            .unwrap();
+        assert_eq!(tool_text(&executor, "alpha_tool").await, "v1:alpha");
+
+        let lib = temp_dir.path().join("lib/common.star");
+        std::fs::write(
+            &lib,
+            "PREFIX = \"v2\"\n\ndef tag(text):\n    return PREFIX + \":\" + text\n",
+        )
+        .unwrap();
+
+        let reloaded = engine.reload_dependents(&lib).await.unwrap();
+        let mut names: Vec<_> = reloaded.iter().map(|e| e.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, vec!["alpha", "beta"]);
+
+        assert_eq!(tool_text(&executor, "alpha_tool").await, "v2:alpha");
+        assert_eq!(tool_text(&executor, "beta_tool").await, "v2:beta");
+    }
+
+    #[tokio::test]
+    async fn test_failed_dependent_reload_keeps_previous_versions() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        write_shared_lib_extensions(temp_dir.path());
+
+        let dir = temp_dir.path().to_str().unwrap().to_string();
+        let executor = ToolExecutor::new().with_extensions_dir(dir.clone());
+        let engine = executor.engine();
+        ExtensionLoader::new(dir)
+            .load_all(&engine, false)
+            .await
+            .unwrap();
+
+        // Dropping `tag` breaks every extension that loads it
+        let lib = temp_dir.path().join("lib/common.star");
+        std::fs::write(&lib, "PREFIX = \"v2\"\n").unwrap();
+
+        let result = engine.reload_dependents(&lib).await;
+        assert!(
+            result.is_err(),
+            "Reload should fail when a dependent breaks"
+        );
+
+        assert_eq!(tool_text(&executor, "alpha_tool").await, "v1:alpha");
+        assert_eq!(tool_text(&executor, "beta_tool").await, "v1:beta");
+    }
+
+    fn counter_extension(marker: &std::path::Path) -> String {
+        format!(
+            r#"
+def on_load():
+    state.set("greeting", "hello")
+
+def on_unload():
+    exec.run("touch", ["{marker}"])
+
+def count(params):
+    calls = state.get("calls", 0) + 1
+    state.set("calls", calls)
+    return {{"content": [{{"type": "text", "text": "%s %d" % (state.get("greeting"), calls)}}]}}
+
+def describe_extension():
+    return Extension(
+        name = "counter",
+        version = "1.0.0",
+        description = "Counts calls",
+        allowed_exec = ["touch"],
+        tools = [Tool(name = "count", description = "Count", handler = count)],
+    )
+"#,
+            marker = marker.display()
+        )
+    }
+
+    #[tokio::test]
+    async fn test_extension_state_persists_between_calls() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+        engine
+            .load_extension("counter", &counter_extension(&temp.path().join("unloaded")))
+            .await
+            .unwrap();
+
+        assert_eq!(tool_text(&executor, "count").await, "hello 1");
+        assert_eq!(tool_text(&executor, "count").await, "hello 2");
+    }
+
+    #[tokio::test]
+    async fn test_reload_drops_state_and_calls_on_unload() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let marker = temp.path().join("unloaded");
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+        let content = counter_extension(&marker);
+
+        engine.load_extension("counter", &content).await.unwrap();
+        assert_eq!(tool_text(&executor, "count").await, "hello 1");
+        assert!(!marker.exists());
+
+        engine.load_extension("counter", &content).await.unwrap();
+        assert!(marker.exists(), "on_unload should run for the old version");
+        assert_eq!(tool_text(&executor, "count").await, "hello 1");
+
+        std::fs::remove_file(&marker).unwrap();
+        engine.remove_extension("counter").await.unwrap();
+        assert!(marker.exists(), "on_unload should run on removal");
+    }
+
+    #[tokio::test]
+    async fn test_failing_on_load_keeps_previous_version() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+        engine
+            .load_extension("counter", &counter_extension(&temp.path().join("unloaded")))
+            .await
+            .unwrap();
+
+        let broken = r#"
+def on_load():
+    fail("cannot connect")
+
+def count(params):
+    return {"content": [{"type": "text", "text": "broken"}]}
+
+def describe_extension():
+    return Extension(
+        name = "counter",
+        version = "2.0.0",
+        description = "Broken",
+        tools = [Tool(name = "count", description = "Count", handler = count)],
+    )
+"#;
+        let err = engine.load_extension("counter", broken).await.unwrap_err();
+        assert!(err.to_string().contains("on_load() failed"));
+        assert_eq!(tool_text(&executor, "count").await, "hello 1");
+    }
+
+    #[tokio::test]
+    async fn test_kv_survives_reload() {
+        let engine =
+            StarlarkEngine::new().with_kv_store(starlark::kv::KvStore::in_memory().unwrap());
+        let executor = ToolExecutor::new().with_engine(engine);
+        let engine = executor.engine();
+
+        let content = r#"
+def visit(params):
+    return {"content": [{"type": "text", "text": str(kv.incr("visits"))}]}
+
+def describe_extension():
+    return Extension(
+        name = "visits",
+        version = "1.0.0",
+        description = "Counts visits",
+        tools = [Tool(name = "visit", description = "Visit", handler = visit)],
+    )
+"#;
+
+        engine.load_extension("visits", content).await.unwrap();
+        assert_eq!(tool_text(&executor, "visit").await, "1");
+
+        engine.load_extension("visits", content).await.unwrap();
+        assert_eq!(tool_text(&executor, "visit").await, "2");
+    }
+
+    #[tokio::test]
+    async fn test_reload_clears_cache() {
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+
+        let versioned = |version: &str| {
+            format!(
+                r#"
+def lookup(params):
+    value = cache.get_or_compute("answer", lambda: "{version}", ttl_secs = None)
+    return {{"content": [{{"type": "text", "text": value}}]}}
+
+def describe_extension():
+    return Extension(
+        name = "cached",
+        version = "1.0.0",
+        description = "Caches a value",
+        tools = [Tool(name = "lookup", description = "Lookup", handler = lookup)],
+    )
+"#
+            )
+        };
+
+        engine
+            .load_extension("cached", &versioned("v1"))
+            .await
+            .unwrap();
+        assert_eq!(tool_text(&executor, "lookup").await, "v1");
+
+        engine
+            .load_extension("cached", &versioned("v2"))
+            .await
+            .unwrap();
+        assert_eq!(tool_text(&executor, "lookup").await, "v2");
+    }
+
+    #[tokio::test]
+    async fn test_missing_required_config_disables_tools() {
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+
+        let extension_content = r#"
+def call_api(params):
+    return {"content": [{"type": "text", "text": config.get("STARLARK_MCP_TEST_UNSET_KEY")}]}
+
+def describe_extension():
+    return Extension(
+        name = "needs_key",
+        version = "1.0.0",
+        description = "Needs an API key",
+        config = [ConfigVar(name = "STARLARK_MCP_TEST_UNSET_KEY", required = True, secret = True)],
+        tools = [Tool(name = "call_api", description = "Call the API", handler = call_api)],
+    )
+"#;
+
+        let extension = engine
+            .load_extension("needs_key", extension_content)
+            .await
+            .unwrap();
+        assert_eq!(
+            extension.missing_config,
+            vec!["STARLARK_MCP_TEST_UNSET_KEY"]
+        );
+        assert!(extension.to_mcp_tools().is_empty());
+
+        let result = executor
+            .execute_tool("call_api", serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(
+            result.structured_content.unwrap()["error"],
+            "missing_config"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_secret_config_is_redacted() {
+        let executor = ToolExecutor::new();
+        let engine = executor.engine();
+
+        // cargo sets CARGO_PKG_NAME when running tests
+        let secret = std::env::var("CARGO_PKG_NAME").unwrap();
+        let extension_content = r#"
+def echo(params):
+    key = config.get("CARGO_PKG_NAME")
+    if params.get("fail"):
+        fail("request rejected for key " + key)
+    return {
+        "content": [{"type": "text", "text": "key=" + key}],
+        "structuredContent": {"key": key, "region": config.get("REGION")},
+    }
+
+def describe_extension():
+    return Extension(
+        name = "secretive",
+        version = "1.0.0",
+        description = "Echoes its secret",
+        config = [
+            ConfigVar(name = "CARGO_PKG_NAME", required = True, secret = True),
+            ConfigVar(name = "REGION", default = "us-east-1"),
+        ],
+        tools = [Tool(name = "echo", description = "Echo", handler = echo)],
+    )
+"#;
+
+        let extension = engine
+            .load_extension("secretive", extension_content)
+            .await
+            .unwrap();
+        assert!(extension.is_configured());
+        assert_eq!(extension.to_mcp_tools().len(), 1);
+
+        let result = executor
+            .execute_tool("echo", serde_json::json!({}))
+            .await
+            .unwrap();
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(!json.contains(&secret));
+        assert!(json.contains("key=[REDACTED]"));
+        assert_eq!(result.structured_content.unwrap()["region"], "us-east-1");
+
+        let result = executor
+            .execute_tool("echo", serde_json::json!({"fail": true}))
+            .await
+            .unwrap();
+        assert_eq!(result.is_error, Some(true));
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("request rejected for key [REDACTED]"));
+        assert!(!json.contains(&secret));
+
+        // A failed reload is reported without the secrets of the version
+        // still loaded
+        let broken = format!("fail(\"rejected key {}\")\n", secret);
+        assert!(engine.load_extension("secretive", &broken).await.is_err());
+        let diagnostics = engine.diagnostics().await;
+        let error = &diagnostics.failed[0].error;
+        assert!(error.contains("rejected key [REDACTED]"), "{}", error);
+        assert!(!error.contains(&secret));
+    }
+
+    const CONFIGURABLE_EXTENSION: &str = r#"
+def region(params):
+    return {"content": [{"type": "text", "text": config.get("REGION") + "/" + env.get("ZONE", "none")}]}
+
+def run(params):
+    result = exec.run("echo", ["hi"])
+    return {"content": [{"type": "text", "text": str(result["success"])}]}
+
+def drop_all(params):
+    return {"content": [{"type": "text", "text": "dropped"}]}
+
+def describe_extension():
+    return Extension(
+        name = "configurable",
+        version = "1.0.0",
+        description = "Reads server-provided settings",
+        config = [ConfigVar(name = "REGION", required = True)],
+        tools = [
+            Tool(name = "region", description = "Region", handler = region),
+            Tool(name = "run", description = "Run", handler = run),
+            Tool(name = "drop_all", description = "Drop everything", handler = drop_all),
+        ],
+    )
+"#;
+
+    #[tokio::test]
+    async fn test_server_settings_applied_at_load() {
+        let settings: starlark::config::ExtensionSettings = toml::from_str(
+            r#"
+disabled_tools = ["drop_all"]
+allowed_exec = ["echo"]
+env = { REGION = "eu-west-1", ZONE = "b" }
+"#,
+        )
+        .unwrap();
+        let engine = StarlarkEngine::new().with_settings(
+            [("configurable".to_string(), settings)]
+                .into_iter()
+                .collect(),
+        );
+        let executor = ToolExecutor::new().with_engine(engine);
+
+        let extension = executor
+            .engine()
+            .load_extension("configurable", CONFIGURABLE_EXTENSION)
+            .await
+            .unwrap();
+        assert!(extension.is_configured());
+        assert_eq!(extension.allowed_exec, vec!["echo"]);
+        let names: Vec<String> = extension
+            .to_mcp_tools()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(names, vec!["region", "run"]);
+
+        assert_eq!(tool_text(&executor, "region").await, "eu-west-1/b");
+        assert_eq!(tool_text(&executor, "run").await, "True");
+        assert!(
+            executor
+                .execute_tool("drop_all", serde_json::json!({}))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disabling_extension_unloads_it() {
+        let engine = StarlarkEngine::new().with_settings(
+            [(
+                "configurable".to_string(),
+                toml::from_str("env = { REGION = \"us\" }").unwrap(),
+            )]
+            .into_iter()
+            .collect(),
+        );
+        engine
+            .load_extension("configurable", CONFIGURABLE_EXTENSION)
+            .await
+            .unwrap();
+
+        let removed = engine
+            .apply_settings(
+                [(
+                    "configurable".to_string(),
+                    toml::from_str("enabled = false").unwrap(),
+                )]
+                .into_iter()
+                .collect(),
+            )
+            .await;
+        assert_eq!(removed, vec!["configurable"]);
+        assert!(engine.get_extension("configurable").await.is_none());
+
+        let err = engine
+            .load_extension("configurable", CONFIGURABLE_EXTENSION)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("disabled"));
+    }
+
+    #[tokio::test]
+    async fn test_requires_limits_modules() {
+        let extension = r#"
+def remember(params):
+    kv.set("city", "Paris")
+    return {"content": [{"type": "text", "text": kv.get("city")}]}
+
+def fetch(params):
+    return {"content": [{"type": "text", "text": http.get("https://example.com")["body"]}]}
+
+def describe_extension():
+    return Extension(
+        name = "scoped",
+        version = "1.0.0",
+        description = "Only uses kv",
+        requires = ["kv"],
+        tools = [
+            Tool(name = "remember", description = "Remember", handler = remember),
+            Tool(name = "fetch", description = "Fetch", handler = fetch),
+        ],
+    )
+"#;
+        let engine =
+            StarlarkEngine::new().with_kv_store(starlark::kv::KvStore::in_memory().unwrap());
+        let executor = ToolExecutor::new().with_engine(engine);
+        executor
+            .engine()
+            .load_extension("scoped", extension)
+            .await
+            .unwrap();
+
+        assert_eq!(tool_text(&executor, "remember").await, "Paris");
+        let text = tool_text(&executor, "fetch").await;
+        assert!(
+            text.contains(
+                "Extension 'scoped' cannot use the http module: add \"http\" to requires in its Extension()"
+            ),
+            "{}",
+            text
+        );
+
+        let err = executor
+            .engine()
+            .load_extension(
+                "scoped",
+                &extension.replace("requires = [\"kv\"],", "min_server_version = \"99.0\","),
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Extension 'scoped' requires starlark-mcp 99.0 or newer"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_one_exec_rule_per_command() {
+        let extension = r#"
+def get(params):
+    return {"content": [{"type": "text", "text": exec.run("kubectl", ["get", "pods"])["stdout"]}]}
+
+def describe_extension():
+    return Extension(
+        name = "kube",
+        version = "1.0.0",
+        description = "Reads the cluster",
+        allowed_exec = [
+            ExecRule("kubectl", subcommands = ["get"]),
+            ExecRule("kubectl", subcommands = ["describe"]),
+        ],
+        tools = [Tool(name = "get", description = "Get", handler = get)],
+    )
+"#;
+        let err = ToolExecutor::new()
+            .engine()
+            .load_extension("kube", extension)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains(
+                "allowed_exec lists 'kubectl' more than once; use a single ExecRule for it"
+            ),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exec_rules_checked_before_spawning() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let extension = r#"
+def touch(params):
+    result = exec.run("touch", [params["flag"], "DIR/" + params["name"]] if params.get("flag") else ["DIR/" + params["name"]])
+    return {"content": [{"type": "text", "text": str(result["success"])}]}
+
+def describe_extension():
+    return Extension(
+        name = "ruled",
+        version = "1.0.0",
+        description = "Touches marker files",
+        allowed_exec = [ExecRule("touch", forbidden_flags = ["-r", "--reference"], args = ["*.ok"])],
+        tools = [Tool(name = "touch", description = "Touch", handler = touch)],
+    )
+"#
+        .replace("DIR", temp_dir.path().to_str().unwrap());
+        let executor = ToolExecutor::new();
+        executor
+            .engine()
+            .load_extension("ruled", &extension)
+            .await
+            .unwrap();
+
+        let touch = |args: serde_json::Value| {
+            let executor = executor.clone();
+            async move {
+                let result = executor.execute_tool("touch", args).await.unwrap();
+                match result.content.first() {
+                    Some(mcp::ToolContent::Text { text }) => text.clone(),
+                    None => panic!("Expected text content in tool result"),
+                }
+            }
+        };
+        assert_eq!(touch(serde_json::json!({"name": "a.ok"})).await, "True");
+        assert!(temp_dir.path().join("a.ok").exists());
+
+        let text = touch(serde_json::json!({"name": "a.bad"})).await;
+        assert!(
+            text.contains(&format!(
+                "Command 'touch {}/a.bad' was rejected by its ExecRule: argument 0 '{}/a.bad' does not match '*.ok'",
+                temp_dir.path().display(),
+                temp_dir.path().display()
+            )),
+            "{}",
+            text
+        );
+        let text =
+            touch(serde_json::json!({"name": "b.ok", "flag": "--reference=/etc/passwd"})).await;
+        assert!(text.contains("flag '--reference' is forbidden"), "{}", text);
+        assert!(!temp_dir.path().join("a.bad").exists());
+        assert!(!temp_dir.path().join("b.ok").exists());
+
+        let err = executor
+            .engine()
+            .load_extension("ruled", &extension.replace("\"*.ok\"", "\"re:(\""))
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("ExecRule 'touch': Invalid regex '('"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_env_limited_to_allowed_env() {
+        let extension = r#"
+def read(params):
+    values = [
+        env.get("PLANE_WORKSPACE_SLUG"),
+        env.get("REGION"),
+        env.get("PLANE_API_KEY", "unset"),
+        env.get("PATH", "hidden"),
+    ]
+    return {"content": [{"type": "text", "text": ",".join(values)}]}
+
+def describe_extension():
+    return Extension(
+        name = "scoped",
+        version = "1.0.0",
+        description = "Only sees its own variables",
+        allowed_env = ["PLANE_*"],
+        tools = [Tool(name = "read", description = "Read", handler = read)],
+    )
+"#;
+        let settings: starlark::config::ExtensionSettings =
+            toml::from_str(r#"env = { PLANE_WORKSPACE_SLUG = "acme", REGION = "eu-west-1" }"#)
+                .unwrap();
+        let settings: std::collections::HashMap<_, _> =
+            [("scoped".to_string(), settings)].into_iter().collect();
+
+        // Injected variables are always visible; others fall back to the
+        // default
+        let engine = StarlarkEngine::new().with_settings(settings.clone());
+        let executor = ToolExecutor::new().with_engine(engine);
+        executor
+            .engine()
+            .load_extension("scoped", extension)
+            .await
+            .unwrap();
+        assert_eq!(
+            tool_text(&executor, "read").await,
+            "acme,eu-west-1,unset,hidden"
+        );
+
+        let engine = StarlarkEngine::new()
+            .with_settings(settings)
+            .with_strict_env(true);
+        let executor = ToolExecutor::new().with_engine(engine);
+        executor
+            .engine()
+            .load_extension("scoped", extension)
+            .await
+            .unwrap();
+        let text = tool_text(&executor, "read").await;
+        assert!(
+            text.contains(
+                "Extension 'scoped' cannot read environment variable 'PATH': add it to allowed_env in its Extension()"
+            ),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_limited_to_allowed_hosts() {
+        use std::io::{Read, Write};
+
+        // Redirects every request to localhost, which isn't an allowed host
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 302 Found\r\nLocation: http://localhost:{}/secret\r\n\
+                     Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    port
+                );
+            }
+        });
+
+        let extension = r#"
+def fetch(url):
+    return {"content": [{"type": "text", "text": str(http.get(url)["status"])}]}
+
+def outside(params):
+    return fetch("https://example.org/")
+
+def local(params):
+    return fetch("http://127.0.0.1:PORT/")
+
+def describe_extension():
+    return Extension(
+        name = "scoped",
+        version = "1.0.0",
+        description = "Only talks to a few hosts",
+        allowed_hosts = ["*.example.com", "127.0.0.1"],
+        tools = [
+            Tool(name = "outside", description = "Outside", handler = outside),
+            Tool(name = "local", description = "Local", handler = local),
+        ],
+    )
+"#
+        .replace("PORT", &port.to_string());
+
+        let executor = ToolExecutor::new();
+        executor
+            .engine()
+            .load_extension("scoped", &extension)
+            .await
+            .unwrap();
+        let text = tool_text(&executor, "outside").await;
+        assert!(
+            text.contains(
+                "Host 'example.org' is not allowed: add it to allowed_hosts in the Extension definition"
+            ),
+            "{}",
+            text
+        );
+        let text = tool_text(&executor, "local").await;
+        assert!(
+            text.contains("Host '127.0.0.1' resolves to private address 127.0.0.1"),
+            "{}",
+            text
+        );
+
+        let executor =
+            ToolExecutor::new().with_engine(StarlarkEngine::new().with_private_network(true));
+        executor
+            .engine()
+            .load_extension("scoped", &extension)
+            .await
+            .unwrap();
+        let text = tool_text(&executor, "local").await;
+        assert!(
+            text.contains(&format!(
+                "Redirect to http://localhost:{}/secret blocked: Host 'localhost' is not allowed",
+                port
+            )),
+            "{}",
+            text
+        );
+    }
+
     #[tokio::test]
     async fn test_extension_with_multiple_tools() {
         let engine = StarlarkEngine::new();
@@ -0,0 +1,291 @@
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Lockfile read from the working directory when `--lockfile` isn't given
+pub const LOCK_FILE_NAME: &str = "starlark-mcp.lock";
+
+/// Format version written to new lockfiles
+const LOCK_VERSION: u32 = 1;
+
+const HEADER: &str =
+    "# SHA-256 of every extension file. Generated by `starlark-mcp lock`; do not edit.\n\n";
+
+/// On-disk form of `starlark-mcp.lock`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LockfileData {
+    version: u32,
+    /// Paths relative to the lockfile's directory, with `/` separators
+    files: BTreeMap<String, String>,
+}
+
+/// Content hashes that extension sources, modules, data files and package
+/// archives must match before they are used.
+///
+/// Files unpacked from a verified package need no entries of their own, but
+/// must still match what the package contained.
+#[derive(Debug)]
+pub struct Lockfile {
+    path: PathBuf,
+    /// SHA-256 by canonical path
+    files: HashMap<PathBuf, String>,
+    /// Files unpacked from verified packages
+    trusted: TrustedFiles,
+}
+
+/// Files vouched for by a verified package, with the SHA-256 each had in the
+/// package, so changes made after unpacking are caught
+#[derive(Debug, Default)]
+pub struct TrustedFiles {
+    files: RwLock<HashMap<PathBuf, String>>,
+}
+
+impl TrustedFiles {
+    /// Trust `files`, SHA-256 by canonical path, in place of whatever was
+    /// trusted under `dir` before
+    pub fn trust(&self, dir: &Path, files: &HashMap<PathBuf, String>) {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        let mut trusted = self.files.write().unwrap();
+        trusted.retain(|path, _| !path.starts_with(&dir));
+        trusted.extend(
+            files
+                .iter()
+                .map(|(path, hash)| (path.clone(), hash.clone())),
+        );
+    }
+
+    /// Stop trusting every file under `dir`
+    pub fn distrust(&self, dir: &Path) {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        self.files
+            .write()
+            .unwrap()
+            .retain(|path, _| !path.starts_with(&dir));
+    }
+
+    /// `None` if `path`, a canonical path, isn't trusted; otherwise whether
+    /// `content` is what the package held
+    pub fn check(&self, path: &Path, content: &[u8]) -> Option<Result<()>> {
+        let trusted = self.files.read().unwrap();
+        let expected = trusted.get(path)?;
+        let actual = sha256(content);
+        Some(if *expected == actual {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{} changed after it was unpacked (expected sha256 {}, found {})",
+                path.display(),
+                expected,
+                actual
+            ))
+        })
+    }
+}
+
+impl Lockfile {
+    /// Read the lockfile at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read lockfile {}: {}", path.display(), e))?;
+        let data: LockfileData = toml::from_str(&content)
+            .map_err(|e| anyhow!("Invalid lockfile {}: {}", path.display(), e))?;
+        if data.version != LOCK_VERSION {
+            return Err(anyhow!(
+                "Unsupported lockfile version {} in {}, expected {}",
+                data.version,
+                path.display(),
+                LOCK_VERSION
+            ));
+        }
+
+        let base = base_dir(path);
+        let files = data
+            .files
+            .into_iter()
+            .map(|(file, hash)| {
+                let full = base.join(&file);
+                (full.canonicalize().unwrap_or(full), hash)
+            })
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            files,
+            trusted: TrustedFiles::default(),
+        })
+    }
+
+    /// Hash `files` and write them to a lockfile at `path`. Returns the
+    /// number of files locked.
+    pub fn write(path: &Path, files: &[PathBuf]) -> Result<usize> {
+        let base = base_dir(path)
+            .canonicalize()
+            .map_err(|e| anyhow!("Failed to resolve {}: {}", path.display(), e))?;
+        let mut locked = BTreeMap::new();
+        for file in files {
+            let content = std::fs::read(file)
+                .map_err(|e| anyhow!("Failed to read {}: {}", file.display(), e))?;
+            let canonical = file.canonicalize()?;
+            let key = match canonical.strip_prefix(&base) {
+                Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+                Err(_) => canonical.to_string_lossy().into_owned(),
+            };
+            locked.insert(key, sha256(&content));
+        }
+
+        let data = LockfileData {
+            version: LOCK_VERSION,
+            files: locked,
+        };
+        let toml =
+            toml::to_string(&data).map_err(|e| anyhow!("Failed to write lockfile: {}", e))?;
+        std::fs::write(path, format!("{}{}", HEADER, toml))
+            .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+        Ok(data.files.len())
+    }
+
+    /// Fail unless `content`, read from `path`, matches the lockfile
+    pub fn verify(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(result) = self.trusted.check(&canonical, content) {
+            return result;
+        }
+
+        let name = self.lockfile_name();
+        let expected = self.files.get(&canonical).ok_or_else(|| {
+            anyhow!(
+                "{} is not in {}; run `starlark-mcp lock` to add it",
+                path.display(),
+                name
+            )
+        })?;
+        let actual = sha256(content);
+        if *expected != actual {
+            return Err(anyhow!(
+                "{} does not match {} (expected sha256 {}, found {})",
+                path.display(),
+                name,
+                expected,
+                actual
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read `path` and verify it
+    pub fn verify_file(&self, path: &Path) -> Result<()> {
+        let content =
+            std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        self.verify(path, &content)
+    }
+
+    /// Trust the files of a package unpacked to `dir` from a verified
+    /// archive, as long as they keep the SHA-256 they had in it
+    pub fn trust_files(&self, dir: &Path, files: &HashMap<PathBuf, String>) {
+        self.trusted.trust(dir, files);
+    }
+
+    fn lockfile_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| LOCK_FILE_NAME.to_string())
+    }
+}
+
+/// Hex SHA-256 of `content`
+pub fn sha256(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Directory that lockfile paths are relative to
+fn base_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_write_and_verify() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("extensions/lib")).unwrap();
+        let weather = root.join("extensions/weather.star");
+        let util = root.join("extensions/lib/util.star");
+        std::fs::write(&weather, "x = 1\n").unwrap();
+        std::fs::write(&util, "y = 2\n").unwrap();
+        let path = root.join(LOCK_FILE_NAME);
+
+        assert_eq!(
+            Lockfile::write(&path, &[weather.clone(), util.clone()]).unwrap(),
+            2
+        );
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# SHA-256"), "{}", content);
+        assert!(
+            content.contains("\"extensions/lib/util.star\" = \""),
+            "{}",
+            content
+        );
+
+        let lock = Lockfile::load(&path).unwrap();
+        assert!(lock.verify_file(&weather).is_ok());
+        assert!(lock.verify(&util, b"y = 2\n").is_ok());
+
+        let err = lock.verify(&weather, b"x = 2\n").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("weather.star does not match starlark-mcp.lock (expected sha256 "),
+            "{}",
+            err
+        );
+
+        let new = root.join("extensions/new.star");
+        std::fs::write(&new, "").unwrap();
+        let err = lock.verify_file(&new).unwrap_err();
+        assert!(
+            err.to_string().ends_with(
+                "new.star is not in starlark-mcp.lock; run `starlark-mcp lock` to add it"
+            ),
+            "{}",
+            err
+        );
+
+        let unpacked = HashMap::from([(new.clone(), sha256(b""))]);
+        lock.trust_files(&root.join("extensions"), &unpacked);
+        assert!(lock.verify_file(&new).is_ok());
+        std::fs::write(&new, "x = 1\n").unwrap();
+        let err = lock.verify_file(&new).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("new.star changed after it was unpacked (expected sha256 "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_rejects_unknown_version() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join(LOCK_FILE_NAME);
+        std::fs::write(&path, "version = 2\n[files]\n").unwrap();
+        let err = Lockfile::load(&path).unwrap_err();
+        assert!(err.to_string().contains("Unsupported lockfile version 2"));
+    }
+}
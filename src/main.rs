@@ -1,15 +1,34 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use argh::FromArgs;
-use tracing::info;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
 
 use starlark_mcp::ExtensionLoader;
+use starlark_mcp::check;
+use starlark_mcp::config::{CONFIG_FILE_NAME, ServerConfig, TransportMode};
+use starlark_mcp::extensions::loader::DiscoveredExtension;
+use starlark_mcp::extensions::package::is_package;
+use starlark_mcp::lockfile::{LOCK_FILE_NAME, Lockfile};
+use starlark_mcp::signing::{self, UnsignedPolicy};
+use starlark_mcp::starlark::backtrace::ErrorVerbosity;
+use starlark_mcp::starlark::kv::{self, KvStore};
+use starlark_mcp::starlark::limits::{ExecutionLimits, heap_bytes};
+use starlark_mcp::{StarlarkMcpHandler, ToolExecutor};
 
 #[derive(FromArgs)]
 /// Starlark-based MCP server
 struct Args {
-    /// path to the extensions directory
-    #[argh(option, short = 'e', default = "\"./extensions\".to_string()")]
-    extensions_dir: String,
+    /// path to an extensions directory; repeat for several, earlier ones
+    /// taking precedence (default: ./extensions, or extension_dirs from the
+    /// config file)
+    #[argh(option, short = 'e')]
+    extensions_dir: Vec<String>,
+
+    /// path to the server config file (default: ./starlark-mcp.toml if present)
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
 
     /// print version and exit
     #[argh(switch, short = 'v')]
@@ -24,10 +43,194 @@ struct Args {
     http: bool,
 
     /// port for HTTP server (default: 3000)
-    #[argh(option, short = 'p', default = "3000")]
-    port: u16,
+    #[argh(option, short = 'p')]
+    port: Option<u16>,
+
+    /// default wall-clock limit for a tool call, in seconds
+    #[argh(option)]
+    timeout_secs: Option<f64>,
+
+    /// default limit on Starlark steps (loop iterations and function calls) per tool call
+    #[argh(option)]
+    max_steps: Option<u64>,
+
+    /// default Starlark heap limit per tool call, in megabytes
+    #[argh(option)]
+    max_heap_mb: Option<u64>,
+
+    /// directory for persistent server data such as the kv store
+    /// (default: $XDG_DATA_HOME/starlark-mcp or ~/.local/share/starlark-mcp)
+    #[argh(option)]
+    data_dir: Option<PathBuf>,
+
+    /// maximum number of tool calls executing at once (default: 16)
+    #[argh(option)]
+    max_concurrent_tools: Option<usize>,
+
+    /// expose tools as <extension>__<tool> so extensions can share tool names
+    #[argh(switch)]
+    namespace_tools: bool,
+
+    /// list a built-in server_status tool reporting loaded and failed
+    /// extensions (always available as the starlark-mcp://diagnostics resource)
+    #[argh(switch)]
+    status_tool: bool,
+
+    /// allow type annotations and typecheck extensions when they load,
+    /// rejecting files with type errors
+    #[argh(switch)]
+    typecheck: bool,
+
+    /// detail in failed tool results: "message" (default) or "backtrace" to
+    /// add the Starlark call stack
+    #[argh(option)]
+    error_verbosity: Option<ErrorVerbosity>,
+
+    /// refuse to load or hot-reload any extension, module, data file or
+    /// package that doesn't match the lockfile
+    #[argh(switch)]
+    locked: bool,
+
+    /// path to the lockfile (default: ./starlark-mcp.lock)
+    #[argh(option)]
+    lockfile: Option<PathBuf>,
+
+    /// let extensions' http requests reach loopback, private and link-local
+    /// addresses, which are blocked by default
+    #[argh(switch)]
+    allow_private_network: bool,
+
+    /// fail env.get() of variables outside an extension's allowed_env
+    /// instead of returning the default
+    #[argh(switch)]
+    strict_env: bool,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Check(CheckArgs),
+    Manifest(ManifestArgs),
+    Lock(LockArgs),
+    Sign(SignArgs),
+}
+
+#[derive(FromArgs)]
+/// Load and lint extensions without starting the server, printing
+/// diagnostics and exiting non-zero if any are errors
+#[argh(subcommand, name = "check")]
+struct CheckArgs {
+    /// extension files or directories to check (default: the extension
+    /// directories)
+    #[argh(positional)]
+    paths: Vec<PathBuf>,
+
+    /// typecheck extensions as well
+    #[argh(switch)]
+    typecheck: bool,
+}
+
+#[derive(FromArgs)]
+/// Show what each extension declares it needs and can reach (modules,
+/// commands, environment variables and tools), including disabled ones
+#[argh(subcommand, name = "manifest")]
+struct ManifestArgs {
+    /// extension files or directories (default: the extension directories)
+    #[argh(positional)]
+    paths: Vec<PathBuf>,
+
+    /// print JSON instead of text
+    #[argh(switch)]
+    json: bool,
+}
+
+#[derive(FromArgs)]
+/// Record the SHA-256 of every file in the extension directories in the
+/// lockfile, for serving them with --locked
+#[argh(subcommand, name = "lock")]
+struct LockArgs {}
+
+#[derive(FromArgs)]
+/// Sign extension files and package archives with an ed25519 key, writing
+/// a .sig file next to each
+#[argh(subcommand, name = "sign")]
+struct SignArgs {
+    /// path to the signing key
+    #[argh(option)]
+    key: PathBuf,
+
+    /// generate a new key at --key and print its public key
+    #[argh(switch)]
+    new_key: bool,
+
+    /// files or directories to sign (default: every extension, module and
+    /// package in the extension directories)
+    #[argh(positional)]
+    paths: Vec<PathBuf>,
 }
 
+impl Args {
+    /// The explicit `--config`, or `starlark-mcp.toml` in the working directory
+    fn config_path(&self) -> Option<PathBuf> {
+        match &self.config {
+            Some(path) => Some(path.clone()),
+            None => {
+                let default = PathBuf::from(CONFIG_FILE_NAME);
+                default.exists().then_some(default)
+            }
+        }
+    }
+
+    /// Limits given on the command line; unset ones fall back to the config file
+    fn limits(&self) -> Result<ExecutionLimits> {
+        if let Some(limit) = self.timeout_secs
+            && (limit.is_nan() || limit <= 0.0)
+        {
+            return Err(anyhow!("--timeout-secs must be positive"));
+        }
+        let max_heap_bytes = match self.max_heap_mb {
+            Some(mb) => {
+                Some(heap_bytes(mb).ok_or_else(|| anyhow!("--max-heap-mb is too large: {}", mb))?)
+            }
+            None => None,
+        };
+        Ok(ExecutionLimits {
+            timeout: self.timeout_secs.map(Duration::from_secs_f64),
+            max_steps: self.max_steps,
+            max_heap_bytes,
+        })
+    }
+
+    /// The explicit `--lockfile`, or `starlark-mcp.lock` in the working
+    /// directory
+    fn lockfile_path(&self) -> PathBuf {
+        self.lockfile
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(LOCK_FILE_NAME))
+    }
+
+    fn extension_dirs(&self, config: &ServerConfig) -> Vec<String> {
+        if !self.extensions_dir.is_empty() {
+            return self.extensions_dir.clone();
+        }
+        if config.extension_dirs.is_empty() {
+            return vec![DEFAULT_EXTENSIONS_DIR.to_string()];
+        }
+        config
+            .extension_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect()
+    }
+}
+
+const DEFAULT_EXTENSIONS_DIR: &str = "./extensions";
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_HOST: &str = "0.0.0.0";
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args: Args = argh::from_env();
@@ -37,14 +240,47 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    // Check prints its own diagnostics; only log what it can't report
+    let level = match args.command {
+        Some(_) => tracing::Level::ERROR,
+        None => tracing::Level::INFO,
+    };
     tracing_subscriber::fmt()
         .with_writer(std::io::stderr)
+        .with_max_level(level)
         .without_time()
         .init();
 
+    let config_path = args.config_path();
+    let config = match config_path {
+        Some(ref path) => {
+            info!("Using server config: {}", path.display());
+            ServerConfig::load(path)?
+        }
+        None => ServerConfig::default(),
+    };
+    let extension_dirs = args.extension_dirs(&config);
+
+    if let Some(ref command) = args.command {
+        let failed = match command {
+            Command::Check(check) => run_check(check, &config, &extension_dirs).await?,
+            Command::Manifest(manifest) => run_manifest(manifest, &config, &extension_dirs).await?,
+            Command::Lock(_) => run_lock(&args.lockfile_path(), &config, &extension_dirs)?,
+            Command::Sign(sign) => run_sign(sign, &config, &extension_dirs)?,
+        };
+        if failed {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     if args.test {
-        info!("Running tests from {}", args.extensions_dir);
-        if let Err(_e) = starlark_mcp::run_tests(&args.extensions_dir).await {
+        let mut failed = false;
+        for dir in &extension_dirs {
+            info!("Running tests from {}", dir);
+            failed |= starlark_mcp::run_tests(dir).await.is_err();
+        }
+        if failed {
             std::process::exit(1);
         }
         return Ok(());
@@ -52,42 +288,350 @@ async fn main() -> Result<()> {
 
     info!("Starting Starlark MCP Server");
 
-    let tool_executor =
-        starlark_mcp::ToolExecutor::new().with_extensions_dir(args.extensions_dir.clone());
-    let engine = tool_executor.engine();
+    let cli_limits = args.limits()?;
+    let limits = cli_limits.or(&config.limits.to_limits());
 
-    let loader = ExtensionLoader::new(args.extensions_dir);
-    loader.load_all(&engine, false).await?;
+    let data_dir = args
+        .data_dir
+        .clone()
+        .or_else(|| config.data_dir.clone())
+        .unwrap_or_else(kv::default_data_dir);
+    let kv_store = KvStore::open(&data_dir.join(kv::KV_FILE_NAME))
+        .inspect_err(|e| warn!("{}; the kv module is disabled", e))
+        .ok();
+    info!("Using data directory: {}", data_dir.display());
 
-    let handler = starlark_mcp::StarlarkMcpHandler::new(tool_executor);
-    let extensions = engine.get_all_extensions().await;
-    for extension in extensions {
+    let lockfile = if args.locked {
+        let path = args.lockfile_path();
+        info!("Locked to {}", path.display());
+        Some(Arc::new(Lockfile::load(&path)?))
+    } else {
+        None
+    };
+
+    let signatures = config.signatures.verifier()?.map(Arc::new);
+    if let Some(ref signatures) = signatures {
         info!(
-            "Registering extension '{}' with {} tools",
-            extension.name,
-            extension.tools.len()
+            "Checking signatures against {} trusted key(s); unsigned extensions are {}",
+            config.signatures.trusted_keys.len(),
+            match signatures.policy() {
+                UnsignedPolicy::Reject => "rejected",
+                UnsignedPolicy::Restrict => "loaded without exec and http",
+            }
         );
+    }
 
-        for tool in extension.to_mcp_tools() {
-            handler.register_tool(tool).await;
-        }
+    let mut engine = extension_dirs
+        .iter()
+        .fold(starlark_mcp::StarlarkEngine::new(), |engine, dir| {
+            engine.with_extensions_dir(dir)
+        })
+        .with_settings(config.extensions.clone())
+        .with_namespaced_tools(args.namespace_tools || config.namespace_tools)
+        .with_private_network(args.allow_private_network || config.allow_private_network)
+        .with_strict_env(args.strict_env || config.strict_env)
+        .with_typecheck(args.typecheck || config.typecheck)?;
+    if let Some(kv_store) = kv_store {
+        engine = engine.with_kv_store(kv_store);
+    }
+    if let Some(ref lockfile) = lockfile {
+        engine = engine.with_lockfile(lockfile.clone());
     }
+    if let Some(ref signatures) = signatures {
+        engine = engine.with_signatures(signatures.clone());
+    }
+
+    let max_concurrent_tools = args
+        .max_concurrent_tools
+        .or(config.max_concurrent_tools)
+        .unwrap_or(starlark_mcp::starlark::engine::DEFAULT_MAX_CONCURRENT_TOOLS);
+    let tool_executor = starlark_mcp::ToolExecutor::new()
+        .with_engine(engine)
+        .with_limits(limits)
+        .with_error_verbosity(
+            args.error_verbosity
+                .or(config.error_verbosity)
+                .unwrap_or_default(),
+        )
+        .with_max_concurrency(max_concurrent_tools);
+    let engine = tool_executor.engine();
+
+    let mut loader = extension_dirs[1..]
+        .iter()
+        .fold(ExtensionLoader::new(&extension_dirs[0]), |loader, dir| {
+            loader.with_dir(dir)
+        })
+        .with_ignore(&config.ignore)?
+        .with_packages_dir(data_dir.join("packages"));
+    if let Some(lockfile) = lockfile {
+        loader = loader.with_lockfile(lockfile);
+    }
+    if let Some(signatures) = signatures {
+        loader = loader.with_signatures(signatures);
+    }
+    loader.load_all(&engine, false).await?;
+
+    let handler = starlark_mcp::StarlarkMcpHandler::new(tool_executor.clone())
+        .with_status_tool(args.status_tool || config.status_tool);
+    handler.refresh_tools().await;
 
     let handler_for_watcher = handler.clone();
-    loader.start_watching(engine.clone(), move || {
+    loader.start_watching(engine.clone(), move |outcome| {
         let handler = handler_for_watcher.clone();
         tokio::spawn(async move {
-            info!("Extension changed, refreshing tools...");
-            handler.refresh_tools().await;
+            info!("Extensions changed, refreshing tools...");
+            handler.apply_reload(outcome).await;
         });
     })?;
 
+    if let Some(path) = config_path {
+        let current = Arc::new(Mutex::new(config.clone()));
+        let handler = handler.clone();
+        let watched = path.clone();
+        ServerConfig::start_watching(&watched, move || {
+            let path = path.clone();
+            let current = current.clone();
+            let handler = handler.clone();
+            let executor = tool_executor.clone();
+            let loader = loader.clone();
+            let cli = RuntimeOverrides {
+                limits: cli_limits,
+                error_verbosity: args.error_verbosity,
+            };
+            tokio::spawn(async move {
+                reload_config(&path, &current, &executor, &handler, &loader, &cli).await;
+            });
+        })?;
+    }
+
     info!("Server ready, starting main loop");
-    if args.http {
-        starlark_mcp::run_rmcp_server_http(handler, args.port).await?;
+    let http = args.http || config.transport.mode == Some(TransportMode::Http);
+    if http {
+        let host = config.transport.host.as_deref().unwrap_or(DEFAULT_HOST);
+        let port = args.port.or(config.transport.port).unwrap_or(DEFAULT_PORT);
+        starlark_mcp::run_rmcp_server_http(handler, host, port).await?;
     } else {
         starlark_mcp::run_rmcp_server(handler).await?;
     }
 
     Ok(())
 }
+
+/// Check the given files and directories, or every extension directory.
+/// Returns whether any errors were found.
+async fn run_check(
+    check: &CheckArgs,
+    config: &ServerConfig,
+    extension_dirs: &[String],
+) -> Result<bool> {
+    let extensions = discover_cli_extensions(&check.paths, config, extension_dirs)?;
+    let engine = cli_engine(&extensions, extension_dirs)?
+        .with_settings(config.extensions.clone())
+        .with_typecheck(check.typecheck || config.typecheck)?;
+
+    let report = check::check_extensions(&engine, &extensions).await;
+    print!("{}", report.output);
+    Ok(report.has_errors())
+}
+
+async fn run_manifest(
+    args: &ManifestArgs,
+    config: &ServerConfig,
+    extension_dirs: &[String],
+) -> Result<bool> {
+    let extensions = discover_cli_extensions(&args.paths, config, extension_dirs)?;
+    let engine = cli_engine(&extensions, extension_dirs)?;
+
+    let (manifests, errors) = check::manifests(&engine, &extensions, &config.extensions).await;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&manifests)?);
+    } else {
+        let text: Vec<String> = manifests.iter().map(|m| m.to_text()).collect();
+        print!("{}", text.join("\n"));
+    }
+    for error in &errors {
+        eprintln!("error: {}", error);
+    }
+    Ok(!errors.is_empty())
+}
+
+/// Write the lockfile for every file in the extension directories
+fn run_lock(path: &Path, config: &ServerConfig, extension_dirs: &[String]) -> Result<bool> {
+    let loader = extension_dirs[1..]
+        .iter()
+        .fold(ExtensionLoader::new(&extension_dirs[0]), |loader, dir| {
+            loader.with_dir(dir)
+        })
+        .with_ignore(&config.ignore)?;
+    let count = Lockfile::write(path, &loader.files())?;
+    println!("Locked {} file(s) in {}", count, path.display());
+    Ok(false)
+}
+
+/// Sign the given files, or every `.star` file and package in the
+/// directories given or the extension directories
+fn run_sign(args: &SignArgs, config: &ServerConfig, extension_dirs: &[String]) -> Result<bool> {
+    let key = if args.new_key {
+        let key = signing::generate_key(&args.key)?;
+        println!(
+            "Generated {}. Add its public key to signatures.trusted_keys:\n  {}",
+            args.key.display(),
+            signing::public_key(&key)
+        );
+        key
+    } else {
+        signing::read_key(&args.key)?
+    };
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    if args.paths.is_empty() {
+        dirs.extend(extension_dirs.iter().map(PathBuf::from));
+    }
+    for path in &args.paths {
+        if path.is_dir() {
+            dirs.push(path.clone());
+        } else {
+            files.push(path.clone());
+        }
+    }
+    for dir in &dirs {
+        let loader = ExtensionLoader::new(dir).with_ignore(&config.ignore)?;
+        files.extend(
+            loader.files().into_iter().filter(|file| {
+                is_package(file) || file.extension().is_some_and(|ext| ext == "star")
+            }),
+        );
+    }
+
+    for file in &files {
+        signing::sign_file(&key, file)?;
+    }
+    println!(
+        "Signed {} file(s) with key {}",
+        files.len(),
+        signing::public_key(&key)
+    );
+    Ok(false)
+}
+
+/// Extensions named on the command line, or those in the extension
+/// directories when none are
+fn discover_cli_extensions(
+    paths: &[PathBuf],
+    config: &ServerConfig,
+    extension_dirs: &[String],
+) -> Result<Vec<DiscoveredExtension>> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    if paths.is_empty() {
+        dirs.extend(extension_dirs.iter().map(PathBuf::from));
+    }
+    for path in paths {
+        if path.is_dir() {
+            dirs.push(path.clone());
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut extensions = Vec::new();
+    for dir in &dirs {
+        let loader = ExtensionLoader::new(dir).with_ignore(&config.ignore)?;
+        extensions.extend(loader.discover(false));
+    }
+    for file in &files {
+        if is_package(file) {
+            extensions.extend(ExtensionLoader::new(".").discover_package(file)?);
+        } else {
+            extensions.push(check::file_extension(file));
+        }
+    }
+    Ok(extensions)
+}
+
+/// An engine for loading `extensions` outside the server
+fn cli_engine(
+    extensions: &[DiscoveredExtension],
+    extension_dirs: &[String],
+) -> Result<starlark_mcp::StarlarkEngine> {
+    // Resolve `load("//...")` against each checked root as well as the
+    // configured extension directories
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for root in extensions
+        .iter()
+        .map(|e| e.root.clone())
+        .chain(extension_dirs.iter().map(PathBuf::from))
+    {
+        if !roots.contains(&root) {
+            roots.push(root);
+        }
+    }
+    let engine = roots
+        .into_iter()
+        .fold(starlark_mcp::StarlarkEngine::new(), |engine, dir| {
+            engine.with_extensions_dir(dir)
+        })
+        .with_kv_store(KvStore::in_memory()?);
+    Ok(engine)
+}
+
+/// Settings given on the command line that would otherwise be reloaded from
+/// the config file
+struct RuntimeOverrides {
+    limits: ExecutionLimits,
+    error_verbosity: Option<ErrorVerbosity>,
+}
+
+/// Re-read the server config and apply what can change at runtime: limits,
+/// error verbosity and per-extension settings. Every extension is reloaded so it picks up new
+/// settings. An invalid file is reported and the previous config stays in effect.
+async fn reload_config(
+    path: &Path,
+    current: &Mutex<ServerConfig>,
+    executor: &ToolExecutor,
+    handler: &StarlarkMcpHandler,
+    loader: &ExtensionLoader,
+    cli: &RuntimeOverrides,
+) {
+    let config = match ServerConfig::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Keeping previous server config: {}", e);
+            return;
+        }
+    };
+
+    {
+        let mut current = current.lock().unwrap();
+        if *current == config {
+            return;
+        }
+        let restart = config.restart_required_changes(&current);
+        if !restart.is_empty() {
+            warn!(
+                "Server config changes to {} take effect after a restart",
+                restart.join(", ")
+            );
+        }
+        *current = config.clone();
+    }
+
+    info!("Server config changed, reloading extensions...");
+    executor.set_limits(cli.limits.or(&config.limits.to_limits()));
+    executor.set_error_verbosity(
+        cli.error_verbosity
+            .or(config.error_verbosity)
+            .unwrap_or_default(),
+    );
+
+    let engine = executor.engine();
+    for name in engine.apply_settings(config.extensions).await {
+        info!("Disabled extension: {}", name);
+    }
+    if let Err(e) = loader.load_all(&engine, false).await {
+        warn!("Failed to reload extensions: {}", e);
+    }
+
+    handler.refresh_tools().await;
+}
@@ -1,18 +1,22 @@
 use anyhow::Result;
 use rmcp::model::{
-    CallToolRequestParam, CallToolResult, Content, Implementation, InitializeRequestParam,
-    InitializeResult, ListToolsResult, PaginatedRequestParam, ProtocolVersion, ServerCapabilities,
-    Tool as RmcpTool, ToolAnnotations as RmcpToolAnnotations, ToolsCapability,
+    AnnotateAble, CallToolRequestParam, CallToolResult, Content, Implementation,
+    InitializeRequestParam, InitializeResult, ListResourcesResult, ListToolsResult, LoggingLevel,
+    LoggingMessageNotificationParam, PaginatedRequestParam, ProtocolVersion, RawResource,
+    ReadResourceRequestParam, ReadResourceResult, ResourceContents, ResourcesCapability,
+    ServerCapabilities, SetLevelRequestParam, Tool as RmcpTool,
+    ToolAnnotations as RmcpToolAnnotations, ToolsCapability,
 };
-use rmcp::service::{RequestContext, RoleServer};
+use rmcp::service::{Peer, RequestContext, RoleServer};
 use rmcp::{ErrorData as McpError, ServerHandler};
 use serde_json::{Map, Value, json};
 use std::borrow::Cow;
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::mcp::Tool;
+use crate::extensions::loader::ReloadOutcome;
+use crate::mcp::{Tool, ToolAnnotations, ToolInputSchema};
 use crate::starlark::engine::ToolExecutor;
 
 /// Adapter that bridges rmcp's ServerHandler with our Starlark ToolExecutor
@@ -20,13 +24,78 @@ use crate::starlark::engine::ToolExecutor;
 pub struct StarlarkMcpHandler {
     tools: Arc<RwLock<Vec<Tool>>>,
     tool_executor: ToolExecutor,
+    /// Connected clients, for server-initiated notifications
+    peers: Arc<RwLock<Vec<ConnectedPeer>>>,
+    /// Least severe level this session's client is sent as log
+    /// notifications. Each session gets its own, see [`Self::for_session`].
+    log_level: Arc<RwLock<LoggingLevel>>,
+    /// Whether the built-in `server_status` tool is listed
+    status_tool: bool,
 }
 
+/// A connected client and the least severe level it asked to be sent
+#[derive(Clone)]
+struct ConnectedPeer {
+    peer: Peer<RoleServer>,
+    log_level: Arc<RwLock<LoggingLevel>>,
+}
+
+/// Log level of a client that hasn't called `logging/setLevel`
+const DEFAULT_LOG_LEVEL: LoggingLevel = LoggingLevel::Warning;
+
+/// Logger name attached to log notifications sent to clients
+const LOGGER: &str = "starlark-mcp";
+
+/// Built-in resource describing loaded and failed extensions
+pub const DIAGNOSTICS_URI: &str = "starlark-mcp://diagnostics";
+
+/// Name of the optional built-in tool that reports the same diagnostics
+pub const STATUS_TOOL: &str = "server_status";
+
 impl StarlarkMcpHandler {
     pub fn new(tool_executor: ToolExecutor) -> Self {
         Self {
             tools: Arc::new(RwLock::new(Vec::new())),
             tool_executor,
+            peers: Arc::new(RwLock::new(Vec::new())),
+            log_level: Arc::new(RwLock::new(DEFAULT_LOG_LEVEL)),
+            status_tool: false,
+        }
+    }
+
+    /// A handler for a new client session: it shares tools and connected
+    /// clients with this one but has its own log level, so one client's
+    /// `logging/setLevel` doesn't change what the others are sent
+    pub fn for_session(&self) -> Self {
+        Self {
+            log_level: Arc::new(RwLock::new(DEFAULT_LOG_LEVEL)),
+            ..self.clone()
+        }
+    }
+
+    /// Also offer the diagnostics as a `server_status` tool, for clients that
+    /// don't surface resources to the model
+    pub fn with_status_tool(mut self, enabled: bool) -> Self {
+        self.status_tool = enabled;
+        self
+    }
+
+    fn status_tool() -> Tool {
+        Tool {
+            name: STATUS_TOOL.to_string(),
+            title: Some("Server status".to_string()),
+            description: "Report which extensions are loaded, with versions and tool counts, \
+                          which failed to load and why, and when extensions were last reloaded. \
+                          Use it to find out why a tool is missing."
+                .to_string(),
+            input_schema: ToolInputSchema::default(),
+            output_schema: None,
+            annotations: Some(ToolAnnotations {
+                destructive_hint: Some(false),
+                idempotent_hint: Some(true),
+                open_world_hint: Some(false),
+                read_only_hint: Some(true),
+            }),
         }
     }
 
@@ -41,14 +110,72 @@ impl StarlarkMcpHandler {
         let mut tools = self.tools.write().await;
         tools.clear();
 
-        let extensions = self.tool_executor.engine().get_all_extensions().await;
-        for extension in extensions {
-            for tool in extension.to_mcp_tools() {
-                info!("Re-registering tool: {}", tool.name);
-                tools.push(tool);
+        if self.status_tool {
+            tools.push(Self::status_tool());
+        }
+        for tool in self.tool_executor.engine().get_all_tools().await {
+            if self.status_tool && tool.name == STATUS_TOOL {
+                warn!(
+                    "Ignoring extension tool '{}': the name is reserved",
+                    STATUS_TOOL
+                );
+                continue;
             }
+            info!("Re-registering tool: {}", tool.name);
+            tools.push(tool);
         }
         info!("Tool refresh complete. Total tools: {}", tools.len());
+        drop(tools);
+
+        for connected in self.live_peers().await {
+            if let Err(e) = connected.peer.notify_tool_list_changed().await {
+                debug!("Failed to notify client of tool list change: {}", e);
+            }
+        }
+    }
+
+    /// React to a hot reload: refresh the tool list if anything changed, and
+    /// tell clients about extensions that failed to load
+    pub async fn apply_reload(&self, outcome: ReloadOutcome) {
+        if outcome.changed {
+            self.refresh_tools().await;
+        }
+        for failure in outcome.errors {
+            self.log_to_clients(
+                LoggingLevel::Error,
+                json!({
+                    "message": format!("Failed to load {}", failure.path.display()),
+                    "path": failure.path,
+                    "error": failure.error,
+                }),
+            )
+            .await;
+        }
+    }
+
+    /// Send a log notification to every connected client that asked for
+    /// messages of this severity
+    pub async fn log_to_clients(&self, level: LoggingLevel, data: Value) {
+        for connected in self.live_peers().await {
+            if severity(level) < severity(*connected.log_level.read().await) {
+                continue;
+            }
+            let param = LoggingMessageNotificationParam {
+                level,
+                logger: Some(LOGGER.to_string()),
+                data: data.clone(),
+            };
+            if let Err(e) = connected.peer.notify_logging_message(param).await {
+                debug!("Failed to send log notification: {}", e);
+            }
+        }
+    }
+
+    /// Connected clients, forgetting those that have disconnected
+    async fn live_peers(&self) -> Vec<ConnectedPeer> {
+        let mut peers = self.peers.write().await;
+        peers.retain(|connected| !connected.peer.is_transport_closed());
+        peers.clone()
     }
 
     fn convert_to_rmcp_tool(tool: &Tool) -> RmcpTool {
@@ -116,6 +243,10 @@ impl ServerHandler for StarlarkMcpHandler {
         if context.peer.peer_info().is_none() {
             context.peer.set_peer_info(request.clone());
         }
+        self.peers.write().await.push(ConnectedPeer {
+            peer: context.peer.clone(),
+            log_level: self.log_level.clone(),
+        });
 
         // Negotiate protocol version: client's version if supported, else latest
         let client_version = request.protocol_version.to_string();
@@ -138,6 +269,8 @@ impl ServerHandler for StarlarkMcpHandler {
             protocol_version,
             capabilities: ServerCapabilities {
                 tools: Some(serde_json::from_value(json!({ "listChanged": true })).unwrap()),
+                logging: Some(Map::new()),
+                resources: Some(ResourcesCapability::default()),
                 ..Default::default()
             },
             server_info: Implementation {
@@ -170,7 +303,7 @@ impl ServerHandler for StarlarkMcpHandler {
     async fn call_tool(
         &self,
         request: CallToolRequestParam,
-        _context: RequestContext<RoleServer>,
+        context: RequestContext<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
         info!("Call tool request received: {}", request.name);
 
@@ -186,6 +319,16 @@ impl ServerHandler for StarlarkMcpHandler {
         }
         drop(tools);
 
+        if self.status_tool && request.name == STATUS_TOOL {
+            let diagnostics = self.tool_executor.engine().diagnostics().await;
+            return Ok(CallToolResult {
+                content: vec![Content::text(diagnostics.to_text())],
+                is_error: Some(false),
+                meta: None,
+                structured_content: serde_json::to_value(&diagnostics).ok(),
+            });
+        }
+
         let arguments = request
             .arguments
             .map(serde_json::Value::Object)
@@ -193,7 +336,7 @@ impl ServerHandler for StarlarkMcpHandler {
 
         match self
             .tool_executor
-            .execute_tool(&request.name, arguments)
+            .execute_tool_for_request(&request.name, arguments, context.ct, Some(context.peer))
             .await
         {
             Ok(result) => {
@@ -224,6 +367,61 @@ impl ServerHandler for StarlarkMcpHandler {
         }
     }
 
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let mut resource = RawResource::new(DIAGNOSTICS_URI, "diagnostics");
+        resource.title = Some("Extension diagnostics".to_string());
+        resource.description = Some(
+            "Loaded extensions with versions and tool counts, extensions that failed to load \
+             with their errors, and the last reload time"
+                .to_string(),
+        );
+        resource.mime_type = Some("application/json".to_string());
+
+        Ok(ListResourcesResult {
+            resources: vec![resource.no_annotation()],
+            next_cursor: None,
+        })
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        if request.uri != DIAGNOSTICS_URI {
+            return Err(McpError::resource_not_found(
+                format!("Resource not found: {}", request.uri),
+                None,
+            ));
+        }
+
+        let diagnostics = self.tool_executor.engine().diagnostics().await;
+        let text = serde_json::to_string_pretty(&diagnostics)
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        Ok(ReadResourceResult {
+            contents: vec![ResourceContents::TextResourceContents {
+                uri: DIAGNOSTICS_URI.to_string(),
+                mime_type: Some("application/json".to_string()),
+                text,
+                meta: None,
+            }],
+        })
+    }
+
+    async fn set_level(
+        &self,
+        request: SetLevelRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        info!("Client set log level to {:?}", request.level);
+        *self.log_level.write().await = request.level;
+        Ok(())
+    }
+
     fn get_info(&self) -> InitializeResult {
         InitializeResult {
             protocol_version: ProtocolVersion::LATEST,
@@ -231,6 +429,7 @@ impl ServerHandler for StarlarkMcpHandler {
                 tools: Some(ToolsCapability {
                     ..Default::default()
                 }),
+                resources: Some(ResourcesCapability::default()),
                 ..Default::default()
             },
             server_info: Implementation {
@@ -245,6 +444,20 @@ impl ServerHandler for StarlarkMcpHandler {
     }
 }
 
+/// Rank of a log level, from least to most severe
+fn severity(level: LoggingLevel) -> u8 {
+    match level {
+        LoggingLevel::Debug => 0,
+        LoggingLevel::Info => 1,
+        LoggingLevel::Notice => 2,
+        LoggingLevel::Warning => 3,
+        LoggingLevel::Error => 4,
+        LoggingLevel::Critical => 5,
+        LoggingLevel::Alert => 6,
+        LoggingLevel::Emergency => 7,
+    }
+}
+
 pub async fn run_server(handler: StarlarkMcpHandler) -> Result<()> {
     info!("Starting rmcp-based MCP server...");
 
@@ -259,24 +472,24 @@ pub async fn run_server(handler: StarlarkMcpHandler) -> Result<()> {
     Ok(())
 }
 
-pub async fn run_server_http(handler: StarlarkMcpHandler, port: u16) -> Result<()> {
+pub async fn run_server_http(handler: StarlarkMcpHandler, host: &str, port: u16) -> Result<()> {
     use rmcp::transport::streamable_http_server::{
         StreamableHttpService, session::local::LocalSessionManager,
     };
 
-    info!("Starting MCP server (HTTP mode) on port {}...", port);
+    info!("Starting MCP server (HTTP mode) on {}:{}...", host, port);
 
     let handler_for_factory = handler.clone();
     let service = StreamableHttpService::new(
-        move || Ok(handler_for_factory.clone()),
+        move || Ok(handler_for_factory.for_session()),
         LocalSessionManager::default().into(),
         Default::default(),
     );
 
     let router = axum::Router::new().nest_service("/mcp", service);
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    let listener = tokio::net::TcpListener::bind((host, port)).await?;
 
-    info!("Server ready at http://0.0.0.0:{}/mcp", port);
+    info!("Server ready at http://{}:{}/mcp", host, port);
 
     axum::serve(listener, router)
         .with_graceful_shutdown(async {
@@ -0,0 +1,297 @@
+use anyhow::{Result, anyhow};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::lockfile::TrustedFiles;
+
+/// Suffix of the detached signature written next to each signed file
+pub const SIGNATURE_SUFFIX: &str = ".sig";
+
+/// What to do with an extension that has no valid signature from a trusted
+/// key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnsignedPolicy {
+    /// Refuse to load it
+    #[default]
+    Reject,
+    /// Load it without the `exec` and `http` modules
+    Restrict,
+}
+
+/// Checks the ed25519 signatures of extension files and package archives
+/// against the trusted public keys from the server config.
+///
+/// Files unpacked from a package with a valid signature need no signatures
+/// of their own, but must still match what the package contained.
+#[derive(Debug)]
+pub struct SignatureVerifier {
+    keys: Vec<VerifyingKey>,
+    policy: UnsignedPolicy,
+    /// Files unpacked from signed packages
+    trusted: TrustedFiles,
+}
+
+impl SignatureVerifier {
+    /// A verifier for `keys`, each a hex-encoded ed25519 public key
+    pub fn new(keys: &[String], policy: UnsignedPolicy) -> Result<Self> {
+        let keys = keys
+            .iter()
+            .map(|key| parse_public_key(key))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            keys,
+            policy,
+            trusted: TrustedFiles::default(),
+        })
+    }
+
+    pub fn policy(&self) -> UnsignedPolicy {
+        self.policy
+    }
+
+    /// Fail unless `content`, read from `path`, is signed by a trusted key
+    pub fn verify(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(result) = self.trusted.check(&canonical, content) {
+            return result;
+        }
+
+        let sig_path = signature_path(path);
+        let encoded = match std::fs::read_to_string(&sig_path) {
+            Ok(encoded) => encoded,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(anyhow!(
+                    "{} is not signed: {} not found",
+                    path.display(),
+                    sig_path.display()
+                ));
+            }
+            Err(e) => {
+                return Err(anyhow!("Failed to read {}: {}", sig_path.display(), e));
+            }
+        };
+        let signature = hex::decode(encoded.trim())
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| anyhow!("{} is not a valid signature", sig_path.display()))?;
+
+        if self
+            .keys
+            .iter()
+            .any(|key| key.verify_strict(content, &signature).is_ok())
+        {
+            Ok(())
+        } else {
+            Err(anyhow!("{} is not signed by a trusted key", path.display()))
+        }
+    }
+
+    /// Read `path` and verify it
+    pub fn verify_file(&self, path: &Path) -> Result<()> {
+        let content =
+            std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        self.verify(path, &content)
+    }
+
+    /// Verify `content` and apply the policy: `Ok(None)` if it is signed,
+    /// `Ok(Some(reason))` if it may load restricted, and an error if it
+    /// must not load
+    pub fn check(&self, path: &Path, content: &[u8]) -> Result<Option<String>> {
+        match (self.verify(path, content), self.policy) {
+            (Ok(()), _) => Ok(None),
+            (Err(e), UnsignedPolicy::Reject) => Err(e),
+            (Err(e), UnsignedPolicy::Restrict) => Ok(Some(e.to_string())),
+        }
+    }
+
+    /// Read `path` and check it
+    pub fn check_file(&self, path: &Path) -> Result<Option<String>> {
+        let content =
+            std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        self.check(path, &content)
+    }
+
+    /// Trust the files of a package unpacked to `dir` from a signed
+    /// archive, as long as they keep the SHA-256 they had in it
+    pub fn trust_files(&self, dir: &Path, files: &HashMap<PathBuf, String>) {
+        self.trusted.trust(dir, files);
+    }
+
+    /// Stop trusting the files under `dir`, e.g. when a package is replaced
+    /// by an unsigned one
+    pub fn distrust_dir(&self, dir: &Path) {
+        self.trusted.distrust(dir);
+    }
+}
+
+/// Where the signature of `path` is stored: `weather.star.sig` for
+/// `weather.star`
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(SIGNATURE_SUFFIX);
+    PathBuf::from(sig)
+}
+
+/// The file that `path` is the signature of, if it is one
+pub fn signed_path(path: &Path) -> Option<PathBuf> {
+    path.to_str()
+        .and_then(|p| p.strip_suffix(SIGNATURE_SUFFIX))
+        .map(PathBuf::from)
+}
+
+/// Generate a signing key and save it to `path`, which must not exist
+pub fn generate_key(path: &Path) -> Result<SigningKey> {
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed).map_err(|e| anyhow!("Failed to generate key: {}", e))?;
+    let key = SigningKey::from_bytes(&seed);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+    std::io::Write::write_all(&mut file, format!("{}\n", hex::encode(seed)).as_bytes())
+        .map_err(|e| anyhow!("Failed to write {}: {}", path.display(), e))?;
+    Ok(key)
+}
+
+/// Read a signing key saved by [`generate_key`]
+pub fn read_key(path: &Path) -> Result<SigningKey> {
+    let encoded = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read signing key {}: {}", path.display(), e))?;
+    let seed: [u8; 32] = hex::decode(encoded.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| anyhow!("{} is not a signing key", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Hex-encoded public key, as listed in `signatures.trusted_keys`
+pub fn public_key(key: &SigningKey) -> String {
+    hex::encode(key.verifying_key().as_bytes())
+}
+
+/// Sign `path` with `key`, writing the signature next to it. Returns the
+/// signature's path.
+pub fn sign_file(key: &SigningKey, path: &Path) -> Result<PathBuf> {
+    let content =
+        std::fs::read(path).map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let sig_path = signature_path(path);
+    let signature = key.sign(&content);
+    std::fs::write(
+        &sig_path,
+        format!("{}\n", hex::encode(signature.to_bytes())),
+    )
+    .map_err(|e| anyhow!("Failed to write {}: {}", sig_path.display(), e))?;
+    Ok(sig_path)
+}
+
+fn parse_public_key(key: &str) -> Result<VerifyingKey> {
+    hex::decode(key.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "Invalid trusted key '{}': expected 64 hex digits from `starlark-mcp sign`",
+                key
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (tempfile::TempDir, SigningKey, PathBuf) {
+        let dir = tempfile::TempDir::new().unwrap();
+        let key = generate_key(&dir.path().join("signing.key")).unwrap();
+        let file = dir.path().join("weather.star");
+        std::fs::write(&file, "x = 1\n").unwrap();
+        (dir, key, file)
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (dir, key, file) = setup();
+        assert_eq!(
+            public_key(&read_key(&dir.path().join("signing.key")).unwrap()),
+            public_key(&key)
+        );
+        assert_eq!(
+            sign_file(&key, &file).unwrap(),
+            dir.path().join("weather.star.sig")
+        );
+        assert_eq!(
+            signed_path(&dir.path().join("weather.star.sig")),
+            Some(file.clone())
+        );
+
+        let verifier = SignatureVerifier::new(&[public_key(&key)], UnsignedPolicy::Reject).unwrap();
+        assert!(verifier.verify_file(&file).is_ok());
+
+        let err = verifier.verify(&file, b"x = 2\n").unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("weather.star is not signed by a trusted key")
+        );
+
+        let other = SigningKey::from_bytes(&[7; 32]);
+        let verifier =
+            SignatureVerifier::new(&[public_key(&other)], UnsignedPolicy::Reject).unwrap();
+        assert!(verifier.verify_file(&file).is_err());
+    }
+
+    #[test]
+    fn test_policy() {
+        let (dir, key, file) = setup();
+        let keys = [public_key(&key)];
+
+        let reject = SignatureVerifier::new(&keys, UnsignedPolicy::Reject).unwrap();
+        let err = reject.check_file(&file).unwrap_err();
+        assert!(
+            err.to_string().contains("weather.star is not signed: "),
+            "{}",
+            err
+        );
+
+        let restrict = SignatureVerifier::new(&keys, UnsignedPolicy::Restrict).unwrap();
+        let reason = restrict.check_file(&file).unwrap().unwrap();
+        assert!(reason.contains("weather.star.sig not found"), "{}", reason);
+
+        sign_file(&key, &file).unwrap();
+        assert_eq!(restrict.check_file(&file).unwrap(), None);
+
+        std::fs::write(&file, "x = 2\n").unwrap();
+        let file = file.canonicalize().unwrap();
+        let unpacked = HashMap::from([(file.clone(), crate::lockfile::sha256(b"x = 2\n"))]);
+        restrict.trust_files(dir.path(), &unpacked);
+        assert_eq!(restrict.check_file(&file).unwrap(), None);
+        std::fs::write(&file, "x = 3\n").unwrap();
+        let reason = restrict.check_file(&file).unwrap().unwrap();
+        assert!(
+            reason.contains("changed after it was unpacked"),
+            "{}",
+            reason
+        );
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let err = SignatureVerifier::new(&["abc".to_string()], UnsignedPolicy::Reject).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid trusted key 'abc'"));
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("signing.key");
+        generate_key(&path).unwrap();
+        assert!(
+            generate_key(&path).is_err(),
+            "existing keys are not overwritten"
+        );
+    }
+}
@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::diagnostics::SourceSpan;
+use crate::mcp::{ToolContent, ToolResult};
+
+/// Header starlark puts before the frames of a call stack
+const TRACEBACK_HEADER: &str = "Traceback (most recent call last):";
+
+/// How much of a failed handler's error goes into the text of its tool result.
+/// The frames are always in `structuredContent`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorVerbosity {
+    /// The error message and where it was raised
+    #[default]
+    Message,
+    /// The message followed by the Starlark call stack
+    Backtrace,
+}
+
+impl FromStr for ErrorVerbosity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "message" => Ok(ErrorVerbosity::Message),
+            "backtrace" => Ok(ErrorVerbosity::Backtrace),
+            _ => Err(format!(
+                "unknown error verbosity '{}', expected 'message' or 'backtrace'",
+                s
+            )),
+        }
+    }
+}
+
+/// One frame of a Starlark call stack: the function running and the point it
+/// had reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StackFrame {
+    /// `None` when the function couldn't be determined, e.g. top-level code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    /// File name as Starlark reports it: the extension name, or a module's
+    /// path within its extensions directory
+    pub file: String,
+    /// The file on disk, when it could be found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    /// The line of source, trimmed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)?;
+        if let Some(ref function) = self.function {
+            write!(f, ", in {}", function)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps the file names in Starlark spans back to files on disk
+#[derive(Debug, Clone, Default)]
+pub struct SourceFiles {
+    /// Name the extension was loaded under, which its spans use as the file
+    extension: String,
+    path: Option<PathBuf>,
+    root: Option<PathBuf>,
+}
+
+impl SourceFiles {
+    pub fn new(extension: &str, path: Option<PathBuf>, root: Option<PathBuf>) -> Self {
+        Self {
+            extension: extension.to_string(),
+            path,
+            root,
+        }
+    }
+
+    pub fn resolve(&self, file: &str) -> Option<PathBuf> {
+        if file == self.extension {
+            return self.path.clone();
+        }
+        let path = self.root.as_ref()?.join(file);
+        path.is_file().then_some(path)
+    }
+}
+
+/// A handler that failed with a Starlark error, with the call stack that led
+/// to it
+#[derive(Debug, Clone)]
+pub struct HandlerError {
+    /// The error message, without location or traceback
+    pub message: String,
+    /// Outermost first; the last frame is where the error was raised
+    pub frames: Vec<StackFrame>,
+}
+
+impl HandlerError {
+    pub fn new(error: &starlark::Error, sources: &SourceFiles) -> Self {
+        let mut lines = SourceLines::new(sources);
+        let mut frames: Vec<StackFrame> = parse_traceback(&error.to_string())
+            .into_iter()
+            .map(|(function, file, line)| {
+                let source = lines.get(&file, line);
+                StackFrame {
+                    function: Some(function),
+                    path: sources.resolve(&file),
+                    column: source.as_deref().map(indent_column).unwrap_or(1),
+                    source: source.map(|s| s.trim().to_string()),
+                    file,
+                    line,
+                }
+            })
+            .collect();
+
+        if let Some(span) = error.span().map(SourceSpan::from_file_span) {
+            let raised_at_call = frames
+                .last()
+                .is_some_and(|last| last.file == span.file && last.line == span.line);
+            if raised_at_call {
+                // A builtin failed; starlark doesn't list builtins as frames
+                frames.last_mut().unwrap().column = span.column;
+            } else {
+                let source = lines.get(&span.file, span.line);
+                frames.push(StackFrame {
+                    function: lines.enclosing_function(&span.file, span.line),
+                    path: sources.resolve(&span.file),
+                    file: span.file,
+                    line: span.line,
+                    column: span.column,
+                    source: source.map(|s| s.trim().to_string()),
+                });
+            }
+        }
+
+        // Each frame but the last stopped at a call to the next one
+        for i in 1..frames.len() {
+            let Some(callee) = frames[i].function.clone() else {
+                continue;
+            };
+            let caller = &frames[i - 1];
+            if let Some(column) = lines
+                .get(&caller.file, caller.line)
+                .and_then(|line| call_column(&line, &callee))
+            {
+                frames[i - 1].column = column;
+            }
+        }
+
+        Self {
+            message: error.without_diagnostic().to_string(),
+            frames,
+        }
+    }
+
+    /// Where the error was raised
+    pub fn location(&self) -> Option<&StackFrame> {
+        self.frames.last()
+    }
+
+    /// The call stack in the style of a Python traceback, innermost call last
+    pub fn backtrace(&self) -> String {
+        let mut text = format!("{}\n", TRACEBACK_HEADER);
+        for frame in &self.frames {
+            text.push_str(&format!("  {}\n", frame));
+            if let Some(ref source) = frame.source {
+                text.push_str(&format!("    {}\n", source));
+            }
+        }
+        text
+    }
+
+    pub fn to_tool_result(&self, tool_name: &str, verbosity: ErrorVerbosity) -> ToolResult {
+        let mut text = format!("Error: tool '{}' failed: {}", tool_name, self.message);
+        if let Some(location) = self.location() {
+            text.push_str(&format!(
+                " ({}:{}:{})",
+                location.file, location.line, location.column
+            ));
+        }
+        if verbosity == ErrorVerbosity::Backtrace && !self.frames.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(self.backtrace().trim_end());
+        }
+
+        ToolResult {
+            content: vec![ToolContent::Text { text }],
+            is_error: Some(true),
+            structured_content: Some(serde_json::json!({
+                "error": "handler_error",
+                "message": self.message,
+                "frames": self.frames,
+            })),
+        }
+    }
+}
+
+/// `(function, file, line)` for each Starlark frame of a formatted error,
+/// outermost first. The location is the call the function was making.
+///
+/// starlark doesn't expose an error's call stack, only its rendering, e.g.
+///
+/// ```text
+/// Traceback (most recent call last):
+///   File <builtin>, in <module>
+///   * weather:10, in forecast
+///       return parse(fetch(city))
+/// error: ...
+/// ```
+fn parse_traceback(formatted: &str) -> Vec<(String, String, usize)> {
+    let mut lines = formatted.lines();
+    if !lines.any(|line| line == TRACEBACK_HEADER) {
+        return Vec::new();
+    }
+
+    let mut frames = Vec::new();
+    for line in lines.take_while(|line| line.starts_with("  ")) {
+        let Some(frame) = line.strip_prefix("  * ") else {
+            continue;
+        };
+        let Some((location, function)) = frame.rsplit_once(", in ") else {
+            continue;
+        };
+        let Some((file, line)) = location.rsplit_once(':') else {
+            continue;
+        };
+        if let Ok(line) = line.parse() {
+            frames.push((function.to_string(), file.to_string(), line));
+        }
+    }
+    frames
+}
+
+/// 1-based column of the first non-blank character
+fn indent_column(line: &str) -> usize {
+    line.chars().take_while(|c| c.is_whitespace()).count() + 1
+}
+
+/// 1-based column of a call to `function` in `line`
+fn call_column(line: &str, function: &str) -> Option<usize> {
+    let call = format!("{}(", function);
+    line.match_indices(&call)
+        .find(|(i, _)| {
+            line[..*i]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !(c.is_alphanumeric() || c == '_'))
+        })
+        .map(|(i, _)| line[..i].chars().count() + 1)
+}
+
+/// Source files read while building a backtrace
+struct SourceLines<'a> {
+    sources: &'a SourceFiles,
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+impl<'a> SourceLines<'a> {
+    fn new(sources: &'a SourceFiles) -> Self {
+        Self {
+            sources,
+            files: HashMap::new(),
+        }
+    }
+
+    fn lines(&mut self, file: &str) -> Option<&Vec<String>> {
+        let sources = self.sources;
+        self.files
+            .entry(file.to_string())
+            .or_insert_with(|| {
+                let path = sources.resolve(file)?;
+                let content = std::fs::read_to_string(Path::new(&path)).ok()?;
+                Some(content.lines().map(str::to_string).collect())
+            })
+            .as_ref()
+    }
+
+    /// 1-based `line` of `file`
+    fn get(&mut self, file: &str, line: usize) -> Option<String> {
+        self.lines(file)?.get(line.checked_sub(1)?).cloned()
+    }
+
+    /// Name of the innermost `def` containing `line`
+    fn enclosing_function(&mut self, file: &str, line: usize) -> Option<String> {
+        let lines = self.lines(file)?;
+        let mut indent = indent_column(lines.get(line.checked_sub(1)?)?);
+        for text in lines[..line - 1].iter().rev() {
+            if text.trim().is_empty() || text.trim_start().starts_with('#') {
+                continue;
+            }
+            let column = indent_column(text);
+            if column >= indent {
+                continue;
+            }
+            if let Some(def) = text.trim_start().strip_prefix("def ") {
+                return def.split('(').next().map(|name| name.trim().to_string());
+            }
+            if column == 1 {
+                return None;
+            }
+            indent = column;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEBACK: &str = "Traceback (most recent call last):
+  File <builtin>, in <module>
+  * weather:10, in forecast
+      return {\"content\": [{\"type\": \"text\", \"text\": str(fetch(city))}]}
+  * weather:5, in fetch
+      return parse(data)
+error: Key `\"missing\"` was not found
+ --> lib/util.star:2:12
+  |
+2 |     return resp[\"json\"][\"missing\"]
+  |            ^^^^^^^^^^^^^^^^^^^^^^^
+";
+
+    #[test]
+    fn test_parse_traceback() {
+        assert_eq!(
+            parse_traceback(TRACEBACK),
+            vec![
+                ("forecast".to_string(), "weather".to_string(), 10),
+                ("fetch".to_string(), "weather".to_string(), 5),
+            ]
+        );
+        assert!(parse_traceback("error: boom\n --> weather:1:1").is_empty());
+    }
+
+    #[test]
+    fn test_call_column() {
+        let line = "    return reparse(x) + parse(fetch(city))";
+        assert_eq!(call_column(line, "parse"), Some(25));
+        assert_eq!(call_column(line, "fetch"), Some(31));
+        assert_eq!(call_column(line, "missing"), None);
+    }
+
+    #[test]
+    fn test_enclosing_function() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("util.star"),
+            "def parse(resp):\n    if resp:\n        # the key\n        return resp[\"x\"]\n\nX = 1\n",
+        )
+        .unwrap();
+        let sources = SourceFiles::new("weather", None, Some(dir.path().to_path_buf()));
+        let mut lines = SourceLines::new(&sources);
+
+        assert_eq!(
+            lines.enclosing_function("util.star", 4).as_deref(),
+            Some("parse")
+        );
+        assert_eq!(lines.enclosing_function("util.star", 6), None);
+        assert_eq!(lines.enclosing_function("missing.star", 1), None);
+    }
+
+    #[test]
+    fn test_verbosity_from_str() {
+        assert_eq!("backtrace".parse(), Ok(ErrorVerbosity::Backtrace));
+        assert!("verbose".parse::<ErrorVerbosity>().is_err());
+    }
+}
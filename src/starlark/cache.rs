@@ -0,0 +1,377 @@
+use allocative::Allocative;
+use anyhow::anyhow;
+use derive_more::Display;
+use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
+use starlark::starlark_module;
+use starlark::starlark_simple_value;
+use starlark::values::none::NoneType;
+use starlark::values::starlark_value;
+use starlark::values::{NoSerialize, ProvidesStaticType, StarlarkValue, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::context::CallContext;
+use super::engine::{json_to_starlark_value, starlark_value_to_json};
+
+/// Maximum number of entries kept per extension
+pub const DEFAULT_CACHE_CAPACITY: usize = 256;
+
+/// Default lifetime of a cached value, in seconds
+const DEFAULT_TTL_SECS: i32 = 300;
+
+/// In-memory memoization cache owned by one loaded extension.
+///
+/// Bounded by least-recently-used eviction. Like [`ExtensionState`], a fresh
+/// cache is created on every load, so hot reloads never serve stale results.
+///
+/// [`ExtensionState`]: super::state::ExtensionState
+#[derive(Debug, Clone)]
+pub struct ExtensionCache {
+    inner: Arc<Mutex<LruCache>>,
+}
+
+#[derive(Debug)]
+struct LruCache {
+    capacity: usize,
+    entries: HashMap<String, CacheEntry>,
+    /// Monotonic counter used to order entries by last use
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    value: serde_json::Value,
+    expires_at: Option<Instant>,
+    last_used: u64,
+}
+
+impl Default for ExtensionCache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CACHE_CAPACITY)
+    }
+}
+
+impl ExtensionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(LruCache {
+                capacity: capacity.max(1),
+                entries: HashMap::new(),
+                tick: 0,
+            })),
+        }
+    }
+
+    /// Look up a live entry, marking it as recently used
+    pub fn get(&self, key: &str) -> Option<serde_json::Value> {
+        let mut cache = self.inner.lock().unwrap();
+        cache.tick += 1;
+        let tick = cache.tick;
+
+        let expired = match cache.entries.get_mut(key) {
+            Some(entry) if entry.expires_at.is_some_and(|at| at <= Instant::now()) => true,
+            Some(entry) => {
+                entry.last_used = tick;
+                return Some(entry.value.clone());
+            }
+            None => return None,
+        };
+
+        if expired {
+            cache.entries.remove(key);
+        }
+        None
+    }
+
+    /// Store a value, evicting the least recently used entry when full
+    pub fn insert(&self, key: &str, value: serde_json::Value, ttl: Option<Duration>) {
+        let mut cache = self.inner.lock().unwrap();
+        cache.tick += 1;
+        let tick = cache.tick;
+
+        if !cache.entries.contains_key(key) && cache.entries.len() >= cache.capacity {
+            let now = Instant::now();
+            let victim = cache
+                .entries
+                .iter()
+                .find(|(_, e)| e.expires_at.is_some_and(|at| at <= now))
+                .or_else(|| cache.entries.iter().min_by_key(|(_, e)| e.last_used))
+                .map(|(k, _)| k.clone());
+            if let Some(victim) = victim {
+                cache.entries.remove(&victim);
+            }
+        }
+
+        cache.entries.insert(
+            key.to_string(),
+            CacheEntry {
+                value,
+                expires_at: ttl.map(|ttl| Instant::now() + ttl),
+                last_used: tick,
+            },
+        );
+    }
+
+    pub fn invalidate(&self, key: &str) -> bool {
+        self.inner.lock().unwrap().entries.remove(key).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
+#[display(fmt = "cache")]
+pub struct CacheModule;
+
+starlark_simple_value!(CacheModule);
+
+#[starlark_value(type = "cache")]
+impl<'v> StarlarkValue<'v> for CacheModule {
+    fn get_methods() -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new("cache", cache_methods);
+        Some(RES.methods())
+    }
+
+    fn dir_attr(&self) -> Vec<String> {
+        vec![
+            "get_or_compute".to_owned(),
+            "invalidate".to_owned(),
+            "clear".to_owned(),
+        ]
+    }
+}
+
+#[starlark_module]
+#[allow(clippy::type_complexity)]
+fn cache_methods(builder: &mut MethodsBuilder) {
+    /// Return the cached value for `key`, or call `compute()` and cache its
+    /// result for `ttl_secs` seconds (default 300; None keeps it until evicted
+    /// or the extension reloads). Results must be JSON-compatible.
+    ///
+    /// # Examples
+    /// ```python
+    /// def fetch_grid():
+    ///     return http.get(url = points_url)["json"]
+    ///
+    /// grid = cache.get_or_compute("points:" + points_url, fetch_grid, ttl_secs = 3600)
+    /// ```
+    fn get_or_compute<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        compute: Value<'v>,
+        #[starlark(default = DEFAULT_TTL_SECS)] ttl_secs: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let ctx = CallContext::require(eval, "cache.get_or_compute")?;
+        let cache = ctx.cache.clone();
+
+        let ttl = if ttl_secs.is_none() {
+            None
+        } else {
+            match ttl_secs.unpack_i32() {
+                Some(secs) if secs > 0 => Some(Duration::from_secs(secs as u64)),
+                _ => {
+                    return Err(anyhow!(
+                        "cache.get_or_compute: ttl_secs must be a positive integer or None, got {}",
+                        ttl_secs
+                    ));
+                }
+            }
+        };
+
+        if let Some(value) = cache.get(key) {
+            return json_to_starlark_value(value, eval.heap());
+        }
+
+        let value = eval
+            .eval_function(compute, &[], &[])
+            .map_err(|e| anyhow!("{}", e))?;
+        let json = starlark_value_to_json(value, eval.heap()).map_err(|e| {
+            anyhow!(
+                "cache.get_or_compute: result for '{}' cannot be cached: {}",
+                key,
+                e
+            )
+        })?;
+        cache.insert(key, json, ttl);
+
+        Ok(value)
+    }
+
+    /// Drop a cached entry. Returns True if it was present.
+    fn invalidate<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<bool> {
+        let ctx = CallContext::require(eval, "cache.invalidate")?;
+        Ok(ctx.cache.invalidate(key))
+    }
+
+    /// Drop every cached entry for this extension.
+    fn clear<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<NoneType> {
+        let ctx = CallContext::require(eval, "cache.clear")?;
+        ctx.cache.clear();
+        Ok(NoneType)
+    }
+}
+
+pub fn register(builder: &mut GlobalsBuilder) {
+    const CACHE: CacheModule = CacheModule;
+    builder.set("cache", CACHE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::environment::{GlobalsBuilder, Module};
+    use starlark::syntax::{AstModule, Dialect};
+
+    fn eval_with_cache(code: &str, cache: &ExtensionCache) -> Result<String, starlark::Error> {
+        let ctx = CallContext::new("test").with_cache(cache.clone());
+
+        let globals = GlobalsBuilder::standard().with(register).build();
+        Module::with_temp_heap(|module| {
+            let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
+            let mut eval = Evaluator::new(&module);
+            ctx.attach(&mut eval);
+            let result = eval.eval_module(ast, &globals)?;
+
+            Ok(result.to_string())
+        })
+    }
+
+    #[test]
+    fn test_get_or_compute_memoizes() {
+        let cache = ExtensionCache::new();
+        let result = eval_with_cache(
+            r#"
+calls = []
+def compute():
+    calls.append(1)
+    return {"temp": 21}
+
+first = cache.get_or_compute("weather", compute)
+second = cache.get_or_compute("weather", compute)
+(first["temp"], second["temp"], len(calls))
+"#,
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(result, "(21, 21, 1)");
+    }
+
+    #[test]
+    fn test_invalidate_and_clear() {
+        let cache = ExtensionCache::new();
+        let result = eval_with_cache(
+            r#"
+calls = []
+def compute():
+    calls.append(1)
+    return len(calls)
+
+cache.get_or_compute("a", compute)
+removed = cache.invalidate("a")
+again = cache.get_or_compute("a", compute)
+cache.clear()
+(removed, cache.invalidate("a"), again)
+"#,
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(result, "(True, False, 2)");
+    }
+
+    #[test]
+    fn test_compute_error_not_cached() {
+        let cache = ExtensionCache::new();
+        let result = eval_with_cache(
+            r#"
+def compute():
+    fail("upstream down")
+
+cache.get_or_compute("a", compute)
+"#,
+            &cache,
+        );
+        assert!(result.unwrap_err().to_string().contains("upstream down"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_uncacheable_result() {
+        let cache = ExtensionCache::new();
+        let result = eval_with_cache(
+            r#"
+def compute():
+    return compute
+
+cache.get_or_compute("a", compute)
+"#,
+            &cache,
+        );
+        assert!(result.unwrap_err().to_string().contains("cannot be cached"));
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let cache = ExtensionCache::new();
+        cache.insert(
+            "short",
+            serde_json::json!(1),
+            Some(Duration::from_millis(20)),
+        );
+        cache.insert("forever", serde_json::json!(2), None);
+
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert_eq!(cache.get("short"), None);
+        assert_eq!(cache.get("forever"), Some(serde_json::json!(2)));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = ExtensionCache::with_capacity(2);
+        cache.insert("a", serde_json::json!(1), None);
+        cache.insert("b", serde_json::json!(2), None);
+
+        // Touch "a" so "b" becomes the least recently used
+        assert!(cache.get("a").is_some());
+        cache.insert("c", serde_json::json!(3), None);
+
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+
+    #[test]
+    fn test_invalid_ttl() {
+        let cache = ExtensionCache::new();
+        let result = eval_with_cache(
+            r#"cache.get_or_compute("a", lambda: 1, ttl_secs = -5)"#,
+            &cache,
+        );
+        assert!(result.unwrap_err().to_string().contains("ttl_secs"));
+    }
+}
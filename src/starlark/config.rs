@@ -0,0 +1,479 @@
+use allocative::Allocative;
+use derive_more::Display;
+use serde::Deserialize;
+use starlark::PrintHandler;
+use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
+use starlark::starlark_module;
+use starlark::starlark_simple_value;
+use starlark::values::none::NoneType;
+use starlark::values::starlark_value;
+use starlark::values::{NoSerialize, ProvidesStaticType, StarlarkValue, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::context::{CallContext, Capabilities};
+use super::mcp_types::StarlarkConfigVar;
+use crate::mcp::ToolResult;
+
+/// Placeholder substituted for secret values
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Secrets shorter than this are rejected as if they were missing: they are
+/// too likely to turn up in ordinary text to be redacted reliably
+pub const MIN_SECRET_LEN: usize = 6;
+
+/// Server-side overrides for one extension, from `[extensions.<name>]` in the
+/// server config file
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExtensionSettings {
+    pub enabled: bool,
+    /// Tools that are never registered or callable
+    pub disabled_tools: Vec<String>,
+    /// Replaces the `allowed_exec` declared by the extension
+    pub allowed_exec: Option<Vec<String>>,
+    /// Replaces the `allowed_hosts` declared by the extension
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Let `http` reach private and link-local addresses
+    pub allow_private_network: bool,
+    /// Replaces the `allowed_env` declared by the extension
+    pub allowed_env: Option<Vec<String>>,
+    /// Environment visible to this extension only, through `env.get()` and
+    /// `ConfigVar()`; takes precedence over the process environment
+    pub env: HashMap<String, String>,
+}
+
+impl Default for ExtensionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            disabled_tools: Vec::new(),
+            allowed_exec: None,
+            allowed_hosts: None,
+            allow_private_network: false,
+            allowed_env: None,
+            env: HashMap::new(),
+        }
+    }
+}
+
+/// Configuration values resolved for one loaded extension from the inputs it
+/// declares with `ConfigVar()`
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionConfig {
+    values: HashMap<String, String>,
+    missing: Vec<String>,
+    /// Declared variables `allowed_env` keeps out of the process environment
+    denied: Vec<String>,
+    redactor: Redactor,
+    /// Environment injected by the server config
+    env: Arc<HashMap<String, String>>,
+    /// Decides which process environment variables the extension may read
+    capabilities: Capabilities,
+}
+
+impl ExtensionConfig {
+    /// Resolve `vars` from `env`, then the process environment. Variables
+    /// outside the extension's `allowed_env` are only read from `env`.
+    pub fn new(
+        vars: &[StarlarkConfigVar],
+        env: HashMap<String, String>,
+        capabilities: &Capabilities,
+    ) -> Self {
+        let mut config = Self::resolve(vars, |name| {
+            env.get(name).cloned().or_else(|| {
+                capabilities
+                    .allows_env(name)
+                    .then(|| std::env::var(name).ok())
+                    .flatten()
+            })
+        });
+        config.denied = vars
+            .iter()
+            .filter(|var| !env.contains_key(&var.name) && !capabilities.allows_env(&var.name))
+            .map(|var| var.name.clone())
+            .collect();
+        config.env = Arc::new(env);
+        config.capabilities = capabilities.clone();
+        config
+    }
+
+    /// Resolve `vars` using `lookup`, falling back to each variable's default.
+    /// Required variables with neither a value nor a default are recorded as
+    /// missing, and so are secrets shorter than [`MIN_SECRET_LEN`], which
+    /// couldn't be redacted.
+    pub fn resolve(vars: &[StarlarkConfigVar], lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let mut values = HashMap::new();
+        let mut missing = Vec::new();
+        let mut secrets = Vec::new();
+
+        for var in vars {
+            let value = lookup(&var.name)
+                .filter(|v| !v.is_empty())
+                .or_else(|| var.default.clone());
+            match value {
+                Some(value) if var.secret && value.len() < MIN_SECRET_LEN => {
+                    tracing::warn!(
+                        "Secret config '{}' is shorter than {} characters, so it can't be redacted; treating it as missing",
+                        var.name,
+                        MIN_SECRET_LEN
+                    );
+                    missing.push(var.name.clone());
+                }
+                Some(value) => {
+                    if var.secret {
+                        secrets.push(value.clone());
+                    }
+                    values.insert(var.name.clone(), value);
+                }
+                None if var.required => missing.push(var.name.clone()),
+                None => {}
+            }
+        }
+
+        Self {
+            values,
+            missing,
+            denied: Vec::new(),
+            redactor: Redactor::new(secrets),
+            env: Arc::default(),
+            capabilities: Capabilities::default(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    /// Required variables that have no value
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    /// Declared variables that weren't read from the process environment
+    /// because they are outside `allowed_env`
+    pub fn denied(&self) -> &[String] {
+        &self.denied
+    }
+
+    pub fn redactor(&self) -> &Redactor {
+        &self.redactor
+    }
+
+    /// Whether the server config sets `name` for this extension
+    pub fn injects(&self, name: &str) -> bool {
+        self.env.contains_key(name)
+    }
+
+    /// An environment variable as seen by the extension: injected values
+    /// first, then the process environment if `allowed_env` permits it
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        self.env.get(name).cloned().or_else(|| {
+            self.capabilities
+                .allows_env(name)
+                .then(|| std::env::var(name).ok())
+                .flatten()
+        })
+    }
+}
+
+/// Replaces secret configuration values with [`REDACTED`] in text that leaves
+/// the extension: logs, error messages, `print()` output and tool results.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    /// Longest first, so a secret containing another is replaced whole
+    secrets: Arc<Vec<String>>,
+}
+
+impl Redactor {
+    /// A redactor for `secrets`, ignoring any shorter than [`MIN_SECRET_LEN`];
+    /// extension config never gets that far, since it rejects them
+    pub fn new(secrets: impl IntoIterator<Item = String>) -> Self {
+        let mut secrets: Vec<String> = secrets
+            .into_iter()
+            .filter(|s| s.len() >= MIN_SECRET_LEN)
+            .collect();
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+        secrets.dedup();
+        Self {
+            secrets: Arc::new(secrets),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        self.secrets.iter().fold(text.to_string(), |text, secret| {
+            text.replace(secret, REDACTED)
+        })
+    }
+
+    /// Redact every string in `value`, including object keys
+    pub fn redact_json(&self, value: serde_json::Value) -> serde_json::Value {
+        if self.is_empty() {
+            return value;
+        }
+        match value {
+            serde_json::Value::String(s) => serde_json::Value::String(self.redact(&s)),
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.into_iter().map(|v| self.redact_json(v)).collect())
+            }
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.into_iter()
+                    .map(|(k, v)| (self.redact(&k), self.redact_json(v)))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    pub fn redact_result(&self, result: ToolResult) -> ToolResult {
+        if self.is_empty() {
+            return result;
+        }
+        match serde_json::to_value(&result) {
+            Ok(json) => serde_json::from_value(self.redact_json(json)).unwrap_or(result),
+            Err(_) => result,
+        }
+    }
+
+    pub fn redact_error(&self, error: anyhow::Error) -> anyhow::Error {
+        if self.is_empty() {
+            return error;
+        }
+        anyhow::anyhow!("{}", self.redact(&format!("{:#}", error)))
+    }
+}
+
+/// `print()` from extensions goes to stderr, as with Starlark's default
+/// handler, but with secrets redacted
+impl PrintHandler for Redactor {
+    fn println(&self, text: &str) -> starlark::Result<()> {
+        eprintln!("{}", self.redact(text));
+        Ok(())
+    }
+}
+
+#[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
+#[display(fmt = "config")]
+pub struct ConfigModule;
+
+starlark_simple_value!(ConfigModule);
+
+#[starlark_value(type = "config")]
+impl<'v> StarlarkValue<'v> for ConfigModule {
+    fn get_methods() -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new("config", config_methods);
+        Some(RES.methods())
+    }
+
+    fn dir_attr(&self) -> Vec<String> {
+        vec!["get".to_owned()]
+    }
+}
+
+#[starlark_module]
+#[allow(clippy::type_complexity)]
+fn config_methods(builder: &mut MethodsBuilder) {
+    /// Get the value of a variable declared with `ConfigVar()`, or `default`
+    /// if it has no value. Values are resolved when the extension loads, so
+    /// they are available from `on_load()` and tool handlers but not from
+    /// top-level code.
+    ///
+    /// # Examples
+    /// ```python
+    /// api_key = config.get("PLANE_API_KEY")
+    /// ```
+    fn get<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        name: &str,
+        #[starlark(default = NoneType)] default: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let ctx = CallContext::require(eval, "config.get")?;
+        match ctx.config.get(name) {
+            Some(value) => Ok(eval.heap().alloc(value)),
+            None => Ok(default),
+        }
+    }
+}
+
+pub fn register(builder: &mut GlobalsBuilder) {
+    const CONFIG: ConfigModule = ConfigModule;
+    builder.set("config", CONFIG);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::environment::{GlobalsBuilder, Module};
+    use starlark::syntax::{AstModule, Dialect};
+
+    fn var(name: &str, required: bool, secret: bool, default: Option<&str>) -> StarlarkConfigVar {
+        StarlarkConfigVar {
+            name: name.to_string(),
+            required,
+            secret,
+            default: default.map(str::to_string),
+            description: String::new(),
+        }
+    }
+
+    fn lookup(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let pairs: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| pairs.get(name).cloned()
+    }
+
+    #[test]
+    fn test_resolve_values_defaults_and_missing() {
+        let vars = vec![
+            var("API_KEY", true, true, None),
+            var("WORKSPACE", true, false, None),
+            var("BASE_URL", false, false, Some("https://api.example.com")),
+            var("OPTIONAL", false, false, None),
+        ];
+        let config = ExtensionConfig::resolve(&vars, lookup(&[("API_KEY", "sk-123456")]));
+
+        assert_eq!(config.get("API_KEY"), Some("sk-123456"));
+        assert_eq!(config.get("BASE_URL"), Some("https://api.example.com"));
+        assert_eq!(config.get("OPTIONAL"), None);
+        assert_eq!(config.missing(), ["WORKSPACE"]);
+    }
+
+    #[test]
+    fn test_injected_env_takes_precedence() {
+        let vars = vec![var("PATH", true, false, None)];
+        let env = HashMap::from([("PATH".to_string(), "/injected".to_string())]);
+        let config = ExtensionConfig::new(&vars, env, &Capabilities::default());
+
+        assert_eq!(config.get("PATH"), Some("/injected"));
+        assert_eq!(config.env_var("PATH").as_deref(), Some("/injected"));
+        assert_eq!(
+            ExtensionConfig::default().env_var("PATH"),
+            std::env::var("PATH").ok()
+        );
+    }
+
+    #[test]
+    fn test_process_env_limited_to_allowed_env() {
+        let vars = vec![
+            var("PATH", true, true, None),
+            var("HOME", false, false, Some("/nowhere")),
+            var("INJECTED", true, false, None),
+        ];
+        let env = HashMap::from([("INJECTED".to_string(), "yes".to_string())]);
+        let capabilities = Capabilities {
+            allowed_env: Some(vec!["PLANE_*".to_string()]),
+            ..Capabilities::default()
+        };
+        let config = ExtensionConfig::new(&vars, env, &capabilities);
+
+        assert_eq!(config.get("PATH"), None);
+        assert_eq!(config.get("HOME"), Some("/nowhere"));
+        assert_eq!(config.get("INJECTED"), Some("yes"));
+        assert_eq!(config.missing(), ["PATH"]);
+        assert_eq!(config.denied(), ["PATH", "HOME"]);
+        assert_eq!(config.env_var("PATH"), None);
+    }
+
+    #[test]
+    fn test_empty_value_counts_as_missing() {
+        let vars = vec![var("API_KEY", true, true, None)];
+        let config = ExtensionConfig::resolve(&vars, lookup(&[("API_KEY", "")]));
+        assert_eq!(config.missing(), ["API_KEY"]);
+    }
+
+    #[test]
+    fn test_redacts_only_secrets() {
+        let vars = vec![
+            var("API_KEY", true, true, None),
+            var("WORKSPACE", true, false, None),
+        ];
+        let config = ExtensionConfig::resolve(
+            &vars,
+            lookup(&[("API_KEY", "sk-123456"), ("WORKSPACE", "acme")]),
+        );
+        let redactor = config.redactor();
+
+        assert_eq!(
+            redactor.redact("GET /acme with key sk-123456"),
+            "GET /acme with key [REDACTED]"
+        );
+        assert_eq!(
+            redactor.redact_json(serde_json::json!({"headers": ["X-API-Key: sk-123456"]})),
+            serde_json::json!({"headers": ["X-API-Key: [REDACTED]"]})
+        );
+        assert_eq!(
+            redactor
+                .redact_error(anyhow::anyhow!("401 for sk-123456"))
+                .to_string(),
+            "401 for [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn test_redact_result() {
+        let redactor = Redactor::new(vec!["hunter2".to_string()]);
+        let result: ToolResult = serde_json::from_value(serde_json::json!({
+            "content": [{"type": "text", "text": "password is hunter2"}],
+            "structuredContent": {"password": "hunter2"},
+        }))
+        .unwrap();
+
+        let redacted = serde_json::to_value(redactor.redact_result(result)).unwrap();
+        assert_eq!(redacted["content"][0]["text"], "password is [REDACTED]");
+        assert_eq!(redacted["structuredContent"]["password"], "[REDACTED]");
+    }
+
+    #[test]
+    fn test_short_secrets_are_not_redacted() {
+        let redactor = Redactor::new(vec!["true".to_string(), "hunter2".to_string()]);
+        assert_eq!(
+            redactor.redact("enabled: true, password: hunter2"),
+            "enabled: true, password: [REDACTED]"
+        );
+        assert!(Redactor::new(vec!["12345".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_short_secrets_count_as_missing() {
+        let vars = vec![
+            var("PIN", false, true, None),
+            var("API_KEY", true, true, Some("12345")),
+            var("PORT", true, false, None),
+        ];
+        let config = ExtensionConfig::resolve(&vars, lookup(&[("PIN", "1234"), ("PORT", "8080")]));
+        assert_eq!(config.missing(), ["PIN", "API_KEY"]);
+        assert_eq!(config.get("PIN"), None);
+        assert_eq!(config.get("API_KEY"), None);
+        assert_eq!(config.get("PORT"), Some("8080"));
+    }
+
+    #[test]
+    fn test_config_get() {
+        let vars = vec![var("REGION", false, false, Some("us-east-1"))];
+        let ctx = CallContext::new("test").with_config(ExtensionConfig::resolve(&vars, |_| None));
+
+        let globals = GlobalsBuilder::standard().with(register).build();
+        Module::with_temp_heap(|module| {
+            let ast = AstModule::parse(
+                "test.star",
+                r#"(config.get("REGION"), config.get("UNSET"), config.get("UNSET", "x"))"#
+                    .to_owned(),
+                &Dialect::Standard,
+            )
+            .unwrap();
+            let mut eval = Evaluator::new(&module);
+            ctx.attach(&mut eval);
+            let result = eval.eval_module(ast, &globals).unwrap();
+
+            assert_eq!(result.to_string(), r#"("us-east-1", None, "x")"#);
+        })
+    }
+}
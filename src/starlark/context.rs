@@ -0,0 +1,381 @@
+use anyhow::{Result, anyhow};
+use rmcp::service::{Peer, RoleServer};
+use starlark::any::ProvidesStaticType;
+use starlark::eval::Evaluator;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use super::cache::ExtensionCache;
+use super::config::ExtensionConfig;
+use super::exec_rules::ExecRule;
+use super::kv::KvStore;
+use super::state::ExtensionState;
+use crate::lockfile::Lockfile;
+use crate::signing::SignatureVerifier;
+
+/// Builtin modules that reach outside the server. An extension that declares
+/// `requires` may only use those it lists; the other builtins are always
+/// available. Top-level code runs before `requires` is known, so its uses
+/// are recorded and checked once `describe_extension()` returns.
+pub const GUARDED_MODULES: &[&str] = &["http", "exec", "postgres", "sqlite", "data", "kv"];
+
+/// Modules an extension without a trusted signature can't use when the
+/// signature policy is `restrict`
+pub const UNSIGNED_DENIED_MODULES: &[&str] = &["http", "exec"];
+
+/// What an extension is permitted to do, as declared in its `Extension()`
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    pub allowed_exec: Vec<String>,
+    /// Argument constraints for commands in `allowed_exec`
+    pub exec_rules: Vec<ExecRule>,
+    /// Modules from `requires`, or `None` if the extension doesn't declare
+    /// them, in which case every module is available
+    pub modules: Option<Vec<String>>,
+    /// Hosts `http` may reach, or `None` for any host
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Whether `http` may reach private and link-local addresses
+    pub allow_private_network: bool,
+    /// Patterns for the environment variables `env.get()` may read, or
+    /// `None` for any variable
+    pub allowed_env: Option<Vec<String>>,
+    /// Fail `env.get()` of a variable outside `allowed_env` instead of
+    /// returning the default
+    pub strict_env: bool,
+}
+
+impl Capabilities {
+    /// Whether handlers may call into `module`
+    pub fn allows_module(&self, module: &str) -> bool {
+        !GUARDED_MODULES.contains(&module)
+            || self
+                .modules
+                .as_ref()
+                .is_none_or(|modules| modules.iter().any(|m| m == module))
+    }
+
+    /// Whether `env.get()` may read the variable `name`
+    pub fn allows_env(&self, name: &str) -> bool {
+        self.allowed_env
+            .as_ref()
+            .is_none_or(|patterns| patterns.iter().any(|p| env_matches(p, name)))
+    }
+
+    /// These capabilities without [`UNSIGNED_DENIED_MODULES`] or any
+    /// commands, for an extension loaded without a trusted signature
+    pub fn restricted(self) -> Self {
+        let modules = self
+            .modules
+            .unwrap_or_else(|| GUARDED_MODULES.iter().map(|m| m.to_string()).collect());
+        Self {
+            allowed_exec: Vec::new(),
+            exec_rules: Vec::new(),
+            allowed_hosts: self.allowed_hosts,
+            allow_private_network: self.allow_private_network,
+            allowed_env: self.allowed_env,
+            strict_env: self.strict_env,
+            modules: Some(
+                modules
+                    .into_iter()
+                    .filter(|m| !UNSIGNED_DENIED_MODULES.contains(&m.as_str()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Whether `name` matches an `allowed_env` pattern, where `*` matches any
+/// run of characters: `PLANE_*` matches `PLANE_API_KEY`
+pub fn env_matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Per-evaluation state visible to builtin modules.
+///
+/// Attached to the [`Evaluator`] via `extra` for both extension loading and
+/// tool calls, so modules never depend on which thread they run on.
+#[derive(Clone, ProvidesStaticType)]
+pub struct CallContext {
+    /// Name of the extension being loaded or called
+    pub extension: String,
+    /// Tool being called, `None` while the extension is loading
+    pub tool: Option<String>,
+    pub capabilities: Capabilities,
+    pub extensions_dir: Option<PathBuf>,
+    /// Cancelled when the client cancels the request or stops waiting for it
+    pub cancellation: CancellationToken,
+    /// When the call's `timeout_secs` runs out; `http` and `exec` stop
+    /// waiting then too
+    pub deadline: Option<Instant>,
+    /// Connection to the client that made the request, when there is one
+    pub peer: Option<Peer<RoleServer>>,
+    /// The extension's mutable state, shared by all of its calls until it is reloaded
+    pub state: ExtensionState,
+    /// The extension's memoization cache; also dropped on reload
+    pub cache: ExtensionCache,
+    /// Persistent key-value store, when the server has a data directory
+    pub kv: Option<KvStore>,
+    /// Namespace for the extension's `kv` entries, set by the engine from
+    /// something the extension can't choose; defaults to `extension`
+    pub kv_namespace: Option<String>,
+    /// Values of the extension's declared `ConfigVar()`s, and the redactor for its secrets
+    pub config: ExtensionConfig,
+    /// Set in `--locked` mode: modules and data files must match it
+    pub lockfile: Option<Arc<Lockfile>>,
+    /// Set when signatures are checked: modules loaded with `load()` must be
+    /// signed unless the extension itself is unsigned
+    pub signatures: Option<Arc<SignatureVerifier>>,
+    /// The extension has no trusted signature and runs with restricted
+    /// capabilities
+    pub unsigned: bool,
+    /// Guarded modules used so far, so the extension's top-level code can be
+    /// checked against `requires` once it is known
+    modules_used: Arc<Mutex<BTreeSet<String>>>,
+}
+
+impl CallContext {
+    pub fn new(extension: impl Into<String>) -> Self {
+        Self {
+            extension: extension.into(),
+            tool: None,
+            capabilities: Capabilities::default(),
+            extensions_dir: None,
+            cancellation: CancellationToken::new(),
+            deadline: None,
+            peer: None,
+            state: ExtensionState::default(),
+            cache: ExtensionCache::default(),
+            kv: None,
+            kv_namespace: None,
+            config: ExtensionConfig::default(),
+            lockfile: None,
+            signatures: None,
+            unsigned: false,
+            modules_used: Arc::default(),
+        }
+    }
+
+    pub fn with_tool(mut self, tool: impl Into<String>) -> Self {
+        self.tool = Some(tool.into());
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_extensions_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.extensions_dir = dir;
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn with_peer(mut self, peer: Option<Peer<RoleServer>>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    pub fn with_state(mut self, state: ExtensionState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: ExtensionCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_kv(mut self, kv: Option<KvStore>) -> Self {
+        self.kv = kv;
+        self
+    }
+
+    pub fn with_kv_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.kv_namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn with_config(mut self, config: ExtensionConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_lockfile(mut self, lockfile: Option<Arc<Lockfile>>) -> Self {
+        self.lockfile = lockfile;
+        self
+    }
+
+    /// Fail if the lockfile doesn't cover `content`, read from `path`
+    pub fn verify_locked(&self, path: &std::path::Path, content: &[u8]) -> Result<()> {
+        match self.lockfile {
+            Some(ref lockfile) => lockfile.verify(path, content),
+            None => Ok(()),
+        }
+    }
+
+    pub fn with_signatures(
+        mut self,
+        signatures: Option<Arc<SignatureVerifier>>,
+        unsigned: bool,
+    ) -> Self {
+        self.signatures = signatures;
+        self.unsigned = unsigned;
+        self
+    }
+
+    /// Fail if `content`, read from `path`, a module being loaded, must be
+    /// signed and isn't. An unsigned extension may load unsigned modules,
+    /// which run as restricted as it does.
+    pub fn verify_module_signature(&self, path: &std::path::Path, content: &[u8]) -> Result<()> {
+        match self.signatures {
+            Some(ref signatures) if self.requires_signed_modules() => {
+                signatures.verify(path, content)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether modules loaded in this context must be signed
+    pub fn requires_signed_modules(&self) -> bool {
+        self.signatures.is_some() && !self.unsigned
+    }
+
+    /// Attach this context to `eval` for the lifetime of the borrow. `print()`
+    /// output is redacted with the extension's secrets.
+    pub fn attach<'a>(&'a self, eval: &mut Evaluator<'_, 'a, '_>) {
+        eval.extra = Some(self);
+        eval.set_print_handler(self.config.redactor());
+    }
+
+    /// The context attached to `eval`, if any
+    pub fn get<'a>(eval: &Evaluator<'_, 'a, '_>) -> Option<&'a CallContext> {
+        eval.extra
+            .and_then(|extra| extra.downcast_ref::<CallContext>())
+    }
+
+    /// Fail unless the extension evaluating with `eval` may use `module`.
+    /// Evaluation without a context is unrestricted.
+    pub fn check_module(eval: &Evaluator, module: &str) -> Result<()> {
+        match Self::get(eval) {
+            Some(ctx) if ctx.unsigned && UNSIGNED_DENIED_MODULES.contains(&module) => Err(anyhow!(
+                "Extension '{}' cannot use the {} module: it is not signed by a trusted key",
+                ctx.extension,
+                module
+            )),
+            Some(ctx) if !ctx.capabilities.allows_module(module) => Err(anyhow!(
+                "Extension '{}' cannot use the {} module: add \"{}\" to requires in its Extension()",
+                ctx.extension,
+                module,
+                module
+            )),
+            Some(ctx) => {
+                if GUARDED_MODULES.contains(&module) {
+                    ctx.modules_used.lock().unwrap().insert(module.to_string());
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Guarded modules used with this context so far, in name order
+    pub fn modules_used(&self) -> Vec<String> {
+        self.modules_used.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Like [`CallContext::get`], for builtins that cannot run without one
+    pub fn require<'a>(eval: &Evaluator<'_, 'a, '_>, what: &str) -> Result<&'a CallContext> {
+        Self::get(eval).ok_or_else(|| anyhow!("{}: no extension context available", what))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::environment::Module;
+
+    #[test]
+    fn test_attach_and_get() {
+        let ctx = CallContext::new("weather")
+            .with_tool("get_forecast")
+            .with_capabilities(Capabilities {
+                allowed_exec: vec!["curl".to_string()],
+                modules: Some(vec!["exec".to_string()]),
+                ..Capabilities::default()
+            });
+
+        Module::with_temp_heap(|module| {
+            let mut eval = Evaluator::new(&module);
+            assert!(CallContext::get(&eval).is_none());
+            assert!(CallContext::require(&eval, "exec.run").is_err());
+
+            ctx.attach(&mut eval);
+
+            let found = CallContext::get(&eval).unwrap();
+            assert_eq!(found.extension, "weather");
+            assert_eq!(found.tool.as_deref(), Some("get_forecast"));
+            assert_eq!(found.capabilities.allowed_exec, vec!["curl"]);
+
+            assert!(CallContext::check_module(&eval, "exec").is_ok());
+            assert!(CallContext::check_module(&eval, "math").is_ok());
+            let err = CallContext::check_module(&eval, "http").unwrap_err();
+            assert!(
+                err.to_string().contains("add \"http\" to requires"),
+                "{}",
+                err
+            );
+        })
+    }
+
+    #[test]
+    fn test_allows_env() {
+        assert!(env_matches("PLANE_*", "PLANE_API_KEY"));
+        assert!(env_matches("PLANE_*", "PLANE_"));
+        assert!(!env_matches("PLANE_*", "XPLANE_API_KEY"));
+        assert!(env_matches("*_TOKEN", "GITHUB_TOKEN"));
+        assert!(env_matches("AWS_*_ID", "AWS_ACCESS_KEY_ID"));
+        assert!(!env_matches("AWS_*_ID", "AWS_ID"));
+        assert!(env_matches("HOME", "HOME"));
+        assert!(!env_matches("HOME", "HOMEBREW_PREFIX"));
+
+        let capabilities = Capabilities {
+            allowed_env: Some(vec![
+                "PLANE_*".to_string(),
+                "GITHUB_DEFAULT_REPO".to_string(),
+            ]),
+            ..Capabilities::default()
+        };
+        assert!(capabilities.allows_env("PLANE_WORKSPACE_SLUG"));
+        assert!(capabilities.allows_env("GITHUB_DEFAULT_REPO"));
+        assert!(!capabilities.allows_env("AWS_SECRET_ACCESS_KEY"));
+        assert!(Capabilities::default().allows_env("AWS_SECRET_ACCESS_KEY"));
+    }
+}
@@ -1,38 +1,14 @@
 use allocative::Allocative;
 use derive_more::Display;
 use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
 use starlark::starlark_module;
 use starlark::starlark_simple_value;
 use starlark::values::dict::AllocDict;
 use starlark::values::starlark_value;
 use starlark::values::{Heap, NoSerialize, ProvidesStaticType, StarlarkValue, Value};
-use std::cell::RefCell;
-use std::path::Path;
 
-thread_local! {
-    /// Thread-local storage for the extensions directory path
-    /// Set by the tool executor before calling tool handler functions
-    static EXTENSIONS_DIR: RefCell<Option<String>> = const { RefCell::new(None) };
-}
-
-/// Set the extensions directory for the current thread
-pub fn set_extensions_dir(dir: String) {
-    EXTENSIONS_DIR.with(|d| {
-        *d.borrow_mut() = Some(dir);
-    });
-}
-
-/// Clear the extensions directory for the current thread
-pub fn clear_extensions_dir() {
-    EXTENSIONS_DIR.with(|d| {
-        *d.borrow_mut() = None;
-    });
-}
-
-/// Get the current extensions directory
-fn get_extensions_dir() -> Option<String> {
-    EXTENSIONS_DIR.with(|d| d.borrow().clone())
-}
+use super::context::CallContext;
 
 #[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
 #[display(fmt = "data")]
@@ -43,8 +19,8 @@ starlark_simple_value!(DataModule);
 #[starlark_value(type = "data")]
 impl<'v> StarlarkValue<'v> for DataModule {
     fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(data_methods)
+        static RES: MethodsStatic = MethodsStatic::new("data", data_methods);
+        Some(RES.methods())
     }
 
     fn dir_attr(&self) -> Vec<String> {
@@ -57,7 +33,8 @@ fn data_methods(builder: &mut MethodsBuilder) {
     /// Load a JSON file from the extensions directory and return its contents as a Starlark value.
     ///
     /// # Arguments
-    /// * `path` - Path to the JSON file, relative to the extensions directory
+    /// * `path` - Path to the JSON file, relative to the extensions directory, or
+    ///   to the package root for extensions loaded from a package
     ///
     /// # Examples
     /// ```python
@@ -65,15 +42,21 @@ fn data_methods(builder: &mut MethodsBuilder) {
     /// ```
     ///
     /// # Security
-    /// Only paths within the extensions directory are allowed. Path traversal (e.g., "../") is rejected.
+    /// Only paths within the extensions directory (or package) are allowed. Path traversal (e.g., "../") is rejected.
+    /// In `--locked` mode the file must match `starlark-mcp.lock`.
     fn load_json<'v>(
         #[allow(unused_variables)] this: Value<'v>,
         path: &str,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
-        let extensions_dir = get_extensions_dir().ok_or_else(|| {
-            anyhow::anyhow!("data.load_json: extensions directory not configured")
-        })?;
+        CallContext::check_module(eval, "data")?;
+        let heap = eval.heap();
+        let context = CallContext::get(eval);
+        let extensions_dir = context
+            .and_then(|ctx| ctx.extensions_dir.as_deref())
+            .ok_or_else(|| {
+                anyhow::anyhow!("data.load_json: extensions directory not configured")
+            })?;
 
         if path.contains("..") {
             return Err(anyhow::anyhow!(
@@ -82,7 +65,7 @@ fn data_methods(builder: &mut MethodsBuilder) {
             ));
         }
 
-        let ext_path = Path::new(&extensions_dir);
+        let ext_path = extensions_dir;
         let full_path = ext_path.join(path);
 
         let canonical_ext = ext_path.canonicalize().map_err(|e| {
@@ -109,6 +92,10 @@ fn data_methods(builder: &mut MethodsBuilder) {
         let content = std::fs::read_to_string(&canonical_file).map_err(|e| {
             anyhow::anyhow!("data.load_json: failed to read file '{}': {}", path, e)
         })?;
+        if let Some(ctx) = context {
+            ctx.verify_locked(&canonical_file, content.as_bytes())
+                .map_err(|e| anyhow::anyhow!("data.load_json: {}", e))?;
+        }
 
         let json_value: serde_json::Value = serde_json::from_str(&content).map_err(|e| {
             anyhow::anyhow!("data.load_json: failed to parse JSON in '{}': {}", path, e)
@@ -121,7 +108,7 @@ fn data_methods(builder: &mut MethodsBuilder) {
 /// Convert a serde_json::Value to a Starlark Value
 fn json_to_starlark_value<'v>(
     json: serde_json::Value,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> anyhow::Result<Value<'v>> {
     match json {
         serde_json::Value::Null => Ok(Value::new_none()),
@@ -164,7 +151,6 @@ pub fn register(builder: &mut GlobalsBuilder) {
 mod tests {
     use super::*;
     use starlark::environment::GlobalsBuilder;
-    use starlark::eval::Evaluator;
     use starlark::syntax::{AstModule, Dialect};
     use std::io::Write;
     use tempfile::TempDir;
@@ -185,17 +171,17 @@ mod tests {
     }
 
     fn eval_with_data(code: &str, extensions_dir: &str) -> Result<String, starlark::Error> {
-        set_extensions_dir(extensions_dir.to_string());
+        let ctx = CallContext::new("test").with_extensions_dir(Some(extensions_dir.into()));
 
         let globals = GlobalsBuilder::new().with(register).build();
-        let module = starlark::environment::Module::new();
-        let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
-        let mut eval = Evaluator::new(&module);
-        let result = eval.eval_module(ast, &globals)?;
-
-        clear_extensions_dir();
-
-        Ok(result.to_string())
+        starlark::environment::Module::with_temp_heap(|module| {
+            let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
+            let mut eval = Evaluator::new(&module);
+            ctx.attach(&mut eval);
+            let result = eval.eval_module(ast, &globals)?;
+
+            Ok(result.to_string())
+        })
     }
 
     #[test]
@@ -0,0 +1,297 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use starlark::codemap::FileSpan;
+use std::fmt;
+use std::path::PathBuf;
+
+use super::lint::LintIssue;
+use super::manifest::Permissions;
+
+/// Location of an error in Starlark source, with 1-based lines and columns
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceSpan {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl SourceSpan {
+    pub fn from_file_span(span: &FileSpan) -> Self {
+        let resolved = span.resolve_span();
+        Self {
+            file: span.filename().to_string(),
+            line: resolved.begin.line + 1,
+            column: resolved.begin.column + 1,
+            end_line: resolved.end.line + 1,
+            end_column: resolved.end.column + 1,
+        }
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// A Starlark error converted for `anyhow`, keeping where it happened so
+/// diagnostics can report the span separately from the message
+#[derive(Debug)]
+pub struct LocatedError {
+    message: String,
+    /// One-line message without starlark's source excerpt
+    summary: String,
+    pub span: Option<SourceSpan>,
+}
+
+impl LocatedError {
+    /// Wrap `error` as "`context`: `error`"
+    pub fn new(context: &str, error: &starlark::Error) -> Self {
+        Self {
+            message: format!("{}: {}", context, error),
+            summary: format!("{}: {}", context, error.without_diagnostic()),
+            span: error.span().map(SourceSpan::from_file_span),
+        }
+    }
+
+    /// Replace the full message, keeping the summary and span
+    pub fn with_message(mut self, message: String) -> Self {
+        self.message = message;
+        self
+    }
+
+    pub fn summary(&self) -> &str {
+        &self.summary
+    }
+}
+
+impl fmt::Display for LocatedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LocatedError {}
+
+/// The first [`LocatedError`] in `error`'s chain
+pub fn located(error: &anyhow::Error) -> Option<&LocatedError> {
+    error.chain().find_map(|e| e.downcast_ref::<LocatedError>())
+}
+
+/// The span of the first [`LocatedError`] in `error`'s chain
+pub fn span_of(error: &anyhow::Error) -> Option<SourceSpan> {
+    located(error).and_then(|e| e.span.clone())
+}
+
+/// An extension file that failed to load. Its last good version, if there is
+/// one, is still being served.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtensionFailure {
+    /// Name the extension was loaded under, usually its file stem
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<SourceSpan>,
+    /// Whether an earlier version of the extension is still loaded
+    pub serving_previous_version: bool,
+    #[serde(serialize_with = "serialize_time")]
+    pub time: DateTime<Utc>,
+}
+
+/// Summary of a loaded extension
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadedSummary {
+    pub name: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    pub tools: usize,
+    /// Required config with no value; the extension's tools are disabled
+    /// until it is set
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub missing_config: Vec<String>,
+    /// What the extension's handlers can reach
+    pub permissions: Permissions,
+    /// Lint issues found when the extension loaded
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lint: Vec<LintIssue>,
+    #[serde(serialize_with = "serialize_time")]
+    pub loaded_at: DateTime<Utc>,
+}
+
+/// What the server has loaded and what failed, for the
+/// `starlark-mcp://diagnostics` resource and the `server_status` tool
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostics {
+    pub loaded: Vec<LoadedSummary>,
+    pub failed: Vec<ExtensionFailure>,
+    /// When an extension was last loaded, reloaded or removed
+    #[serde(serialize_with = "serialize_optional_time")]
+    pub last_reload: Option<DateTime<Utc>>,
+}
+
+impl Diagnostics {
+    /// Plain-text report, for clients that don't read structured content
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Loaded extensions ({}):\n", self.loaded.len());
+        for loaded in &self.loaded {
+            text.push_str(&format!(
+                "- {} v{}: {} tool{}",
+                loaded.name,
+                loaded.version,
+                loaded.tools,
+                if loaded.tools == 1 { "" } else { "s" }
+            ));
+            if !loaded.lint.is_empty() {
+                text.push_str(&format!(
+                    ", {} lint issue{}",
+                    loaded.lint.len(),
+                    if loaded.lint.len() == 1 { "" } else { "s" }
+                ));
+            }
+            if !loaded.missing_config.is_empty() {
+                text.push_str(&format!(
+                    " (disabled, missing config {})",
+                    loaded.missing_config.join(", ")
+                ));
+            }
+            text.push('\n');
+        }
+
+        if !self.failed.is_empty() {
+            text.push_str(&format!("\nFailed extensions ({}):\n", self.failed.len()));
+            for failure in &self.failed {
+                let source = failure
+                    .span
+                    .as_ref()
+                    .map(|s| s.to_string())
+                    .or_else(|| failure.path.as_ref().map(|p| p.display().to_string()))
+                    .unwrap_or_else(|| failure.name.clone());
+                text.push_str(&format!("- {} ({})", failure.name, source));
+                if failure.serving_previous_version {
+                    text.push_str(", previous version still loaded");
+                }
+                text.push_str(&format!(":\n{}\n", indent(&failure.error)));
+            }
+        }
+
+        text.push_str(&format!(
+            "\nLast reload: {}\n",
+            self.last_reload
+                .map(format_time)
+                .unwrap_or_else(|| "never".to_string())
+        ));
+        text
+    }
+}
+
+fn indent(text: &str) -> String {
+    text.trim_end()
+        .lines()
+        .map(|line| format!("    {}", line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn serialize_time<S: serde::Serializer>(time: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format_time(*time))
+}
+
+fn serialize_optional_time<S: serde::Serializer>(
+    time: &Option<DateTime<Utc>>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => serialize_time(time, s),
+        None => s.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::syntax::{AstModule, Dialect};
+
+    #[test]
+    fn test_located_error_keeps_span() {
+        let err = AstModule::parse(
+            "weather.star",
+            "def f():\n    return (\n".to_owned(),
+            &Dialect::Standard,
+        )
+        .unwrap_err();
+
+        let err = anyhow::Error::new(LocatedError::new("Parse error", &err));
+        assert!(err.to_string().starts_with("Parse error: "));
+        let summary = located(&err).unwrap().summary();
+        assert!(summary.starts_with("Parse error: "));
+        assert!(!summary.contains('\n'), "{}", summary);
+
+        let span = span_of(&err).unwrap();
+        assert_eq!(span.file, "weather.star");
+        assert_eq!(span.line, 3);
+        assert!(span.to_string().starts_with("weather.star:3:"));
+    }
+
+    #[test]
+    fn test_text_report() {
+        let time = DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let diagnostics = Diagnostics {
+            loaded: vec![LoadedSummary {
+                name: "plane".to_string(),
+                version: "1.0.0".to_string(),
+                path: None,
+                tools: 4,
+                missing_config: vec!["PLANE_API_KEY".to_string()],
+                permissions: Permissions {
+                    modules: vec!["http".to_string()],
+                    declared: true,
+                    exec: Vec::new(),
+                    exec_rules: Vec::new(),
+                    hosts: Some(vec!["api.plane.so".to_string()]),
+                    private_network: false,
+                    env: vec!["PLANE_API_KEY".to_string()],
+                    allowed_env: None,
+                    unsigned: None,
+                },
+                lint: Vec::new(),
+                loaded_at: time,
+            }],
+            failed: vec![ExtensionFailure {
+                name: "weather".to_string(),
+                path: Some(PathBuf::from("/ext/weather.star")),
+                error: "Eval error: boom".to_string(),
+                span: None,
+                serving_previous_version: true,
+                time,
+            }],
+            last_reload: Some(time),
+        };
+
+        assert_eq!(
+            diagnostics.to_text(),
+            "Loaded extensions (1):\n\
+             - plane v1.0.0: 4 tools (disabled, missing config PLANE_API_KEY)\n\
+             \n\
+             Failed extensions (1):\n\
+             - weather (/ext/weather.star), previous version still loaded:\n    Eval error: boom\n\
+             \n\
+             Last reload: 2025-01-02T03:04:05Z\n"
+        );
+
+        let json = serde_json::to_value(&diagnostics).unwrap();
+        assert_eq!(json["last_reload"], "2025-01-02T03:04:05Z");
+        assert_eq!(json["failed"][0]["path"], "/ext/weather.star");
+        assert_eq!(json["loaded"][0]["permissions"]["modules"][0], "http");
+    }
+}
@@ -1,26 +1,169 @@
 use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use rmcp::service::{Peer, RoleServer};
 use starlark::environment::{FrozenModule, Globals, Module};
 use starlark::eval::Evaluator;
 use starlark::syntax::{AstModule, Dialect};
 use starlark::values::{Value, dict::AllocDict};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{debug, info};
-
-use super::mcp_types::{StarlarkExtension, extract_extension_from_value};
+use std::time::Instant;
+use tokio::sync::{RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use super::backtrace::{ErrorVerbosity, HandlerError, SourceFiles};
+use super::cache::ExtensionCache;
+use super::config::{ExtensionConfig, ExtensionSettings, Redactor};
+use super::context::{CallContext, Capabilities};
+use super::diagnostics::{
+    Diagnostics, ExtensionFailure, LoadedSummary, LocatedError, SourceSpan, span_of,
+};
+use super::kv::KvStore;
+use super::limits::{ExecutionLimits, run_with_limits};
+use super::lint::{LintIssue, Linter, Severity};
+use super::manifest::{Permissions, SERVER_VERSION, check_compatibility};
+use super::mcp_types::{StarlarkExtension, StarlarkTool, extract_extension_from_value};
+use super::module_loader::{ModuleCache, ModuleLoader};
 use super::modules::build_globals;
-use crate::mcp::ToolResult;
+use super::state::ExtensionState;
+use super::typecheck::TypeChecker;
+use crate::extensions::package::PackageManifest;
+use crate::lockfile::Lockfile;
+use crate::mcp::{Tool, ToolContent, ToolResult};
+use crate::signing::SignatureVerifier;
 
 pub struct StarlarkEngine {
     globals: Globals,
-    extensions: Arc<RwLock<HashMap<String, LoadedExtension>>>,
-    extensions_dir: Option<String>,
+    linter: Linter,
+    extensions: Arc<RwLock<Registry>>,
+    /// Extension roots, in precedence order
+    extension_dirs: Vec<PathBuf>,
+    /// Roots of unpacked packages, which take precedence over
+    /// `extension_dirs` for the files inside them
+    package_roots: std::sync::RwLock<Vec<PathBuf>>,
+    module_cache: ModuleCache,
+    kv: Option<KvStore>,
+    /// Per-extension overrides from the server config, keyed by extension name
+    settings: std::sync::RwLock<HashMap<String, ExtensionSettings>>,
+    /// Expose tools as `<extension>__<tool>` instead of their bare names
+    namespace_tools: bool,
+    /// Set in `--typecheck` mode: sources are parsed with the extended
+    /// dialect and typechecked before they run
+    typechecker: Option<TypeChecker>,
+    /// Most recent load failure per file (or name, for extensions not loaded
+    /// from disk), cleared once the file loads or is removed
+    failures: std::sync::RwLock<HashMap<PathBuf, ExtensionFailure>>,
+    /// When an extension was last loaded, reloaded or removed
+    last_reload: std::sync::RwLock<Option<DateTime<Utc>>>,
+    /// Set in `--locked` mode: extension files, modules and data files must
+    /// match it before they are used
+    lockfile: Option<Arc<Lockfile>>,
+    /// Set when the server config lists trusted keys: extension files and
+    /// modules must be signed by one of them
+    signatures: Option<Arc<SignatureVerifier>>,
+    /// Let every extension's `http` calls reach private and link-local
+    /// addresses
+    allow_private_network: bool,
+    /// Fail `env.get()` of variables outside an extension's `allowed_env`
+    /// instead of returning the default
+    strict_env: bool,
+}
+
+/// Separator between extension and tool names when tools are namespaced
+pub const TOOL_NAMESPACE_SEPARATOR: &str = "__";
+
+/// Raised when the server config disables the extension an evaluated file
+/// declares. Settings are keyed by the declared name, which is only known once
+/// `describe_extension()` returns, so a disabled file is evaluated that far.
+#[derive(Debug, Clone)]
+pub struct ExtensionDisabled(pub String);
+
+impl std::fmt::Display for ExtensionDisabled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Extension '{}' is disabled in the server config", self.0)
+    }
+}
+
+impl std::error::Error for ExtensionDisabled {}
+
+impl ExtensionDisabled {
+    /// Whether `error` is a load skipped because the extension is disabled
+    pub fn is(error: &anyhow::Error) -> bool {
+        error.downcast_ref::<ExtensionDisabled>().is_some()
+    }
+}
+
+/// Loaded extensions, indexed by name and by the file each was loaded from.
+///
+/// An extension's name comes from `describe_extension()` and need not match
+/// its file name, so file events must go through `by_path` to find what to
+/// reload or remove.
+#[derive(Default)]
+struct Registry {
+    by_name: HashMap<String, LoadedExtension>,
+    /// Canonical source path to extension name, for extensions loaded from disk
+    by_path: HashMap<PathBuf, String>,
+}
+
+impl Registry {
+    /// Add `loaded`, replacing whatever was previously loaded under the same
+    /// name or from the same file. Returns the replaced extensions.
+    fn insert(&mut self, loaded: LoadedExtension) -> Vec<LoadedExtension> {
+        let name = loaded.extension.name.clone();
+        let mut replaced = Vec::new();
+
+        if let Some(ref path) = loaded.path
+            && let Some(previous) = self.by_path.get(path).cloned()
+            && previous != name
+        {
+            replaced.extend(self.remove(&previous));
+        }
+        replaced.extend(self.remove(&name));
+
+        if let Some(ref path) = loaded.path {
+            self.by_path.insert(path.clone(), name.clone());
+        }
+        self.by_name.insert(name, loaded);
+        replaced
+    }
+
+    fn remove(&mut self, name: &str) -> Option<LoadedExtension> {
+        let removed = self.by_name.remove(name)?;
+        if let Some(ref path) = removed.path
+            && self.by_path.get(path).is_some_and(|n| n == name)
+        {
+            self.by_path.remove(path);
+        }
+        Some(removed)
+    }
+
+    fn get(&self, name: &str) -> Option<&LoadedExtension> {
+        self.by_name.get(name)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&String, &LoadedExtension)> {
+        self.by_name.iter()
+    }
 }
 
 struct LoadedExtension {
     extension: StarlarkExtension,
     module: FrozenModule,
+    /// Canonical path of the source file, when loaded from disk
+    path: Option<PathBuf>,
+    /// Mutable state for this version of the extension; dropped on reload
+    state: ExtensionState,
+    /// Memoized results for this version of the extension; dropped on reload
+    cache: ExtensionCache,
+    /// Values of the extension's declared config, resolved at load
+    config: ExtensionConfig,
+    /// Namespace for its `kv` entries, from [`kv_namespace`]
+    kv_namespace: String,
+    /// Lint issues found when the extension loaded
+    lint: Vec<LintIssue>,
+    loaded_at: DateTime<Utc>,
 }
 
 impl Default for StarlarkEngine {
@@ -31,77 +174,743 @@ impl Default for StarlarkEngine {
 
 impl StarlarkEngine {
     pub fn new() -> Self {
+        let globals = build_globals();
         Self {
-            globals: build_globals(),
-            extensions: Arc::new(RwLock::new(HashMap::new())),
-            extensions_dir: None,
+            linter: Linter::new(&globals),
+            globals,
+            extensions: Arc::new(RwLock::new(Registry::default())),
+            extension_dirs: Vec::new(),
+            package_roots: std::sync::RwLock::new(Vec::new()),
+            module_cache: ModuleCache::new(),
+            kv: None,
+            settings: std::sync::RwLock::new(HashMap::new()),
+            namespace_tools: false,
+            typechecker: None,
+            failures: std::sync::RwLock::new(HashMap::new()),
+            last_reload: std::sync::RwLock::new(None),
+            lockfile: None,
+            signatures: None,
+            allow_private_network: false,
+            strict_env: false,
+        }
+    }
+
+    /// Add an extension root. `load("//...")` paths and `data` files resolve
+    /// against the root an extension lives in; the first root is used for
+    /// extensions loaded from elsewhere.
+    pub fn with_extensions_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.extension_dirs.push(dir.into());
+        self
+    }
+
+    /// Treat `root` as the root of the extensions inside it, so a package's
+    /// `load("//...")` paths and `data` files stay within the package
+    pub fn add_package_root(&self, root: &Path) {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        let mut roots = self.package_roots.write().unwrap();
+        if !roots.contains(&root) {
+            roots.push(root);
         }
     }
 
-    pub fn with_extensions_dir(mut self, dir: String) -> Self {
-        self.extensions_dir = Some(dir);
+    /// Back the `kv` module with `store`; without one, `kv` calls fail
+    pub fn with_kv_store(mut self, store: KvStore) -> Self {
+        self.kv = Some(store);
+        self
+    }
+
+    /// Refuse to load or reload any file that doesn't match `lockfile`
+    pub fn with_lockfile(mut self, lockfile: Arc<Lockfile>) -> Self {
+        self.lockfile = Some(lockfile);
+        self
+    }
+
+    /// Check extension files and the modules they load against trusted
+    /// signatures, rejecting or restricting unsigned ones per the policy
+    pub fn with_signatures(mut self, signatures: Arc<SignatureVerifier>) -> Self {
+        self.signatures = Some(signatures);
+        self
+    }
+
+    /// Let `http` reach private and link-local addresses from every
+    /// extension, not just those whose settings allow it
+    pub fn with_private_network(mut self, allowed: bool) -> Self {
+        self.allow_private_network = allowed;
+        self
+    }
+
+    /// Make `env.get()` of a variable outside the extension's `allowed_env`
+    /// an error rather than a logged fallback to the default
+    pub fn with_strict_env(mut self, strict: bool) -> Self {
+        self.strict_env = strict;
+        self
+    }
+
+    /// Apply per-extension overrides from the server config
+    pub fn with_settings(self, settings: HashMap<String, ExtensionSettings>) -> Self {
+        *self.settings.write().unwrap() = settings;
         self
     }
 
-    pub fn extensions_dir(&self) -> Option<&String> {
-        self.extensions_dir.as_ref()
+    /// Expose every tool as `<extension>__<tool>`, so extensions can reuse
+    /// tool names like `list` or `query` without colliding
+    pub fn with_namespaced_tools(mut self, enabled: bool) -> Self {
+        self.namespace_tools = enabled;
+        self
+    }
+
+    /// Allow type annotations and typecheck every extension and loaded
+    /// module against stubs of the builtin modules, failing the load on type
+    /// errors
+    pub fn with_typecheck(mut self, enabled: bool) -> Result<Self> {
+        self.typechecker = if enabled {
+            Some(TypeChecker::new()?)
+        } else {
+            None
+        };
+        Ok(self)
+    }
+
+    /// Starlark dialect extensions are parsed with
+    fn dialect(&self) -> Dialect {
+        if self.typechecker.is_some() {
+            Dialect::Extended
+        } else {
+            Dialect::Standard
+        }
+    }
+
+    /// The name clients see for `tool` from `extension`
+    pub fn exposed_tool_name(&self, extension: &str, tool: &str) -> String {
+        if self.namespace_tools {
+            format!("{}{}{}", extension, TOOL_NAMESPACE_SEPARATOR, tool)
+        } else {
+            tool.to_string()
+        }
+    }
+
+    /// Settings for the extension called `name`, or the defaults
+    pub fn settings_for(&self, name: &str) -> ExtensionSettings {
+        self.settings
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Whether the server config allows the extension called `name` to load
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.settings_for(name).enabled
+    }
+
+    /// Replace the per-extension settings, unloading extensions that are now
+    /// disabled. The rest only pick up the new settings when next loaded.
+    /// Returns the names of the extensions that were removed.
+    pub async fn apply_settings(
+        &self,
+        settings: HashMap<String, ExtensionSettings>,
+    ) -> Vec<String> {
+        *self.settings.write().unwrap() = settings;
+
+        let disabled: Vec<String> = {
+            let extensions = self.extensions.read().await;
+            extensions
+                .iter()
+                .map(|(name, _)| name)
+                .filter(|name| !self.is_enabled(name))
+                .cloned()
+                .collect()
+        };
+        for name in &disabled {
+            self.remove_extension(name).await;
+        }
+        disabled
+    }
+
+    pub fn extension_dirs(&self) -> &[PathBuf] {
+        &self.extension_dirs
+    }
+
+    /// The root `path` lives in, falling back to the first root
+    fn root_for(&self, path: Option<&Path>) -> Option<PathBuf> {
+        let canonical = path.and_then(|p| p.canonicalize().ok());
+        if let Some(ref path) = canonical
+            && let Some(root) = self
+                .package_roots
+                .read()
+                .unwrap()
+                .iter()
+                .find(|root| path.starts_with(root))
+        {
+            return Some(root.clone());
+        }
+        self.extension_dirs
+            .iter()
+            .find(|dir| match (&canonical, dir.canonicalize()) {
+                (Some(path), Ok(dir)) => path.starts_with(dir),
+                _ => false,
+            })
+            .or(self.extension_dirs.first())
+            .cloned()
+    }
+
+    /// Namespace for an extension's `kv` entries, never the name it gives
+    /// itself in `Extension()`, which another extension could copy. Files are
+    /// keyed by their path within their root, under the package name for
+    /// files from a package, so the entries survive the extensions directory
+    /// moving or a package being unpacked somewhere new. Extensions not
+    /// loaded from disk use the name they were loaded under.
+    fn kv_namespace(&self, name: &str, path: Option<&Path>) -> String {
+        let Some(path) = path.and_then(|p| p.canonicalize().ok()) else {
+            return name.to_string();
+        };
+        let root = self
+            .root_for(Some(&path))
+            .and_then(|root| root.canonicalize().ok());
+        let relative = root
+            .as_ref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .map(|relative| relative.to_string_lossy().replace('\\', "/"));
+        let package = root.as_ref().and_then(|root| {
+            self.package_roots
+                .read()
+                .unwrap()
+                .contains(root)
+                .then(|| PackageManifest::read(root).ok())
+                .flatten()
+        });
+        match (package, relative) {
+            (Some(package), Some(relative)) => format!("{}:{}", package.name, relative),
+            (None, Some(relative)) => relative,
+            _ => path.display().to_string(),
+        }
+    }
+
+    /// Cache of modules pulled in by `load()` statements
+    pub fn module_cache(&self) -> &ModuleCache {
+        &self.module_cache
     }
 
     pub async fn load_extension(&self, name: &str, content: &str) -> Result<StarlarkExtension> {
-        info!("Loading extension: {}", name);
+        self.load_extension_with_path(name, content, None).await
+    }
 
-        let ast = AstModule::parse(name, content.to_owned(), &Dialect::Standard)
-            .map_err(|e| anyhow!("Parse error: {}", e))?;
+    /// Load an extension whose source lives at `path`, so that relative
+    /// `load()` statements resolve from the file's directory.
+    pub async fn load_extension_with_path(
+        &self,
+        name: &str,
+        content: &str,
+        path: Option<&Path>,
+    ) -> Result<StarlarkExtension> {
+        let loaded = self
+            .compile_extension(name, content, path)
+            .inspect_err(|e| {
+                if !ExtensionDisabled::is(e) {
+                    self.record_failure(name, path, e)
+                }
+            })?;
+        let extension = loaded.extension.clone();
 
-        if let Some(ref dir) = self.extensions_dir {
-            super::modules::set_extensions_dir(dir.clone());
+        let installed = {
+            let mut extensions = self.extensions.write().await;
+            match self.check_conflicts(&extensions, &[&loaded]) {
+                Ok(()) => Ok(self.install(&mut extensions, loaded)),
+                Err(e) => Err((e, loaded)),
+            }
+        };
+        match installed {
+            Ok(replaced) => self.unload(replaced),
+            Err((e, rejected)) => {
+                self.record_failure(name, path, &e);
+                self.unload(vec![rejected]);
+                return Err(e);
+            }
         }
 
-        // Use a closure to ensure cleanup happens on all exit paths
-        let result = (|| -> Result<(StarlarkExtension, FrozenModule)> {
-            let module = Module::new();
-            let mut eval = Evaluator::new(&module);
+        Ok(extension)
+    }
 
-            eval.eval_module(ast, &self.globals)
-                .map_err(|e| anyhow!("Eval error: {}", e))?;
+    /// Fail if installing `incoming` would take over an extension name that
+    /// another file still provides, or expose a tool name that another
+    /// extension already exposes. Extensions that `incoming` replaces, by name
+    /// or by source file, don't count.
+    fn check_conflicts(&self, extensions: &Registry, incoming: &[&LoadedExtension]) -> Result<()> {
+        for loaded in incoming {
+            let name = &loaded.extension.name;
+            if let (Some(path), Some(existing)) = (&loaded.path, extensions.get(name))
+                && let Some(ref existing_path) = existing.path
+                && existing_path != path
+                && existing_path.exists()
+                && !incoming
+                    .iter()
+                    .any(|l| l.path.as_ref() == Some(existing_path))
+            {
+                return Err(anyhow!(
+                    "Extension '{}' is already loaded from {}",
+                    name,
+                    existing_path.display()
+                ));
+            }
+        }
 
-            let describe_fn = module
-                .get("describe_extension")
-                .ok_or_else(|| anyhow!("Extension must define describe_extension()"))?;
+        let replaced = |name: &str, existing: &LoadedExtension| {
+            incoming
+                .iter()
+                .any(|l| l.extension.name == name || (l.path.is_some() && l.path == existing.path))
+        };
 
-            let extension_value = eval
-                .eval_function(describe_fn, &[], &[])
-                .map_err(|e| anyhow!("Function call error: {}", e))?;
+        let mut owners: HashMap<String, &str> = HashMap::new();
+        for (name, existing) in extensions.iter() {
+            if replaced(name, existing) {
+                continue;
+            }
+            for tool in &existing.extension.tools {
+                owners.insert(self.exposed_tool_name(name, &tool.name), name);
+            }
+        }
+
+        for loaded in incoming {
+            let name = loaded.extension.name.as_str();
+            for tool in &loaded.extension.tools {
+                let exposed = self.exposed_tool_name(name, &tool.name);
+                if let Some(owner) = owners.insert(exposed.clone(), name) {
+                    return Err(anyhow!(
+                        "Tool '{}' in extension '{}' conflicts with the tool of the same name in extension '{}'; rename one of them or enable tool namespacing",
+                        exposed,
+                        name,
+                        owner
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluate an extension, call `describe_extension()` and then `on_load()`,
+    /// without registering it
+    fn compile_extension(
+        &self,
+        name: &str,
+        content: &str,
+        path: Option<&Path>,
+    ) -> Result<LoadedExtension> {
+        info!("Loading extension: {}", name);
+
+        if let (Some(lockfile), Some(path)) = (&self.lockfile, path) {
+            lockfile.verify(path, content.as_bytes())?;
+        }
+        let unsigned = match (&self.signatures, path) {
+            (Some(signatures), Some(path)) => signatures.check(path, content.as_bytes())?,
+            _ => None,
+        };
+
+        let dialect = self.dialect();
+        let ast = AstModule::parse(name, content.to_owned(), &dialect)
+            .map_err(|e| LocatedError::new("Parse error", &e))?;
+        if let Some(ref typechecker) = self.typechecker {
+            let ast = AstModule::parse(name, content.to_owned(), &dialect)
+                .map_err(|e| LocatedError::new("Parse error", &e))?;
+            typechecker.check(ast)?;
+        }
 
-            let extension = extract_extension_from_value(extension_value, module.heap())?;
+        // Capabilities and settings aren't known until describe_extension()
+        // returns the extension's name, so top-level code runs with none:
+        // env.get() sees no variables and http can't reach any host. Its uses
+        // of guarded modules are checked against requires afterwards.
+        let capabilities = Capabilities {
+            allowed_hosts: Some(Vec::new()),
+            allow_private_network: self.allow_private_network,
+            allowed_env: Some(Vec::new()),
+            strict_env: self.strict_env,
+            ..Capabilities::default()
+        };
+        let capabilities = match unsigned {
+            Some(_) => capabilities.restricted(),
+            None => capabilities,
+        };
+        let state = ExtensionState::new();
+        let cache = ExtensionCache::new();
+        let kv_namespace = self.kv_namespace(name, path);
+        let context = CallContext::new(name)
+            .with_capabilities(capabilities.clone())
+            .with_extensions_dir(self.root_for(path))
+            .with_state(state.clone())
+            .with_cache(cache.clone())
+            .with_kv(self.kv.clone())
+            .with_kv_namespace(kv_namespace.clone())
+            .with_config(ExtensionConfig::new(&[], HashMap::new(), &capabilities))
+            .with_lockfile(self.lockfile.clone())
+            .with_signatures(self.signatures.clone(), unsigned.is_some());
+
+        let (mut extension, module) = (|| -> Result<(StarlarkExtension, FrozenModule)> {
+            let loader = self
+                .module_loader(path)?
+                .map(|loader| loader.with_context(&context));
+
+            let (extension, frozen_module) = Module::with_temp_heap(|module| {
+                let mut eval = Evaluator::new(&module);
+                context.attach(&mut eval);
+                if let Some(ref loader) = loader {
+                    eval.set_loader(loader);
+                }
+
+                eval.eval_module(ast, &self.globals)
+                    .map_err(|e| LocatedError::new("Eval error", &e))?;
+
+                let describe_fn = module
+                    .get("describe_extension")
+                    .ok_or_else(|| anyhow!("Extension must define describe_extension()"))?;
+
+                let extension_value = eval
+                    .eval_function(describe_fn, &[], &[])
+                    .map_err(|e| LocatedError::new("Function call error", &e))?;
+
+                let extension = extract_extension_from_value(extension_value, module.heap())?;
+                check_compatibility(&extension, SERVER_VERSION)?;
+                if let Some(ref requires) = extension.requires
+                    && let Some(module) = context
+                        .modules_used()
+                        .into_iter()
+                        .find(|module| !requires.contains(module))
+                {
+                    return Err(anyhow!(
+                        "Extension '{}' cannot use the {} module: add \"{}\" to requires in its Extension()",
+                        name,
+                        module,
+                        module
+                    ));
+                }
+
+                drop(eval);
+                let frozen_module = module
+                    .freeze()
+                    .map_err(|e| anyhow!("Freeze error: {:?}", e))?;
+                anyhow::Ok((extension, frozen_module))
+            })?;
 
-            drop(eval);
-            let frozen_module = module
-                .freeze()
-                .map_err(|e| anyhow!("Freeze error: {}", e))?;
+            // Catch handlers that can't be called now rather than on first use
+            for tool in &extension.tools {
+                let function_name = handler_function_name(tool);
+                if frozen_module.get(function_name).is_err() {
+                    return Err(anyhow!(
+                        "Handler '{}' for tool '{}' must be a top-level function",
+                        function_name,
+                        tool.name
+                    ));
+                }
+            }
 
             Ok((extension, frozen_module))
-        })();
+        })()?;
+
+        let settings = self.settings_for(&extension.name);
+        if !settings.enabled {
+            return Err(ExtensionDisabled(extension.name).into());
+        }
+        for tool in &settings.disabled_tools {
+            if !extension.tools.iter().any(|t| &t.name == tool) {
+                warn!(
+                    "Server config disables unknown tool '{}' in extension '{}'",
+                    tool, extension.name
+                );
+            }
+        }
+        extension
+            .tools
+            .retain(|t| !settings.disabled_tools.contains(&t.name));
+        if let Some(allowed_exec) = settings.allowed_exec {
+            extension.allowed_exec = allowed_exec;
+        }
+        if let Some(allowed_hosts) = settings.allowed_hosts {
+            extension.allowed_hosts = Some(allowed_hosts);
+        }
+        extension.allow_private_network =
+            self.allow_private_network || settings.allow_private_network;
+        if let Some(allowed_env) = settings.allowed_env {
+            extension.allowed_env = Some(allowed_env);
+        }
+        extension.strict_env = self.strict_env;
+        if let Some(reason) = unsigned {
+            warn!(
+                "Extension '{}' loaded without exec and http: {}",
+                extension.name, reason
+            );
+            extension.unsigned = Some(reason);
+        }
 
-        super::modules::clear_extensions_dir();
-        let (extension, frozen_module) = result?;
+        let config =
+            ExtensionConfig::new(&extension.config, settings.env, &extension.capabilities());
+        for name in config.denied() {
+            let message = format!(
+                "Extension '{}' cannot read environment variable '{}': add it to allowed_env in its Extension()",
+                extension.name, name
+            );
+            if self.strict_env {
+                return Err(anyhow!(message));
+            }
+            warn!("{}", message);
+        }
+        extension.missing_config = config.missing().to_vec();
+        if !extension.is_configured() {
+            warn!(
+                "Extension '{}' is missing required config {}; its tools are disabled",
+                extension.name,
+                extension.missing_config.join(", ")
+            );
+        }
 
-        let mut extensions = self.extensions.write().await;
-        extensions.insert(
-            extension.name.clone(),
-            LoadedExtension {
-                extension: extension.clone(),
-                module: frozen_module,
-            },
+        let lint = self.lint(name, content, Some(&extension));
+        for issue in &lint {
+            warn!("Extension '{}': {}", extension.name, issue);
+        }
+
+        let loaded = LoadedExtension {
+            extension,
+            module,
+            path: path.and_then(|p| p.canonicalize().ok()),
+            state,
+            cache,
+            config,
+            kv_namespace,
+            lint,
+            loaded_at: Utc::now(),
+        };
+
+        self.call_hook(&loaded, "on_load")
+            .map_err(|e| anyhow!("on_load() failed: {}", e))?;
+
+        Ok(loaded)
+    }
+
+    /// Lint an extension's source. `extension` is what it described itself
+    /// as, if it loaded; without it only the source-level checks run.
+    /// Sources that don't parse have no lint issues, only a load error.
+    pub fn lint(
+        &self,
+        name: &str,
+        content: &str,
+        extension: Option<&StarlarkExtension>,
+    ) -> Vec<LintIssue> {
+        match AstModule::parse(name, content.to_owned(), &self.dialect()) {
+            Ok(ast) => self.linter.lint(&ast, extension),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Type errors in an extension's source, one per issue; empty unless
+    /// typechecking is enabled. Modules it loads are checked when it loads.
+    pub fn type_errors(&self, name: &str, content: &str) -> Vec<LintIssue> {
+        let Some(ref typechecker) = self.typechecker else {
+            return Vec::new();
+        };
+        let Ok(ast) = AstModule::parse(name, content.to_owned(), &self.dialect()) else {
+            return Vec::new();
+        };
+        typechecker
+            .errors(ast)
+            .iter()
+            .map(|e| {
+                LintIssue::new(
+                    Severity::Error,
+                    "type-error",
+                    e.without_diagnostic().to_string(),
+                    e.span().map(SourceSpan::from_file_span),
+                )
+            })
+            .collect()
+    }
+
+    /// Context for running code on behalf of a loaded extension
+    fn context_for(&self, loaded: &LoadedExtension) -> CallContext {
+        CallContext::new(loaded.extension.name.clone())
+            .with_capabilities(loaded.extension.capabilities())
+            .with_extensions_dir(self.root_for(loaded.path.as_deref()))
+            .with_state(loaded.state.clone())
+            .with_cache(loaded.cache.clone())
+            .with_kv(self.kv.clone())
+            .with_kv_namespace(loaded.kv_namespace.clone())
+            .with_config(loaded.config.clone())
+            .with_lockfile(self.lockfile.clone())
+            .with_signatures(self.signatures.clone(), loaded.extension.unsigned.is_some())
+    }
+
+    /// Call a lifecycle hook such as `on_load()` if the extension defines it
+    fn call_hook(&self, loaded: &LoadedExtension, hook: &str) -> Result<()> {
+        let Ok(hook_fn) = loaded.module.get(hook) else {
+            return Ok(());
+        };
+
+        debug!(
+            "Calling {}() for extension '{}'",
+            hook, loaded.extension.name
         );
 
+        let context = self.context_for(loaded);
+        Module::with_temp_heap(|module| {
+            let mut eval = Evaluator::new(&module);
+            context.attach(&mut eval);
+            eval.eval_function(module.heap().access_owned_frozen_value(&hook_fn), &[], &[])
+                .map_err(|e| anyhow!("{}", context.config.redactor().redact(&e.to_string())))?;
+            Ok(())
+        })
+    }
+
+    /// Run `on_unload()` for extensions that have been replaced or removed.
+    /// Failures are logged; the extension is gone either way.
+    fn unload(&self, unloaded: Vec<LoadedExtension>) {
+        for loaded in unloaded {
+            if let Err(e) = self.call_hook(&loaded, "on_unload") {
+                warn!(
+                    "on_unload() failed for extension '{}': {}",
+                    loaded.extension.name, e
+                );
+            }
+        }
+    }
+
+    /// Register a compiled extension, replacing whatever was previously loaded
+    /// under the same name or from the same file. Returns the replaced
+    /// extensions so the caller can unload them once the lock is released.
+    fn install(&self, extensions: &mut Registry, loaded: LoadedExtension) -> Vec<LoadedExtension> {
+        self.clear_failure(&loaded.extension.name, loaded.path.as_deref());
+        *self.last_reload.write().unwrap() = Some(loaded.loaded_at);
         info!(
             "Loaded extension '{}' with {} tools",
-            extension.name,
-            extension.tools.len()
+            loaded.extension.name,
+            loaded.extension.tools.len()
         );
 
-        Ok(extension)
+        extensions.insert(loaded)
+    }
+
+    /// Reload every extension that depends on `changed`, directly or through
+    /// `load()`ed libraries, in dependency order.
+    ///
+    /// The new versions are swapped in together only if all of them load
+    /// successfully; otherwise the previous versions keep serving and the
+    /// module cache is rolled back.
+    pub async fn reload_dependents(&self, changed: &Path) -> Result<Vec<StarlarkExtension>> {
+        let changed = changed
+            .canonicalize()
+            .unwrap_or_else(|_| changed.to_path_buf());
+
+        let order = self.module_cache.dependents_in_order(&changed);
+        let extension_paths = self.extensions.read().await.by_path.clone();
+
+        let targets: Vec<(&PathBuf, &String)> = order
+            .iter()
+            .filter_map(|p| extension_paths.get(p).map(|name| (p, name)))
+            .collect();
+
+        if targets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        info!(
+            "{} changed, reloading {} dependent extension(s)",
+            changed.display(),
+            targets.len()
+        );
+
+        let snapshot = self.module_cache.snapshot();
+        for path in &order {
+            self.module_cache.invalidate(path);
+        }
+
+        let mut staged = Vec::with_capacity(targets.len());
+        for (path, name) in targets {
+            let file_name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("unknown");
+            let compiled = std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))
+                .and_then(|content| self.compile_extension(file_name, &content, Some(path)));
+
+            match compiled {
+                Ok(loaded) => staged.push(loaded),
+                Err(e) => {
+                    self.record_failure(file_name, Some(path), &e);
+                    let message = self.redact_failure(file_name, Some(path), &e);
+                    self.module_cache.restore(snapshot);
+                    self.unload(staged);
+                    return Err(anyhow!(
+                        "Reload of '{}' failed, keeping previous versions: {}",
+                        name,
+                        message
+                    ));
+                }
+            }
+        }
+
+        let reloaded = staged.iter().map(|l| l.extension.clone()).collect();
+        let installed = {
+            let mut extensions = self.extensions.write().await;
+            let incoming: Vec<&LoadedExtension> = staged.iter().collect();
+            match self.check_conflicts(&extensions, &incoming) {
+                Ok(()) => Ok(staged
+                    .into_iter()
+                    .flat_map(|loaded| self.install(&mut extensions, loaded))
+                    .collect::<Vec<_>>()),
+                Err(e) => Err((e, staged)),
+            }
+        };
+        match installed {
+            Ok(replaced) => self.unload(replaced),
+            Err((e, staged)) => {
+                for loaded in &staged {
+                    self.record_failure(&loaded.extension.name, loaded.path.as_deref(), &e);
+                }
+                self.module_cache.restore(snapshot);
+                self.unload(staged);
+                return Err(anyhow!("Reload failed, keeping previous versions: {}", e));
+            }
+        }
+
+        Ok(reloaded)
+    }
+
+    /// Build the `load()` resolver for an extension. Loads are anchored at the
+    /// root the extension lives in, or at the file's own directory for
+    /// extensions outside every root.
+    fn module_loader(&self, path: Option<&Path>) -> Result<Option<ModuleLoader<'_>>> {
+        let in_root = |root: &Path, path: &Path| match (root.canonicalize(), path.canonicalize()) {
+            (Ok(root), Ok(path)) => path.starts_with(root),
+            _ => true,
+        };
+        let root = match (self.root_for(path), path) {
+            (Some(root), Some(path)) if !in_root(&root, path) => match path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return Ok(None),
+            },
+            (Some(root), _) => root,
+            (None, Some(path)) => match path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return Ok(None),
+            },
+            (None, None) => return Ok(None),
+        };
+
+        if !root.exists() {
+            return Ok(None);
+        }
+
+        let loader = ModuleLoader::new(&root, &self.globals, &self.module_cache)?
+            .with_dialect(self.dialect())
+            .with_typechecker(self.typechecker.as_ref());
+        Ok(Some(match path {
+            Some(path) => loader.for_file(path),
+            None => loader,
+        }))
+    }
+
+    /// Source file of the extension called `name`, if it was loaded from disk
+    pub async fn extension_path(&self, name: &str) -> Option<PathBuf> {
+        let extensions = self.extensions.read().await;
+        extensions.get(name).and_then(|e| e.path.clone())
     }
 
     pub async fn get_extension(&self, name: &str) -> Option<StarlarkExtension> {
@@ -111,22 +920,170 @@ impl StarlarkEngine {
 
     pub async fn get_all_extensions(&self) -> Vec<StarlarkExtension> {
         let extensions = self.extensions.read().await;
-        extensions.values().map(|e| e.extension.clone()).collect()
+        extensions
+            .iter()
+            .map(|(_, e)| e.extension.clone())
+            .collect()
+    }
+
+    /// Tools of every loaded extension, under the names clients see, ordered
+    /// by extension name
+    pub async fn get_all_tools(&self) -> Vec<Tool> {
+        let extensions = self.extensions.read().await;
+        let mut loaded: Vec<(&String, &LoadedExtension)> = extensions.iter().collect();
+        loaded.sort_by_key(|(name, _)| *name);
+        loaded
+            .into_iter()
+            .flat_map(|(name, loaded)| {
+                loaded.extension.to_mcp_tools().into_iter().map(|mut tool| {
+                    tool.name = self.exposed_tool_name(name, &tool.name);
+                    tool
+                })
+            })
+            .collect()
+    }
+
+    /// Key for an extension's entry in `failures`
+    fn failure_key(name: &str, path: Option<&Path>) -> PathBuf {
+        match path {
+            Some(path) => path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+            None => PathBuf::from(name),
+        }
+    }
+
+    /// Redactor of the version of `name` still serving, if any
+    fn serving_redactor(&self, name: &str, path: Option<&Path>) -> Option<Redactor> {
+        let key = Self::failure_key(name, path);
+        let extensions = self.extensions.try_read().ok()?;
+        let name = match path {
+            Some(_) => extensions.by_path.get(&key)?.as_str(),
+            None => name,
+        };
+        Some(extensions.get(name)?.config.redactor().clone())
+    }
+
+    /// Why `name` failed to load, with its context, redacted with the
+    /// secrets of the version still serving, if any, since a failed reload
+    /// may echo them
+    pub fn redact_failure(&self, name: &str, path: Option<&Path>, error: &anyhow::Error) -> String {
+        let message = format!("{:#}", error);
+        match self.serving_redactor(name, path) {
+            Some(redactor) => redactor.redact(&message),
+            None => message,
+        }
+    }
+
+    /// Remember why `name` failed to load for the diagnostics resource,
+    /// redacted as by [`Self::redact_failure`]
+    fn record_failure(&self, name: &str, path: Option<&Path>, error: &anyhow::Error) {
+        let key = Self::failure_key(name, path);
+        let failure = ExtensionFailure {
+            name: name.to_string(),
+            path: path.map(|_| key.clone()),
+            error: self.redact_failure(name, path, error),
+            span: span_of(error),
+            serving_previous_version: self.serving_redactor(name, path).is_some(),
+            time: Utc::now(),
+        };
+        self.failures.write().unwrap().insert(key, failure);
+    }
+
+    fn clear_failure(&self, name: &str, path: Option<&Path>) {
+        let mut failures = self.failures.write().unwrap();
+        failures.remove(&Self::failure_key(name, path));
+        // Loading from disk also settles a failure recorded under the file stem
+        failures.remove(&PathBuf::from(name));
+    }
+
+    /// Forget load failures for files that are `path` or lie beneath it, once
+    /// they have been removed
+    pub fn clear_failures_under(&self, path: &Path) {
+        self.failures
+            .write()
+            .unwrap()
+            .retain(|key, _| !key.starts_with(path));
+    }
+
+    /// What is loaded, what failed and when extensions last changed
+    pub async fn diagnostics(&self) -> Diagnostics {
+        let extensions = self.extensions.read().await;
+        let mut loaded: Vec<LoadedSummary> = extensions
+            .iter()
+            .map(|(name, loaded)| LoadedSummary {
+                name: name.clone(),
+                version: loaded.extension.version.clone(),
+                path: loaded.path.clone(),
+                tools: loaded.extension.tools.len(),
+                missing_config: loaded.extension.missing_config.clone(),
+                permissions: Permissions::of(&loaded.extension),
+                lint: loaded.lint.clone(),
+                loaded_at: loaded.loaded_at,
+            })
+            .collect();
+        loaded.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut failed: Vec<ExtensionFailure> =
+            self.failures.read().unwrap().values().cloned().collect();
+        failed.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Diagnostics {
+            loaded,
+            failed,
+            last_reload: *self.last_reload.read().unwrap(),
+        }
+    }
+
+    /// Name of the extension loaded from `path`
+    pub async fn extension_at(&self, path: &Path) -> Option<String> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let extensions = self.extensions.read().await;
+        extensions.by_path.get(&path).cloned()
+    }
+
+    /// Source files of loaded extensions that are `path` or lie beneath it
+    pub async fn files_under(&self, path: &Path) -> Vec<PathBuf> {
+        let extensions = self.extensions.read().await;
+        let mut files: Vec<PathBuf> = extensions
+            .by_path
+            .keys()
+            .filter(|file| file.starts_with(path))
+            .cloned()
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Remove the extension loaded from `path`, whatever it is called
+    pub async fn remove_extension_at(&self, path: &Path) -> Option<StarlarkExtension> {
+        let name = self.extension_at(path).await?;
+        self.remove_extension(&name).await
     }
 
     pub async fn remove_extension(&self, name: &str) -> Option<StarlarkExtension> {
         info!("Removing extension: {}", name);
-        let mut extensions = self.extensions.write().await;
-        extensions.remove(name).map(|e| e.extension)
+        let removed = self.extensions.write().await.remove(name)?;
+        if let Some(ref path) = removed.path {
+            self.module_cache.clear_dependencies(path);
+            self.module_cache.invalidate(path);
+        }
+        let extension = removed.extension.clone();
+        *self.last_reload.write().unwrap() = Some(Utc::now());
+        self.unload(vec![removed]);
+        Some(extension)
     }
 }
 
 #[derive(Clone)]
 pub struct ToolExecutor {
     engine: Arc<StarlarkEngine>,
-    extensions_dir: Option<String>,
+    limits: Arc<std::sync::RwLock<ExecutionLimits>>,
+    error_verbosity: Arc<std::sync::RwLock<ErrorVerbosity>>,
+    concurrency: Arc<Semaphore>,
 }
 
+/// Default number of tool handlers allowed to run at once
+pub const DEFAULT_MAX_CONCURRENT_TOOLS: usize = 16;
+
 impl Default for ToolExecutor {
     fn default() -> Self {
         Self::new()
@@ -137,17 +1094,58 @@ impl ToolExecutor {
     pub fn new() -> Self {
         Self {
             engine: Arc::new(StarlarkEngine::new()),
-            extensions_dir: None,
+            limits: Arc::default(),
+            error_verbosity: Arc::default(),
+            concurrency: Arc::new(Semaphore::new(DEFAULT_MAX_CONCURRENT_TOOLS)),
+        }
+    }
+
+    pub fn with_extensions_dir(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            engine: Arc::new(StarlarkEngine::new().with_extensions_dir(dir)),
+            ..self
         }
     }
 
-    pub fn with_extensions_dir(self, dir: String) -> Self {
+    /// Use an engine configured by the caller
+    pub fn with_engine(self, engine: StarlarkEngine) -> Self {
         Self {
-            engine: Arc::new(StarlarkEngine::new().with_extensions_dir(dir.clone())),
-            extensions_dir: Some(dir),
+            engine: Arc::new(engine),
+            ..self
         }
     }
 
+    /// Server-wide execution limits, used for any limit a tool doesn't set itself
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = Arc::new(std::sync::RwLock::new(limits));
+        self
+    }
+
+    /// Replace the server-wide limits for subsequent calls, e.g. when the
+    /// server config is reloaded. Clones of the executor see the change.
+    pub fn set_limits(&self, limits: ExecutionLimits) {
+        *self.limits.write().unwrap() = limits;
+    }
+
+    /// How much detail the text of a failed handler's result includes
+    pub fn with_error_verbosity(mut self, verbosity: ErrorVerbosity) -> Self {
+        self.error_verbosity = Arc::new(std::sync::RwLock::new(verbosity));
+        self
+    }
+
+    /// Replace the error verbosity for subsequent calls; clones of the
+    /// executor see the change
+    pub fn set_error_verbosity(&self, verbosity: ErrorVerbosity) {
+        *self.error_verbosity.write().unwrap() = verbosity;
+    }
+
+    /// Maximum number of tool handlers running at once; further calls wait
+    /// for a slot. Clones of the executor share the same pool.
+    pub fn with_max_concurrency(mut self, max: usize) -> Self {
+        self.concurrency = Arc::new(Semaphore::new(max.max(1)));
+        self
+    }
+
     pub fn engine(&self) -> Arc<StarlarkEngine> {
         self.engine.clone()
     }
@@ -157,71 +1155,181 @@ impl ToolExecutor {
         tool_name: &str,
         arguments: serde_json::Value,
     ) -> Result<ToolResult> {
-        debug!("Executing tool: {}", tool_name);
-
-        let extensions = self.engine.extensions.read().await;
-
-        let (extension_name, tool) = extensions
-            .iter()
-            .find_map(|(ext_name, loaded_ext)| {
-                loaded_ext
-                    .extension
-                    .tools
-                    .iter()
-                    .find(|t| t.name == tool_name)
-                    .map(|t| (ext_name.clone(), t.clone()))
-            })
-            .ok_or_else(|| anyhow!("Tool not found: {}", tool_name))?;
-
-        let loaded_ext = extensions
-            .get(&extension_name)
-            .ok_or_else(|| anyhow!("Extension not found: {}", extension_name))?;
+        self.execute_tool_for_request(tool_name, arguments, CancellationToken::new(), None)
+            .await
+    }
 
-        super::modules::set_exec_whitelist(loaded_ext.extension.allowed_exec.clone());
+    /// Execute a tool on behalf of an MCP request. The handler is stopped at its
+    /// next statement once `cancellation` fires or this future is dropped.
+    pub async fn execute_tool_for_request(
+        &self,
+        tool_name: &str,
+        arguments: serde_json::Value,
+        cancellation: CancellationToken,
+        peer: Option<Peer<RoleServer>>,
+    ) -> Result<ToolResult> {
+        debug!("Executing tool: {}", tool_name);
 
-        if let Some(ref dir) = self.extensions_dir {
-            super::modules::set_extensions_dir(dir.clone());
+        // Only hold the lock long enough to grab the frozen module; a slow
+        // handler must not hold up hot reloads
+        let (tool, module, context, sources, missing_config) = {
+            let extensions = self.engine.extensions.read().await;
+            // Exposed names are unique across extensions, so at most one matches
+            extensions
+                .iter()
+                .find_map(|(name, loaded_ext)| {
+                    loaded_ext
+                        .extension
+                        .tools
+                        .iter()
+                        .find(|t| self.engine.exposed_tool_name(name, &t.name) == tool_name)
+                        .map(|t| {
+                            let context = self.engine.context_for(loaded_ext);
+                            let sources = SourceFiles::new(
+                                name,
+                                loaded_ext.path.clone(),
+                                context.extensions_dir.clone(),
+                            );
+                            (
+                                t.clone(),
+                                loaded_ext.module.clone(),
+                                context,
+                                sources,
+                                loaded_ext.extension.missing_config.clone(),
+                            )
+                        })
+                })
+                .ok_or_else(|| anyhow!("Tool not found: {}", tool_name))?
+        };
+
+        if !missing_config.is_empty() {
+            return Ok(missing_config_result(
+                tool_name,
+                &context.extension,
+                &missing_config,
+            ));
         }
 
-        let module = Module::new();
+        let permit = self
+            .concurrency
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| anyhow!("Tool executor is shut down: {}", e))?;
+
+        let cancellation = cancellation.child_token();
+        let _cancel_on_drop = cancellation.clone().drop_guard();
+
+        let limits = tool.limits.or(&self.limits.read().unwrap());
+        let context = context
+            .with_tool(tool_name)
+            .with_cancellation(cancellation)
+            .with_deadline(limits.timeout.map(|timeout| Instant::now() + timeout))
+            .with_peer(peer);
+        let verbosity = *self.error_verbosity.read().unwrap();
+
+        // Handlers make blocking calls (HTTP, exec, database drivers), so they
+        // run on the blocking pool rather than a runtime worker. The permit
+        // goes with the handler: if this request is dropped, the handler runs
+        // on until it notices and still counts against the limit.
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let redactor = context.config.redactor().clone();
+            run_handler(&tool, &module, &context, &limits, arguments)
+                .map(|result| {
+                    result.unwrap_or_else(|error| {
+                        let error = HandlerError::new(&error, &sources);
+                        warn!(
+                            "Tool '{}' failed: {}",
+                            tool.name,
+                            redactor.redact(&error.message)
+                        );
+                        error.to_tool_result(&tool.name, verbosity)
+                    })
+                })
+                .map(|result| redactor.redact_result(result))
+                .map_err(|e| redactor.redact_error(e))
+        })
+        .await
+        .map_err(|e| anyhow!("Handler for '{}' panicked: {}", tool_name, e))?
+    }
+}
 
-        let function_name = tool
-            .handler_name
-            .split('.')
-            .next_back()
-            .unwrap_or(&tool.handler_name);
+/// Result for a call to a tool whose extension is missing required config
+fn missing_config_result(tool_name: &str, extension: &str, missing: &[String]) -> ToolResult {
+    ToolResult {
+        content: vec![ToolContent::Text {
+            text: format!(
+                "Error: tool '{}' is unavailable: extension '{}' is missing required config {}",
+                tool_name,
+                extension,
+                missing.join(", ")
+            ),
+        }],
+        is_error: Some(true),
+        structured_content: Some(serde_json::json!({
+            "error": "missing_config",
+            "missing": missing,
+        })),
+    }
+}
 
-        let handler_frozen = loaded_ext
-            .module
-            .get(function_name)
-            .map_err(|e| anyhow!("Handler lookup error for '{}': {}", function_name, e))?;
+/// Name the tool's handler is bound to in its extension's module
+fn handler_function_name(tool: &StarlarkTool) -> &str {
+    tool.handler_name
+        .split('.')
+        .next_back()
+        .unwrap_or(&tool.handler_name)
+}
 
+/// Evaluate a tool handler against its extension's frozen module.
+///
+/// Runs synchronously on the calling thread, with `context` attached to the
+/// evaluator. A Starlark error raised by the handler is returned as the inner
+/// error, so the caller can turn it into an error result with a backtrace.
+fn run_handler(
+    tool: &StarlarkTool,
+    frozen: &FrozenModule,
+    context: &CallContext,
+    limits: &ExecutionLimits,
+    arguments: serde_json::Value,
+) -> Result<Result<ToolResult, starlark::Error>> {
+    let function_name = handler_function_name(tool);
+
+    let handler_frozen = frozen
+        .get(function_name)
+        .map_err(|e| anyhow!("Handler lookup error for '{}': {}", function_name, e))?;
+
+    Module::with_temp_heap(|module| {
         let mut eval = Evaluator::new(&module);
+        context.attach(&mut eval);
 
         let heap = module.heap();
         let params_dict = json_to_starlark_value(arguments, heap)?;
-        let handler = handler_frozen.value();
-        let result_value = eval
-            .eval_function(handler, &[params_dict], &[])
-            .map_err(|e| {
-                super::modules::clear_exec_whitelist();
-                super::modules::clear_extensions_dir();
-                anyhow!("Handler execution error: {}", e)
-            })?;
-
-        super::modules::clear_exec_whitelist();
-        super::modules::clear_extensions_dir();
+        let handler = module.heap().access_owned_frozen_value(&handler_frozen);
+        let outcome = run_with_limits(&mut eval, limits, |eval| {
+            eval.eval_function(handler, &[params_dict], &[])
+        });
+
+        let result_value = match outcome {
+            Ok(Ok(result)) => result,
+            Ok(Err(error)) => return Ok(Err(error)),
+            Err(exceeded) => {
+                warn!("Tool '{}' stopped: {}", tool.name, exceeded);
+                return Ok(Ok(exceeded.to_tool_result(&tool.name)));
+            }
+        };
 
         let result_json = starlark_value_to_json(result_value, heap)?;
         let tool_result: ToolResult = serde_json::from_value(result_json)?;
 
-        Ok(tool_result)
-    }
+        Ok(Ok(tool_result))
+    })
 }
 
-fn json_to_starlark_value<'v>(
+pub(crate) fn json_to_starlark_value<'v>(
     json: serde_json::Value,
-    heap: &'v starlark::values::Heap,
+    heap: starlark::values::Heap<'v>,
 ) -> Result<Value<'v>> {
     match json {
         serde_json::Value::Null => Ok(Value::new_none()),
@@ -257,7 +1365,7 @@ fn json_to_starlark_value<'v>(
 
 pub fn starlark_value_to_json<'v>(
     value: Value<'v>,
-    heap: &'v starlark::values::Heap,
+    heap: starlark::values::Heap<'v>,
 ) -> Result<serde_json::Value> {
     // Handle primitives first
     if value.is_none() {
@@ -320,18 +1428,19 @@ mod tests {
     use starlark::environment::Module;
 
     fn eval_to_json(code: &str) -> Result<serde_json::Value> {
-        let module = Module::new();
-        let globals = Globals::standard();
-        let mut eval = Evaluator::new(&module);
+        Module::with_temp_heap(|module| {
+            let globals = Globals::standard();
+            let mut eval = Evaluator::new(&module);
 
-        let ast = AstModule::parse("test", code.to_owned(), &Dialect::Standard)
-            .map_err(|e| anyhow!("Parse error: {}", e))?;
+            let ast = AstModule::parse("test", code.to_owned(), &Dialect::Standard)
+                .map_err(|e| anyhow!("Parse error: {}", e))?;
 
-        let value = eval
-            .eval_module(ast, &globals)
-            .map_err(|e| anyhow!("Eval error: {}", e))?;
+            let value = eval
+                .eval_module(ast, &globals)
+                .map_err(|e| anyhow!("Eval error: {}", e))?;
 
-        starlark_value_to_json(value, module.heap())
+            starlark_value_to_json(value, module.heap())
+        })
     }
 
     #[test]
@@ -365,6 +1474,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn test_starlark_value_to_json_float() {
         let result = eval_to_json("3.14").unwrap();
         assert_eq!(result, json!(3.14));
@@ -472,4 +1582,263 @@ mod tests {
         // Note: floating point arithmetic means this won't be exactly 0.3
         assert!(result.as_f64().unwrap() > 0.29 && result.as_f64().unwrap() < 0.31);
     }
+
+    async fn call_tool(executor: &ToolExecutor, tool: &str) -> String {
+        let result = executor.execute_tool(tool, json!({})).await.unwrap();
+        match result.content.first() {
+            Some(crate::mcp::ToolContent::Text { text }) => text.clone(),
+            None => panic!("Expected text content in tool result"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_top_level_env_is_not_readable() {
+        let extension = r#"
+AT_LOAD = env.get("PATH", "none")
+
+def read(params):
+    return {"content": [{"type": "text", "text": AT_LOAD + "," + env.get("PATH", "none")}]}
+
+def describe_extension():
+    return Extension(
+        name = "scoped",
+        version = "1.0.0",
+        description = "Reads the environment",
+        allowed_env = ["PLANE_*"],
+        tools = [Tool(name = "read", description = "Read", handler = read)],
+    )
+"#;
+        let executor = ToolExecutor::new();
+        executor
+            .engine()
+            .load_extension("scoped", extension)
+            .await
+            .unwrap();
+        assert_eq!(call_tool(&executor, "read").await, "none,none");
+
+        let executor = ToolExecutor::new().with_engine(StarlarkEngine::new().with_strict_env(true));
+        let err = executor
+            .engine()
+            .load_extension("scoped", extension)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("cannot read environment variable 'PATH'"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_var_outside_allowed_env_does_not_resolve() {
+        let extension = r#"
+def read(params):
+    return {"content": [{"type": "text", "text": config.get("PATH", "unset")}]}
+
+def describe_extension():
+    return Extension(
+        name = "scoped",
+        version = "1.0.0",
+        description = "Declares a variable it may not read",
+        allowed_env = ["PLANE_*"],
+        config = [ConfigVar(name = "PATH")],
+        tools = [Tool(name = "read", description = "Read", handler = read)],
+    )
+"#;
+        let executor = ToolExecutor::new();
+        executor
+            .engine()
+            .load_extension("scoped", extension)
+            .await
+            .unwrap();
+        assert_eq!(call_tool(&executor, "read").await, "unset");
+
+        let err = StarlarkEngine::new()
+            .with_strict_env(true)
+            .load_extension("scoped", extension)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("cannot read environment variable 'PATH'"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_top_level_http_is_blocked() {
+        let extension = r#"
+def describe_extension():
+    return Extension(
+        name = "scoped",
+        version = "1.0.0",
+        description = "Calls out while loading",
+        allowed_hosts = ["example.org"],
+        tools = [],
+    )
+
+BODY = http.get("https://example.org/")["body"]
+"#;
+        let err = StarlarkEngine::new()
+            .load_extension("scoped", extension)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Host 'example.org' is not allowed"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exec_is_bounded_by_tool_timeout() {
+        let extension = r#"
+def nap(params):
+    exec.run("sleep", ["30"])
+    return {"content": [{"type": "text", "text": "woke up"}]}
+
+def describe_extension():
+    return Extension(
+        name = "sleepy",
+        version = "1.0.0",
+        description = "Blocks in a child process",
+        allowed_exec = ["sleep"],
+        tools = [Tool(name = "nap", description = "Nap", handler = nap, timeout_secs = 1)],
+    )
+"#;
+        let executor = ToolExecutor::new();
+        executor
+            .engine()
+            .load_extension("sleepy", extension)
+            .await
+            .unwrap();
+        let started = std::time::Instant::now();
+        let text = call_tool(&executor, "nap").await;
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+        assert!(text.contains("execution time limit"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_top_level_code_is_checked_against_requires() {
+        let extension = |requires: &str| {
+            format!(
+                r#"
+def describe_extension():
+    return Extension(
+        name = "scoped",
+        version = "1.0.0",
+        description = "Uses kv while loading",
+        {}
+        tools = [],
+    )
+
+kv.set("loaded", True)
+"#,
+                requires
+            )
+        };
+        let load = |source: String| async move {
+            StarlarkEngine::new()
+                .with_kv_store(KvStore::in_memory().unwrap())
+                .load_extension("scoped", &source)
+                .await
+        };
+
+        load(extension("")).await.unwrap();
+        load(extension("requires = [\"json\", \"kv\"],"))
+            .await
+            .unwrap();
+        let err = load(extension("requires = [\"json\"],")).await.unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("cannot use the kv module"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kv_is_keyed_by_load_name() {
+        let extension = r#"
+def remember(params):
+    kv.set("token", "stolen")
+    return {"content": [{"type": "text", "text": kv.get("token")}]}
+
+def describe_extension():
+    return Extension(
+        name = "github",
+        version = "1.0.0",
+        description = "Claims another extension's name",
+        tools = [Tool(name = "remember", description = "Remember", handler = remember)],
+    )
+"#;
+        let store = KvStore::in_memory().unwrap();
+        let executor =
+            ToolExecutor::new().with_engine(StarlarkEngine::new().with_kv_store(store.clone()));
+        executor
+            .engine()
+            .load_extension("impostor", extension)
+            .await
+            .unwrap();
+
+        assert_eq!(call_tool(&executor, "remember").await, "stolen");
+        assert_eq!(store.list("impostor", "").unwrap(), vec!["token"]);
+        assert!(store.list("github", "").unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_kv_namespace_survives_moving_the_root() {
+        let extension = r#"
+def remember(params):
+    kv.set("seen", True)
+    return {"content": [{"type": "text", "text": "ok"}]}
+
+def describe_extension():
+    return Extension(
+        name = "notes",
+        version = "1.0.0",
+        description = "Remembers things",
+        tools = [Tool(name = "remember", description = "Remember", handler = remember)],
+    )
+"#;
+        let store = KvStore::in_memory().unwrap();
+        let load = |engine: StarlarkEngine, root: &Path| {
+            let file = root.join("team/notes.star");
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(&file, extension).unwrap();
+            let store = store.clone();
+            async move {
+                let executor = ToolExecutor::new().with_engine(engine.with_kv_store(store));
+                executor
+                    .engine()
+                    .load_extension_with_path("notes", extension, Some(&file))
+                    .await
+                    .unwrap();
+                call_tool(&executor, "remember").await;
+            }
+        };
+
+        for _ in 0..2 {
+            let root = tempfile::TempDir::new().unwrap();
+            load(
+                StarlarkEngine::new().with_extensions_dir(root.path()),
+                root.path(),
+            )
+            .await;
+        }
+        assert_eq!(store.list("team/notes.star", "").unwrap(), vec!["seen"]);
+
+        let package = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            package.path().join("package.toml"),
+            "name = \"notes-pkg\"\nversion = \"1.0.0\"\n",
+        )
+        .unwrap();
+        let engine = StarlarkEngine::new();
+        engine.add_package_root(package.path());
+        load(engine, package.path()).await;
+        assert_eq!(
+            store.list("notes-pkg:team/notes.star", "").unwrap(),
+            vec!["seen"]
+        );
+    }
 }
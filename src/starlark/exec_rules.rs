@@ -0,0 +1,297 @@
+use anyhow::{Result, anyhow};
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use std::fmt;
+
+/// Prefix marking an `ExecRule(args=...)` pattern as a regular expression
+/// rather than a glob
+pub const REGEX_PREFIX: &str = "re:";
+
+/// Constraints on the arguments a command in `allowed_exec` may be run with,
+/// declared with `ExecRule()`
+#[derive(Debug, Clone)]
+pub struct ExecRule {
+    pub command: String,
+    /// Values allowed for the first argument, or `None` for any. Flags can't
+    /// come before the subcommand, since a flag's value would then be taken
+    /// for it.
+    pub subcommands: Option<Vec<String>>,
+    /// Flags that may not appear, either alone or as `--flag=value`. A short
+    /// flag like `-s` is also found with its value attached (`-sVALUE`) or
+    /// grouped with other short flags (`-As`).
+    pub forbidden_flags: Vec<String>,
+    /// Pattern for each argument by position in the argument list, flags
+    /// included; `None` leaves that position unconstrained, as are positions
+    /// past the end of the list
+    pub args: Vec<Option<ArgPattern>>,
+}
+
+/// Glob or regular expression that a whole argument must match
+#[derive(Debug, Clone)]
+pub enum ArgPattern {
+    Glob(String, GlobMatcher),
+    Regex(String, Regex),
+}
+
+impl ArgPattern {
+    /// Parse `pattern`: a regular expression if it starts with `re:`,
+    /// otherwise a glob
+    pub fn new(pattern: &str) -> Result<Self> {
+        match pattern.strip_prefix(REGEX_PREFIX) {
+            Some(regex) => Regex::new(&format!("^(?:{})$", regex))
+                .map(|re| Self::Regex(pattern.to_string(), re))
+                .map_err(|e| anyhow!("Invalid regex '{}': {}", regex, e)),
+            None => Glob::new(pattern)
+                .map(|glob| Self::Glob(pattern.to_string(), glob.compile_matcher()))
+                .map_err(|e| anyhow!("Invalid glob '{}': {}", pattern, e)),
+        }
+    }
+
+    pub fn matches(&self, arg: &str) -> bool {
+        match self {
+            Self::Glob(_, glob) => glob.is_match(arg),
+            Self::Regex(_, regex) => regex.is_match(arg),
+        }
+    }
+}
+
+impl fmt::Display for ArgPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Glob(source, _) | Self::Regex(source, _) => f.write_str(source),
+        }
+    }
+}
+
+impl ExecRule {
+    /// A rule for `command`, compiling each pattern in `args`
+    pub fn new(
+        command: String,
+        subcommands: Option<Vec<String>>,
+        forbidden_flags: Vec<String>,
+        args: &[Option<String>],
+    ) -> Result<Self> {
+        let args = args
+            .iter()
+            .map(|pattern| pattern.as_deref().map(ArgPattern::new).transpose())
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow!("ExecRule '{}': {}", command, e))?;
+        Ok(Self {
+            command,
+            subcommands,
+            forbidden_flags,
+            args,
+        })
+    }
+
+    /// Fail unless `args` satisfy the rule, naming the offending argument
+    pub fn check(&self, args: &[String]) -> Result<()> {
+        for arg in args {
+            if let Some(flag) = self.forbidden_flags.iter().find(|flag| is_flag(arg, flag)) {
+                return Err(anyhow!("flag '{}' is forbidden", flag));
+            }
+        }
+
+        if let Some(ref subcommands) = self.subcommands {
+            match args.first() {
+                Some(flag) if flag.starts_with('-') => {
+                    return Err(anyhow!(
+                        "the subcommand must be the first argument, found flag '{}'",
+                        flag
+                    ));
+                }
+                Some(subcommand) if subcommands.contains(subcommand) => {}
+                Some(subcommand) => {
+                    return Err(anyhow!(
+                        "subcommand '{}' is not allowed (allowed: {})",
+                        subcommand,
+                        subcommands.join(", ")
+                    ));
+                }
+                None => {
+                    return Err(anyhow!(
+                        "a subcommand is required (allowed: {})",
+                        subcommands.join(", ")
+                    ));
+                }
+            }
+        }
+
+        for (position, (arg, pattern)) in args.iter().zip(&self.args).enumerate() {
+            if let Some(pattern) = pattern
+                && !pattern.matches(arg)
+            {
+                return Err(anyhow!(
+                    "argument {} '{}' does not match '{}'",
+                    position,
+                    arg,
+                    pattern
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ExecRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut constraints = Vec::new();
+        if let Some(ref subcommands) = self.subcommands {
+            constraints.push(format!("subcommands {}", subcommands.join("|")));
+        }
+        if !self.forbidden_flags.is_empty() {
+            constraints.push(format!("not {}", self.forbidden_flags.join(" ")));
+        }
+        for (position, pattern) in self.args.iter().enumerate() {
+            if let Some(pattern) = pattern {
+                constraints.push(format!("arg {} {}", position, pattern));
+            }
+        }
+        if constraints.is_empty() {
+            write!(f, "{}", self.command)
+        } else {
+            write!(f, "{} ({})", self.command, constraints.join("; "))
+        }
+    }
+}
+
+/// Whether `arg` is `flag`, or `flag` with an `=value` attached. A short
+/// flag also matches any group of short flags containing it: without
+/// knowing which flags take a value, `-As` and `-shttps://host` both have to
+/// be read as setting `-s`.
+fn is_flag(arg: &str, flag: &str) -> bool {
+    if let Some(short) = short_flag(flag)
+        && let Some(group) = arg.strip_prefix('-')
+        && !group.starts_with('-')
+    {
+        return group.contains(short);
+    }
+    arg.strip_prefix(flag)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('='))
+}
+
+/// The letter of a short flag such as `-s`
+fn short_flag(flag: &str) -> Option<char> {
+    let mut chars = flag.strip_prefix('-')?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), None) if letter != '-' => Some(letter),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    fn kubectl() -> ExecRule {
+        ExecRule::new(
+            "kubectl".to_string(),
+            Some(vec!["get".to_string(), "logs".to_string()]),
+            vec!["--as".to_string(), "-A".to_string()],
+            &[
+                None,
+                Some("{pods,services,deployments}".to_string()),
+                Some("re:[a-z0-9-]+".to_string()),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        let rule = kubectl();
+        assert!(rule.check(&args("get pods")).is_ok());
+        assert!(rule.check(&args("get pods web-1 -o json")).is_ok());
+
+        let err = |line| rule.check(&args(line)).unwrap_err().to_string();
+        assert_eq!(
+            err("delete ns prod"),
+            "subcommand 'delete' is not allowed (allowed: get, logs)"
+        );
+        assert_eq!(err(""), "a subcommand is required (allowed: get, logs)");
+        assert_eq!(
+            err("--help"),
+            "the subcommand must be the first argument, found flag '--help'"
+        );
+        // A flag's value must not be mistaken for the subcommand
+        assert_eq!(
+            err("--namespace get delete ns prod"),
+            "the subcommand must be the first argument, found flag '--namespace'"
+        );
+        assert_eq!(
+            err("-n x get secrets"),
+            "the subcommand must be the first argument, found flag '-n'"
+        );
+        // Positions count flags, so a flag can't push an argument out of a
+        // checked position
+        assert_eq!(
+            err("get -o secrets"),
+            "argument 1 '-o' does not match '{pods,services,deployments}'"
+        );
+        assert_eq!(err("get pods -A"), "flag '-A' is forbidden");
+        // Short flags can carry their value or share a dash with others
+        assert_eq!(err("get pods -Aojson"), "flag '-A' is forbidden");
+        assert_eq!(err("get pods -wA"), "flag '-A' is forbidden");
+        assert!(rule.check(&args("get pods -o wide")).is_ok());
+        assert_eq!(err("get pods --as=admin"), "flag '--as' is forbidden");
+        assert!(rule.check(&args("get pods --ascii")).is_ok());
+        assert_eq!(
+            err("get secrets"),
+            "argument 1 'secrets' does not match '{pods,services,deployments}'"
+        );
+        assert_eq!(
+            err("get pods Web_1"),
+            "argument 2 'Web_1' does not match 're:[a-z0-9-]+'"
+        );
+    }
+
+    #[test]
+    fn test_short_flag_with_attached_value() {
+        let rule = ExecRule::new(
+            "kubectl".to_string(),
+            None,
+            vec!["--server".to_string(), "-s".to_string()],
+            &[],
+        )
+        .unwrap();
+        for line in [
+            "get pods -s https://evil",
+            "get pods -shttps://evil",
+            "get pods -s=https://evil",
+            "get pods --server=https://evil",
+        ] {
+            assert!(rule.check(&args(line)).is_err(), "{}", line);
+        }
+        assert!(rule.check(&args("get pods --selector app=web")).is_ok());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            kubectl().to_string(),
+            "kubectl (subcommands get|logs; not --as -A; arg 1 {pods,services,deployments}; arg 2 re:[a-z0-9-]+)"
+        );
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        let err = ExecRule::new(
+            "git".to_string(),
+            None,
+            Vec::new(),
+            &[Some("re:(".to_string())],
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("ExecRule 'git': Invalid regex '('"),
+            "{}",
+            err
+        );
+        assert!(ArgPattern::new("[").is_err());
+    }
+}
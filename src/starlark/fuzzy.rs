@@ -20,8 +20,8 @@ starlark_simple_value!(FuzzyModule);
 #[starlark_value(type = "fuzzy")]
 impl<'v> StarlarkValue<'v> for FuzzyModule {
     fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(fuzzy_methods)
+        static RES: MethodsStatic = MethodsStatic::new("fuzzy", fuzzy_methods);
+        Some(RES.methods())
     }
 
     fn dir_attr(&self) -> Vec<String> {
@@ -46,7 +46,7 @@ enum SearchKeys<'a> {
 }
 
 /// Extract the search text from an item based on the keys parameter
-fn get_search_text<'v>(item: Value<'v>, keys: &SearchKeys, heap: &'v Heap) -> Option<String> {
+fn get_search_text<'v>(item: Value<'v>, keys: &SearchKeys, heap: Heap<'v>) -> Option<String> {
     if let Some(s) = item.unpack_str() {
         return Some(s.to_string());
     }
@@ -82,7 +82,7 @@ fn get_search_text<'v>(item: Value<'v>, keys: &SearchKeys, heap: &'v Heap) -> Op
 fn collect_string_values<'v, 'a>(
     item: Value<'v>,
     keys: impl Iterator<Item = &'a str>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> Option<String> {
     let text_parts: Vec<String> = keys
         .filter_map(|key| {
@@ -107,7 +107,7 @@ fn fuzzy_search_internal<'v>(
     items: Value<'v>,
     keys: &SearchKeys,
     limit: Option<i32>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> anyhow::Result<Vec<ScoredItem<'v>>> {
     let matcher = SkimMatcherV2::default();
     let mut results: Vec<ScoredItem<'v>> = Vec::new();
@@ -124,7 +124,7 @@ fn fuzzy_search_internal<'v>(
         }
     }
 
-    results.sort_by(|a, b| b.score.cmp(&a.score));
+    results.sort_by_key(|b| std::cmp::Reverse(b.score));
 
     if let Some(limit) = limit
         && limit > 0
@@ -138,7 +138,7 @@ fn fuzzy_search_internal<'v>(
 /// Helper to insert a key-value pair into a SmallMap with hashing
 fn insert_hashed<'v>(
     map: &mut SmallMap<Value<'v>, Value<'v>>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
     key: &str,
     value: Value<'v>,
 ) {
@@ -163,7 +163,7 @@ fn parse_search_keys<'a, 'v>(
     keys: Value<'v>,
     key_storage: &'a mut Vec<String>,
     func_name: &str,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> anyhow::Result<SearchKeys<'a>> {
     if !key.is_none() && !keys.is_none() {
         return Err(anyhow::anyhow!(
@@ -243,7 +243,7 @@ fn fuzzy_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] key: Value<'v>,
         #[starlark(default = NoneType)] keys: Value<'v>,
         #[starlark(default = NoneType)] limit: Value<'v>,
-        heap: &'v Heap,
+        heap: Heap<'v>,
     ) -> anyhow::Result<Value<'v>> {
         let mut key_storage = Vec::new();
         let search_keys = parse_search_keys(key, keys, &mut key_storage, "fuzzy.search", heap)?;
@@ -283,7 +283,7 @@ fn fuzzy_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] key: Value<'v>,
         #[starlark(default = NoneType)] keys: Value<'v>,
         #[starlark(default = NoneType)] limit: Value<'v>,
-        heap: &'v Heap,
+        heap: Heap<'v>,
     ) -> anyhow::Result<Value<'v>> {
         let mut key_storage = Vec::new();
         let search_keys = parse_search_keys(
@@ -325,11 +325,12 @@ mod tests {
 
     fn eval_fuzzy(code: &str) -> Result<String, starlark::Error> {
         let globals = GlobalsBuilder::new().with(register).build();
-        let module = starlark::environment::Module::new();
-        let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
-        let mut eval = Evaluator::new(&module);
-        let result = eval.eval_module(ast, &globals)?;
-        Ok(result.to_string())
+        starlark::environment::Module::with_temp_heap(|module| {
+            let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
+            let mut eval = Evaluator::new(&module);
+            let result = eval.eval_module(ast, &globals)?;
+            Ok(result.to_string())
+        })
     }
 
     #[test]
@@ -2,10 +2,12 @@ use allocative::Allocative;
 use anyhow::{Result, anyhow};
 use derive_more::Display;
 use reqwest::blocking::{Client, Response};
-use reqwest::header::CONTENT_TYPE;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::{Method, StatusCode};
 use serde_json::Value as JsonValue;
 use starlark::collections::SmallMap;
 use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
 use starlark::starlark_module;
 use starlark::starlark_simple_value;
 use starlark::values::starlark_value;
@@ -16,14 +18,78 @@ use starlark::values::{
     tuple::TupleRef,
 };
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 use url::Url;
 
-// HTTP client - we'll use a global client for connection pooling
+use super::context::{CallContext, Capabilities};
+use super::network::check_url;
+
+/// Redirects followed before a request fails, as reqwest's default policy
+const MAX_REDIRECTS: usize = 10;
+
+/// Headers dropped when a redirect leads to another host
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "cookie", "proxy-authorization"];
+
+/// Headers describing the body, dropped with it when a redirect turns the
+/// request into a GET
+const BODY_HEADERS: &[&str] = &[
+    "content-type",
+    "content-encoding",
+    "content-length",
+    "transfer-encoding",
+];
+
+/// Pinned clients kept for reuse before the cache starts over
+const MAX_PINNED_CLIENTS: usize = 64;
+
+/// Longest a single request may take, unless the tool call's deadline is
+/// sooner
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// HTTP client - we'll use a global client for connection pooling. Redirects
+// are followed by make_request_with_body so each hop can be checked.
 lazy_static::lazy_static! {
     static ref HTTP_CLIENT: Client = Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
         .build()
         .expect("Failed to create HTTP client");
+
+    /// Clients from [`pinned_client`], by host and pinned addresses, so
+    /// repeated requests to a host share its connection pool
+    static ref PINNED_CLIENTS: Mutex<HashMap<(String, Vec<SocketAddr>), Client>> =
+        Mutex::new(HashMap::new());
+}
+
+/// A client that connects to `host` only at `addresses`, the ones
+/// `check_url` vetted, so a second DNS lookup can't send the request
+/// somewhere else. Proxies from the environment still apply; a proxied
+/// request is only checked by `check_url`, since the proxy resolves the
+/// host itself.
+fn pinned_client(host: &str, addresses: &[SocketAddr]) -> Result<Client> {
+    let mut addresses = addresses.to_vec();
+    addresses.sort();
+    addresses.dedup();
+    let key = (host.to_string(), addresses);
+
+    let mut clients = PINNED_CLIENTS.lock().unwrap();
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.clone());
+    }
+    let client = Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .resolve_to_addrs(host, &key.1)
+        .build()
+        .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+    if clients.len() >= MAX_PINNED_CLIENTS {
+        clients.clear();
+    }
+    clients.insert(key, client.clone());
+    Ok(client)
 }
 
 /// HTTP module for making HTTP requests
@@ -36,8 +102,8 @@ starlark_simple_value!(HttpModule);
 #[starlark_value(type = "http")]
 impl<'v> StarlarkValue<'v> for HttpModule {
     fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(http_methods)
+        static RES: MethodsStatic = MethodsStatic::new("http", http_methods);
+        Some(RES.methods())
     }
 }
 
@@ -52,12 +118,16 @@ fn http_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] params: Value<'v>,
         #[starlark(default = NoneType)] headers: Value<'v>,
         #[starlark(default = NoneType)] auth: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
-        make_request("GET", url, params, headers, auth, None, heap)
+        CallContext::check_module(eval, "http")?;
+        let limits = request_limits(eval);
+        let heap = eval.heap();
+        make_request("GET", url, params, headers, auth, limits, heap)
     }
 
     /// Make an HTTP POST request
+    #[allow(clippy::too_many_arguments)]
     fn post<'v>(
         #[allow(unused_variables)] this: Value<'v>,
         url: &str,
@@ -67,14 +137,18 @@ fn http_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] json_body: Value<'v>,
         #[starlark(default = NoneType)] form_body: Value<'v>,
         #[starlark(default = NoneType)] auth: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "http")?;
+        let limits = request_limits(eval);
+        let heap = eval.heap();
         make_request_with_body(
-            "POST", url, params, headers, auth, body, json_body, form_body, heap,
+            "POST", url, params, headers, auth, body, json_body, form_body, limits, heap,
         )
     }
 
     /// Make an HTTP PUT request
+    #[allow(clippy::too_many_arguments)]
     fn put<'v>(
         #[allow(unused_variables)] this: Value<'v>,
         url: &str,
@@ -84,14 +158,18 @@ fn http_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] json_body: Value<'v>,
         #[starlark(default = NoneType)] form_body: Value<'v>,
         #[starlark(default = NoneType)] auth: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "http")?;
+        let limits = request_limits(eval);
+        let heap = eval.heap();
         make_request_with_body(
-            "PUT", url, params, headers, auth, body, json_body, form_body, heap,
+            "PUT", url, params, headers, auth, body, json_body, form_body, limits, heap,
         )
     }
 
     /// Make an HTTP PATCH request
+    #[allow(clippy::too_many_arguments)]
     fn patch<'v>(
         #[allow(unused_variables)] this: Value<'v>,
         url: &str,
@@ -101,10 +179,13 @@ fn http_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] json_body: Value<'v>,
         #[starlark(default = NoneType)] form_body: Value<'v>,
         #[starlark(default = NoneType)] auth: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "http")?;
+        let limits = request_limits(eval);
+        let heap = eval.heap();
         make_request_with_body(
-            "PATCH", url, params, headers, auth, body, json_body, form_body, heap,
+            "PATCH", url, params, headers, auth, body, json_body, form_body, limits, heap,
         )
     }
 
@@ -115,9 +196,12 @@ fn http_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] params: Value<'v>,
         #[starlark(default = NoneType)] headers: Value<'v>,
         #[starlark(default = NoneType)] auth: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
-        make_request("DELETE", url, params, headers, auth, None, heap)
+        CallContext::check_module(eval, "http")?;
+        let limits = request_limits(eval);
+        let heap = eval.heap();
+        make_request("DELETE", url, params, headers, auth, limits, heap)
     }
 
     /// Make an HTTP OPTIONS request
@@ -127,9 +211,12 @@ fn http_methods(builder: &mut MethodsBuilder) {
         #[starlark(default = NoneType)] params: Value<'v>,
         #[starlark(default = NoneType)] headers: Value<'v>,
         #[starlark(default = NoneType)] auth: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
-        make_request("OPTIONS", url, params, headers, auth, None, heap)
+        CallContext::check_module(eval, "http")?;
+        let limits = request_limits(eval);
+        let heap = eval.heap();
+        make_request("OPTIONS", url, params, headers, auth, limits, heap)
     }
 }
 
@@ -139,6 +226,25 @@ pub fn register(builder: &mut GlobalsBuilder) {
     builder.set("http", HTTP);
 }
 
+/// What a request may do: the network capabilities of the extension making
+/// it, and when its tool call runs out of time or is cancelled. Requests
+/// made without an extension context are unrestricted.
+#[derive(Default)]
+struct RequestLimits {
+    capabilities: Option<Capabilities>,
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+}
+
+fn request_limits(eval: &Evaluator) -> RequestLimits {
+    let ctx = CallContext::get(eval);
+    RequestLimits {
+        capabilities: ctx.map(|ctx| ctx.capabilities.clone()),
+        deadline: ctx.and_then(|ctx| ctx.deadline),
+        cancellation: ctx.map(|ctx| ctx.cancellation.clone()),
+    }
+}
+
 // Helper function for requests without body
 fn make_request<'v>(
     method: &str,
@@ -146,8 +252,8 @@ fn make_request<'v>(
     params: Value<'v>,
     headers: Value<'v>,
     auth: Value<'v>,
-    body: Option<String>,
-    heap: &'v Heap,
+    limits: RequestLimits,
+    heap: Heap<'v>,
 ) -> Result<Value<'v>> {
     make_request_with_body(
         method,
@@ -155,13 +261,22 @@ fn make_request<'v>(
         params,
         headers,
         auth,
-        body.map(|b| heap.alloc(b)).unwrap_or(Value::new_none()),
         Value::new_none(),
         Value::new_none(),
+        Value::new_none(),
+        limits,
         heap,
     )
 }
 
+/// Request body, kept so it can be resent after a 307 or 308 redirect
+enum Body {
+    Empty,
+    Json(String),
+    Form(HashMap<String, String>),
+    Raw(String),
+}
+
 // Main request function with all options
 #[allow(clippy::too_many_arguments)]
 fn make_request_with_body<'v>(
@@ -173,7 +288,8 @@ fn make_request_with_body<'v>(
     body: Value<'v>,
     json_body: Value<'v>,
     form_body: Value<'v>,
-    heap: &'v Heap,
+    limits: RequestLimits,
+    heap: Heap<'v>,
 ) -> Result<Value<'v>> {
     // Build URL with params
     let mut url = Url::parse(url).map_err(|e| anyhow!("Invalid URL: {}", e))?;
@@ -190,23 +306,23 @@ fn make_request_with_body<'v>(
         }
     }
 
-    // Create request builder
-    let mut request = match method {
-        "GET" => HTTP_CLIENT.get(url.as_str()),
-        "POST" => HTTP_CLIENT.post(url.as_str()),
-        "PUT" => HTTP_CLIENT.put(url.as_str()),
-        "PATCH" => HTTP_CLIENT.patch(url.as_str()),
-        "DELETE" => HTTP_CLIENT.delete(url.as_str()),
-        "OPTIONS" => HTTP_CLIENT.request(reqwest::Method::OPTIONS, url.as_str()),
+    let mut method = match method {
+        "GET" => Method::GET,
+        "POST" => Method::POST,
+        "PUT" => Method::PUT,
+        "PATCH" => Method::PATCH,
+        "DELETE" => Method::DELETE,
+        "OPTIONS" => Method::OPTIONS,
         _ => return Err(anyhow!("Unsupported HTTP method: {}", method)),
     };
 
-    // Add headers
+    // Collect headers
+    let mut header_list: Vec<(String, String)> = Vec::new();
     if !headers.is_none() {
         // Check if headers is a dict
         if let Some(dict) = DictRef::from_value(headers) {
             for (key, value) in dict.iter() {
-                request = request.header(key.to_str(), value.to_str());
+                header_list.push((key.to_str(), value.to_str()));
             }
         } else {
             return Err(anyhow!(
@@ -216,7 +332,8 @@ fn make_request_with_body<'v>(
         }
     }
 
-    // Add authentication
+    // Collect authentication
+    let mut credentials = None;
     if !auth.is_none() {
         let auth_list: Vec<String> = auth
             .iterate(heap)
@@ -225,19 +342,16 @@ fn make_request_with_body<'v>(
             .collect();
 
         if auth_list.len() == 2 {
-            request = request.basic_auth(&auth_list[0], Some(&auth_list[1]));
+            credentials = Some((auth_list[0].clone(), auth_list[1].clone()));
         } else {
             return Err(anyhow!("auth must be a tuple of (username, password)"));
         }
     }
 
-    // Set body
-    if !json_body.is_none() {
+    // Collect body
+    let mut request_body = if !json_body.is_none() {
         // Convert Starlark value to JSON
-        let json_str = starlark_to_json_string(json_body, heap)?;
-        request = request
-            .header(CONTENT_TYPE, "application/json")
-            .body(json_str);
+        Body::Json(starlark_to_json_string(json_body, heap)?)
     } else if !form_body.is_none() {
         // Form-encoded body
         if let Some(dict) = DictRef::from_value(form_body) {
@@ -245,7 +359,7 @@ fn make_request_with_body<'v>(
             for (key, value) in dict.iter() {
                 form_data.insert(key.to_str(), value.to_str());
             }
-            request = request.form(&form_data);
+            Body::Form(form_data)
         } else {
             return Err(anyhow!(
                 "form_body must be a dict, got: {}",
@@ -254,21 +368,115 @@ fn make_request_with_body<'v>(
         }
     } else if !body.is_none() {
         // Raw string body
-        let body_str = body.to_str();
-        request = request.body(body_str);
-    }
+        Body::Raw(body.to_str())
+    } else {
+        Body::Empty
+    };
 
-    // Execute request
-    let response = request
-        .send()
-        .map_err(|e| anyhow!("Request failed: {}", e))?;
+    // Execute the request, following redirects ourselves so every hop is
+    // checked against the extension's network capabilities
+    let mut redirects = 0;
+    loop {
+        let pinned = match limits.capabilities {
+            Some(ref capabilities) => check_url(
+                &url,
+                capabilities,
+                limits.deadline,
+                limits.cancellation.as_ref(),
+            )
+            .map_err(|e| {
+                if redirects == 0 {
+                    e
+                } else {
+                    anyhow!("Redirect to {} blocked: {}", url, e)
+                }
+            })?,
+            None => Vec::new(),
+        };
+        let client = match url.host_str() {
+            Some(host) if !pinned.is_empty() => pinned_client(host, &pinned)?,
+            _ => HTTP_CLIENT.clone(),
+        };
+
+        let mut request = client.request(method.clone(), url.as_str());
+        if let Some(deadline) = limits.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(anyhow!("Request failed: the tool call ran out of time"));
+            }
+            request = request.timeout(remaining.min(REQUEST_TIMEOUT));
+        }
+        for (key, value) in &header_list {
+            request = request.header(key, value);
+        }
+        if let Some((ref username, ref password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        request = match request_body {
+            Body::Empty => request,
+            Body::Json(ref json) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(json.clone()),
+            Body::Form(ref form) => request.form(form),
+            Body::Raw(ref raw) => request.body(raw.clone()),
+        };
+
+        let response = request
+            .send()
+            .map_err(|e| anyhow!("Request failed: {}", e))?;
+
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok());
+        let next = match location {
+            Some(location) if status.is_redirection() => response
+                .url()
+                .join(location)
+                .map_err(|e| anyhow!("Invalid redirect location '{}': {}", location, e))?,
+            // Convert response to Starlark value
+            _ => return response_to_starlark(response, heap),
+        };
+
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(anyhow!("Request failed: too many redirects"));
+        }
 
-    // Convert response to Starlark value
-    response_to_starlark(response, heap)
+        // Like reqwest's own redirect handling, resend 307 and 308 as they
+        // were and turn 301, 302 and 303 into a GET (or HEAD) without a body
+        if status == StatusCode::MOVED_PERMANENTLY
+            || status == StatusCode::FOUND
+            || status == StatusCode::SEE_OTHER
+        {
+            if method != Method::GET && method != Method::HEAD {
+                method = Method::GET;
+            }
+            request_body = Body::Empty;
+            header_list.retain(|(key, _)| {
+                !BODY_HEADERS
+                    .iter()
+                    .any(|header| key.eq_ignore_ascii_case(header))
+            });
+        }
+        // Credentials are only sent to the host they were meant for
+        if next.host_str() != url.host_str()
+            || next.port_or_known_default() != url.port_or_known_default()
+        {
+            credentials = None;
+            header_list.retain(|(key, _)| {
+                !SENSITIVE_HEADERS
+                    .iter()
+                    .any(|sensitive| key.eq_ignore_ascii_case(sensitive))
+            });
+        }
+        url = next;
+    }
 }
 
 // Convert response to Starlark dict
-fn response_to_starlark<'v>(response: Response, heap: &'v Heap) -> Result<Value<'v>> {
+fn response_to_starlark<'v>(response: Response, heap: Heap<'v>) -> Result<Value<'v>> {
     let status = response.status().as_u16() as i32;
     let url = response.url().to_string();
 
@@ -344,7 +552,7 @@ fn response_to_starlark<'v>(response: Response, heap: &'v Heap) -> Result<Value<
 }
 
 // Convert JSON value to Starlark value
-fn json_to_starlark<'v>(json: &JsonValue, heap: &'v Heap) -> Result<Value<'v>> {
+fn json_to_starlark<'v>(json: &JsonValue, heap: Heap<'v>) -> Result<Value<'v>> {
     match json {
         JsonValue::Null => Ok(Value::new_none()),
         JsonValue::Bool(b) => Ok(heap.alloc(*b)),
@@ -380,13 +588,13 @@ fn json_to_starlark<'v>(json: &JsonValue, heap: &'v Heap) -> Result<Value<'v>> {
 }
 
 // Convert Starlark value to JSON string
-fn starlark_to_json_string<'v>(value: Value<'v>, heap: &'v Heap) -> Result<String> {
+fn starlark_to_json_string<'v>(value: Value<'v>, heap: Heap<'v>) -> Result<String> {
     let json_value = starlark_to_json(value, heap)?;
     serde_json::to_string(&json_value).map_err(|e| anyhow!("Failed to serialize to JSON: {}", e))
 }
 
 // Convert Starlark value to serde_json::Value
-fn starlark_to_json<'v>(value: Value<'v>, heap: &'v Heap) -> Result<JsonValue> {
+fn starlark_to_json<'v>(value: Value<'v>, heap: Heap<'v>) -> Result<JsonValue> {
     if value.is_none() {
         Ok(JsonValue::Null)
     } else if let Some(b) = value.unpack_bool() {
@@ -430,7 +638,7 @@ fn starlark_to_json<'v>(value: Value<'v>, heap: &'v Heap) -> Result<JsonValue> {
     }
 }
 
-fn extract_dict_item<'v>(item: Value<'v>, _heap: &'v Heap) -> Result<(String, Value<'v>)> {
+fn extract_dict_item<'v>(item: Value<'v>, _heap: Heap<'v>) -> Result<(String, Value<'v>)> {
     // For dict iteration, we get (key, value) tuples
     if let Some(tuple) = TupleRef::from_value(item)
         && tuple.len() == 2
@@ -443,3 +651,101 @@ fn extract_dict_item<'v>(item: Value<'v>, _heap: &'v Heap) -> Result<(String, Va
         item.get_type()
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::environment::Module;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Serve one redirect with `status` to `/next`, then answer `/next` with
+    /// the method and body it was requested with
+    fn redirect_server(status: u16) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().take(2).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':')
+                        && name.eq_ignore_ascii_case("content-length")
+                    {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let response = if i == 0 {
+                    format!(
+                        "HTTP/1.1 {} Redirect\r\nLocation: /next\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    )
+                } else {
+                    let method = request_line.split(' ').next().unwrap();
+                    let text = format!("{} {}", method, String::from_utf8_lossy(&body));
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        text.len(),
+                        text
+                    )
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{}/start", addr)
+    }
+
+    fn follow(method: &str, status: u16) -> String {
+        let url = redirect_server(status);
+        Module::with_temp_heap(|module| {
+            let heap = module.heap();
+            let none = Value::new_none();
+            let response = make_request_with_body(
+                method,
+                &url,
+                none,
+                none,
+                none,
+                heap.alloc_str("payload").to_value(),
+                none,
+                none,
+                RequestLimits::default(),
+                heap,
+            )
+            .unwrap();
+            response
+                .at(heap.alloc_str("body").to_value(), heap)
+                .unwrap()
+                .to_str()
+        })
+    }
+
+    #[test]
+    fn test_redirects_drop_body_like_reqwest() {
+        for status in [301, 302, 303] {
+            for method in ["POST", "PUT", "PATCH", "DELETE"] {
+                assert_eq!(
+                    follow(method, status),
+                    "GET ",
+                    "{} after {}",
+                    method,
+                    status
+                );
+            }
+        }
+        for status in [307, 308] {
+            assert_eq!(follow("PUT", status), "PUT payload");
+        }
+    }
+}
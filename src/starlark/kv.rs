@@ -0,0 +1,539 @@
+use allocative::Allocative;
+use anyhow::{Result, anyhow};
+use derive_more::Display;
+use rusqlite::{Connection, OptionalExtension, params};
+use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
+use starlark::starlark_module;
+use starlark::starlark_simple_value;
+use starlark::values::none::NoneType;
+use starlark::values::starlark_value;
+use starlark::values::{NoSerialize, ProvidesStaticType, StarlarkValue, Value};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::context::CallContext;
+use super::engine::{json_to_starlark_value, starlark_value_to_json};
+
+/// File name of the key-value database inside the server data directory
+pub const KV_FILE_NAME: &str = "kv.sqlite3";
+
+/// Where persistent server data lives when `--data-dir` isn't given:
+/// `$XDG_DATA_HOME/starlark-mcp`, then `~/.local/share/starlark-mcp`,
+/// then `./.starlark-mcp`.
+pub fn default_data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME").filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir).join("starlark-mcp");
+    }
+    if let Some(home) = std::env::var_os("HOME").filter(|d| !d.is_empty()) {
+        return PathBuf::from(home).join(".local/share/starlark-mcp");
+    }
+    PathBuf::from(".starlark-mcp")
+}
+
+/// Persistent key-value store shared by all extensions, with every key
+/// namespaced by the owning extension's name.
+///
+/// Values are stored as JSON. Expired entries are invisible to readers and
+/// are purged lazily.
+#[derive(Clone)]
+pub struct KvStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl KvStore {
+    /// Open (or create) the store at `path`, creating parent directories as needed
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| {
+                anyhow!(
+                    "Failed to create data directory '{}': {}",
+                    parent.display(),
+                    e
+                )
+            })?;
+        }
+
+        let conn = Connection::open(path)
+            .map_err(|e| anyhow!("Failed to open kv store '{}': {}", path.display(), e))?;
+        Self::init(conn)
+    }
+
+    /// A store that lives only as long as the process, used by the test runner
+    pub fn in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()
+            .map_err(|e| anyhow!("Failed to open in-memory kv store: {}", e))?;
+        Self::init(conn)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS kv (
+                namespace TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                expires_at INTEGER,
+                PRIMARY KEY (namespace, key)
+            );",
+        )
+        .map_err(|e| anyhow!("Failed to initialize kv store: {}", e))?;
+
+        conn.execute(
+            "DELETE FROM kv WHERE expires_at IS NOT NULL AND expires_at <= ?1",
+            params![now_millis()],
+        )
+        .map_err(|e| anyhow!("Failed to purge expired keys: {}", e))?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn get(&self, namespace: &str, key: &str) -> Result<Option<serde_json::Value>> {
+        let conn = self.conn.lock().unwrap();
+        let row: Option<(String, Option<i64>)> = conn
+            .query_row(
+                "SELECT value, expires_at FROM kv WHERE namespace = ?1 AND key = ?2",
+                params![namespace, key],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        match row {
+            Some((_, Some(expires_at))) if expires_at <= now_millis() => {
+                conn.execute(
+                    "DELETE FROM kv WHERE namespace = ?1 AND key = ?2",
+                    params![namespace, key],
+                )?;
+                Ok(None)
+            }
+            Some((value, _)) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(
+        &self,
+        namespace: &str,
+        key: &str,
+        value: &serde_json::Value,
+        ttl: Option<Duration>,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO kv (namespace, key, value, expires_at) VALUES (?1, ?2, ?3, ?4)",
+            params![namespace, key, value.to_string(), expiry(ttl)],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a key, returning whether it existed and had not expired
+    pub fn delete(&self, namespace: &str, key: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM kv WHERE namespace = ?1 AND key = ?2
+             AND (expires_at IS NULL OR expires_at > ?3)",
+            params![namespace, key, now_millis()],
+        )?;
+        conn.execute(
+            "DELETE FROM kv WHERE namespace = ?1 AND key = ?2",
+            params![namespace, key],
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Live keys starting with `prefix`, sorted
+    pub fn list(&self, namespace: &str, prefix: &str) -> Result<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT key FROM kv WHERE namespace = ?1
+             AND substr(key, 1, length(?2)) = ?2
+             AND (expires_at IS NULL OR expires_at > ?3)
+             ORDER BY key",
+        )?;
+        let keys = stmt
+            .query_map(params![namespace, prefix, now_millis()], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(keys)
+    }
+
+    /// Atomically add `amount` to an integer value, treating a missing or
+    /// expired key as 0. A new `ttl` replaces the key's expiry; without one
+    /// the existing expiry is kept.
+    pub fn incr(
+        &self,
+        namespace: &str,
+        key: &str,
+        amount: i64,
+        ttl: Option<Duration>,
+    ) -> Result<i64> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let now = now_millis();
+
+        let row: Option<(String, Option<i64>)> = tx
+            .query_row(
+                "SELECT value, expires_at FROM kv WHERE namespace = ?1 AND key = ?2",
+                params![namespace, key],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        let (current, expires_at) = match row {
+            Some((_, Some(expires_at))) if expires_at <= now => (0, None),
+            Some((value, expires_at)) => {
+                let current = serde_json::from_str::<serde_json::Value>(&value)?
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("value of '{}' is not an integer", key))?;
+                (current, expires_at)
+            }
+            None => (0, None),
+        };
+
+        let updated = current
+            .checked_add(amount)
+            .ok_or_else(|| anyhow!("integer overflow incrementing '{}'", key))?;
+        let expires_at = if ttl.is_some() {
+            expiry(ttl)
+        } else {
+            expires_at
+        };
+
+        tx.execute(
+            "INSERT OR REPLACE INTO kv (namespace, key, value, expires_at) VALUES (?1, ?2, ?3, ?4)",
+            params![namespace, key, updated.to_string(), expires_at],
+        )?;
+        tx.commit()?;
+
+        Ok(updated)
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+fn expiry(ttl: Option<Duration>) -> Option<i64> {
+    ttl.map(|ttl| now_millis() + ttl.as_millis() as i64)
+}
+
+/// Convert an optional `ttl_secs` argument, rejecting non-positive values
+fn ttl_from_value(ttl_secs: Value) -> Result<Option<Duration>> {
+    if ttl_secs.is_none() {
+        return Ok(None);
+    }
+    match ttl_secs.unpack_i32() {
+        Some(secs) if secs > 0 => Ok(Some(Duration::from_secs(secs as u64))),
+        _ => Err(anyhow!(
+            "ttl_secs must be a positive integer, got {}",
+            ttl_secs
+        )),
+    }
+}
+
+/// The store and namespace for the calling extension
+fn kv_for<'a>(eval: &Evaluator<'_, 'a, '_>, what: &str) -> Result<(&'a KvStore, &'a str)> {
+    CallContext::check_module(eval, "kv")?;
+    let ctx = CallContext::require(eval, what)?;
+    let store = ctx
+        .kv
+        .as_ref()
+        .ok_or_else(|| anyhow!("{}: the kv store is not available", what))?;
+    Ok((store, ctx.kv_namespace.as_deref().unwrap_or(&ctx.extension)))
+}
+
+#[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
+#[display(fmt = "kv")]
+pub struct KvModule;
+
+starlark_simple_value!(KvModule);
+
+#[starlark_value(type = "kv")]
+impl<'v> StarlarkValue<'v> for KvModule {
+    fn get_methods() -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new("kv", kv_methods);
+        Some(RES.methods())
+    }
+
+    fn dir_attr(&self) -> Vec<String> {
+        vec![
+            "get".to_owned(),
+            "set".to_owned(),
+            "delete".to_owned(),
+            "list".to_owned(),
+            "incr".to_owned(),
+        ]
+    }
+}
+
+#[starlark_module]
+#[allow(clippy::type_complexity)]
+fn kv_methods(builder: &mut MethodsBuilder) {
+    /// Get a stored value, or `default` if the key is missing or expired.
+    ///
+    /// # Examples
+    /// ```python
+    /// last_seen = kv.get("last_comment_id", 0)
+    /// ```
+    fn get<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        #[starlark(default = NoneType)] default: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let (store, namespace) = kv_for(eval, "kv.get")?;
+        match store
+            .get(namespace, key)
+            .map_err(|e| anyhow!("kv.get: {}", e))?
+        {
+            Some(value) => json_to_starlark_value(value, eval.heap()),
+            None => Ok(default),
+        }
+    }
+
+    /// Store a JSON-compatible value, optionally expiring after `ttl_secs`.
+    ///
+    /// # Examples
+    /// ```python
+    /// kv.set("projects", projects, ttl_secs = 3600)
+    /// ```
+    fn set<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        value: Value<'v>,
+        #[starlark(default = NoneType)] ttl_secs: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<NoneType> {
+        let (store, namespace) = kv_for(eval, "kv.set")?;
+        let ttl = ttl_from_value(ttl_secs).map_err(|e| anyhow!("kv.set: {}", e))?;
+        let json = starlark_value_to_json(value, eval.heap())
+            .map_err(|e| anyhow!("kv.set: cannot store '{}': {}", key, e))?;
+        store
+            .set(namespace, key, &json, ttl)
+            .map_err(|e| anyhow!("kv.set: {}", e))?;
+        Ok(NoneType)
+    }
+
+    /// Delete a key. Returns True if it existed.
+    fn delete<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<bool> {
+        let (store, namespace) = kv_for(eval, "kv.delete")?;
+        store
+            .delete(namespace, key)
+            .map_err(|e| anyhow!("kv.delete: {}", e))
+    }
+
+    /// List keys starting with `prefix`, sorted.
+    ///
+    /// # Examples
+    /// ```python
+    /// for key in kv.list("prefs/"):
+    ///     print(key, kv.get(key))
+    /// ```
+    fn list<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        #[starlark(default = "")] prefix: &str,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<Vec<String>> {
+        let (store, namespace) = kv_for(eval, "kv.list")?;
+        store
+            .list(namespace, prefix)
+            .map_err(|e| anyhow!("kv.list: {}", e))
+    }
+
+    /// Atomically add `amount` to an integer value (missing keys start at 0)
+    /// and return the new value. `ttl_secs`, if given, resets the expiry.
+    ///
+    /// # Examples
+    /// ```python
+    /// calls_today = kv.incr("calls", ttl_secs = 86400)
+    /// ```
+    fn incr<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        #[starlark(default = 1)] amount: i32,
+        #[starlark(default = NoneType)] ttl_secs: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<i32> {
+        let (store, namespace) = kv_for(eval, "kv.incr")?;
+        let ttl = ttl_from_value(ttl_secs).map_err(|e| anyhow!("kv.incr: {}", e))?;
+        let updated = store
+            .incr(namespace, key, amount as i64, ttl)
+            .map_err(|e| anyhow!("kv.incr: {}", e))?;
+        i32::try_from(updated).map_err(|_| anyhow!("kv.incr: value of '{}' is out of range", key))
+    }
+}
+
+pub fn register(builder: &mut GlobalsBuilder) {
+    const KV: KvModule = KvModule;
+    builder.set("kv", KV);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::environment::{GlobalsBuilder, Module};
+    use starlark::syntax::{AstModule, Dialect};
+    use tempfile::TempDir;
+
+    fn eval_with_kv(
+        code: &str,
+        store: &KvStore,
+        extension: &str,
+    ) -> Result<String, starlark::Error> {
+        let mut ctx = CallContext::new(extension);
+        ctx.kv = Some(store.clone());
+
+        let globals = GlobalsBuilder::standard().with(register).build();
+        Module::with_temp_heap(|module| {
+            let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
+            let mut eval = Evaluator::new(&module);
+            ctx.attach(&mut eval);
+            let result = eval.eval_module(ast, &globals)?;
+
+            Ok(result.to_string())
+        })
+    }
+
+    #[test]
+    fn test_set_get_delete() {
+        let store = KvStore::in_memory().unwrap();
+        let result = eval_with_kv(
+            r#"
+kv.set("prefs", {"units": "metric"})
+before = kv.get("prefs")["units"]
+deleted = kv.delete("prefs")
+(before, deleted, kv.get("prefs", "gone"), kv.delete("prefs"))
+"#,
+            &store,
+            "weather",
+        )
+        .unwrap();
+        assert_eq!(result, r#"("metric", True, "gone", False)"#);
+    }
+
+    #[test]
+    fn test_namespaced_per_extension() {
+        let store = KvStore::in_memory().unwrap();
+        eval_with_kv(r#"kv.set("token", "a")"#, &store, "github").unwrap();
+        eval_with_kv(r#"kv.set("token", "b")"#, &store, "plane").unwrap();
+
+        let result = eval_with_kv(r#"kv.get("token")"#, &store, "github").unwrap();
+        assert_eq!(result, r#""a""#);
+        assert_eq!(store.list("plane", "").unwrap(), vec!["token"]);
+    }
+
+    #[test]
+    fn test_list_prefix() {
+        let store = KvStore::in_memory().unwrap();
+        let result = eval_with_kv(
+            r#"
+kv.set("prefs/b", 1)
+kv.set("prefs/a", 2)
+kv.set("other", 3)
+(kv.list("prefs/"), kv.list())
+"#,
+            &store,
+            "ext",
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            r#"(["prefs/a", "prefs/b"], ["other", "prefs/a", "prefs/b"])"#
+        );
+    }
+
+    #[test]
+    fn test_incr() {
+        let store = KvStore::in_memory().unwrap();
+        let result = eval_with_kv(
+            r#"
+kv.incr("n")
+kv.incr("n", 5)
+"#,
+            &store,
+            "ext",
+        )
+        .unwrap();
+        assert_eq!(result, "6");
+
+        let err = eval_with_kv(
+            r#"
+kv.set("s", "text")
+kv.incr("s")
+"#,
+            &store,
+            "ext",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("not an integer"));
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let store = KvStore::in_memory().unwrap();
+        let ttl = Some(Duration::from_millis(20));
+        store
+            .set("ext", "short", &serde_json::json!(1), ttl)
+            .unwrap();
+        store
+            .set("ext", "long", &serde_json::json!(2), None)
+            .unwrap();
+        store.incr("ext", "counter", 1, ttl).unwrap();
+
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert_eq!(store.get("ext", "short").unwrap(), None);
+        assert_eq!(store.list("ext", "").unwrap(), vec!["long"]);
+        assert!(!store.delete("ext", "short").unwrap());
+        assert_eq!(store.incr("ext", "counter", 1, None).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_invalid_ttl() {
+        let store = KvStore::in_memory().unwrap();
+        let err = eval_with_kv(r#"kv.set("k", 1, ttl_secs = 0)"#, &store, "ext").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("ttl_secs must be a positive integer")
+        );
+    }
+
+    #[test]
+    fn test_persists_across_open() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("data").join(KV_FILE_NAME);
+
+        KvStore::open(&path)
+            .unwrap()
+            .set("ext", "last_id", &serde_json::json!(42), None)
+            .unwrap();
+
+        let reopened = KvStore::open(&path).unwrap();
+        assert_eq!(
+            reopened.get("ext", "last_id").unwrap(),
+            Some(serde_json::json!(42))
+        );
+    }
+
+    #[test]
+    fn test_without_store() {
+        let globals = GlobalsBuilder::standard().with(register).build();
+        Module::with_temp_heap(|module| {
+            let ctx = CallContext::new("ext");
+            let ast = AstModule::parse("test.star", "kv.get(\"k\")".to_owned(), &Dialect::Standard)
+                .unwrap();
+            let mut eval = Evaluator::new(&module);
+            ctx.attach(&mut eval);
+            let err = eval.eval_module(ast, &globals).unwrap_err();
+            assert!(err.to_string().contains("the kv store is not available"));
+        })
+    }
+}
@@ -0,0 +1,365 @@
+use starlark::eval::Evaluator;
+use starlark::values::Value;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::context::CallContext;
+use crate::mcp::{ToolContent, ToolResult};
+
+/// Resource budget for a single handler invocation.
+///
+/// Unset fields are unlimited. Server-wide defaults are combined with
+/// per-tool values from `Tool(timeout_secs=..., max_steps=..., max_heap_mb=...)`,
+/// with the tool taking precedence.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExecutionLimits {
+    pub timeout: Option<Duration>,
+    pub max_steps: Option<u64>,
+    pub max_heap_bytes: Option<usize>,
+}
+
+impl ExecutionLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.timeout.is_none() && self.max_steps.is_none() && self.max_heap_bytes.is_none()
+    }
+
+    /// Fill in any limit not set on `self` from `defaults`
+    pub fn or(&self, defaults: &ExecutionLimits) -> ExecutionLimits {
+        ExecutionLimits {
+            timeout: self.timeout.or(defaults.timeout),
+            max_steps: self.max_steps.or(defaults.max_steps),
+            max_heap_bytes: self.max_heap_bytes.or(defaults.max_heap_bytes),
+        }
+    }
+}
+
+/// `max_heap_mb` in bytes, or `None` if that doesn't fit in a `usize`
+pub fn heap_bytes(mb: u64) -> Option<usize> {
+    mb.checked_mul(1024 * 1024)
+        .and_then(|bytes| usize::try_from(bytes).ok())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Timeout,
+    Steps,
+    Heap,
+    /// Not a budget as such: the request was cancelled by the client
+    Cancelled,
+}
+
+impl LimitKind {
+    /// Name of the `Tool()` argument that configures this limit
+    pub fn parameter(&self) -> &'static str {
+        match self {
+            LimitKind::Timeout => "timeout_secs",
+            LimitKind::Steps => "max_steps",
+            LimitKind::Heap => "max_heap_mb",
+            LimitKind::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Raised when a handler exceeds one of its [`ExecutionLimits`]
+#[derive(Debug, Clone)]
+pub struct LimitExceeded {
+    pub kind: LimitKind,
+    pub limit: String,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LimitKind::Timeout => write!(f, "execution time limit of {} exceeded", self.limit),
+            LimitKind::Steps => write!(f, "step limit of {} exceeded", self.limit),
+            LimitKind::Heap => write!(f, "heap limit of {} exceeded", self.limit),
+            LimitKind::Cancelled => write!(f, "cancelled by the client"),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl LimitExceeded {
+    pub fn to_tool_result(&self, tool_name: &str) -> ToolResult {
+        let structured = match self.kind {
+            LimitKind::Cancelled => serde_json::json!({"error": "cancelled"}),
+            _ => serde_json::json!({
+                "error": "limit_exceeded",
+                "limit": self.kind.parameter(),
+                "value": self.limit,
+            }),
+        };
+
+        ToolResult {
+            content: vec![ToolContent::Text {
+                text: format!("Error: tool '{}' stopped: {}", tool_name, self),
+            }],
+            is_error: Some(true),
+            structured_content: Some(structured),
+        }
+    }
+}
+
+/// Run `f` on `eval` with the given limits enforced.
+///
+/// The evaluator checks its budget on every loop iteration, comprehensions
+/// included, and every function call, and fails the evaluation with an error
+/// once a limit is hit or the attached [`CallContext`] is cancelled. Such an
+/// error is returned as `Err`; any other error from `f` as `Ok(Err(..))`.
+///
+/// The wall-clock limit runs to the context's deadline when it has one, so it
+/// matches the timeout `http` and `exec` apply to their own calls.
+pub fn run_with_limits<'v, 'a, 'e>(
+    eval: &mut Evaluator<'v, 'a, 'e>,
+    limits: &ExecutionLimits,
+    f: impl FnOnce(&mut Evaluator<'v, 'a, 'e>) -> starlark::Result<Value<'v>>,
+) -> Result<starlark::Result<Value<'v>>, LimitExceeded> {
+    let context = CallContext::get(eval);
+    if limits.is_unlimited() && context.is_none() {
+        return Ok(f(eval));
+    }
+
+    let cancellation = context.map(|ctx| ctx.cancellation.clone());
+    let deadline = context
+        .and_then(|ctx| ctx.deadline)
+        .or_else(|| limits.timeout.map(|timeout| Instant::now() + timeout));
+
+    if let Some(max_steps) = limits.max_steps {
+        set_limit(eval.set_max_tick_count(max_steps));
+    }
+    if let Some(max_heap) = limits.max_heap_bytes {
+        set_limit(eval.set_max_heap_size(max_heap));
+    }
+    {
+        let cancellation = cancellation.clone();
+        eval.set_check_cancelled(Box::new(move || {
+            cancellation
+                .as_ref()
+                .is_some_and(|token| token.is_cancelled())
+                || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+        }));
+    }
+
+    let result = f(eval);
+    if result.is_ok() {
+        return Ok(result);
+    }
+
+    // Work out whether the error came from a limit, most specific first: an
+    // `http` or `exec` call cut short by the deadline fails with its own error
+    if cancellation.is_some_and(|token| token.is_cancelled()) {
+        return Err(LimitExceeded {
+            kind: LimitKind::Cancelled,
+            limit: String::new(),
+        });
+    }
+    if let (Some(deadline), Some(timeout)) = (deadline, limits.timeout)
+        && Instant::now() >= deadline
+    {
+        return Err(LimitExceeded {
+            kind: LimitKind::Timeout,
+            limit: format!("{}s", timeout.as_secs_f64()),
+        });
+    }
+    if let Some(max_steps) = limits.max_steps
+        && eval.get_total_tick_count() > max_steps
+    {
+        return Err(LimitExceeded {
+            kind: LimitKind::Steps,
+            limit: max_steps.to_string(),
+        });
+    }
+    if let Some(max_heap) = limits.max_heap_bytes
+        && eval.heap().peak_allocated_bytes() + eval.frozen_heap().allocated_bytes() > max_heap
+    {
+        return Err(LimitExceeded {
+            kind: LimitKind::Heap,
+            limit: format!("{} MB", max_heap / (1024 * 1024)),
+        });
+    }
+    Ok(result)
+}
+
+/// Limits are set once, on a fresh evaluator, with values validated when
+/// they were parsed, so setting one can't fail
+fn set_limit(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        tracing::warn!("Failed to set execution limit: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::environment::{Globals, Module};
+    use starlark::syntax::{AstModule, Dialect};
+
+    fn run(code: &str, limits: ExecutionLimits) -> Result<String, LimitExceeded> {
+        Module::with_temp_heap(|module| {
+            let globals = Globals::standard();
+            let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard).unwrap();
+            let mut eval = Evaluator::new(&module);
+            eval.eval_module(ast, &globals).unwrap();
+            let main = module.get("main").unwrap();
+
+            run_with_limits(&mut eval, &limits, |eval| {
+                eval.eval_function(main, &[], &[])
+            })
+            .map(|result| result.unwrap().to_str())
+        })
+    }
+
+    const SPIN: &str = r#"
+def main():
+    total = 0
+    for i in range(100000000):
+        total += i
+    return total
+"#;
+
+    #[test]
+    fn test_unlimited_runs_to_completion() {
+        let result = run(
+            "def main():\n    return 1 + 2\n",
+            ExecutionLimits::default(),
+        );
+        assert_eq!(result.unwrap(), "3");
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let limits = ExecutionLimits {
+            max_steps: Some(1000),
+            ..Default::default()
+        };
+        let err = run(SPIN, limits).unwrap_err();
+        assert_eq!(err.kind, LimitKind::Steps);
+        assert_eq!(err.to_string(), "step limit of 1000 exceeded");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let limits = ExecutionLimits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let err = run(SPIN, limits).unwrap_err();
+        assert_eq!(err.kind, LimitKind::Timeout);
+    }
+
+    const COMPREHENSION: &str = r#"
+def main():
+    return len([i for i in range(1000000000) if i < 0])
+"#;
+
+    #[test]
+    fn test_step_limit_stops_comprehension() {
+        let limits = ExecutionLimits {
+            max_steps: Some(1000),
+            ..Default::default()
+        };
+        let err = run(COMPREHENSION, limits).unwrap_err();
+        assert_eq!(err.kind, LimitKind::Steps);
+    }
+
+    #[test]
+    fn test_timeout_stops_comprehension() {
+        let limits = ExecutionLimits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let err = run(COMPREHENSION, limits).unwrap_err();
+        assert_eq!(err.kind, LimitKind::Timeout);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_handler_errors_pass_through() {
+        let limits = ExecutionLimits {
+            max_steps: Some(1000),
+            ..Default::default()
+        };
+        let result = Module::with_temp_heap(|module| {
+            let globals = Globals::standard();
+            let ast = AstModule::parse(
+                "test.star",
+                "def main():\n    fail(\"boom\")\n".to_owned(),
+                &Dialect::Standard,
+            )
+            .unwrap();
+            let mut eval = Evaluator::new(&module);
+            eval.eval_module(ast, &globals).unwrap();
+            let main = module.get("main").unwrap();
+            run_with_limits(&mut eval, &limits, |eval| {
+                eval.eval_function(main, &[], &[])
+            })
+            .map(|result| result.map(|v| v.to_str()).map_err(|e| e.to_string()))
+        });
+        assert!(result.unwrap().unwrap_err().contains("boom"));
+    }
+
+    #[test]
+    fn test_heap_limit() {
+        let code = r#"
+def main():
+    items = []
+    for i in range(10000000):
+        items.append("item-" + str(i))
+    return len(items)
+"#;
+        let limits = ExecutionLimits {
+            max_heap_bytes: Some(1024 * 1024),
+            ..Default::default()
+        };
+        let err = run(code, limits).unwrap_err();
+        assert_eq!(err.kind, LimitKind::Heap);
+
+        let result = err.to_tool_result("hog");
+        assert_eq!(result.is_error, Some(true));
+        assert_eq!(result.structured_content.unwrap()["limit"], "max_heap_mb");
+    }
+
+    #[test]
+    fn test_cancellation_stops_handler() {
+        let err = Module::with_temp_heap(|module| {
+            let globals = Globals::standard();
+            let ast = AstModule::parse("test.star", SPIN.to_owned(), &Dialect::Standard).unwrap();
+            let ctx = CallContext::new("test");
+            let mut eval = Evaluator::new(&module);
+            ctx.attach(&mut eval);
+            eval.eval_module(ast, &globals).unwrap();
+            let main = module.get("main").unwrap();
+
+            ctx.cancellation.cancel();
+            run_with_limits(&mut eval, &ExecutionLimits::default(), |eval| {
+                eval.eval_function(main, &[], &[])
+            })
+            .map(|result| result.unwrap().to_str())
+            .unwrap_err()
+        });
+        assert_eq!(err.kind, LimitKind::Cancelled);
+        assert_eq!(
+            err.to_tool_result("spin").structured_content.unwrap()["error"],
+            "cancelled"
+        );
+    }
+
+    #[test]
+    fn test_tool_limits_override_server_defaults() {
+        let server = ExecutionLimits {
+            timeout: Some(Duration::from_secs(30)),
+            max_steps: Some(10),
+            max_heap_bytes: None,
+        };
+        let tool = ExecutionLimits {
+            max_steps: Some(500),
+            ..Default::default()
+        };
+        let merged = tool.or(&server);
+        assert_eq!(merged.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(merged.max_steps, Some(500));
+        assert_eq!(merged.max_heap_bytes, None);
+    }
+}
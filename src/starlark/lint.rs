@@ -0,0 +1,725 @@
+use serde::Serialize;
+use starlark::analysis::{AstModuleLint, EvalSeverity};
+use starlark::codemap::Span;
+use starlark::environment::Globals;
+use starlark::syntax::AstModule;
+use starlark_syntax::syntax::ast::{
+    ArgumentP, AstExpr, AstLiteral, AstNoPayload, AstStmt, DefP, ExprP, ForP, StmtP,
+};
+use starlark_syntax::syntax::top_level_stmts::top_level_stmts;
+use starlark_syntax::syntax::uniplate::Visit;
+use std::collections::HashSet;
+use std::fmt;
+
+use super::context::GUARDED_MODULES;
+use super::diagnostics::SourceSpan;
+use super::mcp_types::StarlarkExtension;
+
+/// Longest tool name MCP clients reliably accept
+pub const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// Starlark lints that are off by default but worth reporting for extensions.
+/// `unused-argument` stays off: handlers take `params` whether they use it or not.
+const ENABLED_LINTS: &[&str] = &["unused-load", "unused-assign"];
+
+/// Starlark lints reported as errors because the code fails when it runs
+const ERROR_LINTS: &[&str] = &["using-undefined"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A problem found by [`Linter`]
+#[derive(Debug, Clone, Serialize)]
+pub struct LintIssue {
+    pub severity: Severity,
+    /// Short kebab-case name of the check, e.g. `unused-load`
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<SourceSpan>,
+}
+
+impl LintIssue {
+    pub fn new(severity: Severity, code: &str, message: String, span: Option<SourceSpan>) -> Self {
+        Self {
+            severity,
+            code: code.to_string(),
+            message,
+            span,
+        }
+    }
+
+    /// Render in the style of rustc, showing the location in `file` and
+    /// quoting the offending line of `source` when the issue has a span
+    pub fn render(&self, file: &str, source: Option<&str>) -> String {
+        let mut text = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        let Some(ref span) = self.span else {
+            text.push_str(&format!(" --> {}\n", file));
+            return text;
+        };
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        text.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter, file, span.line, span.column
+        ));
+        let line = source.and_then(|s| s.lines().nth(span.line - 1));
+        if let Some(line) = line {
+            let width = if span.end_line == span.line {
+                span.end_column.saturating_sub(span.column).max(1)
+            } else {
+                line.chars().count().saturating_sub(span.column - 1).max(1)
+            };
+            text.push_str(&format!("{} |\n", gutter));
+            text.push_str(&format!("{} | {}\n", span.line, line));
+            text.push_str(&format!(
+                "{} | {}{}\n",
+                gutter,
+                " ".repeat(span.column - 1),
+                "^".repeat(width)
+            ));
+        }
+        text
+    }
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref span) = self.span {
+            write!(f, "{}: ", span)?;
+        }
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)
+    }
+}
+
+/// Static checks for extension sources: starlark's own linter plus checks
+/// specific to MCP extensions.
+pub struct Linter {
+    /// Names of the builtin globals
+    globals: HashSet<String>,
+}
+
+impl Linter {
+    pub fn new(globals: &Globals) -> Self {
+        Self {
+            globals: globals.names().map(|n| n.as_str().to_string()).collect(),
+        }
+    }
+
+    /// Lint `ast`. Checks that need the extension's tools, such as handler
+    /// results and `allowed_exec`, run only when `extension` is given.
+    pub fn lint(&self, ast: &AstModule, extension: Option<&StarlarkExtension>) -> Vec<LintIssue> {
+        let lint = Lint { ast };
+        let mut issues = self.starlark_lints(ast);
+        lint.shadowed_globals(&self.globals, &mut issues);
+        if let Some(extension) = extension {
+            lint.tool_declarations(extension, &mut issues);
+            lint.handler_results(extension, &mut issues);
+            lint.exec_commands(extension, &mut issues);
+            lint.env_variables(extension, &mut issues);
+            lint.required_modules(extension, &mut issues);
+        }
+        issues.sort_by_key(|issue| issue.span.as_ref().map(|s| (s.line, s.column)));
+        issues
+    }
+
+    fn starlark_lints(&self, ast: &AstModule) -> Vec<LintIssue> {
+        ast.lint(Some(&self.globals))
+            .into_iter()
+            .filter_map(|lint| {
+                let severity = if ERROR_LINTS.contains(&lint.short_name.as_str()) {
+                    Severity::Error
+                } else {
+                    match lint.severity {
+                        EvalSeverity::Error => Severity::Error,
+                        EvalSeverity::Warning => Severity::Warning,
+                        EvalSeverity::Disabled
+                            if ENABLED_LINTS.contains(&lint.short_name.as_str()) =>
+                        {
+                            Severity::Warning
+                        }
+                        _ => return None,
+                    }
+                };
+                Some(LintIssue::new(
+                    severity,
+                    &lint.short_name,
+                    lint.problem,
+                    Some(SourceSpan::from_file_span(&lint.location)),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Checks over one parsed module
+struct Lint<'a> {
+    ast: &'a AstModule,
+}
+
+impl<'a> Lint<'a> {
+    fn span(&self, span: Span) -> Option<SourceSpan> {
+        Some(SourceSpan::from_file_span(&self.ast.file_span(span)))
+    }
+
+    fn statements(&self) -> Vec<&'a AstStmt> {
+        let mut statements = Vec::new();
+        walk_statements(self.ast.statement(), &mut |s| statements.push(s));
+        statements
+    }
+
+    fn expressions(&self) -> Vec<&'a AstExpr> {
+        let mut expressions = Vec::new();
+        for statement in self.statements() {
+            statement.visit_children(|child| {
+                if let Visit::Expr(expr) = child {
+                    walk_expressions(expr, &mut |e| expressions.push(e));
+                }
+            });
+        }
+        expressions
+    }
+
+    /// Module-level definitions and parameters that hide a builtin such as
+    /// `json` or `http`. Locals are left alone: a function's `data` or
+    /// `state` variable is ordinary style and can't affect other code.
+    fn shadowed_globals(&self, globals: &HashSet<String>, issues: &mut Vec<LintIssue>) {
+        let mut report = |name: &str, span: Span| {
+            if globals.contains(name) {
+                issues.push(LintIssue::new(
+                    Severity::Warning,
+                    "shadowed-global",
+                    format!("`{}` shadows the builtin of the same name", name),
+                    self.span(span),
+                ));
+            }
+        };
+
+        for statement in top_level_stmts(self.ast.statement()) {
+            match &statement.node {
+                StmtP::Def(DefP { name, .. }) => report(&name.ident, name.span),
+                StmtP::Assign(assign) => {
+                    assign
+                        .lhs
+                        .visit_lvalue(|ident| report(&ident.ident, ident.span));
+                }
+                StmtP::For(ForP { var, .. }) => {
+                    var.visit_lvalue(|ident| report(&ident.ident, ident.span));
+                }
+                StmtP::Load(load) => {
+                    for arg in &load.args {
+                        report(&arg.local.ident, arg.local.span);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for statement in self.statements() {
+            if let StmtP::Def(DefP { params, .. }) = &statement.node {
+                for ident in params.iter().filter_map(|param| param.ident()) {
+                    report(&ident.ident, ident.span);
+                }
+            }
+        }
+    }
+
+    /// Tool names MCP clients may reject and parameters without descriptions
+    fn tool_declarations(&self, extension: &StarlarkExtension, issues: &mut Vec<LintIssue>) {
+        for tool in &extension.tools {
+            if !is_valid_tool_name(&tool.name) {
+                issues.push(LintIssue::new(
+                    Severity::Error,
+                    "invalid-tool-name",
+                    format!(
+                        "Tool name '{}' is not a valid MCP tool name; use 1-{} letters, digits, '_' or '-'",
+                        tool.name, MAX_TOOL_NAME_LEN
+                    ),
+                    self.declaration("Tool", &tool.name)
+                        .and_then(|call| self.span(call.span)),
+                ));
+            }
+
+            for param in &tool.parameters {
+                if param.description.trim().is_empty() {
+                    issues.push(LintIssue::new(
+                        Severity::Warning,
+                        "missing-description",
+                        format!(
+                            "Parameter '{}' of tool '{}' has no description",
+                            param.name, tool.name
+                        ),
+                        self.declaration("ToolParameter", &param.name)
+                            .and_then(|call| self.span(call.span)),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// The first call to `function` with the string literal `name` as its
+    /// `name` argument
+    fn declaration(&self, function: &str, name: &str) -> Option<&'a AstExpr> {
+        self.expressions().into_iter().find(|expr| {
+            let ExprP::Call(callee, args) = &expr.node else {
+                return false;
+            };
+            matches!(&callee.node, ExprP::Identifier(id) if id.ident == function)
+                && args
+                    .args
+                    .iter()
+                    .enumerate()
+                    .any(|(i, arg)| match &arg.node {
+                        ArgumentP::Named(key, value) => {
+                            key.node == "name" && string_literal(value) == Some(name)
+                        }
+                        ArgumentP::Positional(value) => {
+                            i == 0 && string_literal(value) == Some(name)
+                        }
+                        _ => false,
+                    })
+        })
+    }
+
+    /// Handlers must return a dict with a `content` list
+    fn handler_results(&self, extension: &StarlarkExtension, issues: &mut Vec<LintIssue>) {
+        let defs = top_level_defs(self.ast);
+
+        for tool in &extension.tools {
+            let handler = tool.handler_name.rsplit('.').next().unwrap_or_default();
+            let Some(def) = defs.iter().find(|def| def.name.ident == handler) else {
+                continue;
+            };
+
+            let mut returns = Vec::new();
+            collect_returns(&def.body, &mut returns);
+
+            if !returns.iter().any(|(_, value)| value.is_some()) {
+                issues.push(LintIssue::new(
+                    Severity::Error,
+                    "handler-result",
+                    format!(
+                        "Handler '{}' for tool '{}' never returns a result dict",
+                        handler, tool.name
+                    ),
+                    self.span(def.name.span),
+                ));
+                continue;
+            }
+
+            for (span, value) in returns {
+                let problem = match value {
+                    None => Some("returns None instead of a result dict".to_string()),
+                    Some(value) => match non_dict_kind(value) {
+                        Some(kind) => Some(format!("returns a {} instead of a result dict", kind)),
+                        None if lacks_content(value) => {
+                            Some("returns a dict without a \"content\" key".to_string())
+                        }
+                        None => None,
+                    },
+                };
+                if let Some(problem) = problem {
+                    issues.push(LintIssue::new(
+                        Severity::Error,
+                        "handler-result",
+                        format!("Handler '{}' for tool '{}' {}", handler, tool.name, problem),
+                        self.span(span),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// `exec.run()` of a literal command the extension may not run
+    fn exec_commands(&self, extension: &StarlarkExtension, issues: &mut Vec<LintIssue>) {
+        for expr in self.expressions() {
+            let ExprP::Call(callee, args) = &expr.node else {
+                continue;
+            };
+            let is_exec_run = matches!(
+                &callee.node,
+                ExprP::Dot(object, method)
+                    if method.node == "run"
+                        && matches!(&object.node, ExprP::Identifier(id) if id.ident == "exec")
+            );
+            if !is_exec_run {
+                continue;
+            }
+
+            let command = args
+                .args
+                .iter()
+                .enumerate()
+                .find_map(|(i, arg)| match &arg.node {
+                    ArgumentP::Positional(value) if i == 0 => string_literal(value),
+                    ArgumentP::Named(key, value) if key.node == "command" => string_literal(value),
+                    _ => None,
+                });
+            if let Some(command) = command
+                && !extension.allowed_exec.iter().any(|c| c == command)
+            {
+                issues.push(LintIssue::new(
+                    Severity::Error,
+                    "exec-not-allowed",
+                    format!(
+                        "Command '{}' is not in allowed_exec for extension '{}'",
+                        command, extension.name
+                    ),
+                    self.span(expr.span),
+                ));
+            }
+        }
+    }
+
+    /// `env.get()` of a literal variable outside the extension's `allowed_env`
+    fn env_variables(&self, extension: &StarlarkExtension, issues: &mut Vec<LintIssue>) {
+        let capabilities = extension.capabilities();
+        if capabilities.allowed_env.is_none() {
+            return;
+        }
+        for expr in self.expressions() {
+            let ExprP::Call(callee, args) = &expr.node else {
+                continue;
+            };
+            let is_env_get = matches!(
+                &callee.node,
+                ExprP::Dot(object, method)
+                    if method.node == "get"
+                        && matches!(&object.node, ExprP::Identifier(id) if id.ident == "env")
+            );
+            if !is_env_get {
+                continue;
+            }
+
+            let name = args
+                .args
+                .iter()
+                .enumerate()
+                .find_map(|(i, arg)| match &arg.node {
+                    ArgumentP::Positional(value) if i == 0 => string_literal(value),
+                    ArgumentP::Named(key, value) if key.node == "name" => string_literal(value),
+                    _ => None,
+                });
+            if let Some(name) = name
+                && !capabilities.allows_env(name)
+            {
+                issues.push(LintIssue::new(
+                    Severity::Warning,
+                    "env-not-allowed",
+                    format!(
+                        "Environment variable '{}' is not in allowed_env for extension '{}'; env.get() returns the default",
+                        name, extension.name
+                    ),
+                    self.span(expr.span),
+                ));
+            }
+        }
+    }
+
+    /// First use of each guarded module the extension left out of `requires`
+    fn required_modules(&self, extension: &StarlarkExtension, issues: &mut Vec<LintIssue>) {
+        let Some(ref requires) = extension.requires else {
+            return;
+        };
+        let mut reported = HashSet::new();
+        for expr in self.expressions() {
+            let ExprP::Dot(object, _) = &expr.node else {
+                continue;
+            };
+            let ExprP::Identifier(id) = &object.node else {
+                continue;
+            };
+            let module = id.ident.as_str();
+            if GUARDED_MODULES.contains(&module)
+                && !requires.iter().any(|m| m == module)
+                && reported.insert(module)
+            {
+                issues.push(LintIssue::new(
+                    Severity::Error,
+                    "module-not-required",
+                    format!(
+                        "Module '{}' is not in requires for extension '{}'",
+                        module, extension.name
+                    ),
+                    self.span(expr.span),
+                ));
+            }
+        }
+    }
+}
+
+/// Whether `name` is accepted as a tool name by MCP clients
+pub fn is_valid_tool_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_TOOL_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Top-level `def`s of a module
+fn top_level_defs(ast: &AstModule) -> Vec<&DefP<AstNoPayload>> {
+    top_level_stmts(ast.statement())
+        .into_iter()
+        .filter_map(|stmt| match &stmt.node {
+            StmtP::Def(def) => Some(def),
+            _ => None,
+        })
+        .collect()
+}
+
+fn walk_statements<'a>(stmt: &'a AstStmt, f: &mut dyn FnMut(&'a AstStmt)) {
+    f(stmt);
+    stmt.visit_children(|child| {
+        if let Visit::Stmt(child) = child {
+            walk_statements(child, f);
+        }
+    });
+}
+
+fn walk_expressions<'a>(expr: &'a AstExpr, f: &mut dyn FnMut(&'a AstExpr)) {
+    f(expr);
+    expr.visit_expr(|child| walk_expressions(child, f));
+}
+
+/// `return` statements of a function body, excluding nested functions
+fn collect_returns<'a>(stmt: &'a AstStmt, out: &mut Vec<(Span, Option<&'a AstExpr>)>) {
+    match &stmt.node {
+        StmtP::Return(value) => out.push((stmt.span, value.as_ref())),
+        StmtP::Def(_) => {}
+        _ => stmt.visit_children(|child| {
+            if let Visit::Stmt(child) = child {
+                collect_returns(child, out);
+            }
+        }),
+    }
+}
+
+fn string_literal(expr: &AstExpr) -> Option<&str> {
+    match &expr.node {
+        ExprP::Literal(AstLiteral::String(s)) => Some(&s.node),
+        _ => None,
+    }
+}
+
+/// What a returned expression is, if it certainly isn't a dict
+fn non_dict_kind(expr: &AstExpr) -> Option<&'static str> {
+    match &expr.node {
+        ExprP::Literal(AstLiteral::String(_)) | ExprP::FString(_) => Some("string"),
+        ExprP::Literal(AstLiteral::Int(_)) | ExprP::Literal(AstLiteral::Float(_)) => Some("number"),
+        ExprP::List(_) | ExprP::ListComprehension(..) => Some("list"),
+        ExprP::Tuple(_) => Some("tuple"),
+        ExprP::Identifier(id) if id.ident == "None" => Some("None"),
+        ExprP::Identifier(id) if id.ident == "True" || id.ident == "False" => Some("bool"),
+        _ => None,
+    }
+}
+
+/// A dict literal whose keys are all strings, none of them `content`
+fn lacks_content(expr: &AstExpr) -> bool {
+    let ExprP::Dict(items) = &expr.node else {
+        return false;
+    };
+    let keys: Option<Vec<&str>> = items.iter().map(|(k, _)| string_literal(k)).collect();
+    keys.is_some_and(|keys| !keys.contains(&"content"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::starlark::StarlarkEngine;
+
+    fn extension(tools: &str, handlers: &str) -> String {
+        format!(
+            r#"{handlers}
+
+def describe_extension():
+    return Extension(
+        name = "weather",
+        version = "1.0.0",
+        description = "Weather",
+        allowed_exec = ["curl"],
+        tools = [{tools}],
+    )
+"#
+        )
+    }
+
+    const TOOL: &str = r#"Tool(name = "forecast", description = "Forecast", handler = forecast)"#;
+
+    async fn lint(code: &str) -> Vec<(Severity, String, usize)> {
+        let engine = StarlarkEngine::new();
+        let ext = engine.load_extension("weather", code).await.unwrap();
+        engine
+            .lint("weather", code, Some(&ext))
+            .into_iter()
+            .map(|issue| (issue.severity, issue.code, issue.span.unwrap().line))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_clean_extension() {
+        let code = extension(
+            TOOL,
+            "def forecast(params):\n    exec.run(\"curl\", [\"wttr.in\"])\n    return {\"content\": []}",
+        );
+        assert_eq!(lint(&code).await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn test_starlark_lints() {
+        let code = extension(
+            TOOL,
+            "json = 1\n\ndef forecast(params):\n    unused = 1\n    return {\"content\": []}",
+        );
+        assert_eq!(
+            lint(&code).await,
+            vec![
+                (Severity::Warning, "shadowed-global".to_string(), 1),
+                (Severity::Warning, "unused-assign".to_string(), 4),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handler_results() {
+        let handlers = r#"
+def forecast(params):
+    if params.get("city"):
+        return "sunny"
+    if params.get("days"):
+        return {"text": "sunny"}
+    if params.get("raw"):
+        return
+    return {"content": []}
+
+def alerts(params):
+    params.get("region")
+"#;
+        let tools = format!(
+            r#"{}, Tool(name = "alerts", description = "Alerts", handler = alerts)"#,
+            TOOL
+        );
+        let issues = lint(&extension(&tools, handlers)).await;
+        assert_eq!(
+            issues,
+            vec![
+                (Severity::Error, "handler-result".to_string(), 4),
+                (Severity::Error, "handler-result".to_string(), 6),
+                (
+                    Severity::Warning,
+                    "missing-return-expression".to_string(),
+                    8
+                ),
+                (Severity::Error, "handler-result".to_string(), 8),
+                (Severity::Error, "handler-result".to_string(), 11),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tool_declarations() {
+        let tools = r#"Tool(
+            name = "weather forecast",
+            description = "Forecast",
+            parameters = [ToolParameter(name = "city", param_type = "string", required = True, description = "")],
+            handler = forecast,
+        )"#;
+        let code = extension(tools, "def forecast(params):\n    return {\"content\": []}");
+        assert_eq!(
+            lint(&code).await,
+            vec![
+                (Severity::Error, "invalid-tool-name".to_string(), 10),
+                (Severity::Warning, "missing-description".to_string(), 13),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exec_not_allowed() {
+        let code = extension(
+            TOOL,
+            "def forecast(params):\n    exec.run(command = \"wget\", args = [])\n    return {\"content\": []}",
+        );
+        assert_eq!(
+            lint(&code).await,
+            vec![(Severity::Error, "exec-not-allowed".to_string(), 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_env_not_allowed() {
+        let code = extension(
+            TOOL,
+            "def forecast(params):\n    env.get(\"WEATHER_UNITS\")\n    env.get(name = \"AWS_SECRET_ACCESS_KEY\")\n    return {\"content\": []}",
+        )
+        .replace("allowed_exec = [\"curl\"],", "allowed_env = [\"WEATHER_*\"],");
+        assert_eq!(
+            lint(&code).await,
+            vec![(Severity::Warning, "env-not-allowed".to_string(), 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_module_not_required() {
+        let code = extension(
+            TOOL,
+            "def forecast(params):\n    http.get(\"https://wttr.in\")\n    kv.get(\"last\")\n    http.get(\"https://wttr.in\")\n    return {\"content\": []}",
+        )
+        .replace("allowed_exec = [\"curl\"],", "requires = [\"kv\"],");
+        assert_eq!(
+            lint(&code).await,
+            vec![(Severity::Error, "module-not-required".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_valid_tool_names() {
+        assert!(is_valid_tool_name("get_forecast-v2"));
+        assert!(!is_valid_tool_name(""));
+        assert!(!is_valid_tool_name("get.forecast"));
+        assert!(!is_valid_tool_name(&"a".repeat(MAX_TOOL_NAME_LEN + 1)));
+    }
+
+    #[test]
+    fn test_render() {
+        let issue = LintIssue::new(
+            Severity::Warning,
+            "unused-assign",
+            "Variable `x` is assigned but not used".to_string(),
+            Some(SourceSpan {
+                file: "weather".to_string(),
+                line: 10,
+                column: 5,
+                end_line: 10,
+                end_column: 6,
+            }),
+        );
+        let source = format!("{}    x = 1\n", "\n".repeat(9));
+        assert_eq!(
+            issue.render("extensions/weather.star", Some(&source)),
+            "warning[unused-assign]: Variable `x` is assigned but not used\n  \
+             --> extensions/weather.star:10:5\n   \
+             |\n\
+             10 |     x = 1\n   \
+             |     ^\n"
+        );
+        assert_eq!(
+            issue.to_string(),
+            "weather:10:5: warning[unused-assign]: Variable `x` is assigned but not used"
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::path::PathBuf;
+
+use super::context::GUARDED_MODULES;
+use super::mcp_types::StarlarkExtension;
+use super::modules::BUILTIN_MODULES;
+
+/// Version of this server, which `min_server_version` is compared against
+pub const SERVER_VERSION: &str = env!("STARLARK_MCP_VERSION");
+
+/// Refuse an extension that needs a newer server or names a module this
+/// server doesn't have
+pub fn check_compatibility(extension: &StarlarkExtension, server_version: &str) -> Result<()> {
+    if let Some(ref required) = extension.min_server_version {
+        let wanted = parse_version(required).ok_or_else(|| {
+            anyhow!(
+                "Extension '{}' has an invalid min_server_version '{}', expected e.g. \"0.2.0\"",
+                extension.name,
+                required
+            )
+        })?;
+        let running = parse_version(server_version)
+            .ok_or_else(|| anyhow!("Invalid server version '{}'", server_version))?;
+        if compare_versions(&running, &wanted) == Ordering::Less {
+            return Err(anyhow!(
+                "Extension '{}' requires starlark-mcp {} or newer, but this server is {}",
+                extension.name,
+                required,
+                server_version
+            ));
+        }
+    }
+
+    let unknown: Vec<&str> = extension
+        .requires
+        .iter()
+        .flatten()
+        .map(String::as_str)
+        .filter(|module| !BUILTIN_MODULES.contains(module))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(anyhow!(
+            "Extension '{}' requires unknown module{} {}; available modules are {}",
+            extension.name,
+            if unknown.len() == 1 { "" } else { "s" },
+            unknown.join(", "),
+            BUILTIN_MODULES.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Numeric components of a version such as `v0.2.1-beta`; pre-release and
+/// build suffixes are ignored
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    let version = version.trim().trim_start_matches('v');
+    let core = version.split(['-', '+']).next()?;
+    core.split('.').map(|part| part.parse().ok()).collect()
+}
+
+/// Compare component by component, treating missing components as 0
+fn compare_versions(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    (0..len)
+        .map(|i| a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0)))
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// What an extension's handlers can reach outside the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Permissions {
+    /// Modules from [`GUARDED_MODULES`] the handlers may call
+    pub modules: Vec<String>,
+    /// Whether the extension declared `requires`; if not, every module is
+    /// available
+    pub declared: bool,
+    /// Commands `exec.run()` may start
+    pub exec: Vec<String>,
+    /// The `ExecRule()`s constraining their arguments, described
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exec_rules: Vec<String>,
+    /// Hosts `http` requests may reach; any host when `None`
+    pub hosts: Option<Vec<String>>,
+    /// Whether `http` requests may reach loopback, private and link-local
+    /// addresses
+    pub private_network: bool,
+    /// Environment variables read through `ConfigVar()`s
+    pub env: Vec<String>,
+    /// Patterns for the variables `env.get()` may read; any variable when
+    /// `None`
+    pub allowed_env: Option<Vec<String>>,
+    /// Why the extension runs without `exec` and `http`, if it was loaded
+    /// without a trusted signature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsigned: Option<String>,
+}
+
+impl Permissions {
+    pub fn of(extension: &StarlarkExtension) -> Self {
+        let capabilities = extension.capabilities();
+        Self {
+            modules: GUARDED_MODULES
+                .iter()
+                .filter(|module| capabilities.allows_module(module))
+                .map(|module| module.to_string())
+                .collect(),
+            declared: extension.requires.is_some(),
+            exec_rules: capabilities
+                .exec_rules
+                .iter()
+                .filter(|rule| capabilities.allowed_exec.contains(&rule.command))
+                .map(|rule| rule.to_string())
+                .collect(),
+            exec: capabilities.allowed_exec,
+            hosts: capabilities.allowed_hosts,
+            private_network: capabilities.allow_private_network,
+            env: extension
+                .config
+                .iter()
+                .map(|var| var.name.clone())
+                .collect(),
+            allowed_env: extension.allowed_env.clone(),
+            unsigned: extension.unsigned.clone(),
+        }
+    }
+}
+
+/// Everything an extension declares about itself, for reviewing it before
+/// it is enabled
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtensionManifest {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Whether the server config lets the extension load
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_server_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requires: Option<Vec<String>>,
+    pub permissions: Permissions,
+    pub tools: Vec<String>,
+}
+
+impl ExtensionManifest {
+    pub fn new(extension: &StarlarkExtension, path: Option<PathBuf>, enabled: bool) -> Self {
+        Self {
+            name: extension.name.clone(),
+            version: extension.version.clone(),
+            description: extension.description.clone(),
+            path,
+            enabled,
+            homepage: extension.homepage.clone(),
+            authors: extension.authors.clone(),
+            min_server_version: extension.min_server_version.clone(),
+            requires: extension.requires.clone(),
+            permissions: Permissions::of(extension),
+            tools: extension.tools.iter().map(|t| t.name.clone()).collect(),
+        }
+    }
+
+    /// Plain-text summary, one field per line
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{} v{}", self.name, self.version);
+        if !self.enabled {
+            text.push_str(" (disabled)");
+        }
+        text.push('\n');
+        if !self.description.is_empty() {
+            text.push_str(&format!("  {}\n", self.description));
+        }
+        if let Some(ref path) = self.path {
+            text.push_str(&format!("  path:     {}\n", path.display()));
+        }
+        if let Some(ref homepage) = self.homepage {
+            text.push_str(&format!("  homepage: {}\n", homepage));
+        }
+        if !self.authors.is_empty() {
+            text.push_str(&format!("  authors:  {}\n", self.authors.join(", ")));
+        }
+        if let Some(ref version) = self.min_server_version {
+            text.push_str(&format!("  server:   >= {}\n", version));
+        }
+
+        let permissions = &self.permissions;
+        let mut modules = list(&permissions.modules);
+        if !permissions.declared {
+            modules.push_str(" (requires not declared)");
+        }
+        text.push_str(&format!("  modules:  {}\n", modules));
+        let exec: Vec<String> = permissions
+            .exec
+            .iter()
+            .map(|command| {
+                let ruled = format!("{} (", command);
+                permissions
+                    .exec_rules
+                    .iter()
+                    .find(|rule| rule.starts_with(&ruled))
+                    .unwrap_or(command)
+                    .clone()
+            })
+            .collect();
+        text.push_str(&format!("  exec:     {}\n", list(&exec)));
+        let mut hosts = match permissions.hosts {
+            Some(ref hosts) => list(hosts),
+            None => "any".to_string(),
+        };
+        if permissions.private_network {
+            hosts.push_str(" (private networks allowed)");
+        }
+        text.push_str(&format!("  hosts:    {}\n", hosts));
+        text.push_str(&format!("  env:      {}\n", list(&permissions.env)));
+        if let Some(ref allowed_env) = permissions.allowed_env {
+            text.push_str(&format!("  env.get:  {}\n", list(allowed_env)));
+        }
+        if let Some(ref reason) = permissions.unsigned {
+            text.push_str(&format!(
+                "  unsigned: {} (exec and http disabled)\n",
+                reason
+            ));
+        }
+        text.push_str(&format!("  tools:    {}\n", list(&self.tools)));
+        text
+    }
+}
+
+fn list(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::starlark::StarlarkEngine;
+
+    const EXTENSION: &str = r#"
+def forecast(params):
+    return {"content": []}
+
+def describe_extension():
+    return Extension(
+        name = "weather",
+        version = "1.2.0",
+        description = "Weather forecasts",
+        homepage = "https://example.com/weather",
+        authors = ["Ada", "Grace"],
+        min_server_version = "0.1.0",
+        requires = ["http", "kv", "json"],
+        allowed_exec = [ExecRule("curl", forbidden_flags = ["-o", "--output"])],
+        allowed_hosts = ["api.weather.example", "*.tiles.example"],
+        allowed_env = ["WEATHER_*"],
+        config = [ConfigVar(name = "WEATHER_API_KEY", required = False)],
+        tools = [Tool(name = "forecast", description = "Forecast", handler = forecast)],
+    )
+"#;
+
+    async fn load(code: &str) -> StarlarkExtension {
+        StarlarkEngine::new()
+            .load_extension("weather", code)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        let v = |s| parse_version(s).unwrap();
+        assert_eq!(compare_versions(&v("0.1.0"), &v("0.1")), Ordering::Equal);
+        assert_eq!(
+            compare_versions(&v("v0.10.0"), &v("0.9.3")),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare_versions(&v("1.0.0-rc1"), &v("1.0.1")),
+            Ordering::Less
+        );
+        assert_eq!(parse_version("latest"), None);
+    }
+
+    #[tokio::test]
+    async fn test_check_compatibility() {
+        let mut extension = load(EXTENSION).await;
+        assert!(check_compatibility(&extension, "0.1.0").is_ok());
+
+        extension.min_server_version = Some("0.3".to_string());
+        let err = check_compatibility(&extension, "0.2.5").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Extension 'weather' requires starlark-mcp 0.3 or newer, but this server is 0.2.5"
+        );
+
+        extension.min_server_version = None;
+        extension.requires = Some(vec!["http".to_string(), "smtp".to_string()]);
+        let err = check_compatibility(&extension, "0.1.0").unwrap_err();
+        assert!(
+            err.to_string().starts_with(
+                "Extension 'weather' requires unknown module smtp; available modules are cache, "
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manifest() {
+        let manifest = ExtensionManifest::new(&load(EXTENSION).await, None, true);
+        assert_eq!(
+            manifest.permissions,
+            Permissions {
+                modules: vec!["http".to_string(), "kv".to_string()],
+                declared: true,
+                exec: vec!["curl".to_string()],
+                exec_rules: vec!["curl (not -o --output)".to_string()],
+                hosts: Some(vec![
+                    "api.weather.example".to_string(),
+                    "*.tiles.example".to_string()
+                ]),
+                private_network: false,
+                env: vec!["WEATHER_API_KEY".to_string()],
+                allowed_env: Some(vec!["WEATHER_*".to_string()]),
+                unsigned: None,
+            }
+        );
+        assert_eq!(
+            manifest.to_text(),
+            "weather v1.2.0\n  \
+             Weather forecasts\n  \
+             homepage: https://example.com/weather\n  \
+             authors:  Ada, Grace\n  \
+             server:   >= 0.1.0\n  \
+             modules:  http, kv\n  \
+             exec:     curl (not -o --output)\n  \
+             hosts:    api.weather.example, *.tiles.example\n  \
+             env:      WEATHER_API_KEY\n  \
+             env.get:  WEATHER_*\n  \
+             tools:    forecast\n"
+        );
+
+        let undeclared = EXTENSION.replace("requires = [\"http\", \"kv\", \"json\"],", "");
+        let manifest = ExtensionManifest::new(&load(&undeclared).await, None, false);
+        assert!(
+            manifest
+                .to_text()
+                .starts_with("weather v1.2.0 (disabled)\n")
+        );
+        assert!(
+            manifest.to_text().contains(
+                "modules:  http, exec, postgres, sqlite, data, kv (requires not declared)"
+            )
+        );
+    }
+}
@@ -4,6 +4,7 @@ use either::Either;
 use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
 use starlark::starlark_module;
 use starlark::starlark_simple_value;
+use starlark::values::float::UnpackFloat;
 use starlark::values::starlark_value;
 use starlark::values::{NoSerialize, ProvidesStaticType, StarlarkValue, Value};
 
@@ -16,8 +17,8 @@ starlark_simple_value!(MathModule);
 #[starlark_value(type = "math")]
 impl<'v> StarlarkValue<'v> for MathModule {
     fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(math_methods)
+        static RES: MethodsStatic = MethodsStatic::new("math", math_methods);
+        Some(RES.methods())
     }
 
     fn dir_attr(&self) -> Vec<String> {
@@ -46,16 +47,16 @@ fn math_methods(builder: &mut MethodsBuilder) {
     /// ```
     fn pow(
         #[allow(unused_variables)] this: Value,
-        x: Either<i32, f64>,
-        y: Either<i32, f64>,
+        x: Either<i32, UnpackFloat>,
+        y: Either<i32, UnpackFloat>,
     ) -> anyhow::Result<f64> {
         let x_float = match x {
             Either::Left(i) => i as f64,
-            Either::Right(f) => f,
+            Either::Right(f) => f.0,
         };
         let y_float = match y {
             Either::Left(i) => i as f64,
-            Either::Right(f) => f,
+            Either::Right(f) => f.0,
         };
         Ok(x_float.powf(y_float))
     }
@@ -68,10 +69,13 @@ fn math_methods(builder: &mut MethodsBuilder) {
     /// math.sqrt(9)    # 3.0
     /// math.sqrt(2)    # 1.414...
     /// ```
-    fn sqrt(#[allow(unused_variables)] this: Value, x: Either<i32, f64>) -> anyhow::Result<f64> {
+    fn sqrt(
+        #[allow(unused_variables)] this: Value,
+        x: Either<i32, UnpackFloat>,
+    ) -> anyhow::Result<f64> {
         let x_float = match x {
             Either::Left(i) => i as f64,
-            Either::Right(f) => f,
+            Either::Right(f) => f.0,
         };
         if x_float < 0.0 {
             return Err(anyhow::anyhow!(
@@ -89,10 +93,13 @@ fn math_methods(builder: &mut MethodsBuilder) {
     /// math.ceil(-4.2)  # -4
     /// math.ceil(5)     # 5
     /// ```
-    fn ceil(#[allow(unused_variables)] this: Value, x: Either<i32, f64>) -> anyhow::Result<i32> {
+    fn ceil(
+        #[allow(unused_variables)] this: Value,
+        x: Either<i32, UnpackFloat>,
+    ) -> anyhow::Result<i32> {
         let x_float = match x {
             Either::Left(i) => return Ok(i),
-            Either::Right(f) => f,
+            Either::Right(f) => f.0,
         };
         Ok(x_float.ceil() as i32)
     }
@@ -105,10 +112,13 @@ fn math_methods(builder: &mut MethodsBuilder) {
     /// math.floor(-4.2)  # -5
     /// math.floor(5)     # 5
     /// ```
-    fn floor(#[allow(unused_variables)] this: Value, x: Either<i32, f64>) -> anyhow::Result<i32> {
+    fn floor(
+        #[allow(unused_variables)] this: Value,
+        x: Either<i32, UnpackFloat>,
+    ) -> anyhow::Result<i32> {
         let x_float = match x {
             Either::Left(i) => return Ok(i),
-            Either::Right(f) => f,
+            Either::Right(f) => f.0,
         };
         Ok(x_float.floor() as i32)
     }
@@ -124,7 +134,7 @@ fn math_methods(builder: &mut MethodsBuilder) {
     /// ```
     fn round(
         #[allow(unused_variables)] this: Value,
-        x: Either<i32, f64>,
+        x: Either<i32, UnpackFloat>,
         #[starlark(default = 0)] decimals: i32,
     ) -> anyhow::Result<f64> {
         if decimals < 0 {
@@ -133,7 +143,7 @@ fn math_methods(builder: &mut MethodsBuilder) {
 
         let x_float = match x {
             Either::Left(i) => i as f64,
-            Either::Right(f) => f,
+            Either::Right(f) => f.0,
         };
 
         if decimals == 0 {
@@ -152,10 +162,13 @@ fn math_methods(builder: &mut MethodsBuilder) {
     /// math.abs(3.14)  # 3.14
     /// math.abs(-2.7)  # 2.7
     /// ```
-    fn abs(#[allow(unused_variables)] this: Value, x: Either<i32, f64>) -> anyhow::Result<f64> {
+    fn abs(
+        #[allow(unused_variables)] this: Value,
+        x: Either<i32, UnpackFloat>,
+    ) -> anyhow::Result<f64> {
         let x_float = match x {
             Either::Left(i) => i as f64,
-            Either::Right(f) => f,
+            Either::Right(f) => f.0,
         };
         Ok(x_float.abs())
     }
@@ -187,13 +200,14 @@ mod tests {
 
     fn eval_math(code: &str) -> Result<String, starlark::Error> {
         let globals = GlobalsBuilder::new().with(register).build();
-        let module = starlark::environment::Module::new();
-        let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
-        let mut eval = Evaluator::new(&module);
-        let result = eval.eval_module(ast, &globals)?;
-        // Convert to string while the Module and heap are still in scope so we don't
-        // return a Value<'static> that contains non-Send/Sync internals.
-        Ok(result.to_string())
+        starlark::environment::Module::with_temp_heap(|module| {
+            let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
+            let mut eval = Evaluator::new(&module);
+            let result = eval.eval_module(ast, &globals)?;
+            // Convert to string while the Module and heap are still in scope so we don't
+            // return a Value<'static> that contains non-Send/Sync internals.
+            Ok(result.to_string())
+        })
     }
 
     #[test]
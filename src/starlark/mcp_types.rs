@@ -5,7 +5,10 @@ use starlark::values::dict::AllocDict;
 use starlark::values::{Heap, Value, none::NoneType};
 
 use crate::mcp::{Tool, ToolAnnotations, ToolInputSchema};
+use crate::starlark::context::Capabilities;
 use crate::starlark::engine::starlark_value_to_json;
+use crate::starlark::exec_rules::ExecRule;
+use crate::starlark::limits::ExecutionLimits;
 
 // Extension type - represents a loaded Starlark extension
 #[derive(Debug, Clone)]
@@ -15,6 +18,34 @@ pub struct StarlarkExtension {
     pub description: String,
     pub tools: Vec<StarlarkTool>,
     pub allowed_exec: Vec<String>,
+    /// Argument constraints for commands in `allowed_exec`, from the
+    /// `ExecRule()`s listed there
+    pub exec_rules: Vec<ExecRule>,
+    /// Hosts `http` may reach, or `None` for any host
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Whether `http` may reach private and link-local addresses; set from
+    /// the server config, never by the extension
+    pub allow_private_network: bool,
+    /// Patterns for the environment variables `env.get()` may read, or
+    /// `None` for any variable
+    pub allowed_env: Option<Vec<String>>,
+    /// Whether reading a variable outside `allowed_env` fails instead of
+    /// returning the default; set from the server config
+    pub strict_env: bool,
+    pub config: Vec<StarlarkConfigVar>,
+    /// Oldest server release the extension runs on
+    pub min_server_version: Option<String>,
+    /// Builtin modules the extension uses, or `None` if it doesn't say
+    pub requires: Option<Vec<String>>,
+    pub homepage: Option<String>,
+    pub authors: Vec<String>,
+    /// Required config variables that had no value when the extension loaded.
+    /// While any are missing the extension's tools are hidden and disabled.
+    pub missing_config: Vec<String>,
+    /// Why the extension has no trusted signature, when signatures are
+    /// checked and the policy let it load restricted. Its handlers can't use
+    /// `exec` or `http`.
+    pub unsigned: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,6 +57,7 @@ pub struct StarlarkTool {
     pub parameters: Vec<StarlarkToolParameter>,
     pub annotations: Option<StarlarkToolAnnotations>,
     pub output_schema: Option<serde_json::Value>,
+    pub limits: ExecutionLimits,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -45,17 +77,37 @@ pub struct StarlarkToolParameter {
     pub description: String,
 }
 
+/// A configuration input declared with `ConfigVar()`, read from the
+/// environment variable of the same name
+#[derive(Debug, Clone)]
+pub struct StarlarkConfigVar {
+    pub name: String,
+    pub required: bool,
+    /// Secret values are redacted from logs, errors and tool output
+    pub secret: bool,
+    pub default: Option<String>,
+    pub description: String,
+}
+
 // MCP globals for Starlark
 #[starlark_module]
 #[allow(clippy::type_complexity)]
 pub fn mcp_globals(builder: &mut GlobalsBuilder) {
+    #[allow(clippy::too_many_arguments)]
     fn Extension<'v>(
         name: String,
         version: String,
         description: String,
         tools: Value<'v>,
         #[starlark(default = NoneType)] allowed_exec: Value<'v>,
-        heap: &'v Heap,
+        #[starlark(default = NoneType)] config: Value<'v>,
+        #[starlark(default = NoneType)] min_server_version: Value<'v>,
+        #[starlark(default = NoneType)] requires: Value<'v>,
+        #[starlark(default = NoneType)] homepage: Value<'v>,
+        #[starlark(default = NoneType)] authors: Value<'v>,
+        #[starlark(default = NoneType)] allowed_hosts: Value<'v>,
+        #[starlark(default = NoneType)] allowed_env: Value<'v>,
+        heap: Heap<'v>,
     ) -> anyhow::Result<Value<'v>> {
         // Create a dict to return using the allocator
         let dict_items = vec![
@@ -64,20 +116,31 @@ pub fn mcp_globals(builder: &mut GlobalsBuilder) {
             (heap.alloc("description"), heap.alloc(description)),
             (heap.alloc("tools"), tools),
             (heap.alloc("allowed_exec"), allowed_exec),
+            (heap.alloc("config"), config),
+            (heap.alloc("min_server_version"), min_server_version),
+            (heap.alloc("requires"), requires),
+            (heap.alloc("homepage"), homepage),
+            (heap.alloc("authors"), authors),
+            (heap.alloc("allowed_hosts"), allowed_hosts),
+            (heap.alloc("allowed_env"), allowed_env),
         ];
 
         Ok(heap.alloc(AllocDict(dict_items)))
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn Tool<'v>(
         name: String,
         description: String,
         #[starlark(default = NoneType)] parameters: Value<'v>,
-        handler: Value<'v>,
+        #[starlark(require = named)] handler: Value<'v>,
         #[starlark(default = NoneType)] title: Value<'v>,
         #[starlark(default = NoneType)] annotations: Value<'v>,
         #[starlark(default = NoneType)] output_schema: Value<'v>,
-        heap: &'v Heap,
+        #[starlark(default = NoneType)] timeout_secs: Value<'v>,
+        #[starlark(default = NoneType)] max_steps: Value<'v>,
+        #[starlark(default = NoneType)] max_heap_mb: Value<'v>,
+        heap: Heap<'v>,
     ) -> anyhow::Result<Value<'v>> {
         // Create a dict to return using the allocator
         let dict_items = vec![
@@ -88,6 +151,9 @@ pub fn mcp_globals(builder: &mut GlobalsBuilder) {
             (heap.alloc("title"), title),
             (heap.alloc("annotations"), annotations),
             (heap.alloc("output_schema"), output_schema),
+            (heap.alloc("timeout_secs"), timeout_secs),
+            (heap.alloc("max_steps"), max_steps),
+            (heap.alloc("max_heap_mb"), max_heap_mb),
         ];
 
         Ok(heap.alloc(AllocDict(dict_items)))
@@ -98,8 +164,8 @@ pub fn mcp_globals(builder: &mut GlobalsBuilder) {
         param_type: String, // Will be passed with keyword "type" from Starlark
         required: bool,
         #[starlark(default = NoneType)] default: Value<'v>,
-        description: String,
-        heap: &'v Heap,
+        #[starlark(require = named)] description: String,
+        heap: Heap<'v>,
     ) -> anyhow::Result<Value<'v>> {
         // Create a dict to return using the allocator
         let dict_items = vec![
@@ -112,11 +178,58 @@ pub fn mcp_globals(builder: &mut GlobalsBuilder) {
 
         Ok(heap.alloc(AllocDict(dict_items)))
     }
+
+    fn ConfigVar<'v>(
+        name: String,
+        #[starlark(default = false)] required: bool,
+        #[starlark(default = false)] secret: bool,
+        #[starlark(default = NoneType)] default: Value<'v>,
+        #[starlark(default = "")] description: &str,
+        heap: Heap<'v>,
+    ) -> anyhow::Result<Value<'v>> {
+        if !default.is_none() && default.unpack_str().is_none() {
+            return Err(anyhow!(
+                "ConfigVar '{}' default must be a string or None, got {}",
+                name,
+                default.get_type()
+            ));
+        }
+
+        let dict_items = vec![
+            (heap.alloc("name"), heap.alloc(name)),
+            (heap.alloc("required"), heap.alloc(required)),
+            (heap.alloc("secret"), heap.alloc(secret)),
+            (heap.alloc("default"), default),
+            (heap.alloc("description"), heap.alloc(description)),
+        ];
+
+        Ok(heap.alloc(AllocDict(dict_items)))
+    }
+
+    fn ExecRule<'v>(
+        command: String,
+        #[starlark(default = NoneType)] subcommands: Value<'v>,
+        #[starlark(default = NoneType)] forbidden_flags: Value<'v>,
+        #[starlark(default = NoneType)] args: Value<'v>,
+        heap: Heap<'v>,
+    ) -> anyhow::Result<Value<'v>> {
+        let dict_items = vec![
+            (heap.alloc("command"), heap.alloc(command)),
+            (heap.alloc("subcommands"), subcommands),
+            (heap.alloc("forbidden_flags"), forbidden_flags),
+            (heap.alloc("args"), args),
+        ];
+        let rule = heap.alloc(AllocDict(dict_items));
+
+        // Validate now so bad patterns are reported where they are written
+        extract_exec_rule(rule, heap)?;
+        Ok(rule)
+    }
 }
 
 pub fn extract_extension_from_value<'v>(
     value: Value<'v>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> anyhow::Result<StarlarkExtension> {
     // Get dict items via indexing
     let name_val = value
@@ -293,6 +406,12 @@ pub fn extract_extension_from_value<'v>(
             None
         };
 
+        let limits = extract_limits(tool_value, &tool_name, heap)?;
+
+        if tools.iter().any(|t: &StarlarkTool| t.name == tool_name) {
+            return Err(anyhow!("Tool '{}' is declared more than once", tool_name));
+        }
+
         tools.push(StarlarkTool {
             name: tool_name,
             title,
@@ -301,25 +420,67 @@ pub fn extract_extension_from_value<'v>(
             parameters,
             annotations,
             output_schema,
+            limits,
         });
     }
 
-    // Extract allowed_exec if present
-    let allowed_exec = if let Ok(allowed_exec_value) = value.at(heap.alloc("allowed_exec"), heap) {
-        if !allowed_exec_value.is_none() {
-            let mut exec_list = Vec::new();
-            for cmd in allowed_exec_value
-                .iterate(heap)
-                .map_err(|e| anyhow!("Failed to iterate allowed_exec: {}", e))?
-            {
-                exec_list.push(cmd.to_str().to_string());
+    // Extract allowed_exec if present: command names, or ExecRule()s that
+    // also constrain the arguments
+    let mut allowed_exec = Vec::new();
+    let mut exec_rules: Vec<ExecRule> = Vec::new();
+    if let Ok(allowed_exec_value) = value.at(heap.alloc("allowed_exec"), heap)
+        && !allowed_exec_value.is_none()
+    {
+        for cmd in allowed_exec_value
+            .iterate(heap)
+            .map_err(|e| anyhow!("Failed to iterate allowed_exec: {}", e))?
+        {
+            if cmd.unpack_str().is_some() || cmd.at(heap.alloc("command"), heap).is_err() {
+                allowed_exec.push(cmd.to_str().to_string());
+            } else {
+                let rule = extract_exec_rule(cmd, heap)?;
+                allowed_exec.push(rule.command.clone());
+                exec_rules.push(rule);
             }
-            exec_list
-        } else {
-            Vec::new()
         }
-    } else {
-        Vec::new()
+    }
+    for rule in &exec_rules {
+        if allowed_exec.iter().filter(|c| **c == rule.command).count() > 1 {
+            return Err(anyhow!(
+                "allowed_exec lists '{}' more than once; use a single ExecRule for it",
+                rule.command
+            ));
+        }
+    }
+
+    let config = extract_config(value, heap)?;
+
+    let optional_string = |key: &str| -> anyhow::Result<Option<String>> {
+        match value.at(heap.alloc(key), heap) {
+            Ok(v) if !v.is_none() => v
+                .unpack_str()
+                .map(|s| Some(s.to_string()))
+                .ok_or_else(|| anyhow!("Extension {} must be a string, got {}", key, v.get_type())),
+            _ => Ok(None),
+        }
+    };
+    let string_list = |key: &str| -> anyhow::Result<Option<Vec<String>>> {
+        let list = match value.at(heap.alloc(key), heap) {
+            Ok(v) if !v.is_none() => v,
+            _ => return Ok(None),
+        };
+        let mut items = Vec::new();
+        for item in list
+            .iterate(heap)
+            .map_err(|e| anyhow!("Failed to iterate {}: {}", key, e))?
+        {
+            items.push(
+                item.unpack_str()
+                    .ok_or_else(|| anyhow!("Extension {} must be a list of strings", key))?
+                    .to_string(),
+            );
+        }
+        Ok(Some(items))
     };
 
     Ok(StarlarkExtension {
@@ -328,11 +489,185 @@ pub fn extract_extension_from_value<'v>(
         description,
         tools,
         allowed_exec,
+        exec_rules,
+        allowed_hosts: string_list("allowed_hosts")?,
+        allow_private_network: false,
+        allowed_env: string_list("allowed_env")?,
+        strict_env: false,
+        config,
+        min_server_version: optional_string("min_server_version")?,
+        requires: string_list("requires")?,
+        homepage: optional_string("homepage")?,
+        authors: string_list("authors")?.unwrap_or_default(),
+        missing_config: Vec::new(),
+        unsigned: None,
+    })
+}
+
+/// Read an `ExecRule()` listed in `Extension(allowed_exec=...)`
+fn extract_exec_rule<'v>(value: Value<'v>, heap: Heap<'v>) -> anyhow::Result<ExecRule> {
+    let command = value
+        .at(heap.alloc("command"), heap)
+        .ok()
+        .and_then(|v| v.unpack_str().map(str::to_string))
+        .ok_or_else(|| anyhow!("ExecRule 'command' must be a string"))?;
+    let list = |key: &str| -> anyhow::Result<Option<Vec<Option<String>>>> {
+        let list = match value.at(heap.alloc(key), heap) {
+            Ok(v) if !v.is_none() => v,
+            _ => return Ok(None),
+        };
+        let mut items = Vec::new();
+        for item in list
+            .iterate(heap)
+            .map_err(|e| anyhow!("ExecRule '{}' {} must be a list: {}", command, key, e))?
+        {
+            if item.is_none() {
+                items.push(None);
+            } else {
+                let item = item.unpack_str().ok_or_else(|| {
+                    anyhow!("ExecRule '{}' {} must be a list of strings", command, key)
+                })?;
+                items.push(Some(item.to_string()));
+            }
+        }
+        Ok(Some(items))
+    };
+    // Only args may leave a position open with None
+    let strings = |key: &str| -> anyhow::Result<Option<Vec<String>>> {
+        list(key)?
+            .map(|items| {
+                items
+                    .into_iter()
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        anyhow!("ExecRule '{}' {} must be a list of strings", command, key)
+                    })
+            })
+            .transpose()
+    };
+
+    let subcommands = strings("subcommands")?;
+    let forbidden_flags = strings("forbidden_flags")?.unwrap_or_default();
+    let args = list("args")?.unwrap_or_default();
+    ExecRule::new(command.clone(), subcommands, forbidden_flags, &args)
+}
+
+/// Read the `ConfigVar()`s declared in `Extension(config=...)`
+fn extract_config<'v>(value: Value<'v>, heap: Heap<'v>) -> anyhow::Result<Vec<StarlarkConfigVar>> {
+    let config_value = match value.at(heap.alloc("config"), heap) {
+        Ok(v) if !v.is_none() => v,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut vars: Vec<StarlarkConfigVar> = Vec::new();
+    for var_value in config_value
+        .iterate(heap)
+        .map_err(|e| anyhow!("Config iterate error: {}", e))?
+    {
+        let field = |key: &str| {
+            var_value
+                .at(heap.alloc(key), heap)
+                .map_err(|e| anyhow!("ConfigVar '{}' error: {}", key, e))
+        };
+
+        let name = field("name")?
+            .unpack_str()
+            .ok_or_else(|| anyhow!("ConfigVar 'name' must be a string"))?
+            .to_string();
+        if vars.iter().any(|v| v.name == name) {
+            return Err(anyhow!("ConfigVar '{}' is declared more than once", name));
+        }
+
+        let default = field("default")?;
+        let default = if default.is_none() {
+            None
+        } else {
+            Some(default.to_str())
+        };
+
+        vars.push(StarlarkConfigVar {
+            required: field("required")?.unpack_bool().unwrap_or(false),
+            secret: field("secret")?.unpack_bool().unwrap_or(false),
+            default,
+            description: field("description")?.unpack_str().unwrap_or("").to_string(),
+            name,
+        });
+    }
+
+    Ok(vars)
+}
+
+/// Read the optional execution limits declared on a `Tool()`
+fn extract_limits<'v>(
+    tool_value: Value<'v>,
+    tool_name: &str,
+    heap: Heap<'v>,
+) -> anyhow::Result<ExecutionLimits> {
+    let positive_number = |key: &str| -> anyhow::Result<Option<f64>> {
+        let value = match tool_value.at(heap.alloc(key), heap) {
+            Ok(v) if !v.is_none() => v,
+            _ => return Ok(None),
+        };
+        let number = if let Some(i) = value.unpack_i32() {
+            i as f64
+        } else if value.get_type() == "float" {
+            value.to_str().parse::<f64>().unwrap_or(f64::NAN)
+        } else {
+            return Err(anyhow!(
+                "Tool '{}' {} must be a number, got {}",
+                tool_name,
+                key,
+                value.get_type()
+            ));
+        };
+        if number.is_nan() || number <= 0.0 {
+            return Err(anyhow!(
+                "Tool '{}' {} must be positive, got {}",
+                tool_name,
+                key,
+                value
+            ));
+        }
+        Ok(Some(number))
+    };
+
+    Ok(ExecutionLimits {
+        timeout: positive_number("timeout_secs")?.map(std::time::Duration::from_secs_f64),
+        max_steps: positive_number("max_steps")?.map(|n| n as u64),
+        max_heap_bytes: positive_number("max_heap_mb")?.map(|n| (n * 1024.0 * 1024.0) as usize),
     })
 }
 
 impl StarlarkExtension {
+    /// Permissions granted to this extension's handlers
+    pub fn capabilities(&self) -> Capabilities {
+        let capabilities = Capabilities {
+            allowed_exec: self.allowed_exec.clone(),
+            exec_rules: self.exec_rules.clone(),
+            modules: self.requires.clone(),
+            allowed_hosts: self.allowed_hosts.clone(),
+            allow_private_network: self.allow_private_network,
+            allowed_env: self.allowed_env.clone(),
+            strict_env: self.strict_env,
+        };
+        if self.unsigned.is_some() {
+            capabilities.restricted()
+        } else {
+            capabilities
+        }
+    }
+
+    /// Whether every required config variable has a value
+    pub fn is_configured(&self) -> bool {
+        self.missing_config.is_empty()
+    }
+
+    /// Tools to advertise to clients; none while required config is missing
     pub fn to_mcp_tools(&self) -> Vec<Tool> {
+        if !self.is_configured() {
+            return Vec::new();
+        }
+
         self.tools
             .iter()
             .map(|t| {
@@ -1,12 +1,28 @@
+pub mod backtrace;
+pub mod cache;
+pub mod config;
+pub mod context;
 pub mod data;
+pub mod diagnostics;
 pub mod engine;
+pub mod exec_rules;
 pub mod fuzzy;
 pub mod http;
+pub mod kv;
+pub mod limits;
+pub mod lint;
+pub mod manifest;
 pub mod math;
 pub mod mcp_types;
+pub mod module_loader;
 pub mod modules;
+pub mod network;
 pub mod postgres;
 pub mod sqlite;
+pub mod state;
+pub mod typecheck;
 
+pub use context::{CallContext, Capabilities};
 pub use engine::{StarlarkEngine, ToolExecutor};
 pub use mcp_types::StarlarkExtension;
+pub use module_loader::{ModuleCache, ModuleLoader};
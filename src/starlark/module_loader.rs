@@ -0,0 +1,684 @@
+use anyhow::{Result, anyhow};
+use starlark::environment::{FrozenModule, Globals, Module};
+use starlark::eval::{Evaluator, FileLoader};
+use starlark::syntax::{AstModule, Dialect};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
+use tracing::{debug, info};
+
+use super::context::CallContext;
+use super::typecheck::TypeChecker;
+use crate::lockfile::sha256;
+
+/// Cache of frozen modules produced by `load()`, keyed by canonical file path
+/// and [`ModuleScope`], holding the SHA-256 of the source each was evaluated
+/// from.
+///
+/// Shared helpers under `lib/` are evaluated once for each extension that
+/// loads them and reused until the entry is invalidated. The cache also
+/// records which files each module loads, so a change can be traced to
+/// everything built on it.
+#[derive(Default)]
+pub struct ModuleCache {
+    modules: Mutex<HashMap<(PathBuf, ModuleScope), (String, FrozenModule)>>,
+    dependencies: Mutex<DependencyGraph>,
+}
+
+/// Who a module was evaluated for. A module's top-level code runs with the
+/// loading extension's context, so whatever it captured (config values, kv,
+/// state, cache entries) belongs to that extension and is never handed to
+/// another. A cache hit only verifies the requested file, so a module is also
+/// never shared between loaders that check signatures and loaders that don't:
+/// the files it loaded in turn were verified the same way it was.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ModuleScope {
+    /// The extension whose context the module was evaluated in, if any
+    pub extension: Option<String>,
+    /// Whether signatures were checked
+    pub signed: bool,
+}
+
+impl ModuleScope {
+    pub fn of(context: Option<&CallContext>) -> Self {
+        Self {
+            extension: context.map(|context| context.extension.clone()),
+            signed: context.is_some_and(|context| context.requires_signed_modules()),
+        }
+    }
+}
+
+/// Direct `load()` edges, from the loading file to the loaded file
+pub type DependencyGraph = HashMap<PathBuf, HashSet<PathBuf>>;
+
+impl ModuleCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The module cached for `path` in `scope`, if it was evaluated from
+    /// source with SHA-256 `digest`
+    pub fn get(&self, path: &Path, digest: &str, scope: &ModuleScope) -> Option<FrozenModule> {
+        self.modules
+            .lock()
+            .unwrap()
+            .get(&(path.to_path_buf(), scope.clone()))
+            .filter(|(cached, _)| cached == digest)
+            .map(|(_, module)| module.clone())
+    }
+
+    pub fn insert(&self, path: PathBuf, digest: String, scope: ModuleScope, module: FrozenModule) {
+        self.modules
+            .lock()
+            .unwrap()
+            .insert((path, scope), (digest, module));
+    }
+
+    /// Drop a cached module so the next `load()` re-evaluates it from disk
+    pub fn invalidate(&self, path: &Path) -> bool {
+        let mut modules = self.modules.lock().unwrap();
+        let before = modules.len();
+        modules.retain(|(cached, _), _| cached != path);
+        modules.len() != before
+    }
+
+    /// Drop every cached module beneath `dir`
+    pub fn invalidate_under(&self, dir: &Path) {
+        self.modules
+            .lock()
+            .unwrap()
+            .retain(|(path, _), _| !path.starts_with(dir));
+    }
+
+    pub fn clear(&self) {
+        self.modules.lock().unwrap().clear();
+        self.dependencies.lock().unwrap().clear();
+    }
+
+    pub fn record_dependency(&self, from: &Path, to: &Path) {
+        self.dependencies
+            .lock()
+            .unwrap()
+            .entry(from.to_path_buf())
+            .or_default()
+            .insert(to.to_path_buf());
+    }
+
+    /// Forget the loads of `from`, before it is re-evaluated or removed
+    pub fn clear_dependencies(&self, from: &Path) {
+        self.dependencies.lock().unwrap().remove(from);
+    }
+
+    /// Files loaded directly by `from`
+    pub fn dependencies(&self, from: &Path) -> Vec<PathBuf> {
+        let graph = self.dependencies.lock().unwrap();
+        let mut deps: Vec<PathBuf> = graph
+            .get(from)
+            .map(|d| d.iter().cloned().collect())
+            .unwrap_or_default();
+        deps.sort();
+        deps
+    }
+
+    /// Every file that transitively loads `changed`, plus `changed` itself,
+    /// in topological order: each file comes after the files it loads.
+    pub fn dependents_in_order(&self, changed: &Path) -> Vec<PathBuf> {
+        let graph = self.dependencies.lock().unwrap();
+
+        let mut affected = BTreeSet::new();
+        affected.insert(changed.to_path_buf());
+        let mut queue = vec![changed.to_path_buf()];
+        while let Some(current) = queue.pop() {
+            for (from, deps) in graph.iter() {
+                if deps.contains(&current) && affected.insert(from.clone()) {
+                    queue.push(from.clone());
+                }
+            }
+        }
+
+        // Kahn's algorithm restricted to the affected set; BTreeSet keeps it deterministic
+        let mut remaining: HashMap<&PathBuf, usize> = affected
+            .iter()
+            .map(|p| {
+                let count = graph
+                    .get(p)
+                    .map(|deps| deps.iter().filter(|d| affected.contains(*d)).count())
+                    .unwrap_or(0);
+                (p, count)
+            })
+            .collect();
+
+        let mut ready: BTreeSet<&PathBuf> = remaining
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(p, _)| *p)
+            .collect();
+        let mut order = Vec::with_capacity(affected.len());
+
+        while let Some(next) = ready.pop_first() {
+            remaining.remove(next);
+            order.push(next.clone());
+            for (p, count) in remaining.iter_mut() {
+                if graph.get(*p).is_some_and(|deps| deps.contains(next)) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.insert(p);
+                    }
+                }
+            }
+        }
+
+        order
+    }
+
+    /// Capture the cache state so a failed reload can be rolled back
+    pub fn snapshot(&self) -> ModuleCacheSnapshot {
+        ModuleCacheSnapshot {
+            modules: self.modules.lock().unwrap().clone(),
+            dependencies: self.dependencies.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn restore(&self, snapshot: ModuleCacheSnapshot) {
+        *self.modules.lock().unwrap() = snapshot.modules;
+        *self.dependencies.lock().unwrap() = snapshot.dependencies;
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct ModuleCacheSnapshot {
+    modules: HashMap<(PathBuf, ModuleScope), (String, FrozenModule)>,
+    dependencies: DependencyGraph,
+}
+
+/// Resolves `load()` statements against the extensions directory.
+///
+/// Supported forms:
+/// * `load("//lib/k8s.star", ...)` - anchored at the extensions root
+/// * `load("lib/k8s.star", ...)` / `load("../common.star", ...)` - relative to the loading file
+/// * `load("cat_facts", ...)` - the `.star` suffix is optional
+///
+/// Resolved paths must stay inside the extensions root.
+pub struct ModuleLoader<'a> {
+    root: PathBuf,
+    current_dir: PathBuf,
+    globals: &'a Globals,
+    cache: &'a ModuleCache,
+    dialect: Dialect,
+    /// Typechecks each loaded module before it is evaluated
+    typechecker: Option<&'a TypeChecker>,
+    /// Context for builtins called at the top level of loaded modules
+    context: Option<&'a CallContext>,
+    /// Files currently being evaluated, outermost first, used for cycle detection
+    stack: Rc<RefCell<Vec<PathBuf>>>,
+}
+
+impl<'a> ModuleLoader<'a> {
+    pub fn new(root: &Path, globals: &'a Globals, cache: &'a ModuleCache) -> Result<Self> {
+        let root = root.canonicalize().map_err(|e| {
+            anyhow!(
+                "Failed to resolve extensions directory '{}': {}",
+                root.display(),
+                e
+            )
+        })?;
+
+        Ok(Self {
+            current_dir: root.clone(),
+            root,
+            globals,
+            cache,
+            dialect: Dialect::Standard,
+            typechecker: None,
+            context: None,
+            stack: Rc::new(RefCell::new(Vec::new())),
+        })
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Typecheck each module before evaluating it; requires [`Dialect::Extended`]
+    pub fn with_typechecker(mut self, typechecker: Option<&'a TypeChecker>) -> Self {
+        self.typechecker = typechecker;
+        self
+    }
+
+    pub fn with_context(mut self, context: &'a CallContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Resolve relative loads from the directory containing `file` and treat
+    /// `file` itself as the start of the load chain. The file's previously
+    /// recorded dependencies are replaced by the loads seen during this evaluation.
+    pub fn for_file(mut self, file: &Path) -> Self {
+        if let Ok(canonical) = file.canonicalize() {
+            if let Some(parent) = canonical.parent() {
+                self.current_dir = parent.to_path_buf();
+            }
+            self.cache.clear_dependencies(&canonical);
+            self.stack.borrow_mut().push(canonical);
+        }
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn child(&self, dir: PathBuf) -> ModuleLoader<'a> {
+        ModuleLoader {
+            root: self.root.clone(),
+            current_dir: dir,
+            globals: self.globals,
+            cache: self.cache,
+            dialect: self.dialect.clone(),
+            typechecker: self.typechecker,
+            context: self.context,
+            stack: self.stack.clone(),
+        }
+    }
+
+    /// Map a `load()` path to a canonical file inside the extensions root
+    pub fn resolve(&self, load_path: &str) -> Result<PathBuf> {
+        let (base, relative) = match load_path.strip_prefix("//") {
+            Some(rest) => (&self.root, rest),
+            None => (&self.current_dir, load_path),
+        };
+
+        if relative.is_empty() || Path::new(relative).is_absolute() {
+            return Err(anyhow!("Invalid load path: '{}'", load_path));
+        }
+
+        let mut candidate = base.join(relative);
+        if candidate.extension().is_none() {
+            candidate.set_extension("star");
+        }
+
+        let canonical = candidate
+            .canonicalize()
+            .map_err(|e| anyhow!("Cannot load '{}': {}", load_path, e))?;
+
+        if !canonical.starts_with(&self.root) {
+            return Err(anyhow!(
+                "Cannot load '{}': path is outside the extensions directory",
+                load_path
+            ));
+        }
+
+        Ok(canonical)
+    }
+
+    /// Display name used for diagnostics, relative to the extensions root
+    fn display_name(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    fn check_cycle(&self, path: &Path) -> Result<()> {
+        let stack = self.stack.borrow();
+        if let Some(start) = stack.iter().position(|p| p == path) {
+            let chain: Vec<String> = stack[start..]
+                .iter()
+                .chain(std::iter::once(&path.to_path_buf()))
+                .map(|p| self.display_name(p))
+                .collect();
+            return Err(anyhow!("Cyclic load detected: {}", chain.join(" -> ")));
+        }
+        Ok(())
+    }
+
+    /// Evaluate `content`, the already verified source of `path`
+    fn eval_file(&self, path: &Path, content: Vec<u8>) -> Result<FrozenModule> {
+        let name = self.display_name(path);
+        let content =
+            String::from_utf8(content).map_err(|e| anyhow!("Failed to read '{}': {}", name, e))?;
+        let ast = AstModule::parse(&name, content.clone(), &self.dialect)
+            .map_err(|e| anyhow!("Parse error: {}", e))?;
+        if let Some(typechecker) = self.typechecker {
+            let ast = AstModule::parse(&name, content, &self.dialect)
+                .map_err(|e| anyhow!("Parse error: {}", e))?;
+            typechecker.check(ast)?;
+        }
+
+        let dir = path.parent().unwrap_or(&self.root).to_path_buf();
+        let loader = self.child(dir);
+
+        Module::with_temp_heap(|module| {
+            {
+                let mut eval = Evaluator::new(&module);
+                eval.set_loader(&loader);
+                if let Some(context) = self.context {
+                    context.attach(&mut eval);
+                }
+                eval.eval_module(ast, self.globals)
+                    .map_err(|e| anyhow!("Eval error: {}", e))?;
+            }
+
+            module
+                .freeze()
+                .map_err(|e| anyhow!("Freeze error: {:?}", e))
+        })
+    }
+
+    /// Resolve, verify and evaluate `load_path`, or take it from the cache
+    fn load_module(&self, load_path: &str) -> Result<FrozenModule> {
+        let path = self.resolve(load_path)?;
+        self.check_cycle(&path)?;
+
+        if let Some(requester) = self.stack.borrow().last() {
+            self.cache.record_dependency(requester, &path);
+        }
+
+        // Read once: the bytes verified are the bytes evaluated, and the
+        // cache only returns a module evaluated from the same bytes
+        let content = std::fs::read(&path).map_err(|e| {
+            anyhow!(
+                "Failed to load '{}': Failed to read '{}': {}",
+                load_path,
+                self.display_name(&path),
+                e
+            )
+        })?;
+        // Checked before the cache, which may hold a module first loaded by
+        // an unsigned extension
+        if let Some(context) = self.context {
+            context
+                .verify_locked(&path, &content)
+                .and_then(|_| context.verify_module_signature(&path, &content))
+                .map_err(|e| anyhow!("Failed to load '{}': {}", load_path, e))?;
+        }
+
+        let digest = sha256(&content);
+        let scope = ModuleScope::of(self.context);
+        if let Some(module) = self.cache.get(&path, &digest, &scope) {
+            debug!("Using cached module: {}", path.display());
+            return Ok(module);
+        }
+
+        self.cache.clear_dependencies(&path);
+        self.stack.borrow_mut().push(path.clone());
+        let result = self.eval_file(&path, content);
+        self.stack.borrow_mut().pop();
+
+        let module = result.map_err(|e| anyhow!("Failed to load '{}': {}", load_path, e))?;
+        info!("Loaded module: {}", self.display_name(&path));
+        self.cache.insert(path, digest, scope, module.clone());
+        Ok(module)
+    }
+}
+
+impl FileLoader for ModuleLoader<'_> {
+    fn load(&self, load_path: &str) -> starlark::Result<FrozenModule> {
+        Ok(self.load_module(load_path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::starlark::modules::build_globals;
+    use tempfile::TempDir;
+
+    fn write(dir: &Path, name: &str, content: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn eval_with_loader(
+        root: &Path,
+        file: &Path,
+        cache: &ModuleCache,
+        code: &str,
+    ) -> Result<String> {
+        let globals = build_globals();
+        let loader = ModuleLoader::new(root, &globals, cache)?.for_file(file);
+        let ast = AstModule::parse("main.star", code.to_owned(), &Dialect::Standard)
+            .map_err(|e| anyhow!("{}", e))?;
+        Module::with_temp_heap(|module| {
+            let mut eval = Evaluator::new(&module);
+            eval.set_loader(&loader);
+            let value = eval
+                .eval_module(ast, &globals)
+                .map_err(|e| anyhow!("{}", e))?;
+            Ok(value.to_str())
+        })
+    }
+
+    #[test]
+    fn test_load_root_anchored_and_relative() {
+        let temp = TempDir::new().unwrap();
+        write(
+            temp.path(),
+            "lib/common.star",
+            "def greet(n):\n    return 'hi ' + n\n",
+        );
+        write(
+            temp.path(),
+            "lib/wrap.star",
+            "load(\"common.star\", \"greet\")\ndef wrap(n):\n    return '[' + greet(n) + ']'\n",
+        );
+        let main = write(temp.path(), "main.star", "");
+        let cache = ModuleCache::new();
+
+        let result = eval_with_loader(
+            temp.path(),
+            &main,
+            &cache,
+            "load(\"//lib/wrap.star\", \"wrap\")\nload(\"lib/common\", \"greet\")\nwrap(\"a\") + greet(\"b\")",
+        )
+        .unwrap();
+
+        assert_eq!(result, "[hi a]hi b");
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_load_detects_cycles() {
+        let temp = TempDir::new().unwrap();
+        write(
+            temp.path(),
+            "lib/a.star",
+            "load(\"b.star\", \"b\")\na = 1\n",
+        );
+        write(
+            temp.path(),
+            "lib/b.star",
+            "load(\"a.star\", \"a\")\nb = 2\n",
+        );
+        let main = write(temp.path(), "main.star", "");
+        let cache = ModuleCache::new();
+
+        let err = eval_with_loader(temp.path(), &main, &cache, "load(\"//lib/a.star\", \"a\")")
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("Cyclic load detected"), "{}", err);
+        assert!(
+            err.contains("lib/a.star -> lib/b.star -> lib/a.star"),
+            "{}",
+            err
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_load_rejects_paths_outside_root() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path().join("extensions");
+        write(temp.path(), "secret.star", "x = 1\n");
+        let main = write(&root, "main.star", "");
+        let cache = ModuleCache::new();
+
+        let err = eval_with_loader(&root, &main, &cache, "load(\"../secret.star\", \"x\")")
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("outside the extensions directory"), "{}", err);
+    }
+
+    #[test]
+    fn test_dependents_in_order() {
+        let temp = TempDir::new().unwrap();
+        write(temp.path(), "lib/base.star", "base = 1\n");
+        write(
+            temp.path(),
+            "lib/mid.star",
+            "load(\"base.star\", \"base\")\nmid = base + 1\n",
+        );
+        let main = write(temp.path(), "main.star", "");
+        let cache = ModuleCache::new();
+
+        eval_with_loader(
+            temp.path(),
+            &main,
+            &cache,
+            "load(\"//lib/mid.star\", \"mid\")\nload(\"//lib/base.star\", \"base\")\nmid + base",
+        )
+        .unwrap();
+
+        let base = temp.path().join("lib/base.star").canonicalize().unwrap();
+        let mid = temp.path().join("lib/mid.star").canonicalize().unwrap();
+        let main = main.canonicalize().unwrap();
+
+        assert_eq!(
+            cache.dependents_in_order(&base),
+            vec![base.clone(), mid.clone(), main.clone()]
+        );
+        assert_eq!(
+            cache.dependents_in_order(&mid),
+            vec![mid.clone(), main.clone()]
+        );
+        assert_eq!(cache.dependents_in_order(&main), vec![main.clone()]);
+        assert_eq!(cache.dependencies(&main), vec![base, mid]);
+    }
+
+    #[test]
+    fn test_load_uses_cache() {
+        let temp = TempDir::new().unwrap();
+        let lib = write(temp.path(), "lib/value.star", "value = 1\n");
+        let main = write(temp.path(), "main.star", "");
+        let cache = ModuleCache::new();
+        let code = "load(\"//lib/value.star\", \"value\")\nvalue";
+
+        assert_eq!(
+            eval_with_loader(temp.path(), &main, &cache, code).unwrap(),
+            "1"
+        );
+
+        let lib = lib.canonicalize().unwrap();
+        let digest = sha256(b"value = 1\n");
+        assert!(cache.get(&lib, &digest, &ModuleScope::default()).is_some());
+        assert_eq!(
+            eval_with_loader(temp.path(), &main, &cache, code).unwrap(),
+            "1"
+        );
+
+        // The cache never serves a module evaluated from other bytes than
+        // the ones just read and verified
+        std::fs::write(&lib, "value = 2\n").unwrap();
+        assert_eq!(
+            eval_with_loader(temp.path(), &main, &cache, code).unwrap(),
+            "2"
+        );
+        assert!(cache.get(&lib, &digest, &ModuleScope::default()).is_none());
+
+        assert!(cache.invalidate(&lib));
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_cache_is_not_shared_between_extensions() {
+        use crate::starlark::config::ExtensionConfig;
+        use crate::starlark::mcp_types::StarlarkConfigVar;
+
+        let temp = TempDir::new().unwrap();
+        write(
+            temp.path(),
+            "lib/region.star",
+            "region = config.get(\"REGION\")\n",
+        );
+        let main = write(temp.path(), "main.star", "");
+
+        let globals = build_globals();
+        let cache = ModuleCache::new();
+        let vars = [StarlarkConfigVar {
+            name: "REGION".to_string(),
+            required: true,
+            secret: false,
+            default: None,
+            description: String::new(),
+        }];
+        let load = |extension: &str, region: &str| {
+            let config = ExtensionConfig::resolve(&vars, |_| Some(region.to_string()));
+            let context = CallContext::new(extension).with_config(config);
+            let module = ModuleLoader::new(temp.path(), &globals, &cache)
+                .unwrap()
+                .with_context(&context)
+                .for_file(&main)
+                .load_module("//lib/region.star")
+                .unwrap();
+            module.get("region").unwrap().value().to_str()
+        };
+
+        // Each extension sees the module evaluated with its own config
+        assert_eq!(load("weather", "eu-west-1"), "eu-west-1");
+        assert_eq!(load("billing", "us-east-1"), "us-east-1");
+        assert_eq!(load("weather", "eu-west-1"), "eu-west-1");
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_cache_is_not_shared_with_unsigned_loaders() {
+        use crate::signing::{
+            SignatureVerifier, UnsignedPolicy, generate_key, public_key, sign_file,
+        };
+        use std::sync::Arc;
+
+        let temp = TempDir::new().unwrap();
+        let key = generate_key(&temp.path().join("signing.key")).unwrap();
+        let root = temp.path().join("extensions");
+        let outer = write(&root, "lib/outer.star", "load(\"inner.star\", \"value\")\n");
+        write(&root, "lib/inner.star", "value = 1\n");
+        sign_file(&key, &outer).unwrap();
+        let main = write(&root, "main.star", "");
+        let signatures = Arc::new(
+            SignatureVerifier::new(&[public_key(&key)], UnsignedPolicy::Restrict).unwrap(),
+        );
+
+        let globals = build_globals();
+        let cache = ModuleCache::new();
+        let load = |unsigned: bool| {
+            let context =
+                CallContext::new("main").with_signatures(Some(signatures.clone()), unsigned);
+            ModuleLoader::new(&root, &globals, &cache)
+                .unwrap()
+                .with_context(&context)
+                .for_file(&main)
+                .load_module("//lib/outer.star")
+                .map(|_| ())
+        };
+
+        // An unsigned extension may load the unsigned inner module...
+        load(true).unwrap();
+        // ...but a signed one must not get it through the cached outer module
+        let err = load(false).unwrap_err();
+        assert!(format!("{:#}", err).contains("inner.star"), "{:#}", err);
+    }
+}
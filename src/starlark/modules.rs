@@ -4,31 +4,45 @@ use starlark::collections::SmallMap;
 use starlark::environment::{
     Globals, GlobalsBuilder, LibraryExtension, Methods, MethodsBuilder, MethodsStatic,
 };
+use starlark::eval::Evaluator;
 use starlark::starlark_module;
 use starlark::starlark_simple_value;
 use starlark::values::starlark_value;
 use starlark::values::{
-    Heap, NoSerialize, ProvidesStaticType, StarlarkValue, Value, dict::Dict, none::NoneType,
+    NoSerialize, ProvidesStaticType, StarlarkValue, Value, dict::Dict, none::NoneType,
 };
 use std::process::Command;
 
+use super::cache;
+use super::config;
 use super::data;
 use super::fuzzy;
 use super::http;
+use super::kv;
 use super::math;
 use super::mcp_types::mcp_globals;
 use super::postgres;
 use super::sqlite;
+use super::state;
 
-pub use exec::{clear_exec_whitelist, set_exec_whitelist};
-
-pub use data::{clear_extensions_dir, set_extensions_dir};
+/// Builtin modules an extension can name in `Extension(requires=...)`
+pub const BUILTIN_MODULES: &[&str] = &[
+    "cache", "config", "data", "env", "exec", "fuzzy", "http", "json", "kv", "math", "postgres",
+    "sqlite", "state", "time",
+];
 
 pub fn build_globals() -> Globals {
+    globals_builder().build()
+}
+
+/// The builder behind [`build_globals`], for callers that replace some of
+/// the builtins
+pub fn globals_builder() -> GlobalsBuilder {
     GlobalsBuilder::extended_by(&[
         LibraryExtension::StructType,
         LibraryExtension::Json,
         LibraryExtension::Debug,
+        LibraryExtension::Typing,
     ])
     .with(mcp_globals)
     .with(math::register)
@@ -40,7 +54,10 @@ pub fn build_globals() -> Globals {
     .with(sqlite::register)
     .with(data::register)
     .with(fuzzy::register)
-    .build()
+    .with(state::register)
+    .with(kv::register)
+    .with(cache::register)
+    .with(config::register)
 }
 
 pub(crate) mod time {
@@ -55,8 +72,8 @@ pub(crate) mod time {
     #[starlark_value(type = "time")]
     impl<'v> StarlarkValue<'v> for TimeModule {
         fn get_methods() -> Option<&'static Methods> {
-            static RES: MethodsStatic = MethodsStatic::new();
-            RES.methods(time_methods)
+            static RES: MethodsStatic = MethodsStatic::new("time", time_methods);
+            Some(RES.methods())
         }
     }
 
@@ -79,6 +96,7 @@ pub(crate) mod time {
 
 pub(crate) mod env {
     use super::*;
+    use crate::starlark::context::CallContext;
 
     #[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
     #[display(fmt = "env")]
@@ -89,19 +107,40 @@ pub(crate) mod env {
     #[starlark_value(type = "env")]
     impl<'v> StarlarkValue<'v> for EnvModule {
         fn get_methods() -> Option<&'static Methods> {
-            static RES: MethodsStatic = MethodsStatic::new();
-            RES.methods(env_methods)
+            static RES: MethodsStatic = MethodsStatic::new("env", env_methods);
+            Some(RES.methods())
         }
     }
 
     #[starlark_module]
     fn env_methods(builder: &mut MethodsBuilder) {
-        fn get(
-            #[allow(unused_variables)] this: Value,
+        /// Get an environment variable, preferring values the server config
+        /// injects for this extension.
+        ///
+        /// Variables outside the extension's `allowed_env` read as `default`,
+        /// or fail when the server runs with `strict_env`.
+        fn get<'v>(
+            #[allow(unused_variables)] this: Value<'v>,
             name: &str,
             #[starlark(default = "")] default: &str,
+            eval: &mut Evaluator<'v, '_, '_>,
         ) -> anyhow::Result<String> {
-            Ok(std::env::var(name).unwrap_or_else(|_| default.to_string()))
+            let value = match CallContext::get(eval) {
+                Some(ctx) if !ctx.capabilities.allows_env(name) && !ctx.config.injects(name) => {
+                    let message = format!(
+                        "Extension '{}' cannot read environment variable '{}': add it to allowed_env in its Extension()",
+                        ctx.extension, name
+                    );
+                    if ctx.capabilities.strict_env {
+                        return Err(anyhow::anyhow!(message));
+                    }
+                    tracing::warn!("{}", message);
+                    None
+                }
+                Some(ctx) => ctx.config.env_var(name),
+                None => std::env::var(name).ok(),
+            };
+            Ok(value.unwrap_or_else(|| default.to_string()))
         }
     }
 
@@ -113,32 +152,10 @@ pub(crate) mod env {
 
 pub(crate) mod exec {
     use super::*;
-    use std::cell::RefCell;
-
-    thread_local! {
-        /// Thread-local storage for the exec whitelist
-        /// Set by the tool executor before calling tool handler functions
-        static EXEC_WHITELIST: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
-    }
-
-    /// Set the exec whitelist for the current thread
-    pub fn set_exec_whitelist(whitelist: Vec<String>) {
-        EXEC_WHITELIST.with(|w| {
-            *w.borrow_mut() = whitelist;
-        });
-    }
-
-    /// Clear the exec whitelist for the current thread
-    pub fn clear_exec_whitelist() {
-        EXEC_WHITELIST.with(|w| {
-            w.borrow_mut().clear();
-        });
-    }
-
-    /// Get a copy of the current exec whitelist
-    fn get_exec_whitelist() -> Vec<String> {
-        EXEC_WHITELIST.with(|w| w.borrow().clone())
-    }
+    use crate::starlark::context::CallContext;
+    use std::io::{self, Read};
+    use std::process::{Output, Stdio};
+    use std::time::{Duration, Instant};
 
     #[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
     #[display(fmt = "exec")]
@@ -149,8 +166,8 @@ pub(crate) mod exec {
     #[starlark_value(type = "exec")]
     impl<'v> StarlarkValue<'v> for ExecModule {
         fn get_methods() -> Option<&'static Methods> {
-            static RES: MethodsStatic = MethodsStatic::new();
-            RES.methods(exec_methods)
+            static RES: MethodsStatic = MethodsStatic::new("exec", exec_methods);
+            Some(RES.methods())
         }
     }
 
@@ -162,8 +179,10 @@ pub(crate) mod exec {
             #[allow(unused_variables)] this: Value<'v>,
             command: String,
             #[starlark(default = NoneType)] args: Value<'v>,
-            heap: &'v Heap,
+            eval: &mut Evaluator<'v, '_, '_>,
         ) -> anyhow::Result<Value<'v>> {
+            CallContext::check_module(eval, "exec")?;
+            let heap = eval.heap();
             let arg_vec = if args.is_none() {
                 Vec::new()
             } else {
@@ -177,7 +196,10 @@ pub(crate) mod exec {
                 vec
             };
 
-            let whitelist = get_exec_whitelist();
+            let capabilities = CallContext::get(eval).map(|ctx| &ctx.capabilities);
+            let whitelist = capabilities
+                .map(|c| c.allowed_exec.as_slice())
+                .unwrap_or_default();
             if whitelist.is_empty() {
                 return Err(anyhow::anyhow!(
                     "Command '{}' cannot be executed: no exec whitelist configured for this extension. Add allowed_exec=['{}'] to the Extension definition.",
@@ -194,9 +216,27 @@ pub(crate) mod exec {
                 ));
             }
 
-            let output = Command::new(&command)
-                .args(&arg_vec)
-                .output()
+            // Extensions are refused at load if they give a command more
+            // than one rule
+            let rule =
+                capabilities.and_then(|c| c.exec_rules.iter().find(|rule| rule.command == command));
+            if let Some(rule) = rule {
+                rule.check(&arg_vec).map_err(|e| {
+                    anyhow::anyhow!(
+                        "Command '{}' was rejected by its ExecRule: {}",
+                        std::iter::once(command.as_str())
+                            .chain(arg_vec.iter().map(String::as_str))
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        e
+                    )
+                })?;
+            }
+
+            let deadline = CallContext::get(eval).and_then(|ctx| ctx.deadline);
+            let mut child = Command::new(&command);
+            child.args(&arg_vec);
+            let output = output_before(&mut child, deadline)
                 .map_err(|e| anyhow::anyhow!("Failed to execute command '{}': {}", command, e))?;
 
             let mut map = SmallMap::new();
@@ -226,6 +266,53 @@ pub(crate) mod exec {
         }
     }
 
+    /// Run `command` to completion like [`Command::output`], killing it if it
+    /// is still running at `deadline`
+    fn output_before(command: &mut Command, deadline: Option<Instant>) -> io::Result<Output> {
+        let Some(deadline) = deadline else {
+            return command.output();
+        };
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = read_in_background(child.stdout.take());
+        let stderr = read_in_background(child.stderr.take());
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the tool call ran out of time",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+
+    /// Drain a child's pipe on its own thread so a full pipe can't stall it
+    fn read_in_background(
+        pipe: Option<impl Read + Send + 'static>,
+    ) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    }
+
     pub fn register(builder: &mut GlobalsBuilder) {
         const EXEC: ExecModule = ExecModule;
         builder.set("exec", EXEC);
@@ -0,0 +1,295 @@
+use anyhow::{Result, anyhow};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use url::{Host, Url};
+
+use super::context::Capabilities;
+
+/// Fail unless `capabilities` let a request reach `url`: its host must match
+/// `allowed_hosts`, if the extension declared any, and must not resolve to a
+/// private or link-local address unless private networks are allowed.
+///
+/// Returns the addresses a host name was vetted against, which the request
+/// must connect to instead of resolving the name again, or nothing when
+/// there is no name to pin. The lookup is abandoned once `deadline` passes
+/// or `cancellation` is cancelled.
+pub fn check_url(
+    url: &Url,
+    capabilities: &Capabilities,
+    deadline: Option<Instant>,
+    cancellation: Option<&CancellationToken>,
+) -> Result<Vec<SocketAddr>> {
+    let host = url
+        .host()
+        .ok_or_else(|| anyhow!("URL has no host: {}", url))?;
+    let name = match host {
+        Host::Domain(domain) => domain.to_ascii_lowercase(),
+        Host::Ipv4(ip) => ip.to_string(),
+        Host::Ipv6(ip) => ip.to_string(),
+    };
+
+    if let Some(ref allowed) = capabilities.allowed_hosts
+        && !allowed.iter().any(|pattern| host_matches(pattern, &name))
+    {
+        return Err(anyhow!(
+            "Host '{}' is not allowed: add it to allowed_hosts in the Extension definition",
+            name
+        ));
+    }
+
+    if capabilities.allow_private_network {
+        return Ok(Vec::new());
+    }
+    let (addresses, pinned) = match host {
+        Host::Ipv4(ip) => (vec![IpAddr::V4(ip)], Vec::new()),
+        Host::Ipv6(ip) => (vec![IpAddr::V6(ip)], Vec::new()),
+        Host::Domain(domain) => {
+            let port = url.port_or_known_default().unwrap_or(80);
+            let pinned = resolve(domain, port, deadline, cancellation)
+                .map_err(|e| anyhow!("Failed to resolve host '{}': {}", name, e))?;
+            if pinned.is_empty() {
+                return Err(anyhow!("Failed to resolve host '{}'", name));
+            }
+            (pinned.iter().map(|addr| addr.ip()).collect(), pinned)
+        }
+    };
+    if let Some(ip) = addresses.into_iter().find(|ip| is_private(*ip)) {
+        return Err(anyhow!(
+            "Host '{}' resolves to private address {}: set allow_private_network in the server config to reach it",
+            name,
+            ip
+        ));
+    }
+    Ok(pinned)
+}
+
+/// How often a pending lookup checks whether its call was cancelled
+const RESOLVE_POLL: Duration = Duration::from_millis(50);
+
+/// Resolve `domain` on its own thread, so the caller can stop waiting for a
+/// slow lookup when its tool call runs out of time or is cancelled. An
+/// abandoned lookup finishes in the background.
+fn resolve(
+    domain: &str,
+    port: u16,
+    deadline: Option<Instant>,
+    cancellation: Option<&CancellationToken>,
+) -> Result<Vec<SocketAddr>> {
+    let (sender, receiver) = mpsc::channel();
+    let target = (domain.to_string(), port);
+    std::thread::spawn(move || {
+        let result = target
+            .to_socket_addrs()
+            .map(|addresses| addresses.collect::<Vec<_>>());
+        let _ = sender.send(result);
+    });
+
+    loop {
+        if cancellation.is_some_and(|token| token.is_cancelled()) {
+            return Err(anyhow!("the tool call was cancelled"));
+        }
+        let wait = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(anyhow!("the tool call ran out of time"));
+                }
+                remaining.min(RESOLVE_POLL)
+            }
+            None => RESOLVE_POLL,
+        };
+        match receiver.recv_timeout(wait) {
+            Ok(result) => return Ok(result?),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("the lookup failed")),
+        }
+    }
+}
+
+/// Whether `host` matches an `allowed_hosts` entry: either the exact host or,
+/// for `*.example.com`, any subdomain of `example.com`
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.trim().trim_end_matches('.').to_ascii_lowercase();
+    let host = host.trim_end_matches('.');
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.')),
+        None => host == pattern,
+    }
+}
+
+/// Loopback, private, link-local, shared (CGNAT), multicast and unspecified
+/// addresses, which reach the server's own machine or network rather than
+/// the internet. IPv6 addresses that embed an IPv4 address are judged by it.
+pub fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(mapped) => is_private_v4(mapped),
+            None => is_private_v6(ip),
+        },
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // 100.64.0.0/10, shared address space
+        || (a == 100 && (64..128).contains(&b))
+        // 0.0.0.0/8, "this network"
+        || a == 0
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    if let Some(embedded) = embedded_v4(ip) {
+        return is_private_v4(embedded);
+    }
+    let segments = ip.segments();
+    let first = segments[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // fc00::/7, unique local
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10, link-local
+        || (first & 0xffc0) == 0xfe80
+        // 64:ff9b:1::/48, local-use NAT64
+        || segments[..3] == [0x64, 0xff9b, 1]
+}
+
+/// The IPv4 address a translated or tunnelled IPv6 address leads to: NAT64
+/// (64:ff9b::/96), 6to4 (2002::/16) and IPv4-compatible (::a.b.c.d)
+/// addresses
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let v4 = |hi: u16, lo: u16| Ipv4Addr::from(((hi as u32) << 16) | lo as u32);
+    match ip.segments() {
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(v4(hi, lo)),
+        [0x2002, hi, lo, ..] => Some(v4(hi, lo)),
+        // Except :: and ::1, IPv6's own unspecified and loopback addresses
+        [0, 0, 0, 0, 0, 0, hi, lo] if !ip.is_unspecified() && !ip.is_loopback() => Some(v4(hi, lo)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(hosts: Option<&[&str]>, allow_private_network: bool) -> Capabilities {
+        Capabilities {
+            allowed_hosts: hosts.map(|hosts| hosts.iter().map(|h| h.to_string()).collect()),
+            allow_private_network,
+            ..Capabilities::default()
+        }
+    }
+
+    fn check(url: &str, capabilities: &Capabilities) -> Result<Vec<SocketAddr>> {
+        check_url(&Url::parse(url).unwrap(), capabilities, None, None)
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("api.plane.so", "api.plane.so"));
+        assert!(host_matches("API.Plane.so", "api.plane.so"));
+        assert!(!host_matches("api.plane.so", "evil-api.plane.so"));
+        assert!(host_matches("*.github.com", "api.github.com"));
+        assert!(host_matches("*.github.com", "a.b.github.com"));
+        assert!(!host_matches("*.github.com", "github.com"));
+        assert!(!host_matches("*.github.com", "evilgithub.com"));
+    }
+
+    #[test]
+    fn test_is_private() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b::127.0.0.1",
+            "64:ff9b:1::1.1.1.1",
+            "2002:a9fe:a9fe::1",
+            "2002:0a00:0001::",
+            "::169.254.169.254",
+            "::10.0.0.1",
+            "::0.0.0.2",
+            "ff02::1",
+            "ff0e::1",
+            "224.0.0.1",
+        ] {
+            assert!(is_private(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "1.1.1.1",
+            "172.32.0.1",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "64:ff9b::1.1.1.1",
+            "2002:0101:0101::1",
+        ] {
+            assert!(!is_private(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_check_url() {
+        // Private networks are allowed so the allowlist is checked without a
+        // DNS lookup
+        let allowed = capabilities(Some(&["api.plane.so", "*.github.com"]), true);
+        assert!(check("https://api.plane.so/v1", &allowed).is_ok());
+        let err = check("https://example.com/", &allowed).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Host 'example.com' is not allowed: add it to allowed_hosts in the Extension definition"
+        );
+
+        let any = capabilities(None, false);
+        let err = check("http://169.254.169.254/latest/meta-data/", &any).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Host '169.254.169.254' resolves to private address 169.254.169.254"),
+            "{}",
+            err
+        );
+        assert!(check("http://[::1]:8080/", &any).is_err());
+        assert!(check("http://localhost:8080/", &any).is_err());
+        assert!(check("http://localhost:8080/", &capabilities(None, true)).is_ok());
+        // A name that can't be vetted isn't left for the request to resolve
+        assert!(check("http://no-such-host.invalid/", &any).is_err());
+
+        // The allowlist applies even with private networks allowed
+        let internal = capabilities(Some(&["10.0.0.5"]), true);
+        assert_eq!(check("http://10.0.0.5/", &internal).unwrap(), Vec::new());
+        assert!(check("http://10.0.0.6/", &internal).is_err());
+    }
+
+    #[test]
+    fn test_lookup_stops_with_the_call() {
+        let any = capabilities(None, false);
+        let url = Url::parse("http://localhost/").unwrap();
+
+        let expired = Instant::now() - Duration::from_secs(1);
+        let err = check_url(&url, &any, Some(expired), None).unwrap_err();
+        assert!(err.to_string().ends_with("ran out of time"), "{}", err);
+
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        let err = check_url(&url, &any, None, Some(&cancelled)).unwrap_err();
+        assert!(err.to_string().ends_with("was cancelled"), "{}", err);
+    }
+}
@@ -7,6 +7,7 @@ use postgres::{Client, NoTls, Row};
 use serde_json::Value as JsonValue;
 use starlark::collections::SmallMap;
 use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
 use starlark::starlark_module;
 use starlark::starlark_simple_value;
 use starlark::values::starlark_value;
@@ -14,6 +15,8 @@ use starlark::values::{
     Heap, NoSerialize, ProvidesStaticType, StarlarkValue, Value, dict::Dict, none::NoneType,
 };
 
+use super::context::CallContext;
+
 /// PostgreSQL module for database operations
 #[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
 #[display(fmt = "postgres")]
@@ -24,8 +27,8 @@ starlark_simple_value!(PostgresModule);
 #[starlark_value(type = "postgres")]
 impl<'v> StarlarkValue<'v> for PostgresModule {
     fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(postgres_methods)
+        static RES: MethodsStatic = MethodsStatic::new("postgres", postgres_methods);
+        Some(RES.methods())
     }
 }
 
@@ -39,8 +42,10 @@ fn postgres_methods(builder: &mut MethodsBuilder) {
         connection_string: &str,
         query: &str,
         #[starlark(default = NoneType)] params: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "postgres")?;
+        let heap = eval.heap();
         execute_query(connection_string, query, params, heap)
     }
 
@@ -50,8 +55,10 @@ fn postgres_methods(builder: &mut MethodsBuilder) {
         connection_string: &str,
         statement: &str,
         #[starlark(default = NoneType)] params: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<i32> {
+        CallContext::check_module(eval, "postgres")?;
+        let heap = eval.heap();
         execute_statement(connection_string, statement, params, heap)
     }
 
@@ -60,8 +67,10 @@ fn postgres_methods(builder: &mut MethodsBuilder) {
         #[allow(unused_variables)] this: Value<'v>,
         connection_string: &str,
         #[starlark(default = "public")] schema: &str,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "postgres")?;
+        let heap = eval.heap();
         let query = "SELECT tablename FROM pg_tables WHERE schemaname = $1 ORDER BY tablename";
         execute_query(
             connection_string,
@@ -77,8 +86,10 @@ fn postgres_methods(builder: &mut MethodsBuilder) {
         connection_string: &str,
         table_name: &str,
         #[starlark(default = "public")] schema: &str,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "postgres")?;
+        let heap = eval.heap();
         let query = "
             SELECT
                 column_name,
@@ -106,7 +117,7 @@ fn execute_query<'v>(
     connection_string: &str,
     query: &str,
     params: Value<'v>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> Result<Value<'v>> {
     // Parse connection string (obfuscate password in errors)
     let safe_conn_str = obfuscate_password(connection_string);
@@ -156,7 +167,7 @@ fn execute_statement<'v>(
     connection_string: &str,
     statement: &str,
     params: Value<'v>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> Result<i32> {
     // Parse connection string (obfuscate password in errors)
     let safe_conn_str = obfuscate_password(connection_string);
@@ -201,7 +212,7 @@ fn execute_statement<'v>(
 }
 
 // Convert Starlark parameters to PostgreSQL parameters
-fn convert_params_to_postgres<'v>(params: Value<'v>, heap: &'v Heap) -> Result<Vec<PostgresParam>> {
+fn convert_params_to_postgres<'v>(params: Value<'v>, heap: Heap<'v>) -> Result<Vec<PostgresParam>> {
     let mut pg_params = Vec::new();
 
     if !params.is_none() {
@@ -255,7 +266,7 @@ fn starlark_to_postgres_param(value: Value) -> Result<PostgresParam> {
 }
 
 // Convert PostgreSQL rows to Starlark list of dicts
-fn rows_to_starlark<'v>(rows: &[Row], heap: &'v Heap) -> Result<Value<'v>> {
+fn rows_to_starlark<'v>(rows: &[Row], heap: Heap<'v>) -> Result<Value<'v>> {
     let mut result = Vec::new();
 
     for row in rows {
@@ -281,7 +292,7 @@ fn rows_to_starlark<'v>(rows: &[Row], heap: &'v Heap) -> Result<Value<'v>> {
 }
 
 // Convert a PostgreSQL value to a Starlark value
-fn postgres_value_to_starlark<'v>(row: &Row, idx: usize, heap: &'v Heap) -> Result<Value<'v>> {
+fn postgres_value_to_starlark<'v>(row: &Row, idx: usize, heap: Heap<'v>) -> Result<Value<'v>> {
     let column = &row.columns()[idx];
     let column_type = column.type_();
 
@@ -357,7 +368,7 @@ fn postgres_value_to_starlark<'v>(row: &Row, idx: usize, heap: &'v Heap) -> Resu
 }
 
 // Convert JSON value to Starlark value (from http.rs)
-fn json_to_starlark<'v>(json: &JsonValue, heap: &'v Heap) -> Result<Value<'v>> {
+fn json_to_starlark<'v>(json: &JsonValue, heap: Heap<'v>) -> Result<Value<'v>> {
     match json {
         JsonValue::Null => Ok(Value::new_none()),
         JsonValue::Bool(b) => Ok(heap.alloc(*b)),
@@ -4,6 +4,7 @@ use derive_more::Display;
 use rusqlite::{Connection, Row};
 use starlark::collections::SmallMap;
 use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
 use starlark::starlark_module;
 use starlark::starlark_simple_value;
 use starlark::values::starlark_value;
@@ -11,6 +12,8 @@ use starlark::values::{
     Heap, NoSerialize, ProvidesStaticType, StarlarkValue, Value, dict::Dict, none::NoneType,
 };
 
+use super::context::CallContext;
+
 /// SQLite module for database operations
 #[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
 #[display(fmt = "sqlite")]
@@ -21,8 +24,8 @@ starlark_simple_value!(SqliteModule);
 #[starlark_value(type = "sqlite")]
 impl<'v> StarlarkValue<'v> for SqliteModule {
     fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(sqlite_methods)
+        static RES: MethodsStatic = MethodsStatic::new("sqlite", sqlite_methods);
+        Some(RES.methods())
     }
 }
 
@@ -36,8 +39,10 @@ fn sqlite_methods(builder: &mut MethodsBuilder) {
         db_path: &str,
         query: &str,
         #[starlark(default = NoneType)] params: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "sqlite")?;
+        let heap = eval.heap();
         execute_query(db_path, query, params, heap)
     }
 
@@ -47,8 +52,10 @@ fn sqlite_methods(builder: &mut MethodsBuilder) {
         db_path: &str,
         statement: &str,
         #[starlark(default = NoneType)] params: Value<'v>,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<i32> {
+        CallContext::check_module(eval, "sqlite")?;
+        let heap = eval.heap();
         execute_statement(db_path, statement, params, heap)
     }
 
@@ -56,8 +63,10 @@ fn sqlite_methods(builder: &mut MethodsBuilder) {
     fn list_tables<'v>(
         #[allow(unused_variables)] this: Value<'v>,
         db_path: &str,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "sqlite")?;
+        let heap = eval.heap();
         let query = "SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%' ORDER BY name";
         execute_query(db_path, query, Value::new_none(), heap)
     }
@@ -67,8 +76,10 @@ fn sqlite_methods(builder: &mut MethodsBuilder) {
         #[allow(unused_variables)] this: Value<'v>,
         db_path: &str,
         table_name: &str,
-        heap: &'v Heap,
+        eval: &mut Evaluator<'v, '_, '_>,
     ) -> anyhow::Result<Value<'v>> {
+        CallContext::check_module(eval, "sqlite")?;
+        let heap = eval.heap();
         // Use PRAGMA table_info which returns: cid, name, type, notnull, dflt_value, pk
         let query = format!("PRAGMA table_info({})", table_name);
         execute_query(db_path, &query, Value::new_none(), heap)
@@ -86,7 +97,7 @@ fn execute_query<'v>(
     db_path: &str,
     query: &str,
     params: Value<'v>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> Result<Value<'v>> {
     // Convert Starlark parameters to SQLite parameters
     let sqlite_params = convert_params_to_sqlite(params, heap)?;
@@ -148,7 +159,7 @@ fn execute_statement<'v>(
     db_path: &str,
     statement: &str,
     params: Value<'v>,
-    heap: &'v Heap,
+    heap: Heap<'v>,
 ) -> Result<i32> {
     // Convert Starlark parameters to SQLite parameters
     let sqlite_params = convert_params_to_sqlite(params, heap)?;
@@ -187,7 +198,7 @@ fn execute_statement<'v>(
 }
 
 // Convert Starlark parameters to SQLite parameters
-fn convert_params_to_sqlite<'v>(params: Value<'v>, heap: &'v Heap) -> Result<Vec<SqliteParam>> {
+fn convert_params_to_sqlite<'v>(params: Value<'v>, heap: Heap<'v>) -> Result<Vec<SqliteParam>> {
     let mut sqlite_params = Vec::new();
 
     if !params.is_none() {
@@ -282,7 +293,7 @@ fn row_to_values(row: &Row, column_names: &[String]) -> Result<RowData> {
 }
 
 // Convert SQLite rows to Starlark list of dicts
-fn rows_to_starlark<'v>(rows: &[RowData], heap: &'v Heap) -> Result<Value<'v>> {
+fn rows_to_starlark<'v>(rows: &[RowData], heap: Heap<'v>) -> Result<Value<'v>> {
     let mut result = Vec::new();
 
     for row_data in rows {
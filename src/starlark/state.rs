@@ -0,0 +1,235 @@
+use allocative::Allocative;
+use derive_more::Display;
+use starlark::environment::{GlobalsBuilder, Methods, MethodsBuilder, MethodsStatic};
+use starlark::eval::Evaluator;
+use starlark::starlark_module;
+use starlark::starlark_simple_value;
+use starlark::values::none::NoneType;
+use starlark::values::starlark_value;
+use starlark::values::{NoSerialize, ProvidesStaticType, StarlarkValue, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::context::CallContext;
+use super::engine::{json_to_starlark_value, starlark_value_to_json};
+
+/// Mutable key/value state owned by one loaded extension.
+///
+/// Values are stored as JSON so they can outlive the Starlark heap of the call
+/// that wrote them. A fresh store is created each time the extension is loaded,
+/// so hot reloads start from a clean slate.
+#[derive(Debug, Clone, Default)]
+pub struct ExtensionState {
+    values: Arc<Mutex<HashMap<String, serde_json::Value>>>,
+}
+
+impl ExtensionState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<serde_json::Value> {
+        self.values.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn set(&self, key: &str, value: serde_json::Value) {
+        self.values.lock().unwrap().insert(key.to_string(), value);
+    }
+
+    pub fn remove(&self, key: &str) -> Option<serde_json::Value> {
+        self.values.lock().unwrap().remove(key)
+    }
+
+    /// All keys, sorted
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.values.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Display, Allocative, ProvidesStaticType, NoSerialize)]
+#[display(fmt = "state")]
+pub struct StateModule;
+
+starlark_simple_value!(StateModule);
+
+#[starlark_value(type = "state")]
+impl<'v> StarlarkValue<'v> for StateModule {
+    fn get_methods() -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new("state", state_methods);
+        Some(RES.methods())
+    }
+
+    fn dir_attr(&self) -> Vec<String> {
+        vec![
+            "get".to_owned(),
+            "set".to_owned(),
+            "delete".to_owned(),
+            "keys".to_owned(),
+        ]
+    }
+}
+
+#[starlark_module]
+fn state_methods(builder: &mut MethodsBuilder) {
+    /// Get a value from this extension's state, or `default` if it isn't set.
+    ///
+    /// # Examples
+    /// ```python
+    /// config = state.get("config", {})
+    /// ```
+    fn get<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        #[starlark(default = NoneType)] default: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<Value<'v>> {
+        let ctx = CallContext::require(eval, "state.get")?;
+        match ctx.state.get(key) {
+            Some(value) => json_to_starlark_value(value, eval.heap()),
+            None => Ok(default),
+        }
+    }
+
+    /// Store a value in this extension's state. Values must be JSON-compatible
+    /// (None, bool, int, float, string, list, dict).
+    ///
+    /// # Examples
+    /// ```python
+    /// state.set("calls", state.get("calls", 0) + 1)
+    /// ```
+    fn set<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        value: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<NoneType> {
+        let ctx = CallContext::require(eval, "state.set")?;
+        let json = starlark_value_to_json(value, eval.heap())
+            .map_err(|e| anyhow::anyhow!("state.set: cannot store '{}': {}", key, e))?;
+        ctx.state.set(key, json);
+        Ok(NoneType)
+    }
+
+    /// Remove a key from this extension's state. Returns True if it was set.
+    fn delete<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        key: &str,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<bool> {
+        let ctx = CallContext::require(eval, "state.delete")?;
+        Ok(ctx.state.remove(key).is_some())
+    }
+
+    /// List the keys in this extension's state, sorted.
+    fn keys<'v>(
+        #[allow(unused_variables)] this: Value<'v>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> anyhow::Result<Vec<String>> {
+        let ctx = CallContext::require(eval, "state.keys")?;
+        Ok(ctx.state.keys())
+    }
+}
+
+pub fn register(builder: &mut GlobalsBuilder) {
+    const STATE: StateModule = StateModule;
+    builder.set("state", STATE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::environment::{GlobalsBuilder, Module};
+    use starlark::syntax::{AstModule, Dialect};
+
+    fn eval_with_state(code: &str, state: &ExtensionState) -> Result<String, starlark::Error> {
+        let ctx = CallContext::new("test").with_state(state.clone());
+
+        let globals = GlobalsBuilder::standard().with(register).build();
+        Module::with_temp_heap(|module| {
+            let ast = AstModule::parse("test.star", code.to_owned(), &Dialect::Standard)?;
+            let mut eval = Evaluator::new(&module);
+            ctx.attach(&mut eval);
+            let result = eval.eval_module(ast, &globals)?;
+
+            Ok(result.to_string())
+        })
+    }
+
+    #[test]
+    fn test_set_and_get() {
+        let state = ExtensionState::new();
+        eval_with_state(
+            r#"state.set("config", {"region": "us-east-1", "retries": 3})"#,
+            &state,
+        )
+        .unwrap();
+
+        let result = eval_with_state(r#"state.get("config")["region"]"#, &state).unwrap();
+        assert_eq!(result, "\"us-east-1\"");
+        assert_eq!(state.get("config").unwrap()["retries"], 3);
+    }
+
+    #[test]
+    fn test_get_default() {
+        let state = ExtensionState::new();
+        let result = eval_with_state(r#"state.get("missing", 42)"#, &state).unwrap();
+        assert_eq!(result, "42");
+
+        let result = eval_with_state(r#"state.get("missing")"#, &state).unwrap();
+        assert_eq!(result, "None");
+    }
+
+    #[test]
+    fn test_delete_and_keys() {
+        let state = ExtensionState::new();
+        let result = eval_with_state(
+            r#"
+state.set("b", 1)
+state.set("a", 2)
+deleted = state.delete("b")
+missing = state.delete("b")
+(deleted, missing, state.keys())
+"#,
+            &state,
+        )
+        .unwrap();
+        assert_eq!(result, r#"(True, False, ["a"])"#);
+    }
+
+    #[test]
+    fn test_unserializable_value_rejected() {
+        let state = ExtensionState::new();
+        let result = eval_with_state(
+            r#"
+def f():
+    pass
+state.set("fn", f)
+"#,
+            &state,
+        );
+        assert!(result.is_err());
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn test_requires_context() {
+        let globals = GlobalsBuilder::standard().with(register).build();
+        Module::with_temp_heap(|module| {
+            let ast = AstModule::parse("test.star", "state.keys()".to_owned(), &Dialect::Standard)
+                .unwrap();
+            let mut eval = Evaluator::new(&module);
+            let err = eval.eval_module(ast, &globals).unwrap_err();
+            assert!(err.to_string().contains("no extension context"));
+        })
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Result, anyhow};
+use starlark::environment::{FrozenModule, Globals, GlobalsBuilder, LibraryExtension, Module};
+use starlark::eval::Evaluator;
+use starlark::syntax::{AstModule, Dialect};
+use starlark::typing::AstModuleTypecheck;
+use std::collections::HashMap;
+
+use super::diagnostics::LocatedError;
+use super::modules::globals_builder;
+
+/// Typed signatures for the builtin modules, written as Starlark
+const STUBS: &str = include_str!("stubs.star");
+
+/// Builtin modules replaced by their stubs when typechecking
+const STUBBED_MODULES: &[&str] = &[
+    "http", "exec", "env", "config", "postgres", "sqlite", "data", "fuzzy", "state", "kv", "cache",
+    "math", "time",
+];
+
+/// Static typechecker for extensions and the modules they load.
+///
+/// Checks run against the real globals with each builtin module swapped for
+/// a struct of typed stub functions, so calls like `http.get(url, 1)` are
+/// reported when the file loads rather than when a tool runs. Unannotated
+/// code and values from `load()` are treated as `typing.Any`.
+pub struct TypeChecker {
+    globals: Globals,
+}
+
+impl TypeChecker {
+    pub fn new() -> Result<Self> {
+        let stubs = eval_stubs()?;
+
+        let mut builder = globals_builder();
+        builder.frozen_heap().add_reference(stubs.frozen_heap());
+        for name in STUBBED_MODULES {
+            let stub = stubs
+                .get(name)
+                .map_err(|e| anyhow!("Missing type stub for '{}': {}", name, e))?;
+            let value = stub
+                .owned_value(builder.frozen_heap())
+                .unpack_frozen()
+                .ok_or_else(|| anyhow!("Type stub for '{}' is not frozen", name))?;
+            builder.set(name, value);
+        }
+
+        Ok(Self {
+            globals: builder.build(),
+        })
+    }
+
+    /// Every type error in `ast`, which must be parsed with [`Dialect::Extended`]
+    pub fn errors(&self, ast: AstModule) -> Vec<starlark::Error> {
+        let (errors, ..) = ast.typecheck(&self.globals, &HashMap::new());
+        errors
+    }
+
+    /// Fail with every type error in `ast`, located at the first one
+    pub fn check(&self, ast: AstModule) -> Result<()> {
+        let errors = self.errors(ast);
+        match errors.first() {
+            None => Ok(()),
+            Some(first) => Err(LocatedError::new("Type error", first)
+                .with_message(format!(
+                    "Type error: {}",
+                    errors
+                        .iter()
+                        .map(|e| e.to_string())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                ))
+                .into()),
+        }
+    }
+}
+
+fn eval_stubs() -> Result<FrozenModule> {
+    let globals =
+        GlobalsBuilder::extended_by(&[LibraryExtension::StructType, LibraryExtension::Typing])
+            .build();
+    let ast = AstModule::parse("stubs.star", STUBS.to_owned(), &Dialect::Extended)
+        .map_err(|e| anyhow!("Invalid type stubs: {}", e))?;
+
+    Module::with_temp_heap(|module| {
+        {
+            let mut eval = Evaluator::new(&module);
+            eval.eval_module(ast, &globals)
+                .map_err(|e| anyhow!("Invalid type stubs: {}", e))?;
+        }
+        module
+            .freeze()
+            .map_err(|e| anyhow!("Freeze error: {:?}", e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::starlark::diagnostics::span_of;
+    use crate::starlark::modules::build_globals;
+    use starlark::docs::{DocFunction, DocItem, DocMember};
+
+    fn errors(code: &str) -> Vec<String> {
+        let ast = AstModule::parse("weather.star", code.to_owned(), &Dialect::Extended).unwrap();
+        TypeChecker::new()
+            .unwrap()
+            .errors(ast)
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_calls() {
+        let code = r#"
+load("//lib/util.star", "helper")
+
+def fetch(params):
+    url = "https://api.example.com/" + env.get("REGION", "us")
+    resp = http.get(url, params={"q": params.get("q")}, headers={"Accept": "application/json"})
+    rows = sqlite.query("app.db", "SELECT 1", [1, "x"])
+    result = exec.run("echo", ["hi"])
+    kv.set("last", resp["status"], ttl_secs=60)
+    return helper(resp["json"], rows, result["stdout"], math.round(1.5), time.now())
+"#;
+        assert_eq!(errors(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_builtin_misuse_reported() {
+        let found = errors("def fetch(url):\n    return http.get(url, 1)\n");
+        assert_eq!(found.len(), 1);
+        assert!(
+            found[0].contains("None | dict[str, typing.Any]"),
+            "{}",
+            found[0]
+        );
+        assert!(found[0].contains("weather.star:2:"), "{}", found[0]);
+
+        assert_eq!(errors("def f():\n    return http.gett(\"u\")\n").len(), 1);
+        assert_eq!(errors("def f():\n    return exec.run(1)\n").len(), 1);
+        assert_eq!(
+            errors("def f():\n    return postgres.query(\"db\")\n").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_user_annotations_checked() {
+        let found = errors(
+            "def area(r: float) -> float:\n    return r * r\n\ndef f():\n    return area(\"2\")\n",
+        );
+        assert_eq!(found.len(), 1);
+    }
+
+    #[test]
+    fn test_check_locates_first_error() {
+        let ast = AstModule::parse(
+            "weather.star",
+            "def f():\n    http.get(1)\n    env.get(2)\n".to_owned(),
+            &Dialect::Extended,
+        )
+        .unwrap();
+        let err = TypeChecker::new().unwrap().check(ast).unwrap_err();
+
+        assert!(err.to_string().starts_with("Type error: "));
+        assert_eq!(err.to_string().matches("Expected type").count(), 2);
+        let span = span_of(&err).unwrap();
+        assert_eq!((span.line, span.column), (2, 14));
+    }
+
+    /// Names of the parameters of `function`, in order
+    fn param_names(function: &DocFunction) -> Vec<String> {
+        let params = &function.params;
+        params
+            .pos_only
+            .iter()
+            .chain(&params.pos_or_named)
+            .chain(&params.args)
+            .chain(&params.named_only)
+            .chain(&params.kwargs)
+            .map(|param| param.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_stubs_match_modules() {
+        // Modules that come with starlark rather than this crate
+        let library = GlobalsBuilder::extended_by(&[
+            LibraryExtension::StructType,
+            LibraryExtension::Json,
+            LibraryExtension::Debug,
+            LibraryExtension::Typing,
+        ])
+        .build();
+        let library: Vec<&str> = library.iter().map(|(name, _)| name).collect();
+        let stubs = eval_stubs().unwrap();
+        Module::with_temp_heap(|heap| {
+            let mut mismatches = Vec::new();
+            let mut stubbed_modules = Vec::new();
+
+            let globals = build_globals();
+            for (name, value) in globals.iter() {
+                let methods = value.to_value().dir_attr();
+                if methods.is_empty() || library.contains(&name) {
+                    continue;
+                }
+                if !STUBBED_MODULES.contains(&name) {
+                    mismatches.push(format!("{} has no stub", name));
+                    continue;
+                }
+                stubbed_modules.push(name);
+                let stub = stubs.get(name).unwrap();
+                for method in &methods {
+                    let Some(DocItem::Member(DocMember::Function(function))) = value
+                        .to_value()
+                        .get_attr(method, heap.heap())
+                        .unwrap()
+                        .map(|f| f.documentation())
+                    else {
+                        continue;
+                    };
+                    let function = &function;
+                    let stubbed = heap
+                        .heap()
+                        .access_owned_frozen_value(&stub)
+                        .get_attr(method, heap.heap())
+                        .unwrap()
+                        .map(|f| f.documentation());
+                    match stubbed {
+                        Some(DocItem::Member(DocMember::Function(stubbed))) => {
+                            if param_names(&stubbed) != param_names(function) {
+                                mismatches.push(format!(
+                                    "{}.{}: stub takes {:?}, module takes {:?}",
+                                    name,
+                                    method,
+                                    param_names(&stubbed),
+                                    param_names(function)
+                                ));
+                            }
+                        }
+                        _ => mismatches.push(format!("{}.{} has no stub", name, method)),
+                    }
+                }
+            }
+            assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+            stubbed_modules.sort();
+            let mut expected = STUBBED_MODULES.to_vec();
+            expected.sort();
+            assert_eq!(stubbed_modules, expected);
+        })
+    }
+}
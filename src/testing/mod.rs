@@ -1,15 +1,17 @@
+#[allow(clippy::module_inception)]
 mod testing;
 
 use anyhow::{Result, anyhow};
 use starlark::environment::{FrozenModule, Globals, GlobalsBuilder, LibraryExtension, Module};
-use starlark::eval::{Evaluator, FileLoader};
+use starlark::eval::Evaluator;
 use starlark::syntax::{AstModule, Dialect};
-use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use tracing::{debug, error, info};
+use tracing::{debug, error};
 
+use crate::starlark::context::CallContext;
+use crate::starlark::kv::KvStore;
 use crate::starlark::mcp_types::mcp_globals;
+use crate::starlark::module_loader::{ModuleCache, ModuleLoader};
 use crate::starlark::modules::build_globals;
 
 fn build_test_globals() -> Globals {
@@ -29,22 +31,13 @@ fn build_test_globals() -> Globals {
     .with(crate::starlark::sqlite::register)
     .with(crate::starlark::data::register)
     .with(crate::starlark::fuzzy::register)
+    .with(crate::starlark::state::register)
+    .with(crate::starlark::kv::register)
+    .with(crate::starlark::cache::register)
+    .with(crate::starlark::config::register)
     .build()
 }
 
-struct ModuleLoader {
-    modules: HashMap<String, Arc<FrozenModule>>,
-}
-
-impl FileLoader for ModuleLoader {
-    fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
-        self.modules
-            .get(path)
-            .map(|m| m.as_ref().clone())
-            .ok_or_else(|| anyhow!("Module '{}' not found", path))
-    }
-}
-
 #[derive(Debug)]
 pub struct TestResult {
     pub name: String,
@@ -138,10 +131,21 @@ fn discover_test_files(extensions_dir: &str) -> Result<Vec<PathBuf>> {
     Ok(test_files)
 }
 
+/// Context for evaluating a test file: data files resolve against the
+/// extensions directory, `kv` is in-memory, and no commands may be executed
+fn test_context(file_name: &str, extensions_dir: &Path, kv: &KvStore) -> CallContext {
+    CallContext::new(file_name)
+        .with_extensions_dir(Some(extensions_dir.to_path_buf()))
+        .with_kv(Some(kv.clone()))
+}
+
 /// Load a test file and return the frozen module
 fn load_test_file(
     test_path: &Path,
-    available_modules: &HashMap<String, Arc<FrozenModule>>,
+    extensions_dir: &Path,
+    module_globals: &Globals,
+    cache: &ModuleCache,
+    kv: &KvStore,
 ) -> Result<FrozenModule> {
     let content = std::fs::read_to_string(test_path)?;
     let file_name = test_path
@@ -153,21 +157,28 @@ fn load_test_file(
         .map_err(|e| anyhow!("Failed to parse {}: {}", file_name, e))?;
 
     let globals = build_test_globals();
-    let module = Module::new();
+    let context = test_context(file_name, extensions_dir, kv);
 
-    let loader = ModuleLoader {
-        modules: available_modules.clone(),
-    };
+    // Extensions and lib/ helpers are evaluated with the production globals
+    let loader = ModuleLoader::new(extensions_dir, module_globals, cache)?
+        .with_dialect(Dialect::Extended)
+        .with_context(&context)
+        .for_file(test_path);
 
-    // Evaluator must be dropped before freeze() to satisfy borrow checker
-    {
-        let mut eval = Evaluator::new(&module);
-        eval.set_loader(&loader);
-        eval.eval_module(ast, &globals)
-            .map_err(|e| anyhow!("Failed to evaluate {}: {}", file_name, e))?;
-    }
+    Module::with_temp_heap(|module| {
+        // Evaluator must be dropped before freeze() to satisfy borrow checker
+        {
+            let mut eval = Evaluator::new(&module);
+            context.attach(&mut eval);
+            eval.set_loader(&loader);
+            eval.eval_module(ast, &globals)
+                .map_err(|e| anyhow!("Failed to evaluate {}: {}", file_name, e))?;
+        }
 
-    module.freeze()
+        module
+            .freeze()
+            .map_err(|e| anyhow!("Freeze error: {:?}", e))
+    })
 }
 
 /// Discover test functions in a frozen module
@@ -192,6 +203,7 @@ fn execute_test(
     test_name: &str,
     file_name: &str,
     extensions_dir: &str,
+    kv: &KvStore,
 ) -> TestResult {
     let full_name = format!("{}::{}", file_name, test_name);
 
@@ -208,114 +220,35 @@ fn execute_test(
         }
     };
 
-    crate::starlark::modules::set_extensions_dir(extensions_dir.to_string());
-
-    let exec_module = Module::new();
-    let mut eval = Evaluator::new(&exec_module);
-
-    let result = match eval.eval_function(test_fn.value(), &[], &[]) {
-        Ok(_) => TestResult {
-            name: full_name,
-            passed: true,
-            error: None,
-        },
-        Err(e) => TestResult {
-            name: full_name,
-            passed: false,
-            error: Some(format!("{}", e)),
-        },
-    };
-
-    crate::starlark::modules::clear_extensions_dir();
-
-    result
-}
-
-/// Load all non-test extensions as modules that can be imported by tests
-fn load_extension_modules(extensions_dir: &str) -> Result<HashMap<String, Arc<FrozenModule>>> {
-    let path = Path::new(extensions_dir);
-    if !path.exists() {
-        return Ok(HashMap::new());
-    }
-
-    let canonical_dir = std::fs::canonicalize(path)?;
-    let mut modules = HashMap::new();
-    let globals = build_globals();
+    let context = test_context(file_name, Path::new(extensions_dir), kv);
 
-    crate::starlark::modules::set_extensions_dir(canonical_dir.to_string_lossy().to_string());
+    Module::with_temp_heap(|exec_module| {
+        let mut eval = Evaluator::new(&exec_module);
+        context.attach(&mut eval);
 
-    let extension_files: Vec<_> = std::fs::read_dir(&canonical_dir)?
-        .filter_map(|entry| entry.ok())
-        .filter_map(|entry| {
-            let entry_path = entry.path();
-            let canonical_entry = std::fs::canonicalize(&entry_path).ok()?;
-
-            if !canonical_entry.starts_with(&canonical_dir) {
-                debug!("Skipping entry outside directory: {:?}", canonical_entry);
-                return None;
-            }
-
-            let file_name = entry_path.file_name()?.to_str()?.to_string();
-            if entry_path.is_file()
-                && file_name.ends_with(".star")
-                && !file_name.ends_with("_test.star")
-            {
-                Some((entry_path, file_name))
-            } else {
-                None
-            }
-        })
-        .collect();
-
-    for (entry_path, file_name) in extension_files {
-        let module_name = file_name.trim_end_matches(".star");
-
-        if let Err(e) =
-            load_single_module(&entry_path, &file_name, module_name, &globals, &mut modules)
-        {
-            error!("Failed to load module {}: {}", module_name, e);
+        match eval.eval_function(
+            exec_module.heap().access_owned_frozen_value(&test_fn),
+            &[],
+            &[],
+        ) {
+            Ok(_) => TestResult {
+                name: full_name,
+                passed: true,
+                error: None,
+            },
+            Err(e) => TestResult {
+                name: full_name,
+                passed: false,
+                error: Some(format!("{}", e)),
+            },
         }
-    }
-
-    crate::starlark::modules::clear_extensions_dir();
-
-    Ok(modules)
-}
-
-/// Load a single extension module
-fn load_single_module(
-    entry_path: &Path,
-    file_name: &str,
-    module_name: &str,
-    globals: &Globals,
-    modules: &mut HashMap<String, Arc<FrozenModule>>,
-) -> Result<()> {
-    let content = std::fs::read_to_string(entry_path)?;
-    let ast = AstModule::parse(file_name, content, &Dialect::Extended)
-        .map_err(|e| anyhow!("Parse error: {}", e))?;
-
-    let module = Module::new();
-    {
-        let mut eval = Evaluator::new(&module);
-        eval.eval_module(ast, globals)
-            .map_err(|e| anyhow!("Eval error: {}", e))?;
-    }
-
-    let frozen = module
-        .freeze()
-        .map_err(|e| anyhow!("Freeze error: {}", e))?;
-    info!("Loaded module: {}", module_name);
-    modules.insert(module_name.to_string(), Arc::new(frozen));
-    Ok(())
+    })
 }
 
 /// Run all tests in the given directory
 pub async fn run_tests(extensions_dir: &str) -> Result<()> {
     println!("Discovering tests in: {}", extensions_dir);
 
-    let extension_modules = load_extension_modules(extensions_dir)?;
-    info!("Loaded {} extension modules", extension_modules.len());
-
     let test_files = discover_test_files(extensions_dir)?;
 
     if test_files.is_empty() {
@@ -325,6 +258,10 @@ pub async fn run_tests(extensions_dir: &str) -> Result<()> {
 
     println!("Found {} test file(s)", test_files.len());
 
+    let canonical_dir = std::fs::canonicalize(extensions_dir)?;
+    let module_globals = build_globals();
+    let cache = ModuleCache::new();
+    let kv = KvStore::in_memory()?;
     let mut summary = TestSummary::new();
 
     for test_path in test_files {
@@ -335,7 +272,9 @@ pub async fn run_tests(extensions_dir: &str) -> Result<()> {
 
         println!("\nRunning tests from: {}", file_name);
 
-        let test_module = match load_test_file(&test_path, &extension_modules) {
+        let loaded = load_test_file(&test_path, &canonical_dir, &module_globals, &cache, &kv);
+
+        let test_module = match loaded {
             Ok(module) => module,
             Err(e) => {
                 error!("Failed to load test file {}: {}", file_name, e);
@@ -358,7 +297,7 @@ pub async fn run_tests(extensions_dir: &str) -> Result<()> {
         println!("  Found {} test(s)", test_functions.len());
 
         for test_name in test_functions {
-            let result = execute_test(&test_module, &test_name, file_name, extensions_dir);
+            let result = execute_test(&test_module, &test_name, file_name, extensions_dir, &kv);
             let status = if result.passed { "✓" } else { "✗" };
             println!("    {} {}", status, test_name);
             if let Some(error) = &result.error {
@@ -29,8 +29,8 @@ starlark_simple_value!(TestingModule);
 #[starlark_value(type = "testing")]
 impl<'v> StarlarkValue<'v> for TestingModule {
     fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(testing_methods)
+        static RES: MethodsStatic = MethodsStatic::new("testing", testing_methods);
+        Some(RES.methods())
     }
 
     fn dir_attr(&self) -> Vec<String> {